@@ -2,9 +2,16 @@ use std::path::Path;
 use std::process::Command;
 
 fn main() {
+    emit_git_hash();
+
     let ui_dir = "ui";
     let ui_dist_dir = "ui/dist";
 
+    // Without the ui feature nothing embeds the assets, so node is not needed
+    if std::env::var_os("CARGO_FEATURE_UI").is_none() {
+        return;
+    }
+
     // Tell cargo to rerun if ui source files change
     println!("cargo:rerun-if-changed=ui/src");
     println!("cargo:rerun-if-changed=ui/package.json");
@@ -88,6 +95,32 @@ fn main() {
     }
 }
 
+/// Exposes the commit being built as `GIT_HASH`, when building from a git
+/// checkout with git installed.
+fn emit_git_hash() {
+    // Rebuild on commits and checkouts, which move HEAD or the branch it
+    // names. A missing file would rerun this script on every build.
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            let path = Path::new(".git").join(branch);
+            if path.exists() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+    }
+
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if let Some(hash) = hash.filter(|hash| !hash.is_empty()) {
+        println!("cargo:rustc-env=GIT_HASH={hash}");
+    }
+}
+
 fn is_npm_available() -> bool {
     Command::new("npm")
         .args(["--version"])
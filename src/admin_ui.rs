@@ -0,0 +1,163 @@
+use anyhow::Result;
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use crate::recorder::HttpTransaction;
+
+/// Rows shown on the fallback page, newest first.
+const FALLBACK_ROWS: usize = 200;
+
+/// `host:port` for reaching the admin UI on a listener, using `localhost`
+/// for wildcard addresses.
+pub fn local_authority(addr: SocketAddr) -> String {
+    if addr.ip().is_unspecified() {
+        format!("localhost:{}", addr.port())
+    } else {
+        addr.to_string()
+    }
+}
+
+/// `host:port` for reaching the admin UI from another device on the LAN,
+/// or `None` when every listener is loopback-only.
+pub fn lan_authority(listen_addrs: &[SocketAddr]) -> Option<String> {
+    listen_addrs.iter().find_map(|addr| {
+        let ip = addr.ip();
+        if ip.is_loopback() {
+            None
+        } else if ip.is_unspecified() {
+            let lan_ip = lan_ip()?;
+            Some(SocketAddr::new(lan_ip, addr.port()).to_string())
+        } else {
+            Some(addr.to_string())
+        }
+    })
+}
+
+/// The address of the interface that routes to the outside. Connecting a
+/// UDP socket sends nothing; it only picks the source address.
+fn lan_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+/// Renders `text` as a QR code of Unicode half blocks, light on dark so it
+/// scans from a terminal with a dark background.
+pub fn qr_code(text: &str) -> Result<String> {
+    let code = QrCode::new(text)?;
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+/// Opens `url` in the default browser without waiting for it.
+pub fn open_browser(url: &str) -> Result<()> {
+    open::that_detached(url)?;
+    Ok(())
+}
+
+/// A server-rendered page listing the latest transactions, served in place
+/// of the web interface when its assets were not built. It reloads itself
+/// every few seconds.
+pub fn fallback_page<'a>(
+    transactions: impl DoubleEndedIterator<Item = &'a HttpTransaction>,
+    token: &str,
+) -> String {
+    let token = url::form_urlencoded::byte_serialize(token.as_bytes()).collect::<String>();
+    let mut rows = String::new();
+    for t in transactions.rev().take(FALLBACK_ROWS) {
+        let (status, class) = match (&t.response, &t.error) {
+            (Some(response), _) => (
+                response.status.to_string(),
+                if response.status >= 400 { "err" } else { "" },
+            ),
+            (None, Some(error)) => (error.clone(), "err"),
+            (None, None) => ("pending".to_string(), "pending"),
+        };
+        let _ = write!(
+            rows,
+            "<tr class=\"{class}\"><td>{}</td><td>{}</td><td class=\"path\">{}</td>\
+             <td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            clock_time(t.request.timestamp),
+            escape_html(&t.request.method),
+            escape_html(&t.request.path),
+            escape_html(&status),
+            t.response
+                .as_ref()
+                .map_or(String::new(), |response| format!(
+                    "{} ms",
+                    response.duration_ms
+                )),
+            t.response
+                .as_ref()
+                .map_or(String::new(), |response| format!(
+                    "{} B",
+                    response.body.size
+                )),
+            escape_html(&t.request.client_addr),
+        );
+    }
+    if rows.is_empty() {
+        rows.push_str("<tr><td colspan=\"7\">No requests yet</td></tr>");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="2">
+<title>Debug Proxy</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 1.5em; }}
+table {{ border-collapse: collapse; width: 100%; font-size: 14px; }}
+th, td {{ text-align: left; padding: 4px 8px; border-bottom: 1px solid #ddd; }}
+td.path {{ font-family: monospace; word-break: break-all; }}
+tr.err td {{ color: #b00020; }}
+tr.pending td {{ color: #888; }}
+</style>
+</head>
+<body>
+<h1>Debug Proxy</h1>
+<p>The web interface was not built into this binary, so this page lists the latest {FALLBACK_ROWS} requests and reloads every 2 seconds.
+Full details are in the <a href="/_proxy/api/logs?token={token}">JSON API</a>.</p>
+<table>
+<thead><tr><th>Time (UTC)</th><th>Method</th><th>Path</th><th>Status</th><th>Duration</th><th>Size</th><th>Client</th></tr></thead>
+<tbody>{rows}</tbody>
+</table>
+</body>
+</html>
+"#
+    )
+}
+
+/// `HH:MM:SS` of a timestamp in milliseconds since the epoch, in UTC.
+fn clock_time(timestamp: u64) -> String {
+    let seconds = timestamp / 1000 % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,160 @@
+use http::{header, Method, Request};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::SharedConfig;
+use crate::recorder::{now_ms, HttpTransaction, RequestRecorder};
+use crate::upstream::web_client;
+
+/// The traffic an alert looks back over.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// How often the monitor checks whether the alert changed.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Thresholds over the last minute of traffic that raise an alert, so a
+/// failing upstream shows up without watching the request list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertRules {
+    /// Upstream timeouts in the last minute above which to alert.
+    pub max_timeouts_per_minute: Option<usize>,
+    /// Percentage of failed or 5xx requests in the last minute above which
+    /// to alert.
+    pub max_error_rate_percent: Option<f64>,
+    /// Requests the last minute needs before its error rate counts.
+    pub min_requests: usize,
+    /// URL that gets a JSON `POST` whenever the alert is raised or cleared.
+    pub webhook: Option<String>,
+}
+
+impl Default for AlertRules {
+    fn default() -> Self {
+        Self {
+            max_timeouts_per_minute: None,
+            max_error_rate_percent: None,
+            min_requests: 5,
+            webhook: None,
+        }
+    }
+}
+
+/// The alert as of one evaluation, with the figures it was based on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlertStatus {
+    pub active: bool,
+    pub reasons: Vec<String>,
+    pub requests: usize,
+    pub timeouts: usize,
+    pub error_rate_percent: f64,
+}
+
+impl AlertRules {
+    pub fn is_enabled(&self) -> bool {
+        self.max_timeouts_per_minute.is_some() || self.max_error_rate_percent.is_some()
+    }
+
+    /// Checks the transactions recorded in the minute before `now_ms`.
+    pub fn evaluate<'a>(
+        &self,
+        transactions: impl IntoIterator<Item = &'a HttpTransaction>,
+        now_ms: u64,
+    ) -> AlertStatus {
+        let since = now_ms.saturating_sub(WINDOW.as_millis() as u64);
+        let (mut requests, mut timeouts, mut errors) = (0, 0, 0);
+        for transaction in transactions {
+            if transaction.request.timestamp < since {
+                continue;
+            }
+            requests += 1;
+            if transaction.error.as_deref() == Some("Upstream timeout") {
+                timeouts += 1;
+            }
+            if transaction.error.is_some()
+                || transaction
+                    .response
+                    .as_ref()
+                    .is_some_and(|r| r.status >= 500)
+            {
+                errors += 1;
+            }
+        }
+        let error_rate_percent = if requests == 0 {
+            0.0
+        } else {
+            errors as f64 * 100.0 / requests as f64
+        };
+
+        let mut reasons = Vec::new();
+        if let Some(max) = self.max_timeouts_per_minute {
+            if timeouts > max {
+                reasons.push(format!("{timeouts} upstream timeouts in the last minute"));
+            }
+        }
+        if let Some(max) = self.max_error_rate_percent {
+            if requests >= self.min_requests.max(1) && error_rate_percent > max {
+                reasons.push(format!(
+                    "{error_rate_percent:.0}% of {requests} requests failed in the last minute"
+                ));
+            }
+        }
+        AlertStatus {
+            active: !reasons.is_empty(),
+            reasons,
+            requests,
+            timeouts,
+            error_rate_percent,
+        }
+    }
+}
+
+/// Logs the alert being raised and cleared, and posts each change to the
+/// rules' webhook. The rules are reread on every check, so they can be
+/// changed at runtime.
+pub fn spawn_alert_monitor(recorder: RequestRecorder, config: SharedConfig) {
+    let client = web_client();
+
+    tokio::spawn(async move {
+        let mut active = false;
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            let rules = config.read().alerts.clone();
+            if !rules.is_enabled() && !active {
+                continue;
+            }
+            let status = rules.evaluate(recorder.snapshot().iter().map(AsRef::as_ref), now_ms());
+            if status.active == active {
+                continue;
+            }
+            active = status.active;
+            if active {
+                warn!("Alert raised: {}", status.reasons.join(", "));
+            } else {
+                info!("Alert cleared");
+            }
+
+            let Some(webhook) = rules.webhook else {
+                continue;
+            };
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(&webhook)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "alert": status }).to_string(),
+                ));
+            let sent = match request {
+                Ok(request) => tokio::time::timeout(CHECK_INTERVAL, client.request(request))
+                    .await
+                    .map_err(|_| "timed out".to_string())
+                    .and_then(|result| result.map_err(|e| e.to_string())),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = sent {
+                warn!("Failed to notify alert webhook {webhook}: {e}");
+            }
+        }
+    });
+}
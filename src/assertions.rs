@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use http::{HeaderMap, Method};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::recorder::Violation;
+use crate::route::PathTemplate;
+use crate::schema;
+
+const SOURCE: &str = "schema";
+
+/// A JSON Schema assertion for the bodies exchanged on one route.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaAssertion {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+}
+
+/// Contents of a `--schema-assertions` file, also accepted by the admin API.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SchemaAssertionSet {
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub routes: Vec<SchemaAssertion>,
+}
+
+struct CompiledAssertion {
+    assertion: SchemaAssertion,
+    template: PathTemplate,
+}
+
+/// Runtime-editable set of per-route schema assertions.
+#[derive(Clone, Default)]
+pub struct SchemaAssertions {
+    routes: Arc<RwLock<Vec<CompiledAssertion>>>,
+    strict: Arc<AtomicBool>,
+}
+
+impl SchemaAssertions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads assertions from a YAML or JSON file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read schema assertions: {}", path.display()))?;
+        let set: SchemaAssertionSet = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse schema assertions: {}", path.display()))?;
+        let assertions = Self::new();
+        assertions.replace(set);
+        Ok(assertions)
+    }
+
+    /// Replaces all assertions and the strict flag.
+    pub fn replace(&self, set: SchemaAssertionSet) {
+        *self.routes.write() = set.routes.into_iter().map(compile).collect();
+        self.strict.store(set.strict, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, assertion: SchemaAssertion) {
+        self.routes.write().push(compile(assertion));
+    }
+
+    pub fn clear(&self) {
+        self.routes.write().clear();
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict.load(Ordering::Relaxed)
+    }
+
+    pub fn set_strict(&self, strict: bool) {
+        self.strict.store(strict, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> SchemaAssertionSet {
+        SchemaAssertionSet {
+            strict: self.is_strict(),
+            routes: self
+                .routes
+                .read()
+                .iter()
+                .map(|c| c.assertion.clone())
+                .collect(),
+        }
+    }
+
+    /// Validates a request body against every matching assertion.
+    pub fn check_request(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Vec<Violation> {
+        self.check(method, path, headers, body, "request_body", |a| {
+            a.request.as_ref()
+        })
+    }
+
+    /// Validates a response body against every matching assertion.
+    pub fn check_response(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Vec<Violation> {
+        self.check(method, path, headers, body, "response_body", |a| {
+            a.response.as_ref()
+        })
+    }
+
+    fn check<F>(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+        kind: &str,
+        select: F,
+    ) -> Vec<Violation>
+    where
+        F: Fn(&SchemaAssertion) -> Option<&Value>,
+    {
+        let routes = self.routes.read();
+        let mut violations = Vec::new();
+
+        for compiled in routes.iter() {
+            let assertion = &compiled.assertion;
+            if compiled.template.matches(path).is_none() {
+                continue;
+            }
+            if let Some(ref expected) = assertion.method {
+                if !expected.eq_ignore_ascii_case(method.as_str()) {
+                    continue;
+                }
+            }
+            let Some(body_schema) = select(assertion) else {
+                continue;
+            };
+
+            // Bodiless messages, such as 204s and answers to HEAD, have
+            // nothing to validate
+            if body.is_empty() {
+                continue;
+            }
+            let instance = match serde_json::from_slice::<Value>(body) {
+                Ok(value) => value,
+                Err(e) => {
+                    let content_type = headers
+                        .get(http::header::CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("no content type");
+                    violations.push(violation(
+                        kind,
+                        format!(
+                            "{}: body ({content_type}) is not valid JSON: {e}",
+                            assertion.path
+                        ),
+                    ));
+                    continue;
+                }
+            };
+
+            for message in schema::validate(body_schema, body_schema, &instance) {
+                violations.push(violation(kind, format!("{}: {message}", assertion.path)));
+            }
+        }
+
+        violations
+    }
+}
+
+fn compile(assertion: SchemaAssertion) -> CompiledAssertion {
+    CompiledAssertion {
+        template: PathTemplate::parse(&assertion.path),
+        assertion,
+    }
+}
+
+fn violation(kind: &str, message: String) -> Violation {
+    Violation {
+        source: SOURCE.to_string(),
+        kind: kind.to_string(),
+        message,
+    }
+}
@@ -0,0 +1,101 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entries kept; the oldest are dropped first.
+const MAX_ENTRIES: usize = 1000;
+
+/// A change made through the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    /// The address the admin request came from.
+    pub client_addr: String,
+    pub method: String,
+    pub path: String,
+    /// The status the admin API answered with.
+    pub status: u16,
+    /// Config settings the request changed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<ConfigChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigChange {
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+/// The admin API's changes, oldest first, so a proxy shared by a team shows
+/// who changed what.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    entries: Arc<Mutex<VecDeque<AuditEntry>>>,
+}
+
+impl AuditLog {
+    pub fn record(
+        &self,
+        client_addr: &str,
+        method: &str,
+        path: &str,
+        status: u16,
+        changes: Vec<ConfigChange>,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut entries = self.entries.lock();
+        let id = entries.back().map_or(1, |entry| entry.id + 1);
+        if entries.len() == MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(AuditEntry {
+            id,
+            timestamp,
+            client_addr: client_addr.to_string(),
+            method: method.to_string(),
+            path: path.to_string(),
+            status,
+            changes,
+        });
+    }
+
+    /// Entries after the one with id `after`, or all of them.
+    pub fn list(&self, after: Option<u64>) -> Vec<AuditEntry> {
+        self.entries
+            .lock()
+            .iter()
+            .filter(|entry| after.is_none_or(|after| entry.id > after))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The top-level settings that differ between two config snapshots.
+pub fn config_changes(before: &Value, after: &Value) -> Vec<ConfigChange> {
+    let (Some(before), Some(after)) = (before.as_object(), after.as_object()) else {
+        return Vec::new();
+    };
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let old = before.get(field).unwrap_or(&Value::Null);
+            let new = after.get(field).unwrap_or(&Value::Null);
+            (old != new).then(|| ConfigChange {
+                field: field.clone(),
+                before: old.clone(),
+                after: new.clone(),
+            })
+        })
+        .collect()
+}
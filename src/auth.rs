@@ -0,0 +1,117 @@
+use http::{header, HeaderMap};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::recorder::sha256_hex;
+
+/// Invalid tokens allowed from one address within [`FAILURE_WINDOW`].
+pub const MAX_FAILURES: u32 = 5;
+
+/// How far back failed attempts are counted.
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long an address that exceeded [`MAX_FAILURES`] is turned away.
+pub const LOCKOUT: Duration = Duration::from_secs(300);
+
+/// Cookie set with the admin page so the browser can load
+/// `/_proxy/assets/*`, which it requests without the token.
+pub const ASSET_COOKIE: &str = "debug_proxy_assets";
+
+/// Compares a token in time that depends only on the expected token's
+/// length, so response times do not reveal how much of a guess was right.
+pub fn tokens_match(provided: &str, expected: &str) -> bool {
+    let (provided, expected) = (provided.as_bytes(), expected.as_bytes());
+    let mut diff = provided.len() ^ expected.len();
+    for (i, &byte) in expected.iter().enumerate() {
+        diff |= usize::from(provided.get(i).copied().unwrap_or(0) ^ byte);
+    }
+    diff == 0
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Counts invalid admin tokens per client address and locks out addresses
+/// that keep guessing.
+#[derive(Clone, Default)]
+pub struct TokenGuard {
+    failures: Arc<Mutex<HashMap<String, Failures>>>,
+}
+
+impl TokenGuard {
+    /// How much longer `client` is locked out, if it is.
+    pub fn locked_for(&self, client: &str) -> Option<Duration> {
+        let key = address_key(client);
+        let mut failures = self.failures.lock();
+        let until = failures.get(key)?.locked_until?;
+        let remaining = until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            failures.remove(key);
+            return None;
+        }
+        Some(remaining)
+    }
+
+    /// Counts an invalid token from `client`. Returns true when that locks
+    /// it out.
+    pub fn record_failure(&self, client: &str) -> bool {
+        let now = Instant::now();
+        let mut failures = self.failures.lock();
+        // Forget addresses that stopped trying, so the map stays small
+        failures.retain(|_, f| {
+            f.locked_until
+                .map_or(now - f.since < FAILURE_WINDOW, |until| until > now)
+        });
+        let entry = failures
+            .entry(address_key(client).to_string())
+            .or_insert(Failures {
+                count: 0,
+                since: now,
+                locked_until: None,
+            });
+        entry.count += 1;
+        if entry.count > MAX_FAILURES && entry.locked_until.is_none() {
+            entry.locked_until = Some(now + LOCKOUT);
+            return true;
+        }
+        false
+    }
+
+    /// Clears the count for `client` after a valid token.
+    pub fn record_success(&self, client: &str) {
+        self.failures.lock().remove(address_key(client));
+    }
+}
+
+/// The IP of a `host:port` client address, so reconnecting from another
+/// port does not reset the count.
+fn address_key(client: &str) -> &str {
+    match client.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => client,
+    }
+}
+
+/// The asset cookie's value for `token`: a hash, so the cookie does not
+/// hold the token itself and changing the token revokes it.
+pub fn asset_key(token: &str) -> String {
+    sha256_hex(format!("debug-proxy assets:{token}").as_bytes())
+}
+
+/// The value of cookie `name` on a request.
+pub fn request_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+}
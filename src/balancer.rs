@@ -0,0 +1,295 @@
+use anyhow::bail;
+use http::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::ProxyConfig;
+use crate::upstream::{build_client, upstream_base_url};
+
+/// Cookie that pins a client to an instance under [`Stickiness::Cookie`].
+pub const STICKY_COOKIE: &str = "debug_proxy_instance";
+
+/// How a client keeps reaching the same upstream instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stickiness {
+    /// The first response sets a cookie naming the instance.
+    Cookie,
+    /// The client IP address picks the instance.
+    Ip,
+}
+
+impl std::str::FromStr for Stickiness {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "cookie" => Ok(Self::Cookie),
+            "ip" => Ok(Self::Ip),
+            _ => bail!("Expected cookie or ip, got {s}"),
+        }
+    }
+}
+
+/// How instances are probed for health.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Path that answers with a non-5xx status while the instance is
+    /// healthy; `None` only checks that a TCP connection opens.
+    pub path: Option<String>,
+    pub interval: Duration,
+    /// Consecutive probes that must agree before an instance's health
+    /// changes.
+    pub threshold: u32,
+}
+
+impl std::str::FromStr for HealthCheck {
+    type Err = anyhow::Error;
+
+    /// Parses `tcp` or an HTTP path such as `/health`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let path = match s {
+            "tcp" => None,
+            path if path.starts_with('/') => Some(path.to_string()),
+            _ => bail!("Expected tcp or a path starting with '/', got {s}"),
+        };
+        Ok(Self {
+            path,
+            interval: Duration::from_secs(2),
+            threshold: 2,
+        })
+    }
+}
+
+/// The instance a request goes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pick {
+    pub instance: String,
+    /// Sent with the response to pin the client to `instance`.
+    pub set_cookie: Option<HeaderValue>,
+    /// The instance the request would have gone to, when it was unhealthy.
+    pub failover_from: Option<String>,
+}
+
+/// An instance's health, for the stats API.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceHealth {
+    pub instance: String,
+    pub healthy: bool,
+    /// Requests sent elsewhere because this instance was unhealthy.
+    pub failovers: u64,
+}
+
+#[derive(Debug)]
+struct Instance {
+    address: String,
+    healthy: AtomicBool,
+    /// Consecutive probes that disagreed with `healthy`.
+    streak: AtomicU32,
+    failovers: AtomicU64,
+}
+
+/// Spreads requests for the default upstream across several instances of
+/// it, round-robin unless the client is sticky, skipping instances that
+/// fail their health check.
+#[derive(Debug)]
+pub struct Balancer {
+    instances: Vec<Instance>,
+    sticky: Option<Stickiness>,
+    health_check: Option<HealthCheck>,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub fn new(instances: Vec<String>) -> Self {
+        assert!(!instances.is_empty(), "a balancer needs an instance");
+        Self {
+            instances: instances
+                .into_iter()
+                .map(|address| Instance {
+                    address,
+                    healthy: AtomicBool::new(true),
+                    streak: AtomicU32::new(0),
+                    failovers: AtomicU64::new(0),
+                })
+                .collect(),
+            sticky: None,
+            health_check: None,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_stickiness(mut self, sticky: Stickiness) -> Self {
+        self.sticky = Some(sticky);
+        self
+    }
+
+    /// Probes instances with `check` once [`Balancer::spawn_health_checks`]
+    /// runs. Until then every instance counts as healthy.
+    pub fn with_health_check(mut self, check: HealthCheck) -> Self {
+        self.health_check = Some(check);
+        self
+    }
+
+    pub fn instances(&self) -> Vec<&str> {
+        self.instances
+            .iter()
+            .map(|instance| instance.address.as_str())
+            .collect()
+    }
+
+    pub fn health_check(&self) -> Option<&HealthCheck> {
+        self.health_check.as_ref()
+    }
+
+    pub fn health(&self) -> Vec<InstanceHealth> {
+        self.instances
+            .iter()
+            .map(|instance| InstanceHealth {
+                instance: instance.address.clone(),
+                healthy: instance.healthy.load(Ordering::Relaxed),
+                failovers: instance.failovers.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    pub fn stickiness(&self) -> Option<Stickiness> {
+        self.sticky
+    }
+
+    /// Picks the instance for a request from `client_addr`.
+    pub fn pick(&self, headers: &HeaderMap, client_addr: &str) -> Pick {
+        let (preferred, pinned) = match self.sticky {
+            Some(Stickiness::Ip) => {
+                // Ignore the port, which changes with every connection
+                let ip = client_addr
+                    .parse::<SocketAddr>()
+                    .map(|addr| addr.ip().to_string())
+                    .unwrap_or_else(|_| client_addr.to_string());
+                let mut hasher = DefaultHasher::new();
+                ip.hash(&mut hasher);
+                (
+                    (hasher.finish() % self.instances.len() as u64) as usize,
+                    true,
+                )
+            }
+            Some(Stickiness::Cookie) => match self.cookie_instance(headers) {
+                Some(index) => (index, true),
+                None => (self.next_index(), false),
+            },
+            None => (self.next_index(), false),
+        };
+
+        // Fail over to the next healthy instance; when none is healthy the
+        // preferred one is as good as any
+        let index = (0..self.instances.len())
+            .map(|offset| (preferred + offset) % self.instances.len())
+            .find(|&index| self.instances[index].healthy.load(Ordering::Relaxed))
+            .unwrap_or(preferred);
+        let failover_from = (index != preferred).then(|| {
+            let from = &self.instances[preferred];
+            from.failovers.fetch_add(1, Ordering::Relaxed);
+            from.address.clone()
+        });
+
+        let instance = self.instances[index].address.clone();
+        let set_cookie = match self.sticky {
+            Some(Stickiness::Cookie) if !pinned || failover_from.is_some() => {
+                HeaderValue::from_str(&format!("{STICKY_COOKIE}={instance}; Path=/")).ok()
+            }
+            _ => None,
+        };
+        Pick {
+            instance,
+            set_cookie,
+            failover_from,
+        }
+    }
+
+    fn next_index(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % self.instances.len()
+    }
+
+    /// The instance named by the request's sticky cookie, if it still is one.
+    fn cookie_instance(&self, headers: &HeaderMap) -> Option<usize> {
+        let value = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == STICKY_COOKIE)
+            .map(|(_, value)| value)?;
+        self.instances
+            .iter()
+            .position(|instance| instance.address == value)
+    }
+
+    /// Probes every instance in the background, if a health check is set.
+    /// Probes connect the way proxied requests do under `config`.
+    pub fn spawn_health_checks(self: &Arc<Self>, config: &ProxyConfig) {
+        let Some(ref check) = self.health_check else {
+            return;
+        };
+        let client = build_client(config);
+        for index in 0..self.instances.len() {
+            let balancer = Arc::clone(self);
+            let client = client.clone();
+            let check = check.clone();
+            tokio::spawn(async move {
+                let instance = &balancer.instances[index];
+                let base_url = upstream_base_url(&instance.address);
+                loop {
+                    let probe = async {
+                        match check.path {
+                            Some(ref path) => match format!("{base_url}{path}").parse() {
+                                Ok(uri) => client
+                                    .get(uri)
+                                    .await
+                                    .is_ok_and(|response| !response.status().is_server_error()),
+                                Err(_) => false,
+                            },
+                            None => {
+                                let authority = base_url
+                                    .split_once("://")
+                                    .map_or(base_url.as_str(), |(_, authority)| authority);
+                                tokio::net::TcpStream::connect(authority).await.is_ok()
+                            }
+                        }
+                    };
+                    let passed = tokio::time::timeout(check.interval, probe)
+                        .await
+                        .unwrap_or(false);
+                    instance.observe(passed, check.threshold);
+                    tokio::time::sleep(check.interval).await;
+                }
+            });
+        }
+    }
+}
+
+impl Instance {
+    /// Counts a probe result, flipping the instance's health once
+    /// `threshold` probes in a row disagree with it.
+    fn observe(&self, passed: bool, threshold: u32) {
+        if passed == self.healthy.load(Ordering::Relaxed) {
+            self.streak.store(0, Ordering::Relaxed);
+            return;
+        }
+        if self.streak.fetch_add(1, Ordering::Relaxed) + 1 >= threshold.max(1) {
+            self.streak.store(0, Ordering::Relaxed);
+            self.healthy.store(passed, Ordering::Relaxed);
+            if passed {
+                info!("Upstream instance {} is healthy again", self.address);
+            } else {
+                warn!("Upstream instance {} is unhealthy", self.address);
+            }
+        }
+    }
+}
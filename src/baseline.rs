@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::diff::{diff_transactions, BodyDiff, TransactionDiff};
+use crate::recorder::HttpTransaction;
+
+/// Headers that differ on every response and are ignored unless asked for.
+pub const DEFAULT_IGNORED_HEADERS: &[&str] = &["date"];
+
+/// A saved set of known-good responses, one per method + path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Baseline {
+    pub created_at: u64,
+    pub transactions: Vec<HttpTransaction>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Regression {
+    pub id: String,
+    pub baseline_id: String,
+    pub method: String,
+    pub path: String,
+    pub diff: TransactionDiff,
+}
+
+#[derive(Clone, Default)]
+pub struct BaselineStore {
+    baseline: Arc<RwLock<Option<Baseline>>>,
+    path: Option<PathBuf>,
+}
+
+impl BaselineStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Persists captured baselines to `path`, loading it first if it exists.
+    pub fn with_file(path: PathBuf) -> Result<Self> {
+        let baseline = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read baseline: {}", path.display()))?;
+            let baseline: Baseline = serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse baseline: {}", path.display()))?;
+            Some(baseline)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            baseline: Arc::new(RwLock::new(baseline)),
+            path: Some(path),
+        })
+    }
+
+    /// Makes the completed transactions in `transactions` the new baseline.
+    /// Later transactions win when a method + path was seen more than once.
+    pub fn capture(&self, transactions: Vec<HttpTransaction>) -> Result<usize> {
+        let mut by_key: HashMap<(String, String), HttpTransaction> = HashMap::new();
+        let mut order = Vec::new();
+        for transaction in transactions {
+            if transaction.response.is_none() {
+                continue;
+            }
+            let key = route_key(&transaction);
+            if by_key.insert(key.clone(), transaction).is_none() {
+                order.push(key);
+            }
+        }
+
+        let transactions: Vec<_> = order
+            .into_iter()
+            .filter_map(|key| by_key.remove(&key))
+            .collect();
+        let count = transactions.len();
+
+        let baseline = Baseline {
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            transactions,
+        };
+
+        if let Some(ref path) = self.path {
+            let content = serde_json::to_string_pretty(&baseline)?;
+            std::fs::write(path, content)
+                .with_context(|| format!("Failed to write baseline: {}", path.display()))?;
+        }
+
+        *self.baseline.write() = Some(baseline);
+        Ok(count)
+    }
+
+    pub fn get(&self) -> Option<Baseline> {
+        self.baseline.read().clone()
+    }
+
+    pub fn clear(&self) {
+        *self.baseline.write() = None;
+    }
+
+    /// Compares every transaction against the baseline entry for the same
+    /// method + path and returns the ones that differ.
+    pub fn regressions(
+        &self,
+        transactions: &[HttpTransaction],
+        ignored_headers: &[String],
+    ) -> Vec<Regression> {
+        let baseline = self.baseline.read();
+        let Some(baseline) = baseline.as_ref() else {
+            return Vec::new();
+        };
+
+        let expected: HashMap<(String, String), &HttpTransaction> = baseline
+            .transactions
+            .iter()
+            .map(|t| (route_key(t), t))
+            .collect();
+        let baseline_ids: HashSet<&str> = baseline
+            .transactions
+            .iter()
+            .map(|t| t.request.id.as_str())
+            .collect();
+
+        transactions
+            .iter()
+            .filter(|t| t.response.is_some() && !baseline_ids.contains(t.request.id.as_str()))
+            .filter_map(|actual| {
+                let expected = expected.get(&route_key(actual))?;
+                let mut diff = diff_transactions(expected, actual);
+                // Only the response matters for regressions.
+                diff.response.headers.retain(|change| {
+                    change
+                        .name
+                        .as_ref()
+                        .is_none_or(|name| !ignored_headers.iter().any(|i| i == name))
+                });
+                if diff.response.status.is_none()
+                    && diff.response.headers.is_empty()
+                    && matches!(diff.response.body, BodyDiff::Identical)
+                {
+                    return None;
+                }
+                Some(Regression {
+                    id: actual.request.id.clone(),
+                    baseline_id: expected.request.id.clone(),
+                    method: actual.request.method.clone(),
+                    path: actual.request.path.clone(),
+                    diff,
+                })
+            })
+            .collect()
+    }
+}
+
+fn route_key(transaction: &HttpTransaction) -> (String, String) {
+    (
+        transaction.request.method.clone(),
+        transaction.request.path.clone(),
+    )
+}
@@ -0,0 +1,428 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request};
+use hyper::{Body, Client};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::export::SKIPPED_HEADERS;
+use crate::recorder::{HttpTransaction, RequestInfo, RequestRecorder, ResponseInfo};
+use crate::upstream::{upstream_base_url, web_client, WebConnector};
+
+/// A request to replay against the upstream.
+#[derive(Debug, Clone)]
+pub struct BenchRequest {
+    pub method: Method,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub concurrency: usize,
+    pub duration: Duration,
+    pub timeout: Duration,
+}
+
+/// The outcome of a single replayed request.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub duration_ms: u64,
+    pub status: Option<u16>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub total: usize,
+    pub errors: usize,
+    pub error_rate: f64,
+    pub requests_per_sec: f64,
+    pub statuses: BTreeMap<u16, usize>,
+    pub latency_ms: Latency,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Latency {
+    pub min: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+#[derive(Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Deserialize)]
+struct HarEntry {
+    request: HarRequest,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+    post_data: Option<HarPostData>,
+}
+
+#[derive(Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct HarPostData {
+    #[serde(default)]
+    text: String,
+}
+
+impl BenchRequest {
+    fn new(method: &str, path: String, headers: &[(String, String)], body: Bytes) -> Result<Self> {
+        let method = Method::from_bytes(method.as_bytes())
+            .map_err(|_| anyhow!("Invalid method: {method}"))?;
+
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            if name.starts_with(':')
+                || SKIPPED_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+            {
+                continue;
+            }
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) else {
+                continue;
+            };
+            header_map.append(name, value);
+        }
+
+        Ok(Self {
+            method,
+            path,
+            headers: header_map,
+            body,
+        })
+    }
+
+    /// Rebuilds a request from a recorded transaction. Only the recorded
+    /// body preview is available, so binary bodies are sent empty.
+    pub fn from_transaction(transaction: &HttpTransaction) -> Result<Self> {
+        let request = &transaction.request;
+        let body = if request.body.is_binary {
+            Bytes::new()
+        } else {
+            Bytes::copy_from_slice(request.body.preview.as_bytes())
+        };
+        Self::new(
+            &request.method,
+            request.path.clone(),
+            &request.headers,
+            body,
+        )
+    }
+}
+
+/// Loads requests from a saved `/_proxy/api/logs` response.
+pub fn load_history(path: &Path) -> Result<Vec<BenchRequest>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read history: {}", path.display()))?;
+    let transactions: Vec<HttpTransaction> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse history: {}", path.display()))?;
+
+    let incomplete = transactions
+        .iter()
+        .filter(|t| t.request.body.truncated || t.request.body.is_binary)
+        .count();
+    if incomplete > 0 {
+        warn!("{incomplete} recorded request bodies are truncated or binary and will not be replayed verbatim");
+    }
+
+    transactions
+        .iter()
+        .map(BenchRequest::from_transaction)
+        .collect()
+}
+
+/// Loads requests from the entries of a HAR file.
+pub fn load_har(path: &Path) -> Result<Vec<BenchRequest>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read HAR: {}", path.display()))?;
+    let har: Har = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse HAR: {}", path.display()))?;
+
+    har.log
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let request = entry.request;
+            let url = url::Url::parse(&request.url)
+                .with_context(|| format!("Invalid URL in HAR: {}", request.url))?;
+            let path = match url.query() {
+                Some(query) => format!("{}?{query}", url.path()),
+                None => url.path().to_string(),
+            };
+            let headers: Vec<(String, String)> = request
+                .headers
+                .into_iter()
+                .map(|h| (h.name, h.value))
+                .collect();
+            let body = Bytes::from(request.post_data.map(|p| p.text).unwrap_or_default());
+            BenchRequest::new(&request.method, path, &headers, body)
+        })
+        .collect()
+}
+
+/// Replays `requests` round-robin against `upstream` from `concurrency`
+/// workers until `duration` has passed. Every replayed request is recorded
+/// in `recorder`.
+pub async fn run(
+    upstream: &str,
+    requests: Vec<BenchRequest>,
+    options: &BenchOptions,
+    recorder: &RequestRecorder,
+) -> Result<BenchReport> {
+    if requests.is_empty() {
+        bail!("No requests to replay");
+    }
+    let concurrency = options.concurrency.max(1);
+
+    let client = web_client();
+
+    let requests = Arc::new(requests);
+    let start = Instant::now();
+    let deadline = start + options.duration;
+
+    let workers: Vec<_> = (0..concurrency)
+        .map(|worker| {
+            let client = client.clone();
+            let requests = Arc::clone(&requests);
+            let recorder = recorder.clone();
+            let upstream = upstream.to_string();
+            let timeout = options.timeout;
+            tokio::spawn(async move {
+                let mut samples = Vec::new();
+                let mut next = worker;
+                while Instant::now() < deadline {
+                    let request = &requests[next % requests.len()];
+                    next += concurrency;
+                    samples.push(send(&client, &upstream, request, timeout, &recorder).await);
+                }
+                samples
+            })
+        })
+        .collect();
+
+    let mut samples = Vec::new();
+    for worker in workers {
+        samples.extend(worker.await?);
+    }
+
+    Ok(BenchReport::from_samples(&samples, start.elapsed()))
+}
+
+async fn send(
+    client: &Client<WebConnector>,
+    upstream: &str,
+    request: &BenchRequest,
+    timeout: Duration,
+    recorder: &RequestRecorder,
+) -> Sample {
+    let request_id = recorder.record_request(RequestInfo {
+        method: &request.method,
+        path: &request.path,
+        version: http::Version::HTTP_11,
+        headers: &request.headers,
+        body: &request.body,
+        client_addr: "bench".to_string(),
+        listener: None,
+        target: None,
+        trailers: None,
+        truncate_at: 1024,
+    });
+
+    let upstream_req = request
+        .headers
+        .iter()
+        .fold(
+            Request::builder().method(&request.method).uri(format!(
+                "{}{}",
+                upstream_base_url(upstream),
+                request.path
+            )),
+            |req, (name, value)| req.header(name, value),
+        )
+        .body(Body::from(request.body.clone()))
+        .unwrap();
+
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, async {
+        let response = client.request(upstream_req).await?;
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        Ok::<_, hyper::Error>((parts, body))
+    })
+    .await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(Ok((parts, body))) => {
+            recorder.record_response(ResponseInfo {
+                request_id: &request_id,
+                status: parts.status,
+                version: parts.version,
+                headers: &parts.headers,
+                body: &body,
+                duration_ms,
+                trailers: None,
+                truncate_at: 1024,
+            });
+            Sample {
+                duration_ms,
+                status: Some(parts.status.as_u16()),
+            }
+        }
+        Ok(Err(e)) => {
+            recorder.record_error(&request_id, format!("Upstream error: {e}"));
+            Sample {
+                duration_ms,
+                status: None,
+            }
+        }
+        Err(_) => {
+            recorder.record_error(&request_id, "Upstream timeout".to_string());
+            Sample {
+                duration_ms,
+                status: None,
+            }
+        }
+    }
+}
+
+impl BenchReport {
+    /// Summarizes samples collected over `elapsed`. Failed requests and
+    /// `5xx` responses count as errors.
+    pub fn from_samples(samples: &[Sample], elapsed: Duration) -> Self {
+        let total = samples.len();
+        let errors = samples
+            .iter()
+            .filter(|s| s.status.is_none_or(|status| status >= 500))
+            .count();
+
+        let mut statuses = BTreeMap::new();
+        for status in samples.iter().filter_map(|s| s.status) {
+            *statuses.entry(status).or_insert(0) += 1;
+        }
+
+        let durations: Vec<u64> = samples.iter().map(|s| s.duration_ms).collect();
+        let latency_ms = Latency::from_durations(durations);
+
+        let seconds = elapsed.as_secs_f64();
+        Self {
+            total,
+            errors,
+            error_rate: if total == 0 {
+                0.0
+            } else {
+                errors as f64 / total as f64
+            },
+            requests_per_sec: if seconds > 0.0 {
+                total as f64 / seconds
+            } else {
+                0.0
+            },
+            statuses,
+            latency_ms,
+        }
+    }
+}
+
+impl Latency {
+    pub fn from_durations(mut durations: Vec<u64>) -> Self {
+        if durations.is_empty() {
+            return Self::default();
+        }
+        durations.sort_unstable();
+        let count = durations.len();
+        Self {
+            min: durations[0],
+            mean: durations.iter().sum::<u64>() as f64 / count as f64,
+            p50: percentile(&durations, 50.0),
+            p90: percentile(&durations, 90.0),
+            p95: percentile(&durations, 95.0),
+            p99: percentile(&durations, 99.0),
+            max: durations[count - 1],
+        }
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Requests:    {}", self.total)?;
+        writeln!(f, "  Throughput:  {:.1} req/s", self.requests_per_sec)?;
+        writeln!(
+            f,
+            "  Errors:      {} ({:.2}%)",
+            self.errors,
+            self.error_rate * 100.0
+        )?;
+        let statuses: Vec<String> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{status}={count}"))
+            .collect();
+        writeln!(f, "  Statuses:    {}", statuses.join(" "))?;
+        let l = &self.latency_ms;
+        write!(
+            f,
+            "  Latency:     min={}ms mean={:.1}ms p50={}ms p90={}ms p95={}ms p99={}ms max={}ms",
+            l.min, l.mean, l.p50, l.p90, l.p95, l.p99, l.max
+        )
+    }
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Parses durations such as `30s`, `500ms`, `2m` or `1h`. A bare number is
+/// taken as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration: {value}"))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "" | "s" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        "h" => Ok(Duration::from_secs(amount * 3600)),
+        _ => bail!("Invalid duration unit in {value}, expected ms, s, m or h"),
+    }
+}
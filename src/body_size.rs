@@ -0,0 +1,104 @@
+use http::header::{self, HeaderMap};
+use http::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Which side of a transaction a body belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyPart {
+    Request,
+    Response,
+}
+
+impl BodyPart {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Request => "request",
+            Self::Response => "response",
+        }
+    }
+}
+
+/// A body that is suspicious for its size, often the sign of a broken
+/// handler that is otherwise only noticed on the client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "warning", rename_all = "snake_case")]
+pub enum SizeWarning {
+    /// The body is larger than `max_body_size`.
+    OverLimit {
+        part: BodyPart,
+        size: usize,
+        limit: usize,
+    },
+    /// `Content-Length` announced a different number of bytes than arrived.
+    LengthMismatch {
+        part: BodyPart,
+        content_length: u64,
+        received: usize,
+    },
+}
+
+impl fmt::Display for SizeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OverLimit { part, size, limit } => write!(
+                f,
+                "{} body of {size} bytes exceeds max_body_size of {limit}",
+                part.as_str()
+            ),
+            Self::LengthMismatch {
+                part,
+                content_length,
+                received,
+            } => write!(
+                f,
+                "{} Content-Length is {content_length} but {received} bytes arrived",
+                part.as_str()
+            ),
+        }
+    }
+}
+
+/// Checks a body of `received` bytes against `limit` and the
+/// `Content-Length` in `headers`. `has_body` is false for messages that
+/// announce a length without carrying a body, such as responses to `HEAD`.
+pub fn check(
+    part: BodyPart,
+    headers: &HeaderMap,
+    received: usize,
+    limit: usize,
+    has_body: bool,
+) -> Vec<SizeWarning> {
+    let mut warnings = Vec::new();
+    if received > limit {
+        warnings.push(SizeWarning::OverLimit {
+            part,
+            size: received,
+            limit,
+        });
+    }
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    if let Some(content_length) = content_length.filter(|_| has_body) {
+        if content_length != received as u64 {
+            warnings.push(SizeWarning::LengthMismatch {
+                part,
+                content_length,
+                received,
+            });
+        }
+    }
+    warnings
+}
+
+/// Whether a response to `method` with `status` carries the body its
+/// `Content-Length` announces.
+pub fn response_has_body(method: &Method, status: StatusCode) -> bool {
+    method != Method::HEAD
+        && !status.is_informational()
+        && status != StatusCode::NO_CONTENT
+        && status != StatusCode::NOT_MODIFIED
+}
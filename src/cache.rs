@@ -0,0 +1,37 @@
+use http::header::{self, HeaderMap, HeaderValue};
+
+/// Request headers that let the upstream answer `304 Not Modified` without a
+/// body.
+const CONDITIONAL_HEADERS: &[header::HeaderName] = &[
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+    header::IF_MATCH,
+    header::IF_UNMODIFIED_SINCE,
+    header::IF_RANGE,
+];
+
+/// Response headers a browser uses to cache a response or revalidate it.
+const VALIDATOR_HEADERS: &[header::HeaderName] = &[
+    header::ETAG,
+    header::LAST_MODIFIED,
+    header::EXPIRES,
+    header::AGE,
+];
+
+/// Removes the conditional headers from a request to the upstream, so it
+/// answers with the full body.
+pub fn strip_conditional_headers(headers: &mut HeaderMap) {
+    for name in CONDITIONAL_HEADERS {
+        headers.remove(name);
+    }
+}
+
+/// Keeps the client from caching a response: validators are removed, so it
+/// has nothing to revalidate with, and `Cache-Control` is replaced with
+/// `no-store`.
+pub fn disable_caching(headers: &mut HeaderMap) {
+    for name in VALIDATOR_HEADERS {
+        headers.remove(name);
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+}
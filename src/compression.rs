@@ -0,0 +1,230 @@
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::Body;
+use std::io::{Read, Write};
+use tracing::{error, warn};
+
+/// A content coding the admin API can answer with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Picks a coding from an `Accept-Encoding` header, preferring gzip.
+    /// Codings refused with `q=0` are skipped, and `*` only stands for
+    /// codings the header does not list.
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let listed: Vec<(&str, bool)> = accept_encoding
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';').map(str::trim);
+                let coding = parts.next().filter(|coding| !coding.is_empty())?;
+                let refused = parts.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q <= 0.0)
+                });
+                Some((coding, refused))
+            })
+            .collect();
+
+        let accepts = |name: &str| {
+            let entry = |name: &str| {
+                listed
+                    .iter()
+                    .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+            };
+            entry(name)
+                .or_else(|| entry("*"))
+                .is_some_and(|(_, refused)| !refused)
+        };
+        if accepts("gzip") {
+            Some(Self::Gzip)
+        } else if accepts("deflate") {
+            Some(Self::Deflate)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+}
+
+/// A reader that decodes `body` from `encoding`, for the codings that can
+/// be decoded here.
+pub fn decoder<'a>(encoding: &str, body: &'a [u8]) -> Option<Box<dyn Read + 'a>> {
+    if encoding.eq_ignore_ascii_case("gzip") {
+        Some(Box::new(MultiGzDecoder::new(body)))
+    } else if encoding.eq_ignore_ascii_case("deflate") {
+        Some(Box::new(ZlibDecoder::new(body)))
+    } else {
+        None
+    }
+}
+
+/// Removes a gzip or deflate `Content-Encoding` from an upstream response,
+/// returning whether it did. Other codings, bodies that fail to decode and
+/// bodies that decode to more than `limit` bytes are left as they are.
+pub fn decode_response(headers: &mut HeaderMap, body: &mut bytes::Bytes, limit: usize) -> bool {
+    let Some(encoding) = headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+    else {
+        return false;
+    };
+    let Some(decoder) = decoder(encoding, body) else {
+        return false;
+    };
+    let mut decoded = Vec::new();
+    if let Err(e) = decoder.take(limit as u64 + 1).read_to_end(&mut decoded) {
+        error!("Error decoding {encoding} response: {e}");
+        return false;
+    }
+    if decoded.len() > limit {
+        warn!("Leaving {encoding} response encoded: it decodes to more than {limit} bytes");
+        return false;
+    }
+    headers.remove(header::CONTENT_ENCODING);
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(decoded.len()));
+    *body = decoded.into();
+    true
+}
+
+/// Whether a response of this content type is worth compressing. Event
+/// streams are left alone so events are not held back in the encoder.
+pub fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    if content_type.starts_with("text/event-stream") {
+        return false;
+    }
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("application/javascript")
+        || content_type.starts_with("application/x-ndjson")
+        || content_type.starts_with("image/svg+xml")
+}
+
+/// Compresses `response` for a client that sent `accept_encoding`, if it
+/// accepts a supported coding and the content is compressible.
+pub fn compress_response(
+    response: Response<Body>,
+    accept_encoding: Option<&HeaderValue>,
+) -> Response<Body> {
+    let Some(encoding) = accept_encoding
+        .and_then(|value| value.to_str().ok())
+        .and_then(Encoding::negotiate)
+    else {
+        return response;
+    };
+    let compressible = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(is_compressible);
+    if !compressible
+        || response.status() == StatusCode::NOT_MODIFIED
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    Response::from_parts(parts, compress_body(body, encoding))
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => Self::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Encoding::Deflate => {
+                Self::Deflate(ZlibEncoder::new(Vec::new(), Compression::default()))
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Gzip(encoder) => encoder.write_all(data),
+            Self::Deflate(encoder) => encoder.write_all(data),
+        }
+    }
+
+    /// Takes what has been compressed so far.
+    fn take(&mut self) -> Vec<u8> {
+        match self {
+            Self::Gzip(encoder) => std::mem::take(encoder.get_mut()),
+            Self::Deflate(encoder) => std::mem::take(encoder.get_mut()),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Compresses `body` chunk by chunk as it is produced, so streamed bodies
+/// stay streamed.
+fn compress_body(mut body: Body, encoding: Encoding) -> Body {
+    let (mut sender, compressed) = Body::channel();
+    tokio::spawn(async move {
+        let mut encoder = Encoder::new(encoding);
+        while let Some(chunk) = body.data().await {
+            let written = match chunk {
+                Ok(chunk) => encoder.write(&chunk),
+                Err(e) => {
+                    error!("Error reading response body: {e}");
+                    sender.abort();
+                    return;
+                }
+            };
+            if let Err(e) = written {
+                error!("Error compressing response: {e}");
+                sender.abort();
+                return;
+            }
+            let output = encoder.take();
+            if !output.is_empty() && sender.send_data(output.into()).await.is_err() {
+                return;
+            }
+        }
+        match encoder.finish() {
+            Ok(output) => {
+                let _ = sender.send_data(output.into()).await;
+            }
+            Err(e) => {
+                error!("Error compressing response: {e}");
+                sender.abort();
+            }
+        }
+    });
+    compressed
+}
@@ -1,8 +1,21 @@
+use anyhow::{Context, Result};
+use http::{header, HeaderMap, Method};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::alerts::AlertRules;
+use crate::cors::CorsPolicy;
+use crate::mock::OfflineMode;
+use crate::outbound::OutboundProxy;
+use crate::recorder::{BinaryDetection, PreflightView};
+use crate::route::PathTemplate;
+use crate::safe_mode::SafeMode;
+use crate::upstream::{ClientIdentity, ResolveOverride, UpstreamCa, UpstreamHeader};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyConfig {
     pub client_timeout: Duration,
@@ -10,7 +23,69 @@ pub struct ProxyConfig {
     pub max_history_size: usize,
     pub max_body_size: usize,
     pub truncate_body_at: usize,
+    /// Limit for recorded request bodies, instead of `truncate_body_at`.
+    pub truncate_request_at: Option<usize>,
+    /// Limit for recorded response bodies, instead of `truncate_body_at`.
+    pub truncate_response_at: Option<usize>,
+    /// Limits by content type, such as `application/json` or `image/*`,
+    /// ahead of the request and response limits.
+    pub truncate_by_content_type: BTreeMap<String, usize>,
+    /// How recorded bodies are told apart from text.
+    pub binary_detection: BinaryDetection,
     pub access_token: String,
+    /// Idle upstream connections kept open per host for reuse.
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle upstream connection is kept before closing it.
+    pub pool_idle_timeout: Duration,
+    /// Reuse upstream connections; when off each request opens its own.
+    pub http1_keep_alive: bool,
+    /// Speak HTTP/2 to the upstream: h2c with prior knowledge over plain
+    /// HTTP, negotiated h2 over TLS.
+    pub upstream_http2: bool,
+    /// Client certificate for upstreams that require mutual TLS; routes may
+    /// name their own.
+    pub upstream_client_identity: Option<ClientIdentity>,
+    /// CA bundles trusted for upstream certificates besides the system ones.
+    pub upstream_ca: Vec<UpstreamCa>,
+    /// Headers set on every request to the upstream, replacing any the
+    /// client sent. Not added in forward proxy mode, where the upstream is
+    /// whatever host the client names.
+    pub upstream_headers: Vec<UpstreamHeader>,
+    /// Fixed addresses for upstream hosts, like curl's `--resolve`.
+    pub resolve: Vec<ResolveOverride>,
+    /// How long resolved upstream addresses are reused; zero resolves for
+    /// every new connection.
+    pub dns_cache_ttl: Duration,
+    /// Proxy that upstream connections go through.
+    pub outbound_proxy: Option<OutboundProxy>,
+    /// Alternate upstream that receives `split_percent` of the requests for
+    /// the default upstream.
+    pub upstream_b: Option<String>,
+    pub split_percent: u8,
+    /// Add `X-Debug-Proxy-Id` and `Server-Timing` to proxied responses.
+    pub debug_headers: bool,
+    /// Strip conditional request headers and caching headers from responses,
+    /// so every response is a full `200` with a body.
+    pub no_cache: bool,
+    /// Keeps compressed upstream responses from reaching the client and the
+    /// recorder.
+    pub decompression: Decompression,
+    /// Identical requests closer together than this are flagged as
+    /// duplicates; zero turns detection off.
+    pub duplicate_window: Duration,
+    /// Answer requests from the recorded history instead of the upstream.
+    pub offline: OfflineMode,
+    /// Answer CORS preflights and allow cross-origin calls to the upstream.
+    pub cors: Option<CorsPolicy>,
+    /// How CORS preflights appear in the transaction list.
+    pub preflights: PreflightView,
+    /// Requests kept from the upstream.
+    pub safe_mode: SafeMode,
+    /// Timeouts and truncation for particular routes; the most specific
+    /// match wins.
+    pub routes: Vec<RouteOverride>,
+    /// When to raise the alert shown in the stats.
+    pub alerts: AlertRules,
 }
 
 impl Default for ProxyConfig {
@@ -21,11 +96,208 @@ impl Default for ProxyConfig {
             max_history_size: 100,
             max_body_size: 1024 * 1024, // 1MB
             truncate_body_at: 1024,     // 1KB
+            truncate_request_at: None,
+            truncate_response_at: None,
+            truncate_by_content_type: BTreeMap::new(),
+            binary_detection: BinaryDetection::default(),
             access_token: uuid::Uuid::new_v4().to_string(),
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: Duration::from_secs(90),
+            http1_keep_alive: true,
+            upstream_http2: false,
+            upstream_client_identity: None,
+            upstream_ca: Vec::new(),
+            upstream_headers: Vec::new(),
+            resolve: Vec::new(),
+            dns_cache_ttl: Duration::ZERO,
+            outbound_proxy: None,
+            upstream_b: None,
+            split_percent: 0,
+            debug_headers: false,
+            no_cache: false,
+            decompression: Decompression::Off,
+            duplicate_window: Duration::from_secs(1),
+            offline: OfflineMode::Off,
+            cors: None,
+            preflights: PreflightView::Show,
+            safe_mode: SafeMode::default(),
+            routes: Vec::new(),
+            alerts: AlertRules::default(),
         }
     }
 }
 
+impl ProxyConfig {
+    /// The settings for a request, with the best matching route override
+    /// applied over the global ones.
+    pub fn for_route(&self, method: &Method, path: &str) -> RouteSettings {
+        let route = self.route_override(method, path);
+
+        let ms = Duration::from_millis;
+        RouteSettings {
+            upstream_timeout: route
+                .and_then(|r| r.upstream_timeout_ms)
+                .map_or(self.upstream_timeout, ms),
+            client_timeout: route
+                .and_then(|r| r.client_timeout_ms)
+                .map_or(self.client_timeout, ms),
+            truncate_body_at: route.and_then(|r| r.truncate_body_at),
+        }
+    }
+
+    /// The client certificate a route presents instead of the global one.
+    pub fn route_client_identity(&self, method: &Method, path: &str) -> Option<ClientIdentity> {
+        self.route_override(method, path)?.client_identity.clone()
+    }
+
+    /// The most specific override matching the request.
+    fn route_override(&self, method: &Method, path: &str) -> Option<&RouteOverride> {
+        self.routes
+            .iter()
+            .filter(|route| {
+                route
+                    .method
+                    .as_deref()
+                    .is_none_or(|m| m.eq_ignore_ascii_case(method.as_str()))
+            })
+            .filter_map(|route| {
+                PathTemplate::parse(&route.path)
+                    .matches(path)
+                    .map(|score| (score, route))
+            })
+            .min_by_key(|(score, _)| *score)
+            .map(|(_, route)| route)
+    }
+
+    /// Where a request body with `headers` is truncated in the recording.
+    pub fn request_truncate_at(&self, route: &RouteSettings, headers: &HeaderMap) -> usize {
+        route
+            .truncate_body_at
+            .or_else(|| self.content_type_limit(headers))
+            .or(self.truncate_request_at)
+            .unwrap_or(self.truncate_body_at)
+    }
+
+    /// Where a response body with `headers` is truncated in the recording.
+    pub fn response_truncate_at(&self, route: &RouteSettings, headers: &HeaderMap) -> usize {
+        route
+            .truncate_body_at
+            .or_else(|| self.content_type_limit(headers))
+            .or(self.truncate_response_at)
+            .unwrap_or(self.truncate_body_at)
+    }
+
+    /// The limit for the body's content type, an exact match before a
+    /// `type/*` one.
+    fn content_type_limit(&self, headers: &HeaderMap) -> Option<usize> {
+        if self.truncate_by_content_type.is_empty() {
+            return None;
+        }
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let (kind, _) = essence.split_once('/')?;
+        self.truncate_by_content_type
+            .get(&essence)
+            .or_else(|| self.truncate_by_content_type.get(&format!("{kind}/*")))
+            .copied()
+    }
+}
+
+/// Parses a `--truncate-type` value such as `application/json=65536` or
+/// `image/*=0`.
+pub fn parse_content_type_limit(value: &str) -> Result<(String, usize)> {
+    let (content_type, size) = value
+        .split_once('=')
+        .with_context(|| format!("Invalid limit {value:?}, expected TYPE=BYTES"))?;
+    if !content_type.contains('/') {
+        anyhow::bail!("Expected a content type such as application/json, got {content_type:?}");
+    }
+    let size = size
+        .parse()
+        .with_context(|| format!("Invalid byte count {size:?}"))?;
+    Ok((content_type.trim().to_ascii_lowercase(), size))
+}
+
+/// Settings that replace the global ones for requests matching `path`, a
+/// path template such as `/export/*` or `/users/{id}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteOverride {
+    pub path: String,
+    /// Only requests with this method; any method when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncate_body_at: Option<usize>,
+    /// Client certificate presented to the upstream instead of the global
+    /// one, as `{cert, key}` PEM file paths.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_identity: Option<ClientIdentity>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoutesFile {
+    routes: Vec<RouteOverride>,
+}
+
+impl RouteOverride {
+    /// Loads overrides from a YAML or JSON file with a `routes` list.
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read routes: {}", path.display()))?;
+        let file: RoutesFile = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse routes: {}", path.display()))?;
+        Ok(file.routes)
+    }
+}
+
+/// How compressed upstream responses are made readable, so `curl` users
+/// and the recorder see plain text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decompression {
+    /// Responses pass through as the upstream encoded them.
+    #[default]
+    Off,
+    /// Remove `Accept-Encoding` from requests, so the upstream answers
+    /// uncompressed.
+    Identity,
+    /// Decode gzip and deflate responses before they are recorded and sent
+    /// on.
+    Decode,
+}
+
+impl std::str::FromStr for Decompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "identity" => Ok(Self::Identity),
+            "decode" => Ok(Self::Decode),
+            _ => anyhow::bail!("Expected off, identity or decode, got {s}"),
+        }
+    }
+}
+
+/// The settings in effect for one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteSettings {
+    pub upstream_timeout: Duration,
+    /// How long the client may take to send the request body.
+    pub client_timeout: Duration,
+    /// The route's own truncation limit, ahead of every other one.
+    pub truncate_body_at: Option<usize>,
+}
+
 #[derive(Debug, Clone)]
 pub struct SharedConfig {
     inner: Arc<RwLock<ProxyConfig>>,
@@ -38,12 +310,12 @@ impl SharedConfig {
         }
     }
 
-    pub fn read(&self) -> parking_lot::RwLockReadGuard<ProxyConfig> {
+    pub fn read(&self) -> parking_lot::RwLockReadGuard<'_, ProxyConfig> {
         self.inner.read()
     }
 
     #[allow(dead_code)]
-    pub fn write(&self) -> parking_lot::RwLockWriteGuard<ProxyConfig> {
+    pub fn write(&self) -> parking_lot::RwLockWriteGuard<'_, ProxyConfig> {
         self.inner.write()
     }
 
@@ -76,13 +348,52 @@ impl Default for SharedConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ConfigUpdate {
     pub client_timeout_ms: Option<u64>,
     pub upstream_timeout_ms: Option<u64>,
     pub max_history_size: Option<usize>,
     pub max_body_size: Option<usize>,
     pub truncate_body_at: Option<usize>,
+    #[serde(default)]
+    pub truncate_request_at: Option<usize>,
+    #[serde(default)]
+    pub truncate_response_at: Option<usize>,
+    /// Replaces the limits by content type.
+    #[serde(default)]
+    pub truncate_by_content_type: Option<BTreeMap<String, usize>>,
+    /// Replaces the binary detection rules; fields left out get their
+    /// defaults.
+    #[serde(default)]
+    pub binary_detection: Option<BinaryDetection>,
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    #[serde(default)]
+    pub pool_idle_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub http1_keep_alive: Option<bool>,
+    #[serde(default)]
+    pub split_percent: Option<u8>,
+    #[serde(default)]
+    pub debug_headers: Option<bool>,
+    #[serde(default)]
+    pub no_cache: Option<bool>,
+    #[serde(default)]
+    pub decompression: Option<Decompression>,
+    pub duplicate_window_ms: Option<u64>,
+    #[serde(default)]
+    pub offline: Option<OfflineMode>,
+    #[serde(default)]
+    pub preflights: Option<PreflightView>,
+    /// Turns blocking of write methods on or off.
+    #[serde(default)]
+    pub safe_mode: Option<bool>,
+    /// Replaces the route overrides.
+    #[serde(default)]
+    pub routes: Option<Vec<RouteOverride>>,
+    /// Replaces the alert rules.
+    #[serde(default)]
+    pub alerts: Option<AlertRules>,
 }
 
 impl ConfigUpdate {
@@ -102,5 +413,67 @@ impl ConfigUpdate {
         if let Some(size) = self.truncate_body_at {
             config.truncate_body_at = size;
         }
+        if let Some(size) = self.truncate_request_at {
+            config.truncate_request_at = Some(size);
+        }
+        if let Some(size) = self.truncate_response_at {
+            config.truncate_response_at = Some(size);
+        }
+        if let Some(ref limits) = self.truncate_by_content_type {
+            config.truncate_by_content_type = limits
+                .iter()
+                .map(|(content_type, size)| (content_type.to_ascii_lowercase(), *size))
+                .collect();
+        }
+        if let Some(ref binary) = self.binary_detection {
+            config.binary_detection = binary.clone();
+        }
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            config.pool_max_idle_per_host = max_idle;
+        }
+        if let Some(timeout) = self.pool_idle_timeout_ms {
+            config.pool_idle_timeout = Duration::from_millis(timeout);
+        }
+        if let Some(keep_alive) = self.http1_keep_alive {
+            config.http1_keep_alive = keep_alive;
+        }
+        if let Some(percent) = self.split_percent {
+            config.split_percent = percent.min(100);
+        }
+        if let Some(debug_headers) = self.debug_headers {
+            config.debug_headers = debug_headers;
+        }
+        if let Some(no_cache) = self.no_cache {
+            config.no_cache = no_cache;
+        }
+        if let Some(decompression) = self.decompression {
+            config.decompression = decompression;
+        }
+        if let Some(window) = self.duplicate_window_ms {
+            config.duplicate_window = Duration::from_millis(window);
+        }
+        if let Some(offline) = self.offline {
+            config.offline = offline;
+        }
+        if let Some(preflights) = self.preflights {
+            config.preflights = preflights;
+        }
+        if let Some(block_writes) = self.safe_mode {
+            config.safe_mode.block_writes = block_writes;
+        }
+        if let Some(ref routes) = self.routes {
+            config.routes = routes.clone();
+        }
+        if let Some(ref alerts) = self.alerts {
+            config.alerts = alerts.clone();
+        }
+    }
+
+    /// Whether the update touches the upstream connection pool, which then
+    /// has to be rebuilt.
+    pub fn changes_pool(&self) -> bool {
+        self.pool_max_idle_per_host.is_some()
+            || self.pool_idle_timeout_ms.is_some()
+            || self.http1_keep_alive.is_some()
     }
 }
@@ -0,0 +1,158 @@
+use anyhow::bail;
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Method, Response, StatusCode};
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+
+/// Methods allowed in preflights that do not name one.
+const ALLOW_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, HEAD, OPTIONS";
+
+/// Response headers a browser exposes to scripts without being told.
+const SAFELISTED_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-language",
+    "content-length",
+    "content-type",
+    "expires",
+    "last-modified",
+    "pragma",
+];
+
+/// Cross-origin requests the proxy allows on the upstream's behalf. The
+/// proxy answers preflights itself and adds `Access-Control-Allow-*` to
+/// proxied responses, replacing whatever the upstream sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorsPolicy {
+    /// Origins allowed to call the upstream; empty allows any, echoing the
+    /// request's `Origin`.
+    pub allow_origins: Vec<String>,
+    /// Let requests carry cookies and `Authorization`.
+    pub allow_credentials: bool,
+    /// Seconds a browser may cache a preflight answer.
+    pub max_age: u64,
+}
+
+impl CorsPolicy {
+    /// Allows any origin, with credentials.
+    pub fn permissive() -> Self {
+        Self {
+            allow_origins: Vec::new(),
+            allow_credentials: true,
+            max_age: 600,
+        }
+    }
+
+    /// Whether the request is a CORS preflight rather than an `OPTIONS`
+    /// request meant for the upstream.
+    pub fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::OPTIONS
+            && headers.contains_key(header::ORIGIN)
+            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// The request's `Origin`, if it is allowed.
+    fn allowed_origin<'a>(&self, headers: &'a HeaderMap) -> Option<&'a HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        let allowed = self.allow_origins.is_empty()
+            || origin.to_str().is_ok_and(|origin| {
+                self.allow_origins
+                    .iter()
+                    .any(|allowed| allowed.trim_end_matches('/') == origin)
+            });
+        allowed.then_some(origin)
+    }
+
+    /// The answer to a preflight, allowing the requested method and headers
+    /// when the origin is allowed.
+    pub fn preflight_response(&self, headers: &HeaderMap) -> Response<Body> {
+        let Some(origin) = self.allowed_origin(headers) else {
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .header(header::VARY, "Origin")
+                .body(Body::from("Origin not allowed by --cors"))
+                .unwrap();
+        };
+
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+            .header(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                headers
+                    .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+                    .cloned()
+                    .unwrap_or(HeaderValue::from_static(ALLOW_METHODS)),
+            )
+            .header(header::ACCESS_CONTROL_MAX_AGE, self.max_age)
+            .header(header::VARY, "Origin");
+        if let Some(requested) = headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            response = response.header(header::ACCESS_CONTROL_ALLOW_HEADERS, requested);
+        }
+        if self.allow_credentials {
+            response = response.header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        response.body(Body::empty()).unwrap()
+    }
+
+    /// Adds the `Access-Control-Allow-*` headers for a request with
+    /// `request_headers` to its response. Requests without an allowed
+    /// `Origin` are left alone.
+    pub fn apply(&self, request_headers: &HeaderMap, response_headers: &mut HeaderMap) {
+        let Some(origin) = self.allowed_origin(request_headers) else {
+            return;
+        };
+
+        // With credentials a `*` is not a wildcard, so name the headers
+        let exposed = response_headers
+            .keys()
+            .filter(|name| {
+                !SAFELISTED_HEADERS.contains(&name.as_str())
+                    && !name.as_str().starts_with("access-control-")
+            })
+            .map(HeaderName::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(exposed) = HeaderValue::from_str(&exposed) {
+            if !exposed.is_empty() {
+                response_headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+            }
+        }
+        response_headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        if self.allow_credentials {
+            response_headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        } else {
+            response_headers.remove(header::ACCESS_CONTROL_ALLOW_CREDENTIALS);
+        }
+        response_headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+}
+
+impl std::str::FromStr for CorsPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `permissive` or a comma-separated list of allowed origins such
+    /// as `http://localhost:5173`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "permissive" {
+            return Ok(Self::permissive());
+        }
+        let allow_origins = s
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect::<Vec<_>>();
+        if let Some(origin) = allow_origins.iter().find(|origin| !origin.contains("://")) {
+            bail!("Expected permissive or origins such as http://localhost:5173, got {origin}");
+        }
+        if allow_origins.is_empty() {
+            bail!("Expected permissive or a list of origins");
+        }
+        Ok(Self {
+            allow_origins,
+            ..Self::permissive()
+        })
+    }
+}
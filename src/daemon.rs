@@ -0,0 +1,164 @@
+use anyhow::{bail, Context, Result};
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// How long `start --daemon` waits for the proxy to write its pidfile.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The running proxy's PID on disk, for `stop` and `status`. Removed on
+/// shutdown with [`PidFile::remove`], since the proxy leaves through
+/// `exit` rather than dropping it.
+#[derive(Debug, Clone)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes this process's PID, unless another running proxy holds the
+    /// file. A stale file from a proxy that died is replaced.
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(pid) = running_pid(path)? {
+            if pid != std::process::id() {
+                bail!(
+                    "debug-proxy is already running with PID {pid} ({})",
+                    path.display()
+                );
+            }
+        }
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write pidfile: {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn remove(&self) {
+        if read_pid(&self.path).ok().flatten() == Some(std::process::id()) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The PID in `path`, or `None` when there is no pidfile.
+pub fn read_pid(path: &Path) -> Result<Option<u32>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid pidfile: {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read pidfile: {}", path.display())),
+    }
+}
+
+/// The PID in `path` if that process is still alive.
+pub fn running_pid(path: &Path) -> Result<Option<u32>> {
+    Ok(read_pid(path)?.filter(|&pid| is_running(pid)))
+}
+
+#[cfg(unix)]
+pub fn is_running(pid: u32) -> bool {
+    let alive = unsafe { libc::kill(pid as i32, 0) } == 0;
+    // Another user's process is alive too, just not ours to signal
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+pub fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// Runs the proxy with `args` in the background, its output appended to
+/// `log_file`, and returns its PID once it is listening and has written
+/// `pidfile`. `args` must include `--pidfile` naming `pidfile`.
+pub fn spawn(args: &[String], pidfile: &Path, log_file: &Path) -> Result<u32> {
+    if let Some(pid) = running_pid(pidfile)? {
+        bail!(
+            "debug-proxy is already running with PID {pid} ({})",
+            pidfile.display()
+        );
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .with_context(|| format!("Failed to open log file: {}", log_file.display()))?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // Keep Ctrl+C in the starting shell from reaching the proxy
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command.spawn().context("Failed to start debug-proxy")?;
+
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            bail!(
+                "debug-proxy exited during startup ({status}), see {}",
+                log_file.display()
+            );
+        }
+        if read_pid(pidfile).ok().flatten() == Some(child.id()) {
+            return Ok(child.id());
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            bail!(
+                "debug-proxy did not start within {STARTUP_TIMEOUT:?}, see {}",
+                log_file.display()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Asks the proxy in `pidfile` to shut down, which also stops its managed
+/// command, and waits up to `timeout` for it to exit.
+#[cfg(unix)]
+pub async fn stop(pidfile: &Path, timeout: Duration) -> Result<u32> {
+    let Some(pid) = read_pid(pidfile)? else {
+        bail!("debug-proxy is not running (no {})", pidfile.display());
+    };
+    if !is_running(pid) {
+        let _ = std::fs::remove_file(pidfile);
+        bail!(
+            "debug-proxy is not running (removed stale {})",
+            pidfile.display()
+        );
+    }
+    if unsafe { libc::kill(pid as i32, libc::SIGTERM) } != 0 {
+        bail!(
+            "Failed to signal PID {pid}: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    let deadline = Instant::now() + timeout;
+    while is_running(pid) {
+        if Instant::now() > deadline {
+            bail!("debug-proxy (PID {pid}) is still running after {timeout:?}");
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    // Gone without cleaning up, such as after SIGKILL
+    if read_pid(pidfile).ok().flatten() == Some(pid) {
+        let _ = std::fs::remove_file(pidfile);
+    }
+    Ok(pid)
+}
+
+#[cfg(not(unix))]
+pub async fn stop(_pidfile: &Path, _timeout: Duration) -> Result<u32> {
+    bail!("Stopping a background proxy is not supported on this platform")
+}
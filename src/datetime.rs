@@ -0,0 +1,160 @@
+use anyhow::{bail, Context, Result};
+
+/// The offset from UTC that recorded times are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UtcOffset {
+    /// Seconds east of UTC.
+    Fixed(i32),
+    /// This machine's timezone, looked up for each timestamp so daylight
+    /// saving changes are followed.
+    Local,
+}
+
+impl Default for UtcOffset {
+    fn default() -> Self {
+        Self::UTC
+    }
+}
+
+impl UtcOffset {
+    pub const UTC: Self = Self::Fixed(0);
+
+    pub fn from_seconds(seconds: i32) -> Result<Self> {
+        if seconds.abs() >= 86_400 {
+            bail!("UTC offset out of range: {seconds} seconds");
+        }
+        Ok(Self::Fixed(seconds))
+    }
+
+    /// This machine's timezone, if its offset can be looked up here.
+    pub fn local() -> Result<Self> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        if local_offset_at(now).is_none() {
+            bail!("Failed to read the local timezone, give an offset like +02:00");
+        }
+        Ok(Self::Local)
+    }
+
+    /// Seconds east of UTC at `timestamp_ms`. The local timezone falls back
+    /// to UTC if it cannot be read.
+    pub fn seconds_at(self, timestamp_ms: u64) -> i32 {
+        match self {
+            Self::Fixed(seconds) => seconds,
+            Self::Local => local_offset_at(timestamp_ms).unwrap_or(0),
+        }
+    }
+}
+
+impl std::str::FromStr for UtcOffset {
+    type Err = anyhow::Error;
+
+    /// `UTC`, `local`, or an offset like `+05:30`, `-0800` or `+02`.
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(Self::UTC);
+        }
+        if s.eq_ignore_ascii_case("local") {
+            return Self::local();
+        }
+        let (sign, rest) = match s.as_bytes().first() {
+            Some(b'+') => (1, &s[1..]),
+            Some(b'-') => (-1, &s[1..]),
+            _ => bail!("Expected UTC, local or an offset like +05:30, got {s}"),
+        };
+        let digits = rest.replace(':', "");
+        if !matches!(digits.len(), 2 | 4) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            bail!("Expected an offset like +05:30, got {s}");
+        }
+        let hours: i32 = digits[..2].parse().context("Invalid hours")?;
+        let minutes: i32 = match digits.get(2..) {
+            Some(minutes) if !minutes.is_empty() => minutes.parse()?,
+            _ => 0,
+        };
+        if minutes >= 60 {
+            bail!("Expected an offset like +05:30, got {s}");
+        }
+        Self::from_seconds(sign * (hours * 3600 + minutes * 60))
+    }
+}
+
+/// Formats Unix milliseconds as an RFC 3339 timestamp at `offset`, such as
+/// `2024-05-01T14:03:07.250+02:00`, or with `Z` in UTC.
+pub fn rfc3339(timestamp_ms: u64, offset: UtcOffset) -> String {
+    let offset = offset.seconds_at(timestamp_ms);
+    let local_ms = timestamp_ms as i64 + i64::from(offset) * 1000;
+    let seconds = local_ms.div_euclid(1000);
+    let (hour, minute, second) = (
+        seconds.rem_euclid(86_400) / 3600,
+        seconds.rem_euclid(3600) / 60,
+        seconds.rem_euclid(60),
+    );
+
+    // Days since the epoch to a civil date, after Howard Hinnant's algorithm
+    let days = seconds.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let zone = if offset == 0 {
+        "Z".to_string()
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let minutes = offset.abs() / 60;
+        format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+    };
+    format!(
+        "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{:03}{zone}",
+        local_ms.rem_euclid(1000)
+    )
+}
+
+/// The local timezone's offset at `timestamp_ms`, from the C library so
+/// the `TZ` variable and the system timezone database are honoured.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn local_offset_at(timestamp_ms: u64) -> Option<i32> {
+    let time = libc::time_t::try_from(timestamp_ms / 1000).ok()?;
+    let mut tm = std::mem::MaybeUninit::<libc::tm>::zeroed();
+    // SAFETY: localtime_r only writes to the tm it is given
+    if unsafe { libc::localtime_r(&time, tm.as_mut_ptr()) }.is_null() {
+        return None;
+    }
+    // SAFETY: localtime_r succeeded, so it filled in the tm
+    let offset = unsafe { tm.assume_init() }.tm_gmtoff;
+    i32::try_from(offset)
+        .ok()
+        .filter(|seconds| seconds.abs() < 86_400)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+fn local_offset_at(_timestamp_ms: u64) -> Option<i32> {
+    None
+}
@@ -0,0 +1,222 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::recorder::{BodyRecord, HttpTransaction};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionDiff {
+    pub a: String,
+    pub b: String,
+    pub identical: bool,
+    pub request: PartDiff,
+    pub response: PartDiff,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PartDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Change<Option<u16>>>,
+    pub headers: Vec<Change<Option<String>>>,
+    pub body: BodyDiff,
+}
+
+/// A value that differs between transaction `a` and transaction `b`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Change<T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub a: T,
+    pub b: T,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BodyDiff {
+    Identical,
+    Json {
+        changes: Vec<Change<Option<Value>>>,
+    },
+    Text {
+        a: String,
+        b: String,
+    },
+    /// The recorded text matches, but the bodies' hashes show they differ
+    /// where they were not recorded: past the truncation point, or in
+    /// binary content.
+    Unrecorded {
+        a_sha256: String,
+        b_sha256: String,
+    },
+}
+
+impl PartDiff {
+    fn is_empty(&self) -> bool {
+        self.method.is_none()
+            && self.path.is_none()
+            && self.status.is_none()
+            && self.headers.is_empty()
+            && matches!(self.body, BodyDiff::Identical)
+    }
+}
+
+/// Produces a structured diff of two recorded transactions.
+pub fn diff_transactions(a: &HttpTransaction, b: &HttpTransaction) -> TransactionDiff {
+    let request = PartDiff {
+        method: change(&a.request.method, &b.request.method),
+        path: change(&a.request.path, &b.request.path),
+        status: None,
+        headers: diff_headers(&a.request.headers, &b.request.headers),
+        body: diff_bodies(Some(&a.request.body), Some(&b.request.body)),
+    };
+
+    let empty = Vec::new();
+    let (a_response, b_response) = (a.response.as_ref(), b.response.as_ref());
+    let response = PartDiff {
+        method: None,
+        path: None,
+        status: change(&a_response.map(|r| r.status), &b_response.map(|r| r.status)),
+        headers: diff_headers(
+            a_response.map_or(&empty, |r| &r.headers),
+            b_response.map_or(&empty, |r| &r.headers),
+        ),
+        body: diff_bodies(a_response.map(|r| &r.body), b_response.map(|r| &r.body)),
+    };
+
+    TransactionDiff {
+        a: a.request.id.clone(),
+        b: b.request.id.clone(),
+        identical: request.is_empty() && response.is_empty(),
+        request,
+        response,
+    }
+}
+
+fn change<T: PartialEq + Clone>(a: &T, b: &T) -> Option<Change<T>> {
+    (a != b).then(|| Change {
+        name: None,
+        a: a.clone(),
+        b: b.clone(),
+    })
+}
+
+fn diff_headers(a: &[(String, String)], b: &[(String, String)]) -> Vec<Change<Option<String>>> {
+    let a = group_headers(a);
+    let b = group_headers(b);
+
+    let mut names: Vec<&String> = a.keys().chain(b.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (a_value, b_value) = (a.get(name).cloned(), b.get(name).cloned());
+            (a_value != b_value).then(|| Change {
+                name: Some(name.clone()),
+                a: a_value,
+                b: b_value,
+            })
+        })
+        .collect()
+}
+
+fn group_headers(headers: &[(String, String)]) -> BTreeMap<String, String> {
+    let mut grouped: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        grouped
+            .entry(name.to_ascii_lowercase())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.clone());
+    }
+    grouped
+}
+
+fn diff_bodies(a: Option<&BodyRecord>, b: Option<&BodyRecord>) -> BodyDiff {
+    // Records from before bodies were hashed have no hash to compare
+    let hashes = a
+        .zip(b)
+        .map(|(a, b)| (&a.sha256, &b.sha256))
+        .filter(|(a, b)| !a.is_empty() && !b.is_empty());
+    if hashes.is_some_and(|(a, b)| a == b) {
+        return BodyDiff::Identical;
+    }
+    let a_text = a.map(|body| body.preview.as_str()).unwrap_or_default();
+    let b_text = b.map(|body| body.preview.as_str()).unwrap_or_default();
+    if a_text == b_text {
+        return match hashes {
+            Some((a, b)) => BodyDiff::Unrecorded {
+                a_sha256: a.clone(),
+                b_sha256: b.clone(),
+            },
+            None => BodyDiff::Identical,
+        };
+    }
+
+    match (
+        serde_json::from_str::<Value>(a_text),
+        serde_json::from_str::<Value>(b_text),
+    ) {
+        (Ok(a_json), Ok(b_json)) => {
+            let mut changes = Vec::new();
+            diff_json("$", &a_json, &b_json, &mut changes);
+            if changes.is_empty() {
+                BodyDiff::Identical
+            } else {
+                BodyDiff::Json { changes }
+            }
+        }
+        _ => BodyDiff::Text {
+            a: a_text.to_string(),
+            b: b_text.to_string(),
+        },
+    }
+}
+
+/// Records every leaf that differs between two JSON documents.
+pub fn diff_json(location: &str, a: &Value, b: &Value, changes: &mut Vec<Change<Option<Value>>>) {
+    match (a, b) {
+        (Value::Object(a_map), Value::Object(b_map)) => {
+            let mut keys: Vec<&String> = a_map.keys().chain(b_map.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let child = format!("{location}.{key}");
+                match (a_map.get(key), b_map.get(key)) {
+                    (Some(a_value), Some(b_value)) => diff_json(&child, a_value, b_value, changes),
+                    (a_value, b_value) => changes.push(Change {
+                        name: Some(child),
+                        a: a_value.cloned(),
+                        b: b_value.cloned(),
+                    }),
+                }
+            }
+        }
+        (Value::Array(a_items), Value::Array(b_items)) => {
+            for i in 0..a_items.len().max(b_items.len()) {
+                let child = format!("{location}[{i}]");
+                match (a_items.get(i), b_items.get(i)) {
+                    (Some(a_value), Some(b_value)) => diff_json(&child, a_value, b_value, changes),
+                    (a_value, b_value) => changes.push(Change {
+                        name: Some(child),
+                        a: a_value.cloned(),
+                        b: b_value.cloned(),
+                    }),
+                }
+            }
+        }
+        _ if a != b => changes.push(Change {
+            name: Some(location.to_string()),
+            a: Some(a.clone()),
+            b: Some(b.clone()),
+        }),
+        _ => {}
+    }
+}
@@ -0,0 +1,84 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::bench::Latency;
+use crate::recorder::HttpTransaction;
+
+/// Traffic totals for one method and path template.
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStats {
+    pub method: String,
+    /// The path with numeric and UUID segments replaced by `{id}`, such as
+    /// `/users/{id}/orders`.
+    pub path: String,
+    pub count: usize,
+    /// Transactions that failed or got a 5xx response.
+    pub errors: usize,
+    pub error_rate: f64,
+    pub latency_ms: Latency,
+}
+
+/// Groups transactions by endpoint, slowest first by p95 latency.
+pub fn summarize<'a>(
+    transactions: impl IntoIterator<Item = &'a HttpTransaction>,
+) -> Vec<EndpointStats> {
+    let mut groups: HashMap<(String, String), (usize, usize, Vec<u64>)> = HashMap::new();
+    for transaction in transactions {
+        let key = (
+            transaction.request.method.clone(),
+            path_template(&transaction.request.path),
+        );
+        let (count, errors, durations) = groups.entry(key).or_default();
+        *count += 1;
+        let failed = transaction.error.is_some()
+            || transaction
+                .response
+                .as_ref()
+                .is_some_and(|r| r.status >= 500);
+        if failed {
+            *errors += 1;
+        }
+        if let Some(ref response) = transaction.response {
+            durations.push(response.duration_ms);
+        }
+    }
+
+    let mut endpoints: Vec<EndpointStats> = groups
+        .into_iter()
+        .map(
+            |((method, path), (count, errors, durations))| EndpointStats {
+                method,
+                path,
+                count,
+                errors,
+                error_rate: errors as f64 / count as f64,
+                latency_ms: Latency::from_durations(durations),
+            },
+        )
+        .collect();
+    endpoints.sort_by(|a, b| {
+        b.latency_ms
+            .p95
+            .cmp(&a.latency_ms.p95)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.method.cmp(&b.method))
+    });
+    endpoints
+}
+
+/// The path without its query, with segments that look like identifiers
+/// replaced by `{id}`.
+pub fn path_template(path: &str) -> String {
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    path.split('/')
+        .map(|segment| {
+            let is_number = !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit());
+            if is_number || uuid::Uuid::try_parse(segment).is_ok() {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,220 @@
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::body_size::SizeWarning;
+
+/// Default number of connection events kept.
+pub const DEFAULT_MAX_EVENTS: usize = 1000;
+
+/// Something that happened to a client or upstream connection, including
+/// failures that never became a complete transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    ClientConnected,
+    ClientDisconnected {
+        /// Requests the client sent on the connection.
+        requests: u64,
+        duration_ms: u64,
+    },
+    /// The client went away while its request was waiting on the upstream.
+    ClientAborted {
+        method: String,
+        path: String,
+    },
+    /// The client connection failed while the request body was being read.
+    ClientReset {
+        error: String,
+    },
+    /// A client's TLS handshake with an intercepted `CONNECT` tunnel failed.
+    #[cfg(feature = "tls")]
+    ClientTlsError {
+        host: String,
+        error: String,
+    },
+    UpstreamConnectFailed {
+        upstream: String,
+        error: String,
+    },
+    #[cfg(feature = "tls")]
+    UpstreamTlsError {
+        upstream: String,
+        error: String,
+    },
+    /// The upstream connection failed after the request was sent, before or
+    /// while the response arrived.
+    UpstreamReset {
+        upstream: String,
+        error: String,
+    },
+    /// A body over `max_body_size`, or with a `Content-Length` that did not
+    /// match what arrived.
+    BodySize {
+        method: String,
+        path: String,
+        #[serde(flatten)]
+        warning: SizeWarning,
+    },
+    /// The upstream switched protocols and the connection is now tunnelled
+    /// byte for byte.
+    Upgraded {
+        protocol: String,
+    },
+    TunnelClosed {
+        protocol: String,
+        bytes_to_upstream: u64,
+        bytes_to_client: u64,
+        duration_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionEvent {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub client_addr: String,
+    /// The transaction the event interrupted, if one was recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Ring buffer of connection events, kept apart from the transaction
+/// history.
+#[derive(Clone)]
+pub struct ConnectionEvents {
+    events: Arc<RwLock<VecDeque<ConnectionEvent>>>,
+    next_seq: Arc<Mutex<u64>>,
+    max_events: usize,
+}
+
+impl Default for ConnectionEvents {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_EVENTS)
+    }
+}
+
+impl ConnectionEvents {
+    pub fn new(max_events: usize) -> Self {
+        Self {
+            events: Arc::new(RwLock::new(VecDeque::with_capacity(max_events))),
+            next_seq: Arc::new(Mutex::new(1)),
+            max_events,
+        }
+    }
+
+    pub fn push(&self, client_addr: &str, request_id: Option<&str>, kind: EventKind) {
+        let mut next_seq = self.next_seq.lock();
+        let event = ConnectionEvent {
+            seq: *next_seq,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            client_addr: client_addr.to_string(),
+            request_id: request_id.map(str::to_string),
+            kind,
+        };
+        *next_seq += 1;
+
+        let mut events = self.events.write();
+        if events.len() >= self.max_events {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Returns the buffered events with a sequence number above `since`.
+    pub fn get(&self, since: Option<u64>) -> Vec<ConnectionEvent> {
+        let since = since.unwrap_or(0);
+        self.events
+            .read()
+            .iter()
+            .filter(|event| event.seq > since)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.write().clear();
+    }
+
+    /// Records the client connecting, and its disconnect once the returned
+    /// guard is dropped.
+    pub fn track_connection(&self, client_addr: &str) -> ConnectionGuard {
+        self.push(client_addr, None, EventKind::ClientConnected);
+        ConnectionGuard {
+            events: self.clone(),
+            client_addr: client_addr.to_string(),
+            connected_at: Instant::now(),
+            requests: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Lives as long as a client connection's service, which hyper drops when
+/// the connection closes.
+pub struct ConnectionGuard {
+    events: ConnectionEvents,
+    client_addr: String,
+    connected_at: Instant,
+    requests: AtomicU64,
+}
+
+impl ConnectionGuard {
+    pub fn count_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.events.push(
+            &self.client_addr,
+            None,
+            EventKind::ClientDisconnected {
+                requests: self.requests.load(Ordering::Relaxed),
+                duration_ms: self.connected_at.elapsed().as_millis() as u64,
+            },
+        );
+    }
+}
+
+/// Classifies a failed upstream request: a TLS failure anywhere in the
+/// error chain, a failure to connect, or a connection lost afterwards.
+pub fn upstream_failure(upstream: &str, error: &hyper::Error) -> EventKind {
+    let upstream = upstream.to_string();
+    let message = format!("{error}");
+    let mut source: Option<&(dyn StdError + 'static)> = error.source();
+    while let Some(cause) = source {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = cause.downcast_ref::<rustls::Error>() {
+            return EventKind::UpstreamTlsError {
+                upstream,
+                error: tls.to_string(),
+            };
+        }
+        // io::Error hides the error it wraps from `source`
+        source = match cause.downcast_ref::<std::io::Error>() {
+            Some(io) => io.get_ref().map(|inner| inner as &(dyn StdError + 'static)),
+            None => cause.source(),
+        };
+    }
+    if error.is_connect() {
+        let error = error.source().map_or(message, |cause| cause.to_string());
+        EventKind::UpstreamConnectFailed { upstream, error }
+    } else {
+        EventKind::UpstreamReset {
+            upstream,
+            error: message,
+        }
+    }
+}
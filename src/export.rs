@@ -0,0 +1,614 @@
+use anyhow::Result;
+use std::io::{Cursor, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::datetime::{rfc3339, UtcOffset};
+use crate::process::LogLine;
+use crate::recorder::{BodyRecord, HttpTransaction, RequestRecord};
+use crate::version::BuildInfo;
+
+/// Request headers that the load tool sets itself and must not be replayed.
+pub(crate) const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "upgrade",
+    "proxy-connection",
+    "te",
+    "trailer",
+];
+
+/// A single exported Hurl file.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HurlFile {
+    pub name: String,
+    pub content: String,
+}
+
+/// Renders the transactions as a k6 script that replays them in order,
+/// checking each response status against the recorded one.
+pub fn to_k6(transactions: &[HttpTransaction], base_url: &str) -> String {
+    let mut script = String::new();
+    script.push_str("import http from \"k6/http\";\n");
+    script.push_str("import { check } from \"k6\";\n\n");
+    script.push_str(&format!(
+        "const BASE_URL = __ENV.BASE_URL || {};\n\n",
+        js_string(base_url)
+    ));
+    script.push_str("export default function () {\n");
+    script.push_str("  let res;\n");
+
+    for transaction in transactions {
+        let request = &transaction.request;
+        script.push('\n');
+        script.push_str(&format!("  // {} {}\n", request.method, request.path));
+        if let Some(warning) = body_warning(&request.body) {
+            script.push_str(&format!("  // {warning}\n"));
+        }
+
+        let headers: Vec<String> = replayed_headers(&request.headers)
+            .map(|(name, value)| format!("{}: {}", js_string(name), js_string(value)))
+            .collect();
+        let body = replayed_body(&request.body).map_or("null".to_string(), js_string);
+        script.push_str(&format!(
+            "  res = http.request({}, BASE_URL + {}, {}, {{ headers: {{ {} }} }});\n",
+            js_string(&request.method),
+            js_string(&request.path),
+            body,
+            headers.join(", ")
+        ));
+
+        if let Some(ref response) = transaction.response {
+            script.push_str(&format!(
+                "  check(res, {{ {}: (r) => r.status === {} }});\n",
+                js_string(&format!(
+                    "{} {} is {}",
+                    request.method, request.path, response.status
+                )),
+                response.status
+            ));
+        }
+    }
+
+    script.push_str("}\n");
+    script
+}
+
+/// Renders each transaction as its own Hurl file, numbered to keep the
+/// recorded order. Recorded response statuses become assertions.
+pub fn to_hurl(transactions: &[HttpTransaction]) -> Vec<HurlFile> {
+    transactions
+        .iter()
+        .enumerate()
+        .map(|(i, transaction)| HurlFile {
+            name: format!(
+                "{:03}-{}-{}.hurl",
+                i + 1,
+                transaction.request.method.to_ascii_lowercase(),
+                slug(&transaction.request.path)
+            ),
+            content: hurl_entry(transaction),
+        })
+        .collect()
+}
+
+/// Renders the transactions as a HAR 1.2 log. Failed exchanges get status
+/// `0`, as browsers record them, and the error in `_error`.
+pub fn to_har(transactions: &[HttpTransaction], base_url: &str) -> serde_json::Value {
+    let entries: Vec<serde_json::Value> = transactions
+        .iter()
+        .map(|transaction| {
+            let request = &transaction.request;
+            let mut har_request = serde_json::json!({
+                "method": request.method,
+                "url": format!(
+                    "{}{}",
+                    request.target.as_deref().unwrap_or(base_url).trim_end_matches('/'),
+                    request.path
+                ),
+                "httpVersion": request.version,
+                "headers": har_headers(&request.headers),
+                "queryString": request
+                    .query
+                    .iter()
+                    .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+                    .collect::<Vec<_>>(),
+                "cookies": [],
+                "headersSize": -1,
+                "bodySize": request.body.size,
+            });
+            if request.body.size > 0 {
+                har_request["postData"] = serde_json::json!({
+                    "mimeType": request.body.content_type.clone().unwrap_or_default(),
+                    "text": har_text(&request.body),
+                });
+            }
+
+            let (har_response, time) = match transaction.response {
+                Some(ref response) => (
+                    serde_json::json!({
+                        "status": response.status,
+                        "statusText": http::StatusCode::from_u16(response.status)
+                            .ok()
+                            .and_then(|status| status.canonical_reason())
+                            .unwrap_or_default(),
+                        "httpVersion": response.version,
+                        "headers": har_headers(&response.headers),
+                        "cookies": [],
+                        "content": {
+                            "size": response.body.size,
+                            "mimeType": response.body.content_type.clone().unwrap_or_default(),
+                            "text": har_text(&response.body),
+                        },
+                        "redirectURL": "",
+                        "headersSize": -1,
+                        "bodySize": response.body.size,
+                    }),
+                    response.duration_ms,
+                ),
+                None => (
+                    serde_json::json!({
+                        "status": 0,
+                        "statusText": "",
+                        "httpVersion": request.version,
+                        "headers": [],
+                        "cookies": [],
+                        "content": { "size": 0, "mimeType": "" },
+                        "redirectURL": "",
+                        "headersSize": -1,
+                        "bodySize": -1,
+                        "_error": transaction.error,
+                    }),
+                    0,
+                ),
+            };
+
+            serde_json::json!({
+                "startedDateTime": started_date_time(request),
+                "time": time,
+                "request": har_request,
+                "response": har_response,
+                "cache": {},
+                "timings": { "send": 0, "wait": time, "receive": 0 },
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "log": {
+            "version": "1.2",
+            "creator": { "name": "debug-proxy", "version": env!("CARGO_PKG_VERSION") },
+            "entries": entries,
+        }
+    })
+}
+
+/// One JSON transaction per line, in the format of `/_proxy/api/logs`.
+pub fn to_jsonl(transactions: &[HttpTransaction]) -> String {
+    transactions
+        .iter()
+        .map(|transaction| serde_json::to_string(transaction).unwrap() + "\n")
+        .collect()
+}
+
+/// Everything in a bug report bundle besides the transactions.
+pub struct BundleContext<'a> {
+    pub base_url: &'a str,
+    /// As `GET /_proxy/api/config` shows it.
+    pub config: &'a serde_json::Value,
+    /// As `GET /_proxy/api/stats` shows it.
+    pub stats: &'a serde_json::Value,
+    /// Output of the managed command, `None` without one.
+    pub process_logs: Option<&'a [LogLine]>,
+    /// The time range the transactions were picked from, in Unix
+    /// milliseconds.
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// The timezone of the times in `process.log` and `bundle.json`.
+    pub utc_offset: UtcOffset,
+}
+
+/// Packs the transactions into a zip to attach to an issue: `traffic.har`,
+/// each recorded body under `bodies/`, the managed command's output as
+/// `process.log`, `config.json`, `stats.json`, and `bundle.json` saying
+/// what the bundle covers. Deflated when built with the `decoders` feature.
+pub fn to_bundle(transactions: &[HttpTransaction], context: &BundleContext) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+
+    zip.start_file("traffic.har", options)?;
+    serde_json::to_writer_pretty(&mut zip, &to_har(transactions, context.base_url))?;
+
+    let mut truncated_bodies = 0;
+    for (i, transaction) in transactions.iter().enumerate() {
+        let response = transaction.response.as_ref().map(|r| &r.body);
+        for (part, body) in [
+            ("request", Some(&transaction.request.body)),
+            ("response", response),
+        ] {
+            let Some(body) = body.filter(|body| body.size > 0 && !body.is_binary) else {
+                continue;
+            };
+            truncated_bodies += usize::from(body.truncated);
+            zip.start_file(
+                format!(
+                    "bodies/{:03}-{}-{part}.{}",
+                    i + 1,
+                    slug(&transaction.request.path),
+                    body_extension(body)
+                ),
+                options,
+            )?;
+            zip.write_all(body.preview.as_str().as_bytes())?;
+        }
+    }
+
+    if let Some(lines) = context.process_logs {
+        zip.start_file("process.log", options)?;
+        for line in lines {
+            writeln!(
+                zip,
+                "{} [{}] {}",
+                rfc3339(line.timestamp, context.utc_offset),
+                line.stream.as_str(),
+                line.line
+            )?;
+        }
+    }
+
+    zip.start_file("config.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, context.config)?;
+    zip.start_file("stats.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, context.stats)?;
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    zip.start_file("bundle.json", options)?;
+    serde_json::to_writer_pretty(
+        &mut zip,
+        &serde_json::json!({
+            "generated_at": rfc3339(now_ms, context.utc_offset),
+            "since": context.since.map(|since| rfc3339(since, context.utc_offset)),
+            "until": context.until.map(|until| rfc3339(until, context.utc_offset)),
+            "transactions": transactions.len(),
+            // Bodies are the recorded previews, cut at the truncation size
+            "truncated_bodies": truncated_bodies,
+            "build": BuildInfo::current(),
+        }),
+    )?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// A file extension for a recorded body, from its content type.
+fn body_extension(body: &BodyRecord) -> &'static str {
+    let content_type = body.content_type.as_deref().unwrap_or_default();
+    if content_type.contains("json") {
+        "json"
+    } else if content_type.contains("html") {
+        "html"
+    } else if content_type.contains("xml") {
+        "xml"
+    } else {
+        "txt"
+    }
+}
+
+/// mitmproxy's flow format version written by [`to_mitmproxy`], that of
+/// mitmproxy 10. Newer releases migrate it when loading.
+const MITMPROXY_FLOW_VERSION: i64 = 19;
+
+/// Renders the transactions as a mitmproxy flow file, for opening in
+/// mitmweb or `mitmproxy -r`. Bodies are the recorded previews, so binary
+/// bodies are empty and long ones truncated, as in the HAR export.
+pub fn to_mitmproxy(transactions: &[HttpTransaction], base_url: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for transaction in transactions {
+        mitmproxy_flow(transaction, base_url).write_to(&mut out);
+    }
+    out
+}
+
+/// A value in the tnetstring encoding mitmproxy stores flows in.
+enum Tnet {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Tnet>),
+    Dict(Vec<(&'static str, Tnet)>),
+}
+
+impl Tnet {
+    fn str(value: &str) -> Self {
+        Self::Str(value.to_string())
+    }
+
+    fn bytes(value: &str) -> Self {
+        Self::Bytes(value.as_bytes().to_vec())
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        let (payload, tag) = match self {
+            Self::Null => (Vec::new(), b'~'),
+            Self::Bool(value) => (value.to_string().into_bytes(), b'!'),
+            Self::Int(value) => (value.to_string().into_bytes(), b'#'),
+            Self::Float(value) => (format!("{value:?}").into_bytes(), b'^'),
+            Self::Str(value) => (value.as_bytes().to_vec(), b';'),
+            Self::Bytes(value) => (value.clone(), b','),
+            Self::List(items) => {
+                let mut payload = Vec::new();
+                for item in items {
+                    item.write_to(&mut payload);
+                }
+                (payload, b']')
+            }
+            Self::Dict(entries) => {
+                let mut payload = Vec::new();
+                for (key, value) in entries {
+                    Self::str(key).write_to(&mut payload);
+                    value.write_to(&mut payload);
+                }
+                (payload, b'}')
+            }
+        };
+        out.extend_from_slice(payload.len().to_string().as_bytes());
+        out.push(b':');
+        out.extend_from_slice(&payload);
+        out.push(tag);
+    }
+}
+
+fn mitmproxy_flow(transaction: &HttpTransaction, base_url: &str) -> Tnet {
+    let request = &transaction.request;
+    let seconds = |ms: u64| Tnet::Float(ms as f64 / 1000.0);
+    let url = request
+        .url
+        .as_deref()
+        .or(request.target.as_deref())
+        .unwrap_or(base_url);
+    let url = url::Url::parse(url)
+        .or_else(|_| url::Url::parse(&format!("http://{url}")))
+        .ok();
+    let scheme = url.as_ref().map_or("http", |url| url.scheme()).to_string();
+    let host = url
+        .as_ref()
+        .and_then(|url| url.host_str())
+        .unwrap_or("localhost")
+        .to_string();
+    let port = url
+        .as_ref()
+        .and_then(|url| url.port_or_known_default())
+        .unwrap_or(80);
+    let address = || Tnet::List(vec![Tnet::str(&host), Tnet::Int(i64::from(port))]);
+    let headers = |headers: &[(String, String)]| {
+        Tnet::List(
+            headers
+                .iter()
+                .map(|(name, value)| Tnet::List(vec![Tnet::bytes(name), Tnet::bytes(value)]))
+                .collect(),
+        )
+    };
+    let content = |body: &BodyRecord| Tnet::bytes(har_text(body));
+    let end_ms = transaction
+        .response
+        .as_ref()
+        .map_or(request.timestamp, |response| response.timestamp);
+
+    // mitmproxy requires both client addresses, so unknown ones are zeroed
+    let socket_address = |addr: Option<&str>| {
+        let addr = addr
+            .and_then(|addr| addr.parse::<std::net::SocketAddr>().ok())
+            .unwrap_or_else(|| std::net::SocketAddr::from(([0, 0, 0, 0], 0)));
+        Tnet::List(vec![
+            Tnet::str(&addr.ip().to_string()),
+            Tnet::Int(i64::from(addr.port())),
+        ])
+    };
+    let connection = |extra: Vec<(&'static str, Tnet)>| {
+        let mut state = vec![
+            ("id", Tnet::str(&uuid::Uuid::new_v4().to_string())),
+            ("transport_protocol", Tnet::str("tcp")),
+            ("error", Tnet::Null),
+            ("tls", Tnet::Bool(scheme == "https")),
+            ("certificate_list", Tnet::List(Vec::new())),
+            ("alpn", Tnet::Null),
+            ("alpn_offers", Tnet::List(Vec::new())),
+            ("cipher", Tnet::Null),
+            ("cipher_list", Tnet::List(Vec::new())),
+            ("tls_version", Tnet::Null),
+            ("sni", Tnet::Null),
+            ("timestamp_start", seconds(request.timestamp)),
+            ("timestamp_end", seconds(end_ms)),
+            ("timestamp_tls_setup", Tnet::Null),
+        ];
+        state.extend(extra);
+        Tnet::Dict(state)
+    };
+    let client_conn = connection(vec![
+        ("peername", socket_address(Some(&request.client_addr))),
+        ("sockname", socket_address(request.listener.as_deref())),
+        ("mitmcert", Tnet::Null),
+        ("proxy_mode", Tnet::str("regular")),
+    ]);
+    let server_conn = connection(vec![
+        ("address", address()),
+        ("peername", address()),
+        ("sockname", Tnet::Null),
+        ("timestamp_tcp_setup", Tnet::Null),
+        ("via", Tnet::Null),
+    ]);
+
+    let http_request = Tnet::Dict(vec![
+        ("http_version", Tnet::bytes(&request.version)),
+        ("headers", headers(&request.headers)),
+        ("content", content(&request.body)),
+        ("trailers", Tnet::Null),
+        ("timestamp_start", seconds(request.timestamp)),
+        ("timestamp_end", seconds(request.timestamp)),
+        ("host", Tnet::str(&host)),
+        ("port", Tnet::Int(i64::from(port))),
+        ("method", Tnet::bytes(&request.method)),
+        ("scheme", Tnet::bytes(&scheme)),
+        ("authority", Tnet::bytes("")),
+        ("path", Tnet::bytes(&request.path)),
+    ]);
+    let http_response = transaction
+        .response
+        .as_ref()
+        .map_or(Tnet::Null, |response| {
+            let reason = http::StatusCode::from_u16(response.status)
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or_default();
+            Tnet::Dict(vec![
+                ("http_version", Tnet::bytes(&response.version)),
+                ("headers", headers(&response.headers)),
+                ("content", content(&response.body)),
+                ("trailers", Tnet::Null),
+                ("timestamp_start", seconds(response.timestamp)),
+                ("timestamp_end", seconds(response.timestamp)),
+                ("status_code", Tnet::Int(i64::from(response.status))),
+                ("reason", Tnet::bytes(reason)),
+            ])
+        });
+    let error = transaction.error.as_ref().map_or(Tnet::Null, |msg| {
+        Tnet::Dict(vec![
+            ("msg", Tnet::str(msg)),
+            ("timestamp", seconds(end_ms)),
+        ])
+    });
+
+    Tnet::Dict(vec![
+        ("version", Tnet::Int(MITMPROXY_FLOW_VERSION)),
+        ("type", Tnet::str("http")),
+        ("id", Tnet::str(&request.id)),
+        ("error", error),
+        ("client_conn", client_conn),
+        ("server_conn", server_conn),
+        ("intercepted", Tnet::Bool(false)),
+        ("is_replay", Tnet::Null),
+        ("marked", Tnet::str("")),
+        ("metadata", Tnet::Dict(Vec::new())),
+        ("comment", Tnet::str("")),
+        ("timestamp_created", seconds(request.timestamp)),
+        ("request", http_request),
+        ("response", http_response),
+        ("websocket", Tnet::Null),
+    ])
+}
+
+fn har_headers(headers: &[(String, String)]) -> Vec<serde_json::Value> {
+    headers
+        .iter()
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+        .collect()
+}
+
+/// When the request arrived, in the timezone it was recorded in.
+fn started_date_time(request: &RequestRecord) -> String {
+    if request.time.is_empty() {
+        rfc3339(request.timestamp, UtcOffset::UTC)
+    } else {
+        request.time.clone()
+    }
+}
+
+/// The recorded preview; binary bodies were not recorded and stay empty.
+fn har_text(body: &BodyRecord) -> &str {
+    if body.is_binary {
+        ""
+    } else {
+        &body.preview
+    }
+}
+
+fn hurl_entry(transaction: &HttpTransaction) -> String {
+    let request = &transaction.request;
+    let mut entry = String::new();
+    if let Some(warning) = body_warning(&request.body) {
+        entry.push_str(&format!("# {warning}\n"));
+    }
+    entry.push_str(&format!(
+        "{} {{{{base_url}}}}{}\n",
+        request.method, request.path
+    ));
+    for (name, value) in replayed_headers(&request.headers) {
+        entry.push_str(&format!("{name}: {value}\n"));
+    }
+    if let Some(body) = replayed_body(&request.body) {
+        entry.push_str("```\n");
+        entry.push_str(body);
+        if !body.ends_with('\n') {
+            entry.push('\n');
+        }
+        entry.push_str("```\n");
+    }
+    if let Some(ref response) = transaction.response {
+        entry.push_str(&format!("\nHTTP {}\n", response.status));
+    }
+    entry
+}
+
+fn replayed_headers(headers: &[(String, String)]) -> impl Iterator<Item = (&str, &str)> {
+    headers
+        .iter()
+        .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.to_ascii_lowercase().as_str()))
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+}
+
+fn replayed_body(body: &BodyRecord) -> Option<&str> {
+    (body.size > 0 && !body.is_binary).then_some(body.preview.as_str())
+}
+
+/// Explains why a recorded body cannot be replayed verbatim, if it can't.
+fn body_warning(body: &BodyRecord) -> Option<String> {
+    if body.is_binary {
+        Some(format!(
+            "binary request body ({} bytes) was not recorded",
+            body.size
+        ))
+    } else if body.truncated {
+        Some(format!(
+            "request body truncated to {} of {} bytes",
+            body.preview.len(),
+            body.size
+        ))
+    } else {
+        None
+    }
+}
+
+fn js_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+fn slug(path: &str) -> String {
+    let slug: String = path
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "root".to_string()
+    } else {
+        let mut collapsed = String::with_capacity(slug.len());
+        for c in slug.chars() {
+            if !(c == '-' && collapsed.ends_with('-')) {
+                collapsed.push(c);
+            }
+        }
+        collapsed
+    }
+}
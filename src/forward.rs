@@ -0,0 +1,217 @@
+#[cfg(feature = "tls")]
+use anyhow::{Context, Result};
+#[cfg(feature = "tls")]
+use parking_lot::Mutex;
+#[cfg(feature = "tls")]
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType,
+};
+#[cfg(feature = "tls")]
+use rustls::server::{ClientHello, ResolvesServerCert};
+#[cfg(feature = "tls")]
+use rustls::sign::CertifiedKey;
+#[cfg(feature = "tls")]
+use rustls::ServerConfig;
+#[cfg(feature = "tls")]
+use std::collections::HashMap;
+#[cfg(feature = "tls")]
+use std::net::IpAddr;
+#[cfg(feature = "tls")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+#[cfg(feature = "tls")]
+use time::{Duration, OffsetDateTime};
+
+#[cfg(feature = "tls")]
+const CA_CERT_FILE: &str = "debug-proxy-ca.pem";
+#[cfg(feature = "tls")]
+const CA_KEY_FILE: &str = "debug-proxy-ca-key.pem";
+
+/// Settings for serving as an HTTP forward proxy, which accepts
+/// absolute-form requests and CONNECT tunnels to any host.
+#[derive(Clone, Default)]
+pub struct ForwardProxy {
+    /// Decrypt CONNECT tunnels with certificates from this CA so the HTTPS
+    /// traffic inside is recorded; without it tunnels are passed through.
+    #[cfg(feature = "tls")]
+    pub mitm: Option<Arc<CertificateAuthority>>,
+}
+
+/// A local CA that issues certificates for intercepted hosts. Clients must
+/// trust its certificate.
+#[cfg(feature = "tls")]
+pub struct CertificateAuthority {
+    ca: Certificate,
+    cert_pem: String,
+    cert_path: PathBuf,
+    leaves: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+#[cfg(feature = "tls")]
+impl CertificateAuthority {
+    /// Loads the CA from `dir`, generating and saving one on first use so
+    /// clients only need to trust it once.
+    pub fn load_or_create(dir: &Path) -> Result<Self> {
+        let cert_path = dir.join(CA_CERT_FILE);
+        let key_path = dir.join(CA_KEY_FILE);
+
+        let (ca, cert_pem) = if cert_path.exists() && key_path.exists() {
+            let key_pem = std::fs::read_to_string(&key_path)
+                .with_context(|| format!("Failed to read {}", key_path.display()))?;
+            let key_pair = KeyPair::from_pem(&key_pem)
+                .with_context(|| format!("Invalid CA key in {}", key_path.display()))?;
+            let cert_pem = std::fs::read_to_string(&cert_path)
+                .with_context(|| format!("Failed to read {}", cert_path.display()))?;
+            // Signing needs only the CA's name and key, which match the
+            // saved certificate
+            (
+                Certificate::from_params(ca_params(Some(key_pair)))?,
+                cert_pem,
+            )
+        } else {
+            let ca = Certificate::from_params(ca_params(None))?;
+            let cert_pem = ca.serialize_pem()?;
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            std::fs::write(&cert_path, &cert_pem)
+                .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+            write_private(&key_path, &ca.serialize_private_key_pem())
+                .with_context(|| format!("Failed to write {}", key_path.display()))?;
+            (ca, cert_pem)
+        };
+
+        Ok(Self {
+            ca,
+            cert_pem,
+            cert_path,
+            leaves: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The CA certificate, for installing in clients.
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    pub fn cert_path(&self) -> &Path {
+        &self.cert_path
+    }
+
+    /// A TLS server config presenting a certificate for the SNI name, or for
+    /// `default_host` when the client sends none.
+    pub fn server_config(self: &Arc<Self>, default_host: &str) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(HostResolver {
+                ca: Arc::clone(self),
+                default_host: default_host.to_string(),
+            }));
+        // The intercepted connection is served over HTTP/1
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Arc::new(config)
+    }
+
+    /// A certificate for `host` signed by the CA, issued once per host.
+    pub fn certified_key(&self, host: &str) -> Result<Arc<CertifiedKey>> {
+        if let Some(key) = self.leaves.lock().get(host) {
+            return Ok(Arc::clone(key));
+        }
+
+        let mut params = CertificateParams::default();
+        params.subject_alt_names = vec![match host.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(host.to_string()),
+        }];
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, host);
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        // Clients reject leaf certificates valid for much over a year
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + Duration::days(365);
+
+        let leaf = Certificate::from_params(params)?;
+        let cert = rustls::Certificate(leaf.serialize_der_with_signer(&self.ca)?);
+        let key = rustls::sign::any_supported_type(&rustls::PrivateKey(
+            leaf.serialize_private_key_der(),
+        ))?;
+        let certified = Arc::new(CertifiedKey::new(vec![cert], key));
+
+        self.leaves
+            .lock()
+            .insert(host.to_string(), Arc::clone(&certified));
+        Ok(certified)
+    }
+}
+
+#[cfg(feature = "tls")]
+fn ca_params(key_pair: Option<KeyPair>) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "debug-proxy CA");
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "debug-proxy");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(3650);
+    params.key_pair = key_pair;
+    params
+}
+
+/// Writes the CA key readable by the owner only.
+#[cfg(feature = "tls")]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+#[cfg(feature = "tls")]
+struct HostResolver {
+    ca: Arc<CertificateAuthority>,
+    default_host: String,
+}
+
+#[cfg(feature = "tls")]
+impl ResolvesServerCert for HostResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let host = client_hello.server_name().unwrap_or(&self.default_host);
+        match self.ca.certified_key(host) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                tracing::error!("Failed to issue a certificate for {}: {}", host, e);
+                None
+            }
+        }
+    }
+}
+
+/// The default directory for the CA, `~/.debug-proxy`.
+#[cfg(feature = "tls")]
+pub fn default_ca_dir() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".debug-proxy")
+}
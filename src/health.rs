@@ -0,0 +1,130 @@
+use http::Uri;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+use crate::process::ProcessStatus;
+use crate::services::ServiceStatus;
+use crate::upstream::upstream_base_url;
+
+/// How long `/_proxy/readyz` waits to connect to an upstream.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Body of `GET /_proxy/healthz`: the proxy is up and answering.
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub status: &'static str,
+    pub version: &'static str,
+    pub pid: u32,
+}
+
+impl Health {
+    pub fn current() -> Self {
+        Self {
+            status: "ok",
+            version: env!("CARGO_PKG_VERSION"),
+            pid: std::process::id(),
+        }
+    }
+}
+
+/// Body of `GET /_proxy/readyz`. Ready when an upstream accepts
+/// connections and every managed command is running, and each service with
+/// a readiness check passes it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// The default upstream, or each balanced instance; one reachable is
+    /// enough.
+    pub upstreams: Vec<UpstreamCheck>,
+    /// `None` without a managed command.
+    pub process: Option<ProcessCheck>,
+    pub services: Vec<ServiceCheck>,
+}
+
+impl Readiness {
+    pub fn new(
+        upstreams: Vec<UpstreamCheck>,
+        process: Option<ProcessStatus>,
+        services: Vec<ServiceStatus>,
+    ) -> Self {
+        let process = process.map(|status| ProcessCheck {
+            running: status.running,
+            pid: status.pid,
+        });
+        let services: Vec<ServiceCheck> = services
+            .into_iter()
+            .map(|status| ServiceCheck {
+                name: status.name,
+                running: status.process.running,
+                ready: status.ready,
+            })
+            .collect();
+        let ready = upstreams.iter().any(|check| check.reachable)
+            && process.as_ref().is_none_or(|check| check.running)
+            && services
+                .iter()
+                .all(|check| check.running && check.ready != Some(false));
+        Self {
+            ready,
+            upstreams,
+            process,
+            services,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamCheck {
+    pub upstream: String,
+    pub reachable: bool,
+    /// Time to connect, when it did.
+    pub connect_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessCheck {
+    pub running: bool,
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceCheck {
+    pub name: String,
+    pub running: bool,
+    /// `None` when the service has no readiness check.
+    pub ready: Option<bool>,
+}
+
+/// Opens and closes a TCP connection to `upstream`, which is enough to know
+/// something listens there without sending it a request.
+pub async fn check_upstream(upstream: &str, timeout: Duration) -> UpstreamCheck {
+    let started = Instant::now();
+    let result = match upstream_base_url(upstream).parse::<Uri>() {
+        Ok(uri) => {
+            let host = uri
+                .host()
+                .unwrap_or_default()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string();
+            let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                Some("https") => 443,
+                _ => 80,
+            });
+            match tokio::time::timeout(timeout, TcpStream::connect((host.as_str(), port))).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err(format!("No connection within {timeout:?}")),
+            }
+        }
+        Err(e) => Err(format!("Invalid upstream: {e}")),
+    };
+    UpstreamCheck {
+        upstream: upstream.to_string(),
+        reachable: result.is_ok(),
+        connect_ms: result.is_ok().then(|| started.elapsed().as_millis() as u64),
+        error: result.err(),
+    }
+}
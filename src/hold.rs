@@ -0,0 +1,228 @@
+use anyhow::{bail, Context};
+use http::Method;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use crate::bench::parse_duration;
+use crate::recorder::now_ms;
+use crate::route::PathTemplate;
+
+/// Holds matching requests back from the upstream, written as
+/// `[METHOD ]PATH[@DELAY][ open]`:
+///
+/// ```text
+/// POST /hooks/*@5s        answer 202 now, deliver after 5s
+/// POST /hooks/stripe      answer 202 now, deliver when released
+/// /api/orders@2s open     keep the client waiting until delivered
+/// ```
+///
+/// Without a delay a request waits until it is released from the admin API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HoldRule {
+    source: String,
+    method: Option<Method>,
+    path: String,
+    delay: Option<Duration>,
+    /// Keep the client's request open instead of answering `202`.
+    open: bool,
+}
+
+impl FromStr for HoldRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut words: Vec<&str> = s.split_whitespace().collect();
+        let open = words.last() == Some(&"open");
+        if open {
+            words.pop();
+        }
+        let (method, target) = match words[..] {
+            [target] => (None, target),
+            [method, target] => (
+                Some(
+                    Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                        .with_context(|| format!("Invalid method: {method}"))?,
+                ),
+                target,
+            ),
+            _ => bail!("Expected [METHOD ]PATH[@DELAY][ open], got {s}"),
+        };
+        let (path, delay) = match target.split_once('@') {
+            Some((path, delay)) => (path, Some(parse_duration(delay)?)),
+            None => (target, None),
+        };
+        if !path.starts_with('/') {
+            bail!("Hold path must start with '/', got {path}");
+        }
+        Ok(Self {
+            source: s.trim().to_string(),
+            method,
+            path: path.to_string(),
+            delay,
+            open,
+        })
+    }
+}
+
+impl fmt::Display for HoldRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl HoldRule {
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        self.method.as_ref().is_none_or(|m| m == method)
+            && PathTemplate::parse(&self.path).matches(path).is_some()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+/// A request waiting in the hold queue.
+#[derive(Debug, Clone, Serialize)]
+pub struct HeldRequest {
+    /// The id of the request's transaction.
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    pub rule: String,
+    /// When it was queued, in milliseconds since the epoch.
+    pub queued_at: u64,
+    /// When the delay runs out; `None` until released from the admin API.
+    pub release_at: Option<u64>,
+    /// The client is still waiting for the upstream's response.
+    pub open: bool,
+}
+
+/// How a held request got out of the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Release {
+    Delay,
+    Admin,
+}
+
+impl Release {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Delay => "delay",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+struct Entry {
+    request: HeldRequest,
+    release: oneshot::Sender<()>,
+}
+
+/// The hold rules and the requests waiting on them, in the order they
+/// arrived.
+#[derive(Clone, Default)]
+pub struct HoldQueue {
+    rules: Arc<Vec<HoldRule>>,
+    held: Arc<Mutex<Vec<Entry>>>,
+}
+
+impl HoldQueue {
+    pub fn new(rules: Vec<HoldRule>) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            held: Arc::default(),
+        }
+    }
+
+    pub fn rules(&self) -> &[HoldRule] {
+        &self.rules
+    }
+
+    /// The first rule holding `method` and `path`.
+    pub fn rule_for(&self, method: &Method, path: &str) -> Option<&HoldRule> {
+        self.rules.iter().find(|rule| rule.matches(method, path))
+    }
+
+    /// Queues the request with transaction `id` and waits until its delay
+    /// runs out or it is released.
+    pub async fn hold(&self, id: &str, method: &Method, path: &str, rule: &HoldRule) -> Release {
+        let queued_at = now_ms();
+        let (release, released) = oneshot::channel();
+        self.held.lock().push(Entry {
+            request: HeldRequest {
+                id: id.to_string(),
+                method: method.to_string(),
+                path: path.to_string(),
+                rule: rule.to_string(),
+                queued_at,
+                release_at: rule.delay.map(|delay| queued_at + delay.as_millis() as u64),
+                open: rule.open,
+            },
+            release,
+        });
+
+        // Also leaves the queue when the client goes away while waiting
+        let _queued = Queued { queue: self, id };
+        let deadline = rule.delay.map(|delay| Instant::now() + delay);
+        match deadline {
+            Some(deadline) => tokio::select! {
+                _ = released => Release::Admin,
+                _ = tokio::time::sleep_until(deadline.into()) => Release::Delay,
+            },
+            None => match released.await {
+                Ok(()) => Release::Admin,
+                // Dropped without a release, which nothing does today
+                Err(_) => Release::Delay,
+            },
+        }
+    }
+
+    /// Held requests, oldest first.
+    pub fn list(&self) -> Vec<HeldRequest> {
+        self.held
+            .lock()
+            .iter()
+            .map(|entry| entry.request.clone())
+            .collect()
+    }
+
+    /// Releases the held request with transaction `id`, returning whether
+    /// it was waiting.
+    pub fn release(&self, id: &str) -> bool {
+        let mut held = self.held.lock();
+        let Some(index) = held.iter().position(|entry| entry.request.id == id) else {
+            return false;
+        };
+        let _ = held.remove(index).release.send(());
+        true
+    }
+
+    /// Releases every held request, returning how many there were.
+    pub fn release_all(&self) -> usize {
+        let entries: Vec<Entry> = self.held.lock().drain(..).collect();
+        let count = entries.len();
+        for entry in entries {
+            let _ = entry.release.send(());
+        }
+        count
+    }
+}
+
+struct Queued<'a> {
+    queue: &'a HoldQueue,
+    id: &'a str,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.queue
+            .held
+            .lock()
+            .retain(|entry| entry.request.id != self.id);
+    }
+}
@@ -0,0 +1,56 @@
+use std::str::FromStr;
+
+/// Accepts webhook deliveries without an upstream: requests under the
+/// prefix are answered `200` and recorded, so they can be inspected and
+/// replayed to the real upstream later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inbox {
+    prefix: String,
+    /// Also captures paths no service or upstream would take.
+    catch_all: bool,
+}
+
+impl FromStr for Inbox {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let prefix = s.trim().trim_end_matches('/');
+        if !s.trim().starts_with('/') {
+            anyhow::bail!("Expected a path prefix starting with '/'");
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+            catch_all: false,
+        })
+    }
+}
+
+impl Inbox {
+    /// Captures every path that has nowhere else to go, for running
+    /// without an upstream.
+    pub fn with_catch_all(mut self) -> Self {
+        self.catch_all = true;
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        if self.prefix.is_empty() {
+            "/"
+        } else {
+            &self.prefix
+        }
+    }
+
+    pub fn is_catch_all(&self) -> bool {
+        self.catch_all
+    }
+
+    /// Whether a request for `path` is captured. `routed` says whether a
+    /// service or upstream would take it otherwise.
+    pub fn captures(&self, path: &str, routed: bool) -> bool {
+        let under_prefix = path
+            .strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        under_prefix || (self.catch_all && !routed)
+    }
+}
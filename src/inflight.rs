@@ -0,0 +1,153 @@
+use http::StatusCode;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+/// Where a proxied request is waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InFlightState {
+    /// Sent upstream, no response headers yet.
+    AwaitingUpstream,
+    /// Response headers arrived; the body is still coming.
+    StreamingResponse,
+}
+
+/// A request being proxied right now.
+#[derive(Debug, Clone, Serialize)]
+pub struct InFlightRequest {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    pub client_addr: String,
+    pub upstream: String,
+    /// When the request arrived, in milliseconds since the epoch.
+    pub started_at: u64,
+    pub elapsed_ms: u64,
+    pub state: InFlightState,
+}
+
+struct Entry {
+    method: String,
+    path: String,
+    client_addr: String,
+    upstream: String,
+    started: Instant,
+    started_at: u64,
+    state: InFlightState,
+    /// Taken by the first cancellation.
+    cancel: Option<oneshot::Sender<StatusCode>>,
+}
+
+/// Requests between being sent upstream and their response being ready.
+#[derive(Clone, Default)]
+pub struct InFlightRequests {
+    requests: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl InFlightRequests {
+    /// Lists the request until the returned guard is dropped. `started`
+    /// is when it arrived, to count elapsed time from.
+    pub fn track(
+        &self,
+        id: &str,
+        method: &str,
+        path: &str,
+        client_addr: &str,
+        upstream: &str,
+        started: Instant,
+    ) -> InFlightGuard {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .saturating_sub(started.elapsed())
+            .as_millis() as u64;
+        let (cancel, cancelled) = oneshot::channel();
+        self.requests.lock().insert(
+            id.to_string(),
+            Entry {
+                method: method.to_string(),
+                path: path.to_string(),
+                client_addr: client_addr.to_string(),
+                upstream: upstream.to_string(),
+                started,
+                started_at,
+                state: InFlightState::AwaitingUpstream,
+                cancel: Some(cancel),
+            },
+        );
+        InFlightGuard {
+            requests: self.clone(),
+            id: id.to_string(),
+            cancelled,
+        }
+    }
+
+    /// Asks the request to give up on the upstream and answer its client
+    /// with `status`. Returns false when it is not in flight or was already
+    /// cancelled.
+    pub fn cancel(&self, id: &str, status: StatusCode) -> bool {
+        let cancel = self
+            .requests
+            .lock()
+            .get_mut(id)
+            .and_then(|entry| entry.cancel.take());
+        cancel.is_some_and(|cancel| cancel.send(status).is_ok())
+    }
+
+    /// The requests in flight, longest running first.
+    pub fn list(&self) -> Vec<InFlightRequest> {
+        let mut requests: Vec<InFlightRequest> = self
+            .requests
+            .lock()
+            .iter()
+            .map(|(id, entry)| InFlightRequest {
+                id: id.clone(),
+                method: entry.method.clone(),
+                path: entry.path.clone(),
+                client_addr: entry.client_addr.clone(),
+                upstream: entry.upstream.clone(),
+                started_at: entry.started_at,
+                elapsed_ms: entry.started.elapsed().as_millis() as u64,
+                state: entry.state,
+            })
+            .collect();
+        requests.sort_by_key(|request| std::cmp::Reverse(request.elapsed_ms));
+        requests
+    }
+}
+
+/// Keeps a request listed as in flight; dropping it, however the request
+/// ends, takes it off.
+pub struct InFlightGuard {
+    requests: InFlightRequests,
+    id: String,
+    cancelled: oneshot::Receiver<StatusCode>,
+}
+
+impl InFlightGuard {
+    pub fn set_state(&self, state: InFlightState) {
+        if let Some(entry) = self.requests.requests.lock().get_mut(&self.id) {
+            entry.state = state;
+        }
+    }
+
+    /// Resolves with the status to answer with once the request is
+    /// cancelled, for racing against the upstream.
+    pub async fn cancelled(&mut self) -> StatusCode {
+        match (&mut self.cancelled).await {
+            Ok(status) => status,
+            // The entry keeps the sender while the guard lives
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.requests.requests.lock().remove(&self.id);
+    }
+}
@@ -1,12 +1,65 @@
+pub mod admin_ui;
+pub mod alerts;
+pub mod assertions;
+pub mod audit;
+pub mod auth;
+pub mod balancer;
+pub mod baseline;
+pub mod bench;
+pub mod body_size;
+pub mod cache;
+#[cfg(feature = "decoders")]
+pub mod compression;
 pub mod config;
+pub mod cors;
+pub mod daemon;
+pub mod datetime;
+pub mod diff;
+pub mod endpoints;
+pub mod events;
+pub mod export;
+pub mod forward;
+pub mod health;
+pub mod hold;
+pub mod inbox;
+pub mod inflight;
+pub mod memory;
+pub mod mock;
+pub mod openapi;
+pub mod outbound;
+pub mod probe;
 pub mod process;
 pub mod proxy;
+pub mod public_tunnel;
 pub mod recorder;
+pub mod route;
+pub mod safe_mode;
+pub mod schema;
+pub mod script;
+pub mod search;
+pub mod services;
+pub mod snapshot;
+pub mod tcp;
+pub mod template;
+pub mod test_support;
+pub mod timeline;
+pub mod transform;
+pub mod tunnel;
+pub mod udp;
+pub mod upstream;
+pub mod usage;
+pub mod version;
+pub mod watch;
 
-pub use config::{ConfigUpdate, ProxyConfig, SharedConfig};
-pub use process::ProcessManager;
-pub use proxy::DebugProxy;
+pub use assertions::{SchemaAssertion, SchemaAssertionSet, SchemaAssertions};
+pub use baseline::BaselineStore;
+pub use config::{ConfigUpdate, Decompression, ProxyConfig, SharedConfig};
+pub use openapi::OpenApiSpec;
+pub use process::{ProcessLogs, ProcessManager, RestartPolicy};
+pub use proxy::{DebugProxy, ProxyService};
 pub use recorder::{
-    BodyRecord, HttpTransaction, RequestInfo, RequestRecord, RequestRecorder, ResponseInfo,
-    ResponseRecord,
+    BodyRecord, BodyText, CertificateInfo, ClientAbort, HoldRecord, HttpTransaction, ProxyOverhead,
+    RequestInfo, RequestRecord, RequestRecorder, ResponseInfo, ResponseRecord, TunnelRecord,
+    UpstreamConnection, UpstreamTls, Violation,
 };
+pub use services::Services;
@@ -1,29 +1,167 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use tracing::{error, info, warn};
+use tracing::{error, info};
 
+mod admin_ui;
+mod alerts;
+mod assertions;
+mod audit;
+mod auth;
+mod balancer;
+mod baseline;
+mod bench;
+mod body_size;
+mod cache;
+#[cfg(feature = "decoders")]
+mod compression;
 mod config;
+mod cors;
+mod daemon;
+mod datetime;
+mod diff;
+mod endpoints;
+mod events;
+mod export;
+mod forward;
+mod health;
+mod hold;
+mod inbox;
+mod inflight;
+mod memory;
+mod mock;
+mod openapi;
+mod outbound;
+mod probe;
 mod process;
 mod proxy;
+mod public_tunnel;
 mod recorder;
+mod route;
+mod safe_mode;
+mod schema;
+mod script;
+mod search;
+mod services;
+mod snapshot;
+mod tcp;
+mod template;
+mod timeline;
+mod transform;
+mod tunnel;
+mod udp;
+mod upstream;
+mod usage;
+mod version;
+mod watch;
 
-use config::{ProxyConfig, SharedConfig};
-use process::ProcessManager;
+use assertions::SchemaAssertions;
+use baseline::BaselineStore;
+use bench::BenchOptions;
+use config::{ProxyConfig, RouteOverride, SharedConfig};
+use hold::{HoldQueue, HoldRule};
+use inbox::Inbox;
+use openapi::OpenApiSpec;
+use outbound::OutboundProxy;
+use process::{ProcessManager, RestartPolicy};
 use proxy::DebugProxy;
+use public_tunnel::{PublicTunnel, TunnelProvider};
 use recorder::RequestRecorder;
+use script::RouteScript;
+use services::Services;
+use transform::{ResponseTransforms, TransformRule};
+use upstream::{ClientIdentity, ResolveOverride, UpstreamCa, UpstreamHeader};
+use watch::{FileWatcher, WatchOptions};
 
 #[derive(Parser)]
 #[command(name = "debug-proxy")]
 #[command(about = "HTTP debugging reverse proxy with timeout handling")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(help = "Upstream target in format host:port (e.g., 192.168.1.1:3000, localhost:3000)")]
-    upstream: String,
+    #[command(subcommand)]
+    subcommand: Option<Commands>,
 
-    #[arg(short, long, default_value = "8080", help = "Local port to listen on")]
-    port: u16,
+    #[arg(
+        required_unless_present_any = ["upstream_port_env", "services", "inbox"],
+        help = "Upstream target: host:port, [ipv6]:port, a bare host (port 80) or an http:// or https:// URL"
+    )]
+    upstream: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Local port to listen on (default: 8080); with --listen, also listen on it"
+    )]
+    port: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "ADDR:PORT",
+        help = "Listen on this address as well as or instead of --host/--port, e.g. [::1]:8080; repeatable"
+    )]
+    listen: Vec<std::net::SocketAddr>,
 
-    #[arg(long, default_value = "0.0.0.0", help = "Host address to bind to")]
+    #[arg(
+        long,
+        help = "If a listen port is in use, listen on the next free port above it instead"
+    )]
+    port_auto: bool,
+
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Also serve the admin API on 127.0.0.1:PORT without the token, for scripts on this machine"
+    )]
+    admin_port: Option<u16>,
+
+    #[arg(long, help = "Open the admin UI in the default browser once listening")]
+    open: bool,
+
+    #[arg(
+        long,
+        help = "Print the listen addresses, admin URL, token, upstream and child PID as one line of JSON on stdout instead of the banner; logs go to stderr"
+    )]
+    print_startup_json: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the proxy's PID to FILE while it runs, for debug-proxy stop and status"
+    )]
+    pidfile: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Print a QR code of the admin UI URL for opening it from a phone on the same LAN"
+    )]
+    qr: bool,
+
+    #[arg(
+        long,
+        help = "Also act as an HTTP forward proxy: absolute-form requests and CONNECT tunnels reach any host"
+    )]
+    forward_proxy: bool,
+
+    #[arg(
+        long,
+        requires = "forward_proxy",
+        help = "Decrypt and record HTTPS in CONNECT tunnels, using a generated CA that clients must trust"
+    )]
+    mitm: bool,
+
+    #[arg(
+        long,
+        requires = "mitm",
+        help = "Directory holding the --mitm CA certificate and key (default: ~/.debug-proxy)"
+    )]
+    ca_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "0.0.0.0",
+        help = "Host address to bind to; :: listens on both IPv6 and IPv4"
+    )]
     host: String,
 
     #[arg(
@@ -38,7 +176,7 @@ struct Args {
         short,
         long,
         default_value = "30000",
-        help = "Client timeout in milliseconds"
+        help = "Milliseconds a client may take to send its request body"
     )]
     client_timeout: u64,
 
@@ -50,9 +188,508 @@ struct Args {
     )]
     max_history: usize,
 
+    #[arg(
+        long,
+        value_name = "MB",
+        help = "Record less as the proxy's memory nears MB: shorter bodies from 70%, no bodies from 85%, then pause recording"
+    )]
+    memory_limit: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "UTC",
+        value_name = "UTC|local|+HH:MM",
+        help = "Timezone of the RFC 3339 times in recorded transactions and exports"
+    )]
+    timezone: datetime::UtcOffset,
+
+    #[arg(
+        long,
+        value_name = "ngrok|cloudflared|COMMAND",
+        help = "Expose the proxy publicly through ngrok, a cloudflared quick tunnel or a command that prints the public URL ({port} is replaced by the proxy's port)"
+    )]
+    tunnel: Option<TunnelProvider>,
+
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        num_args = 0..=1,
+        default_missing_value = "/hooks",
+        help = "Answer requests under PREFIX (default /hooks) with 200 and record them without an upstream, to inspect and later replay webhook deliveries; with no upstream, every request is captured"
+    )]
+    inbox: Option<Inbox>,
+
+    #[arg(
+        long = "hold",
+        value_name = "RULE",
+        help = "Hold matching requests back from the upstream, written as '[METHOD ]PATH[@DELAY][ open]', e.g. 'POST /hooks/*@5s'. Clients get 202 and the request is delivered after the delay, or when released from the admin API without one; 'open' keeps the client waiting instead (repeatable)"
+    )]
+    hold: Vec<HoldRule>,
+
     #[arg(long, default_value = "1024", help = "Body truncation size in bytes")]
     truncate_body: usize,
 
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "Truncation size for request bodies, instead of --truncate-body"
+    )]
+    truncate_request: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "Truncation size for response bodies, instead of --truncate-body"
+    )]
+    truncate_response: Option<usize>,
+
+    #[arg(
+        long = "truncate-type",
+        value_name = "TYPE=BYTES",
+        value_parser = config::parse_content_type_limit,
+        help = "Truncation size for bodies of a content type, e.g. image/*=0 (repeatable)"
+    )]
+    truncate_types: Vec<(String, usize)>,
+
+    #[arg(
+        long = "text-type",
+        value_name = "TYPE",
+        help = "Content type to always record as text, e.g. application/x-ndjson (repeatable)"
+    )]
+    text_types: Vec<String>,
+
+    #[arg(
+        long = "binary-type",
+        value_name = "TYPE",
+        help = "Content type to always record as binary, e.g. application/x-protobuf (repeatable)"
+    )]
+    binary_types: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "32",
+        help = "Idle upstream connections kept per host for reuse"
+    )]
+    pool_max_idle: usize,
+
+    #[arg(
+        long,
+        default_value = "90000",
+        help = "Milliseconds an idle upstream connection is kept open"
+    )]
+    pool_idle_timeout: u64,
+
+    #[arg(long, help = "Open a new upstream connection for every request")]
+    no_keep_alive: bool,
+
+    #[arg(
+        long,
+        help = "Speak HTTP/2 to the upstream (h2c over plain HTTP, h2 over TLS), as gRPC servers need"
+    )]
+    upstream_http2: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "upstream_client_key",
+        help = "PEM client certificate presented to upstreams that require mutual TLS"
+    )]
+    upstream_client_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "upstream_client_cert",
+        help = "PEM private key for --upstream-client-cert"
+    )]
+    upstream_client_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "[HOST=]FILE",
+        help = "PEM CA bundle trusted for HTTPS upstream certificates besides the system roots, for every upstream or only HOST; repeatable"
+    )]
+    upstream_ca: Vec<UpstreamCa>,
+
+    #[arg(
+        long,
+        value_name = "USER:PASSWORD",
+        value_parser = UpstreamHeader::basic_auth,
+        help = "Basic auth credentials sent to the upstream with every request"
+    )]
+    upstream_auth: Option<UpstreamHeader>,
+
+    #[arg(
+        long = "upstream-header",
+        value_name = "'NAME: VALUE'",
+        help = "Header set on every request to the upstream, e.g. 'X-Api-Key: secret'; repeatable"
+    )]
+    upstream_headers: Vec<UpstreamHeader>,
+
+    #[arg(
+        long,
+        value_name = "HOST:PORT:ADDR",
+        help = "Connect to ADDR for upstream requests to HOST:PORT, like curl --resolve; repeatable"
+    )]
+    resolve: Vec<ResolveOverride>,
+
+    #[arg(
+        long,
+        value_name = "[METHOD ]PATH@INTERVAL",
+        help = "Send a request through the proxy on a schedule and record it like traffic, such as /health@5s or \"POST /api/ping@500ms\"; repeatable"
+    )]
+    probe: Vec<probe::Probe>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Milliseconds to cache resolved upstream addresses; 0 resolves for every new connection"
+    )]
+    dns_ttl: u64,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Connect to the upstream through this proxy (socks5://host:port or http://host:port); defaults to HTTP_PROXY or ALL_PROXY"
+    )]
+    outbound_proxy: Option<String>,
+
+    #[arg(
+        long,
+        requires = "split",
+        help = "Alternate upstream that receives --split percent of the requests"
+    )]
+    upstream_b: Option<String>,
+
+    #[arg(
+        long,
+        requires = "upstream_b",
+        value_name = "PERCENT",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "Percentage of requests sent to --upstream-b; adjustable through /_proxy/api/config"
+    )]
+    split: Option<u8>,
+
+    #[arg(
+        long = "instance",
+        value_name = "HOST:PORT",
+        help = "Another instance of the upstream; requests are balanced round-robin across UPSTREAM and every --instance; repeatable"
+    )]
+    instances: Vec<String>,
+
+    #[arg(
+        long,
+        requires = "instances",
+        value_name = "cookie|ip",
+        help = "Keep each client on one instance, by a cookie set on the first response or by client IP"
+    )]
+    sticky: Option<balancer::Stickiness>,
+
+    #[arg(
+        long,
+        requires = "instances",
+        value_name = "tcp|PATH",
+        help = "Probe every instance by opening a TCP connection or requesting PATH, and route only to healthy ones"
+    )]
+    health_check: Option<balancer::HealthCheck>,
+
+    #[arg(
+        long,
+        requires = "health_check",
+        default_value = "2000",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Milliseconds between health probes"
+    )]
+    health_interval: u64,
+
+    #[arg(
+        long,
+        requires = "health_check",
+        default_value = "2",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Consecutive probes that must fail, or pass, before an instance is marked unhealthy, or healthy again"
+    )]
+    health_threshold: u32,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Rhai script that picks the upstream per request from `request.method`, `path`, `query`, `headers` and `client_addr`; returning () keeps the default, and failures fall back to it"
+    )]
+    route_script: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Add X-Debug-Proxy-Id and a Server-Timing breakdown (queue, upstream, proxy) to proxied responses"
+    )]
+    debug_headers: bool,
+
+    #[arg(
+        long,
+        help = "Strip If-None-Match/If-Modified-Since from requests and caching headers from responses, so every response is a full 200"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        default_value = "off",
+        value_name = "off|identity|decode",
+        help = "Keep compressed responses away from clients and the recorder: identity asks the upstream for identity encoding, decode decompresses gzip and deflate responses"
+    )]
+    decompression: config::Decompression,
+
+    #[arg(
+        long,
+        default_value = "1000",
+        help = "Milliseconds within which identical requests (method, path, body) are flagged as duplicates; 0 turns this off"
+    )]
+    duplicate_window: u64,
+
+    #[arg(
+        long,
+        default_value = "off",
+        value_name = "off|strict|passthrough",
+        help = "Answer requests from recorded responses to the same method and path instead of the upstream; requests with no recording get 503 (strict) or go upstream (passthrough)"
+    )]
+    offline: mock::OfflineMode,
+
+    #[arg(
+        long,
+        value_name = "permissive|ORIGINS",
+        help = "Answer CORS preflights and allow cross-origin calls to the upstream: from any origin, or from a comma-separated list such as http://localhost:5173"
+    )]
+    cors: Option<cors::CorsPolicy>,
+
+    #[arg(
+        long,
+        default_value = "show",
+        value_name = "show|collapse|hide",
+        help = "How CORS preflights appear in the admin UI's request list; they are still recorded and counted in stats"
+    )]
+    preflights: recorder::PreflightView,
+
+    #[arg(
+        long,
+        help = "Safe mode: answer POST, PUT, PATCH and DELETE with 403 instead of sending them upstream"
+    )]
+    safe: bool,
+
+    #[arg(
+        long,
+        requires = "safe",
+        value_name = "PATH",
+        help = "Path pattern, such as /graphql, where --safe still lets writes through; repeatable"
+    )]
+    safe_allow: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Path pattern, such as /admin/* or /users/{id}, answered with 403 for every method; repeatable"
+    )]
+    block_path: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "File (YAML or JSON) of per-route upstream timeout, client timeout and body truncation overrides"
+    )]
+    routes: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Raise an alert when more than N upstream timeouts happen in a minute"
+    )]
+    alert_timeouts: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        help = "Raise an alert when more than PERCENT of the last minute's requests fail or get a 5xx"
+    )]
+    alert_error_rate: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "URL to POST to whenever the alert is raised or cleared"
+    )]
+    alert_webhook: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SPEC",
+        help = "OpenAPI spec (YAML or JSON) to validate proxied traffic against"
+    )]
+    openapi: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Per-route JSON Schema assertions (YAML or JSON) for request/response bodies"
+    )]
+    schema_assertions: Option<PathBuf>,
+
+    #[arg(long, help = "Answer 502 to the client when a schema assertion fails")]
+    strict_schemas: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Response transforms (YAML or JSON with a `rules` list) to apply to upstream responses"
+    )]
+    transforms: Option<PathBuf>,
+
+    #[arg(
+        long = "transform",
+        value_name = "RULE",
+        help = "Response transform, e.g. 'response.body.json.user.email = \"test@example.com\" when path == \"/api/me\"' (repeatable)"
+    )]
+    transform: Vec<TransformRule>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Baseline file to compare traffic against; captured baselines are saved here"
+    )]
+    baseline: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Snapshot saved through /_proxy/api/snapshot to load into the history at startup"
+    )]
+    load_snapshot: Option<PathBuf>,
+
+    #[arg(
+        long = "env",
+        value_name = "KEY=VALUE",
+        value_parser = process::parse_env_var,
+        help = "Environment variable for the upstream command (repeatable)"
+    )]
+    env: Vec<(String, String)>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Load environment variables for the upstream command from a .env file"
+    )]
+    env_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Working directory for the upstream command"
+    )]
+    cwd: Option<PathBuf>,
+
+    #[arg(long, help = "Do not restart the upstream command when it exits")]
+    no_restart: bool,
+
+    #[arg(
+        long,
+        default_value = "5",
+        help = "Consecutive crashes before giving up on the upstream command (0 = never)"
+    )]
+    max_restarts: u32,
+
+    #[arg(
+        long,
+        default_value = "1000",
+        help = "Initial restart delay in milliseconds, doubled after each crash"
+    )]
+    restart_backoff: u64,
+
+    #[arg(
+        long,
+        default_value = "30000",
+        help = "Maximum restart delay in milliseconds"
+    )]
+    max_restart_backoff: u64,
+
+    #[arg(
+        long,
+        default_value = "5000",
+        help = "Milliseconds to wait after SIGTERM (CTRL_BREAK on Windows) before killing the upstream command"
+    )]
+    kill_timeout: u64,
+
+    #[arg(
+        long,
+        requires = "command",
+        help = "Exit with the upstream command's exit code when it terminates, instead of restarting it"
+    )]
+    exit_on_child_exit: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Save the recorded traffic here on exit: HAR for .har files, JSON Lines otherwise"
+    )]
+    save_traffic: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        requires = "command",
+        help = "Shell command to run before every start of the upstream command, e.g. migrations (repeatable)"
+    )]
+    pre_start: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        requires = "command",
+        help = "Shell command to run after the upstream command is stopped (repeatable)"
+    )]
+    post_stop: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "VAR",
+        conflicts_with = "upstream",
+        requires = "command",
+        help = "Pick a free port for the upstream command, pass it in this environment variable and in {port} arguments, and proxy to it"
+    )]
+    upstream_port_env: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "command",
+        help = "Restart the upstream command when files under this path change (repeatable)"
+    )]
+    watch: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Changed paths matching this glob do not trigger a restart (repeatable)"
+    )]
+    watch_ignore: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "300",
+        help = "Milliseconds without further changes before restarting"
+    )]
+    watch_debounce: u64,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Managed services (YAML or JSON), each proxied under its own route prefix"
+    )]
+    services: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "30000",
+        help = "Milliseconds to wait at startup for services to pass their readiness checks"
+    )]
+    services_ready_timeout: u64,
+
     #[arg(
         last = true,
         help = "Command to run as upstream service (use -- before command)"
@@ -60,124 +697,974 @@ struct Args {
     command: Vec<String>,
 }
 
+#[derive(Subcommand)]
+enum Commands {
+    /// Replay recorded requests against the upstream under load
+    Bench(BenchArgs),
+    /// Serve recorded responses as an offline mock of the upstream
+    Mock(MockArgs),
+    /// Run the proxy with a pidfile, in the background with --daemon
+    Start(StartArgs),
+    /// Stop a proxy started with a pidfile, and its upstream command
+    Stop(StopArgs),
+    /// Report whether the proxy in a pidfile is running
+    Status(StatusArgs),
+    /// Forward plain TCP connections, such as a database's, logging bytes and timing
+    Tcp(TcpArgs),
+    /// Relay UDP datagrams, logging counts, sizes and samples per client
+    Udp(UdpArgs),
+}
+
+#[derive(clap::Args)]
+struct UdpArgs {
+    #[arg(help = "Local port to listen on")]
+    port: u16,
+
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        help = "Where to relay datagrams (e.g., localhost:53)"
+    )]
+    upstream: String,
+
+    #[arg(
+        long,
+        default_value = "0.0.0.0",
+        help = "Host address to bind to, e.g. :: for IPv6"
+    )]
+    host: String,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_name = "BYTES",
+        help = "Log a hex dump of the first BYTES of each client's first datagrams each way"
+    )]
+    hex_dump: usize,
+
+    #[arg(
+        long,
+        default_value = "30000",
+        help = "Milliseconds without datagrams either way before a client's flow closes"
+    )]
+    idle_timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write every flow's counts and samples here as JSON on exit"
+    )]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct TcpArgs {
+    #[arg(help = "Local port to listen on")]
+    port: u16,
+
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        help = "Where to forward connections (e.g., localhost:5433)"
+    )]
+    upstream: String,
+
+    #[arg(
+        long,
+        default_value = "0.0.0.0",
+        help = "Host address to bind to; :: listens on both IPv6 and IPv4"
+    )]
+    host: String,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_name = "BYTES",
+        help = "Log a hex dump of the first BYTES sent each way on every connection"
+    )]
+    hex_dump: usize,
+
+    #[arg(
+        long,
+        default_value = "5000",
+        help = "Upstream connect timeout in milliseconds"
+    )]
+    connect_timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the last 1000 connection events here as JSON on exit"
+    )]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct StartArgs {
+    #[arg(long, help = "Run in the background, logging to --log-file")]
+    daemon: bool,
+
+    #[arg(long, default_value = "debug-proxy.pid", value_name = "FILE")]
+    pidfile: PathBuf,
+
+    #[arg(
+        long,
+        default_value = "debug-proxy.log",
+        value_name = "FILE",
+        help = "Where a background proxy's output is appended"
+    )]
+    log_file: PathBuf,
+
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required = true,
+        value_name = "PROXY_ARGS",
+        help = "The upstream and options, as for debug-proxy itself"
+    )]
+    proxy_args: Vec<String>,
+}
+
+#[derive(clap::Args)]
+struct StopArgs {
+    #[arg(long, default_value = "debug-proxy.pid", value_name = "FILE")]
+    pidfile: PathBuf,
+
+    #[arg(
+        long,
+        default_value = "30000",
+        help = "Milliseconds to wait for the proxy and its upstream command to exit"
+    )]
+    timeout: u64,
+}
+
+#[derive(clap::Args)]
+struct StatusArgs {
+    #[arg(long, default_value = "debug-proxy.pid", value_name = "FILE")]
+    pidfile: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct MockArgs {
+    #[arg(
+        long,
+        value_name = "FILE",
+        required_unless_present = "rules",
+        help = "Snapshot, saved /_proxy/api/logs response or --save-traffic JSON Lines to serve"
+    )]
+    from: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Mock rules (YAML or JSON with a `rules` list) answered ahead of the recordings, with templated bodies"
+    )]
+    rules: Option<PathBuf>,
+
+    #[arg(short, long, default_value = "8080", help = "Local port to listen on")]
+    port: u16,
+
+    #[arg(
+        long,
+        default_value = "0.0.0.0",
+        help = "Host address to bind to; :: listens on both IPv6 and IPv4"
+    )]
+    host: String,
+
+    #[arg(
+        long,
+        value_name = "UPSTREAM",
+        help = "Send requests without a recorded response here instead of answering 404"
+    )]
+    passthrough: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct BenchArgs {
+    #[arg(help = "Upstream target in format host:port (e.g., 192.168.1.1:3000, localhost:3000)")]
+    upstream: String,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        required_unless_present = "from_har",
+        conflicts_with = "from_har",
+        help = "Saved /_proxy/api/logs response to replay"
+    )]
+    from_history: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE", help = "HAR file to replay")]
+    from_har: Option<PathBuf>,
+
+    #[arg(long, default_value = "10", help = "Number of concurrent workers")]
+    concurrency: usize,
+
+    #[arg(
+        long,
+        default_value = "10s",
+        value_parser = bench::parse_duration,
+        help = "How long to run, e.g. 30s, 500ms, 2m"
+    )]
+    duration: std::time::Duration,
+
+    #[arg(
+        short,
+        long,
+        default_value = "5000",
+        help = "Upstream timeout in milliseconds"
+    )]
+    upstream_timeout: u64,
+
+    #[arg(
+        short,
+        long,
+        default_value = "100",
+        help = "Number of replayed transactions to keep for --output"
+    )]
+    max_history: usize,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the most recent replayed transactions here as JSON"
+    )]
+    output: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+    let args = match Args::parse() {
+        Args {
+            subcommand: Some(Commands::Start(start)),
+            ..
+        } => {
+            // Ahead of the upstream, so it never lands in a trailing command
+            let mut argv = vec![
+                "debug-proxy".to_string(),
+                "--pidfile".to_string(),
+                start.pidfile.display().to_string(),
+            ];
+            argv.extend(start.proxy_args);
+            let args = Args::try_parse_from(&argv).unwrap_or_else(|e| e.exit());
+            if args.subcommand.is_some() {
+                anyhow::bail!("debug-proxy start takes the proxy's upstream and options");
+            }
+            if start.daemon {
+                let pid = daemon::spawn(&argv[1..], &start.pidfile, &start.log_file)?;
+                println!(
+                    "debug-proxy is running in the background with PID {pid}, logging to {}",
+                    start.log_file.display()
+                );
+                return Ok(());
+            }
+            args
+        }
+        args => args,
+    };
+
+    // Initialize tracing, keeping stdout for the startup JSON if asked for
+    let logs = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO);
+    if args.print_startup_json {
+        logs.with_writer(std::io::stderr).init();
+    } else {
+        logs.init();
+    }
+
+    match args.subcommand {
+        Some(Commands::Bench(bench_args)) => return run_bench(bench_args).await,
+        Some(Commands::Mock(mock_args)) => return run_mock(mock_args).await,
+        Some(Commands::Tcp(tcp_args)) => return run_tcp(tcp_args).await,
+        Some(Commands::Udp(udp_args)) => return run_udp(udp_args).await,
+        Some(Commands::Stop(stop_args)) => {
+            let timeout = std::time::Duration::from_millis(stop_args.timeout);
+            let pid = daemon::stop(&stop_args.pidfile, timeout).await?;
+            println!("Stopped debug-proxy (PID {pid})");
+            return Ok(());
+        }
+        Some(Commands::Status(status_args)) => {
+            match daemon::running_pid(&status_args.pidfile)? {
+                Some(pid) => println!("debug-proxy is running with PID {pid}"),
+                None => {
+                    println!("debug-proxy is not running");
+                    exit(3);
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Start(_)) => unreachable!("start runs the proxy above"),
+        None => {}
+    }
+
+    let listen_addrs = listen_addrs(&args)?;
+
+    // Parse upstream target, or pick a port for the managed command
+    let upstream_port = match args.upstream_port_env {
+        Some(_) => Some(process::free_port()?),
+        None => None,
+    };
+    let services = match args.services {
+        Some(ref path) => Services::load(path)?,
+        None => Services::default(),
+    };
+    // Without an explicit upstream, paths no service claims go to the
+    // service mounted at the root
+    let upstream = match (args.upstream, upstream_port) {
+        (Some(upstream), _) => Some(upstream),
+        (None, Some(port)) => Some(format!("127.0.0.1:{port}")),
+        (None, None) => match services.route("/") {
+            Some(service) => Some(service.upstream.clone()),
+            None if args.inbox.is_some() => None,
+            None => anyhow::bail!(
+                "Missing upstream target; give one or define a service with prefix \"/\""
+            ),
+        },
+    };
+    // The inbox takes whatever has no upstream, which is left empty
+    let upstream_addr = match upstream {
+        Some(ref upstream) => parse_upstream_target(upstream)
+            .with_context(|| format!("Invalid upstream target: {upstream}"))?,
+        None => String::new(),
+    };
+    let inbox = args.inbox.clone().map(|inbox| {
+        if upstream.is_none() {
+            inbox.with_catch_all()
+        } else {
+            inbox
+        }
+    });
+
+    let forward_proxy = if args.forward_proxy {
+        #[cfg(feature = "tls")]
+        let mitm = if args.mitm {
+            let dir = args.ca_dir.clone().unwrap_or_else(forward::default_ca_dir);
+            Some(std::sync::Arc::new(
+                forward::CertificateAuthority::load_or_create(&dir)?,
+            ))
+        } else {
+            None
+        };
+        #[cfg(not(feature = "tls"))]
+        if args.mitm {
+            anyhow::bail!("--mitm needs debug-proxy built with the tls feature");
+        }
+        Some(forward::ForwardProxy {
+            #[cfg(feature = "tls")]
+            mitm,
+        })
+    } else {
+        None
+    };
 
-    let args = Args::parse();
+    let instances = args
+        .instances
+        .iter()
+        .map(|instance| {
+            parse_upstream_target(instance)
+                .with_context(|| format!("Invalid upstream target: {instance}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    // Parse upstream target
-    let upstream_addr = parse_upstream_target(&args.upstream).context(
-        "Invalid upstream target format. Use format: host:port (e.g., 192.168.1.1:3000)",
-    )?;
-    let local_port = args.port;
+    let upstream_b = match args.upstream_b {
+        Some(ref upstream) => Some(
+            parse_upstream_target(upstream)
+                .with_context(|| format!("Invalid upstream target: {upstream}"))?,
+        ),
+        None => None,
+    };
 
     // Create configuration
+    let outbound_proxy = match args.outbound_proxy {
+        Some(ref url) => Some(OutboundProxy::parse(url)?),
+        None => OutboundProxy::from_env()?,
+    };
+    let routes = match args.routes {
+        Some(ref path) => RouteOverride::load(path)?,
+        None => Vec::new(),
+    };
+    let upstream_client_identity = match (&args.upstream_client_cert, &args.upstream_client_key) {
+        (Some(cert), Some(key)) => Some(ClientIdentity::load(cert, key)?),
+        _ => None,
+    };
+    #[cfg(not(feature = "decoders"))]
+    if args.decompression == config::Decompression::Decode {
+        anyhow::bail!("--decompression decode needs debug-proxy built with the decoders feature");
+    }
     let config = ProxyConfig {
         upstream_timeout: std::time::Duration::from_millis(args.upstream_timeout),
         client_timeout: std::time::Duration::from_millis(args.client_timeout),
         max_history_size: args.max_history,
         truncate_body_at: args.truncate_body,
+        truncate_request_at: args.truncate_request,
+        truncate_response_at: args.truncate_response,
+        truncate_by_content_type: args.truncate_types.iter().cloned().collect(),
+        binary_detection: recorder::BinaryDetection {
+            text_types: args.text_types.clone(),
+            binary_types: args.binary_types.clone(),
+            ..Default::default()
+        },
+        pool_max_idle_per_host: args.pool_max_idle,
+        pool_idle_timeout: std::time::Duration::from_millis(args.pool_idle_timeout),
+        http1_keep_alive: !args.no_keep_alive,
+        upstream_http2: args.upstream_http2,
+        upstream_client_identity: upstream_client_identity.clone(),
+        upstream_ca: args.upstream_ca.clone(),
+        upstream_headers: args
+            .upstream_auth
+            .iter()
+            .chain(&args.upstream_headers)
+            .cloned()
+            .collect(),
+        resolve: args.resolve.clone(),
+        dns_cache_ttl: std::time::Duration::from_millis(args.dns_ttl),
+        outbound_proxy: outbound_proxy.clone(),
+        upstream_b: upstream_b.clone(),
+        split_percent: args.split.unwrap_or(0),
+        debug_headers: args.debug_headers,
+        no_cache: args.no_cache,
+        decompression: args.decompression,
+        duplicate_window: std::time::Duration::from_millis(args.duplicate_window),
+        offline: args.offline,
+        cors: args.cors.clone(),
+        preflights: args.preflights,
+        safe_mode: safe_mode::SafeMode {
+            block_writes: args.safe,
+            allow_paths: args.safe_allow.clone(),
+            block_paths: args.block_path.clone(),
+        },
+        routes,
+        alerts: alerts::AlertRules {
+            max_timeouts_per_minute: args.alert_timeouts,
+            max_error_rate_percent: args.alert_error_rate,
+            webhook: args.alert_webhook.clone(),
+            ..Default::default()
+        },
         ..Default::default()
     };
 
+    let safe_mode = config.safe_mode.clone();
     let shared_config = SharedConfig::new(config);
     let access_token = shared_config.get_access_token();
 
     // Create request recorder
-    let recorder = RequestRecorder::new(args.max_history);
+    let recorder = RequestRecorder::new(args.max_history)
+        .with_background_writer(recorder::DEFAULT_QUEUE_CAPACITY);
+    recorder.set_utc_offset(args.timezone);
+    if let Some(ref path) = args.load_snapshot {
+        let snapshot = snapshot::Snapshot::load(path)?;
+        info!(
+            "Loaded {} transactions from {}",
+            snapshot.transactions.len(),
+            path.display()
+        );
+        recorder.restore(snapshot.transactions);
+    }
 
     // Create process manager and start upstream service
     let process_manager = if !args.command.is_empty() {
-        let pm = ProcessManager::new(args.command.clone());
-        pm.start()
-            .with_context(|| format!("Failed to start upstream command: {:?}", args.command))?;
+        // Variables given with --env override those from --env-file
+        let mut env = match args.env_file {
+            Some(ref path) => process::load_env_file(path)?,
+            None => Vec::new(),
+        };
+        env.extend(args.env.iter().cloned());
+
+        let mut command = args.command.clone();
+        if let (Some(ref var), Some(port)) = (&args.upstream_port_env, upstream_port) {
+            env.push((var.clone(), port.to_string()));
+            command = apply_port_template(&command, port);
+        }
+
+        let restart_policy = RestartPolicy {
+            enabled: !args.no_restart && !args.exit_on_child_exit,
+            max_retries: args.max_restarts,
+            initial_backoff: std::time::Duration::from_millis(args.restart_backoff),
+            max_backoff: std::time::Duration::from_millis(args.max_restart_backoff),
+            ..Default::default()
+        };
+        let mut pm = ProcessManager::new(command.clone())
+            .with_env(env)
+            .with_restart_policy(restart_policy)
+            .with_kill_timeout(std::time::Duration::from_millis(args.kill_timeout))
+            .with_pre_start_hooks(args.pre_start.clone())
+            .with_post_stop_hooks(args.post_stop.clone());
+        if let Some(ref cwd) = args.cwd {
+            pm = pm.with_cwd(cwd.clone());
+        }
         Some(pm)
     } else {
         None
     };
 
-    // Create proxy service
-    let proxy = DebugProxy::new(shared_config, recorder, upstream_addr.clone());
+    // Bind before starting anything, so a taken port fails early and the
+    // banner shows the ports actually in use
+    let listeners = proxy::bind_listeners(&listen_addrs, args.port_auto)?;
+    let listen_addrs = listeners
+        .iter()
+        .map(|listener| listener.local_addr())
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let local_admin_listener = match args.admin_port {
+        Some(port) => {
+            let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+            proxy::bind_listeners(&[addr], false)?.pop()
+        }
+        None => None,
+    };
+    let local_admin_addr = local_admin_listener
+        .as_ref()
+        .map(|listener| listener.local_addr())
+        .transpose()?;
 
-    // Print startup information
-    println!("🚀 DebugProxy started successfully!");
-    println!();
-    println!("📊 Proxy Configuration:");
-    println!("  Listen Address:   {}:{local_port}", args.host);
-    println!("  Upstream Target:  {upstream_addr}");
-    println!("  Client Timeout:   {}ms", args.client_timeout);
-    println!("  Upstream Timeout: {}ms", args.upstream_timeout);
-    println!("  Max History:      {} requests", args.max_history);
-    println!("  Body Truncation:  {} bytes", args.truncate_body);
-    println!();
-    println!("🌐 Web Interface:");
-    let web_host = if args.host == "0.0.0.0" {
-        "localhost"
+    // Subscribe before starting so a command that exits at once is seen
+    let child_exits = match process_manager {
+        Some(ref pm) if args.exit_on_child_exit => Some(pm.subscribe()),
+        _ => None,
+    };
+    if let Some(ref pm) = process_manager {
+        pm.start().await.with_context(|| {
+            format!(
+                "Failed to start upstream command: {:?}",
+                pm.status().command
+            )
+        })?;
+    }
+
+    if !services.is_empty() {
+        services.start_all().await?;
+        services.spawn_readiness_checks();
+    }
+
+    // Restart the upstream command on file changes; keep the watcher alive
+    let _file_watcher = match process_manager {
+        Some(ref pm) if !args.watch.is_empty() => Some(FileWatcher::start(
+            pm.clone(),
+            WatchOptions {
+                paths: args.watch.clone(),
+                ignore: args.watch_ignore.clone(),
+                debounce: std::time::Duration::from_millis(args.watch_debounce),
+            },
+        )?),
+        _ => None,
+    };
+
+    let balancer = if instances.is_empty() {
+        None
     } else {
-        &args.host
+        let mut balancer =
+            balancer::Balancer::new([vec![upstream_addr.clone()], instances.clone()].concat());
+        if let Some(sticky) = args.sticky {
+            balancer = balancer.with_stickiness(sticky);
+        }
+        if let Some(ref check) = args.health_check {
+            balancer = balancer.with_health_check(balancer::HealthCheck {
+                interval: std::time::Duration::from_millis(args.health_interval),
+                threshold: args.health_threshold,
+                ..check.clone()
+            });
+        }
+        let balancer = std::sync::Arc::new(balancer);
+        balancer.spawn_health_checks(&shared_config.read());
+        Some(balancer)
     };
-    println!("  URL: http://{web_host}:{local_port}/_proxy?token={access_token}",);
-    println!();
-    println!("🔧 Upstream Process:");
+
+    alerts::spawn_alert_monitor(recorder.clone(), shared_config.clone());
+
+    // Create proxy service
+    let mut proxy = DebugProxy::new(shared_config, recorder.clone(), upstream_addr.clone());
+    if let Some(ref path) = args.openapi {
+        let spec = OpenApiSpec::load(path)?;
+        proxy = proxy.with_openapi_spec(spec);
+    }
+    let assertions = match args.schema_assertions {
+        Some(ref path) => SchemaAssertions::load(path)?,
+        None => SchemaAssertions::new(),
+    };
+    if args.strict_schemas {
+        assertions.set_strict(true);
+    }
+    proxy = proxy.with_schema_assertions(assertions);
+    let transforms = match args.transforms {
+        Some(ref path) => ResponseTransforms::load(path)?,
+        None => ResponseTransforms::new(),
+    };
+    for rule in args.transform.iter().cloned() {
+        transforms.add(rule);
+    }
+    proxy = proxy.with_transforms(transforms);
+    if let Some(ref path) = args.baseline {
+        proxy = proxy.with_baseline(BaselineStore::with_file(path.clone())?);
+    }
     if let Some(ref pm) = process_manager {
-        if let Some(pid) = pm.get_pid() {
-            println!("  Status: PID {pid} (running)");
+        proxy = proxy.with_process_manager(pm.clone());
+    }
+    proxy = proxy.with_services(services.clone());
+    if let Some(ref forward_proxy) = forward_proxy {
+        proxy = proxy.with_forward_proxy(forward_proxy.clone());
+    }
+    if let Some(ref balancer) = balancer {
+        proxy = proxy.with_balancer(balancer.clone());
+    }
+    if let Some(ref path) = args.route_script {
+        proxy = proxy.with_route_script(RouteScript::load(path)?);
+    }
+    if !args.probe.is_empty() {
+        proxy = proxy.with_probes(args.probe.clone());
+    }
+    if let Some(limit) = args.memory_limit {
+        proxy = proxy.with_memory_limit(limit << 20);
+    }
+    if let Some(ref inbox) = inbox {
+        proxy = proxy.with_inbox(inbox.clone());
+    }
+    let holds = HoldQueue::new(args.hold.clone());
+    proxy = proxy.with_holds(holds.clone());
+    let public_tunnel = PublicTunnel::default();
+    if args.tunnel.is_some() {
+        proxy = proxy.with_public_tunnel(public_tunnel.clone());
+    }
+
+    let admin_url = format!(
+        "http://{}/_proxy?token={access_token}",
+        admin_ui::local_authority(listen_addrs[0])
+    );
+    if args.print_startup_json {
+        services
+            .wait_ready(std::time::Duration::from_millis(
+                args.services_ready_timeout,
+            ))
+            .await;
+        let startup = serde_json::json!({
+            "listen": listen_addrs,
+            "admin_url": admin_url,
+            "token": access_token,
+            "upstream": upstream_addr,
+            "pid": process_manager.as_ref().and_then(|pm| pm.get_pid()),
+            "local_admin": local_admin_addr,
+        });
+        println!("{startup}");
+    } else {
+        // Print startup information
+        println!("🚀 DebugProxy started successfully!");
+        println!();
+        println!("📊 Proxy Configuration:");
+        for listen_addr in &listen_addrs {
+            println!("  Listen Address:   {listen_addr}");
+        }
+        if upstream_addr.is_empty() {
+            println!("  Upstream Target:  none");
         } else {
-            println!("  Status: Not running");
+            println!("  Upstream Target:  {upstream_addr}");
         }
-    } else {
-        println!("  Status: External (not managed)");
+        for instance in &instances {
+            println!("  Upstream Target:  {instance}");
+        }
+        if let Some(sticky) = args.sticky {
+            println!("  Sticky Sessions:  {sticky:?}");
+        }
+        if let Some(check) = balancer
+            .as_ref()
+            .and_then(|balancer| balancer.health_check())
+        {
+            println!(
+                "  Health Check:     {} every {}ms",
+                check.path.as_deref().unwrap_or("tcp"),
+                check.interval.as_millis()
+            );
+        }
+        if let Some(ref upstream_b) = upstream_b {
+            println!(
+                "  Upstream B:       {upstream_b} ({}% of requests)",
+                args.split.unwrap_or(0)
+            );
+        }
+        if let Some(ref path) = args.route_script {
+            println!("  Route Script:     {}", path.display());
+        }
+        for rule in holds.rules() {
+            println!("  Hold:             {rule}");
+        }
+        if let Some(ref inbox) = inbox {
+            if inbox.is_catch_all() {
+                println!("  Inbox:            every request no service takes");
+            } else {
+                println!("  Inbox:            {}", inbox.prefix());
+            }
+        }
+        for probe in &args.probe {
+            println!(
+                "  Probe:            {} {} every {}ms",
+                probe.method,
+                probe.path,
+                probe.interval.as_millis()
+            );
+        }
+        println!("  Client Timeout:   {}ms", args.client_timeout);
+        println!("  Upstream Timeout: {}ms", args.upstream_timeout);
+        println!("  Max History:      {} requests", args.max_history);
+        if let Some(limit) = args.memory_limit {
+            println!("  Memory Limit:     {limit} MB");
+        }
+        println!("  Body Truncation:  {} bytes", args.truncate_body);
+        if let Some(size) = args.truncate_request {
+            println!("    Requests:       {size} bytes");
+        }
+        if let Some(size) = args.truncate_response {
+            println!("    Responses:      {size} bytes");
+        }
+        for (content_type, size) in &args.truncate_types {
+            println!("    {content_type}: {size} bytes");
+        }
+        if let Some(ref proxy) = outbound_proxy {
+            println!("  Outbound Proxy:   {}:{}", proxy.host, proxy.port);
+        }
+        if let Some(ref identity) = upstream_client_identity {
+            println!("  Client Cert:      {}", identity.cert_path().display());
+        }
+        for ca in &args.upstream_ca {
+            println!("  Upstream CA:      {}", String::from(ca.clone()));
+        }
+        for header in args.upstream_auth.iter().chain(&args.upstream_headers) {
+            println!("  Upstream Header:  {header:?}");
+        }
+        if safe_mode.is_enabled() {
+            let mut blocked = Vec::new();
+            if safe_mode.block_writes {
+                blocked.push("writes".to_string());
+            }
+            blocked.extend(safe_mode.block_paths.iter().cloned());
+            println!("  Safe Mode:        blocking {}", blocked.join(", "));
+        }
+        if let Some(timeouts) = args.alert_timeouts {
+            println!("  Alert:            over {timeouts} upstream timeouts a minute");
+        }
+        if let Some(rate) = args.alert_error_rate {
+            println!("  Alert:            over {rate}% failed requests a minute");
+        }
+        if let Some(ref cors) = args.cors {
+            if cors.allow_origins.is_empty() {
+                println!("  CORS:             any origin");
+            } else {
+                println!("  CORS:             {}", cors.allow_origins.join(", "));
+            }
+        }
+        #[cfg(feature = "tls")]
+        if let Some(ref forward_proxy) = forward_proxy {
+            match forward_proxy.mitm {
+                Some(ref ca) => println!(
+                    "  Forward Proxy:    enabled, intercepting HTTPS (trust {})",
+                    ca.cert_path().display()
+                ),
+                None => println!("  Forward Proxy:    enabled"),
+            }
+        }
+        #[cfg(not(feature = "tls"))]
+        if forward_proxy.is_some() {
+            println!("  Forward Proxy:    enabled");
+        }
+        if let Some(ref path) = args.openapi {
+            println!("  OpenAPI Spec:     {}", path.display());
+        }
+        if let Some(ref path) = args.schema_assertions {
+            println!("  Schema Asserts:   {}", path.display());
+        }
+        if let Some(ref path) = args.transforms {
+            println!("  Transforms:       {}", path.display());
+        }
+        if !args.transform.is_empty() {
+            println!("  Transform Rules:  {}", args.transform.len());
+        }
+        if let Some(ref path) = args.baseline {
+            println!("  Baseline:         {}", path.display());
+        }
+        if !args.watch.is_empty() {
+            let watched: Vec<String> = args.watch.iter().map(|p| p.display().to_string()).collect();
+            println!("  Watching:         {}", watched.join(", "));
+        }
+        println!();
+        println!("🌐 Web Interface:");
+        println!("  URL: {admin_url}");
+        if let Some(addr) = local_admin_addr {
+            println!("  Local API: http://{addr}/_proxy/api (no token, this machine only)");
+        }
+        if args.qr {
+            match admin_ui::lan_authority(&listen_addrs) {
+                Some(authority) => {
+                    let lan_url = format!("http://{authority}/_proxy?token={access_token}");
+                    println!("  LAN: {lan_url}");
+                    match admin_ui::qr_code(&lan_url) {
+                        Ok(code) => println!("{code}"),
+                        Err(e) => error!("Failed to render QR code: {}", e),
+                    }
+                }
+                None => println!("  LAN: not reachable, listening on loopback only (see --host)"),
+            }
+        }
+        println!();
+        println!("🔧 Upstream Process:");
+        if let Some(ref pm) = process_manager {
+            if let Some(pid) = pm.get_pid() {
+                println!("  Status: PID {pid} (running)");
+            } else {
+                println!("  Status: Not running");
+            }
+        } else {
+            println!("  Status: External (not managed)");
+        }
+        if !services.is_empty() {
+            println!();
+            println!("🧩 Services:");
+            for service in services.iter() {
+                println!(
+                    "  {:<16} {} -> {}",
+                    service.name, service.prefix, service.upstream
+                );
+            }
+            services
+                .wait_ready(std::time::Duration::from_millis(
+                    args.services_ready_timeout,
+                ))
+                .await;
+        }
+        println!();
+        println!("Ready to receive requests. Press Ctrl+C to stop.");
     }
-    println!();
-    println!("Ready to receive requests. Press Ctrl+C to stop.");
+
+    // Written once everything is up, which start --daemon waits for
+    let pidfile = match args.pidfile {
+        Some(ref path) => Some(daemon::PidFile::create(path)?),
+        None => None,
+    };
+
+    // Forward reload/profiling signals to the upstream command
+    #[cfg(unix)]
+    if let Some(ref pm) = process_manager {
+        for (name, kind) in [
+            ("SIGHUP", tokio::signal::unix::SignalKind::hangup()),
+            ("SIGUSR1", tokio::signal::unix::SignalKind::user_defined1()),
+            ("SIGUSR2", tokio::signal::unix::SignalKind::user_defined2()),
+        ] {
+            let mut stream = tokio::signal::unix::signal(kind)
+                .with_context(|| format!("Failed to register {name} handler"))?;
+            let pm = pm.clone();
+            tokio::spawn(async move {
+                while stream.recv().await.is_some() {
+                    info!("Received {name}, forwarding to upstream process");
+                    if let Err(e) = pm.signal(kind.as_raw_value()) {
+                        tracing::warn!("Failed to forward {name}: {e:#}");
+                    }
+                }
+            });
+        }
+    }
+
+    // The listeners are bound, so the tunnel can connect before serving
+    let tunnel_process = match args.tunnel {
+        Some(ref provider) => Some(
+            start_public_tunnel(
+                provider,
+                public_tunnel,
+                listen_addrs[0].port(),
+                !args.print_startup_json,
+            )
+            .await?,
+        ),
+        None => None,
+    };
 
     // Set up signal handling
     // Clone process manager for signal handler if it exists
     let process_manager_for_signal = process_manager.clone();
-    tokio::spawn(async move {
-        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
-            .expect("Failed to register SIGTERM handler");
-        let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
-            .expect("Failed to register SIGINT handler");
-
-        tokio::select! {
-            _ = sigterm.recv() => {
-                info!("Received SIGTERM, shutting down gracefully...");
-            }
-            _ = sigint.recv() => {
-                info!("Received SIGINT, shutting down gracefully...");
+    let tunnel_for_signal = tunnel_process.clone();
+    let services_for_signal = services.clone();
+    let pidfile_for_signal = pidfile.clone();
+    let save_traffic_on_exit = {
+        let path = args.save_traffic.clone();
+        let recorder = recorder.clone();
+        let base_url = upstream::upstream_base_url(&upstream_addr);
+        move || {
+            let (path, recorder, base_url) = (path.clone(), recorder.clone(), base_url.clone());
+            async move {
+                if let Some(ref path) = path {
+                    if let Err(e) = save_traffic(path, &recorder, &base_url).await {
+                        error!("{e:#}");
+                    }
+                }
             }
         }
+    };
+    let save_traffic_on_signal = save_traffic_on_exit.clone();
+    tokio::spawn(async move {
+        let signal = shutdown_signal().await;
+        info!("Received {signal}, shutting down gracefully...");
 
         if let Some(pm) = process_manager_for_signal {
             info!("Stopping upstream process...");
-            if let Err(e) = pm.stop() {
+            if let Err(e) = pm.stop().await {
                 error!("Error stopping upstream process: {}", e);
             }
         }
+        services_for_signal.stop_all().await;
+        if let Some(tunnel) = tunnel_for_signal {
+            if let Err(e) = tunnel.stop().await {
+                error!("Error stopping the tunnel: {}", e);
+            }
+        }
+        save_traffic_on_signal().await;
+        if let Some(pidfile) = pidfile_for_signal {
+            pidfile.remove();
+        }
 
         info!("Shutdown complete");
         exit(0);
     });
 
-    // Start the proxy server (non-blocking)
-    let host_addr: std::net::IpAddr = args
-        .host
-        .parse()
-        .with_context(|| format!("Invalid host address: {}", args.host))?;
-    let listen_addr = (host_addr, local_port).into();
+    // In CI mode, leave with the command's exit code once it terminates
+    if let Some(mut exits) = child_exits {
+        let services = services.clone();
+        let pidfile = pidfile.clone();
+        tokio::spawn(async move {
+            loop {
+                match exits.recv().await {
+                    Ok(child) if !child.requested => {
+                        info!(
+                            "Upstream process exited with code {:?}, shutting down",
+                            child.code
+                        );
+                        services.stop_all().await;
+                        save_traffic_on_exit().await;
+                        if let Some(ref pidfile) = pidfile {
+                            pidfile.remove();
+                        }
+                        exit(child.code.unwrap_or(1));
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    if let Some(listener) = local_admin_listener {
+        let proxy = proxy.clone();
+        tokio::spawn(async move {
+            if let Err(e) = proxy.serve_local_admin(listener).await {
+                error!("Local admin listener error: {}", e);
+                std::process::exit(1);
+            }
+        });
+    }
 
     // Start the proxy server and monitor for failures
     let server_handle = tokio::spawn(async move {
-        if let Err(e) = proxy.start_server(listen_addr).await {
+        if let Err(e) = proxy.serve(listeners).await {
             error!("Proxy server error: {}", e);
             std::process::exit(1);
         }
     });
+    if args.open {
+        if let Err(e) = admin_ui::open_browser(&admin_url) {
+            error!("Failed to open the browser: {}", e);
+        }
+    }
 
     // Keep the main thread alive and monitor subprocess
     loop {
@@ -185,47 +1672,373 @@ async fn main() -> Result<()> {
         if server_handle.is_finished() {
             error!("Proxy server has stopped unexpectedly");
             if let Some(ref pm) = process_manager {
-                if let Err(e) = pm.stop() {
+                if let Err(e) = pm.stop().await {
                     error!("Error stopping subprocess: {}", e);
                 }
             }
+            services.stop_all().await;
+            if let Some(ref pidfile) = pidfile {
+                pidfile.remove();
+            }
             std::process::exit(1);
         }
 
         // Monitor subprocess if it exists
         if let Some(ref pm) = process_manager {
-            if !pm.is_running() {
-                warn!("Subprocess has exited unexpectedly, restarting...");
-                if let Err(e) = pm.restart() {
-                    error!("Failed to restart subprocess: {}", e);
-                } else {
-                    info!("Subprocess restarted successfully");
-                }
+            match pm.supervise().await {
+                Ok(true) => info!("Subprocess restarted successfully"),
+                Ok(false) => {}
+                Err(e) => error!("Failed to restart subprocess: {}", e),
             }
+            pm.sample_usage();
         }
+        services.supervise().await;
+        services.sample_usage();
 
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
     }
 }
 
-fn parse_upstream_target(target: &str) -> Result<String> {
-    // Validate the format host:port
-    let parts: Vec<&str> = target.split(':').collect();
-    if parts.len() != 2 {
-        return Err(anyhow::anyhow!(
-            "Upstream target must be in format host:port"
-        ));
+/// Starts the tunnel command for `provider` and prints the public URL once
+/// it reports one.
+async fn start_public_tunnel(
+    provider: &TunnelProvider,
+    tunnel: PublicTunnel,
+    port: u16,
+    print_url: bool,
+) -> Result<ProcessManager> {
+    let command = provider.command(port);
+    // A restarted tunnel would get a new URL
+    let process = ProcessManager::new(command.clone()).with_restart_policy(RestartPolicy {
+        enabled: false,
+        ..Default::default()
+    });
+    process
+        .start()
+        .await
+        .with_context(|| format!("Failed to start the tunnel: {command:?}"))?;
+    let (provider, watched) = (provider.clone(), process.clone());
+    tokio::spawn(async move {
+        if let Some(url) = tunnel.discover(&provider, &watched).await {
+            if print_url {
+                println!("🌍 Public URL: {url} (requests through it are marked via_public_tunnel)");
+            }
+        }
+    });
+    Ok(process)
+}
+
+async fn run_bench(args: BenchArgs) -> Result<()> {
+    let upstream_addr = parse_upstream_target(&args.upstream)
+        .with_context(|| format!("Invalid upstream target: {}", args.upstream))?;
+    let requests = match (args.from_history, args.from_har) {
+        (Some(path), _) => bench::load_history(&path)?,
+        (None, Some(path)) => bench::load_har(&path)?,
+        (None, None) => unreachable!("clap requires --from-history or --from-har"),
+    };
+
+    println!(
+        "🏋️  Replaying {} requests against {upstream_addr} with {} workers for {:?}",
+        requests.len(),
+        args.concurrency,
+        args.duration
+    );
+
+    let recorder = RequestRecorder::new(args.max_history);
+    let options = BenchOptions {
+        concurrency: args.concurrency,
+        duration: args.duration,
+        timeout: std::time::Duration::from_millis(args.upstream_timeout),
+    };
+    let report = bench::run(&upstream_addr, requests, &options, &recorder).await?;
+
+    println!();
+    println!("📈 Results:");
+    println!("{report}");
+
+    if let Some(ref path) = args.output {
+        let content = serde_json::to_string_pretty(&recorder.get_transactions())?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write output: {}", path.display()))?;
+        println!();
+        println!("Replayed transactions written to {}", path.display());
+    }
+
+    Ok(())
+}
+
+async fn run_mock(args: MockArgs) -> Result<()> {
+    let host: std::net::IpAddr = args
+        .host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("Invalid host address: {}", args.host))?;
+    let mut mock = match args.from {
+        Some(ref from) => {
+            let mock = mock::MockServer::load(from)?;
+            if mock.is_empty() {
+                anyhow::bail!("No recorded responses in {}", from.display());
+            }
+            mock
+        }
+        None => mock::MockServer::new(Vec::new()),
+    };
+    if let Some(ref path) = args.rules {
+        mock = mock.with_rules(mock::MockRule::load(path)?);
+    }
+    if let Some(ref upstream) = args.passthrough {
+        let upstream = parse_upstream_target(upstream)
+            .with_context(|| format!("Invalid upstream target: {upstream}"))?;
+        mock = mock.with_passthrough(upstream);
+    }
+
+    let addr = std::net::SocketAddr::from((host, args.port));
+    match args.from {
+        Some(ref from) => println!(
+            "🎭 Serving {} recorded responses from {} on {addr}",
+            mock.len(),
+            from.display()
+        ),
+        None => println!("🎭 Serving mock rules on {addr}"),
+    }
+    for rule in mock.rules() {
+        println!(
+            "  Rule: {} {}",
+            rule.method.as_deref().unwrap_or("*"),
+            rule.path
+        );
+    }
+    match args.passthrough {
+        Some(ref upstream) => println!("  Unrecorded requests go to {upstream}"),
+        None => println!("  Unrecorded requests get 404"),
+    }
+
+    tokio::select! {
+        result = mock.serve(addr) => result,
+        signal = shutdown_signal() => {
+            info!("Received {signal}, shutting down");
+            Ok(())
+        }
+    }
+}
+
+async fn run_tcp(args: TcpArgs) -> Result<()> {
+    let host: std::net::IpAddr = args
+        .host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("Invalid host address: {}", args.host))?;
+    if args.upstream.contains("://") {
+        anyhow::bail!("Give the TCP upstream as host:port, without a scheme");
+    }
+    let upstream = parse_upstream_target(&args.upstream)
+        .with_context(|| format!("Invalid upstream target: {}", args.upstream))?;
+    let addr = std::net::SocketAddr::from((host, args.port));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind {addr}"))?;
+    let forwarder = tcp::TcpForwarder::new(upstream.clone())
+        .with_connect_timeout(std::time::Duration::from_millis(args.connect_timeout))
+        .with_hex_dump(args.hex_dump);
+
+    println!("🔌 Forwarding TCP from {addr} to {upstream}");
+    if args.hex_dump > 0 {
+        println!("  Logging the first {} bytes each way", args.hex_dump);
+    }
+
+    let result = tokio::select! {
+        result = forwarder.clone().serve(listener) => result,
+        signal = shutdown_signal() => {
+            info!("Received {signal}, shutting down");
+            Ok(())
+        }
+    };
+    println!();
+    println!("📈 Totals:");
+    println!("{}", forwarder.stats());
+
+    if let Some(ref path) = args.output {
+        let content = serde_json::to_string_pretty(&forwarder.events().get(None))?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write output: {}", path.display()))?;
+        println!();
+        println!("Connection events written to {}", path.display());
+    }
+    result
+}
+
+async fn run_udp(args: UdpArgs) -> Result<()> {
+    let host: std::net::IpAddr = args
+        .host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("Invalid host address: {}", args.host))?;
+    if args.upstream.contains("://") {
+        anyhow::bail!("Give the UDP upstream as host:port, without a scheme");
+    }
+    let upstream = parse_upstream_target(&args.upstream)
+        .with_context(|| format!("Invalid upstream target: {}", args.upstream))?;
+    let addr = std::net::SocketAddr::from((host, args.port));
+    let socket = tokio::net::UdpSocket::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind {addr}"))?;
+    let relay = udp::UdpRelay::new(upstream.clone())
+        .with_idle_timeout(std::time::Duration::from_millis(args.idle_timeout))
+        .with_hex_dump(args.hex_dump);
+
+    println!("📡 Relaying UDP from {addr} to {upstream}");
+    if args.hex_dump > 0 {
+        println!(
+            "  Logging the first {} bytes of each client's first {} datagrams each way",
+            args.hex_dump,
+            udp::SAMPLED_DATAGRAMS
+        );
+    }
+
+    let result = tokio::select! {
+        result = relay.clone().serve(socket) => result,
+        signal = shutdown_signal() => {
+            info!("Received {signal}, shutting down");
+            Ok(())
+        }
+    };
+    println!();
+    println!("📈 Totals:");
+    println!("{}", relay.stats());
+
+    if let Some(ref path) = args.output {
+        let content = serde_json::to_string_pretty(&relay.flows())?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write output: {}", path.display()))?;
+        println!();
+        println!("Flows written to {}", path.display());
+    }
+    result
+}
+
+/// Writes the recorded traffic as HAR when `path` ends in `.har`, and as
+/// JSON Lines otherwise.
+async fn save_traffic(path: &Path, recorder: &RequestRecorder, base_url: &str) -> Result<()> {
+    recorder.flush().await;
+    let transactions = recorder.get_transactions();
+    let content = if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("har"))
+    {
+        serde_json::to_string_pretty(&export::to_har(&transactions, base_url))?
+    } else {
+        export::to_jsonl(&transactions)
+    };
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to save traffic: {}", path.display()))?;
+    info!(
+        "Saved {} transactions to {}",
+        transactions.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Waits for Ctrl+C, or SIGTERM on Unix, and returns which one arrived.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to register SIGTERM handler");
+        tokio::select! {
+            _ = sigterm.recv() => "SIGTERM",
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to register Ctrl+C handler");
+        "Ctrl+C"
     }
+}
 
-    // Validate host part (not empty)
-    if parts[0].is_empty() {
-        return Err(anyhow::anyhow!("Host part cannot be empty"));
+/// The addresses to listen on: each `--listen`, plus `--host`/`--port`
+/// unless only `--listen` was given.
+fn listen_addrs(args: &Args) -> Result<Vec<std::net::SocketAddr>> {
+    let mut addrs = args.listen.clone();
+    if addrs.is_empty() || args.port.is_some() {
+        let host: std::net::IpAddr = args
+            .host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .with_context(|| format!("Invalid host address: {}", args.host))?;
+        addrs.insert(0, (host, args.port.unwrap_or(8080)).into());
     }
+    Ok(addrs)
+}
+
+/// Replaces `{port}` in the command's arguments with the assigned port.
+fn apply_port_template(command: &[String], port: u16) -> Vec<String> {
+    command
+        .iter()
+        .map(|arg| arg.replace("{port}", &port.to_string()))
+        .collect()
+}
+
+/// Normalizes an upstream target to `host:port`, or `https://host:port` for
+/// HTTPS. Accepts `host:port`, `[ipv6]:port`, a bare host or IP address
+/// (port 80) and `http://` or `https://` URLs.
+fn parse_upstream_target(target: &str) -> Result<String> {
+    let (tls, authority) = match target.split_once("://") {
+        Some((scheme, rest)) => {
+            let tls = match scheme.to_ascii_lowercase().as_str() {
+                "http" => false,
+                "https" => true,
+                _ => anyhow::bail!("Unsupported scheme: {scheme}"),
+            };
+            let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+            if !path.is_empty() {
+                anyhow::bail!("Upstream URL cannot have a path");
+            }
+            (tls, authority)
+        }
+        None => (false, target),
+    };
+    let default_port = if tls { 443 } else { 80 };
 
-    // Validate port part
-    let _port = parts[1].parse::<u16>().context("Invalid port number")?;
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        // Bracketed IPv6 literal, with an optional port
+        let (ip, after) = rest
+            .split_once(']')
+            .context("Missing ']' after IPv6 address")?;
+        ip.parse::<std::net::Ipv6Addr>()
+            .context("Invalid IPv6 address")?;
+        let port = match after.strip_prefix(':') {
+            Some(port) => port.parse::<u16>().context("Invalid port number")?,
+            None if after.is_empty() => default_port,
+            None => anyhow::bail!("Unexpected characters after IPv6 address"),
+        };
+        (format!("[{ip}]"), port)
+    } else if authority.parse::<std::net::Ipv6Addr>().is_ok() {
+        (format!("[{authority}]"), default_port)
+    } else {
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().context("Invalid port number")?),
+            None => (authority, default_port),
+        };
+        (host.to_string(), port)
+    };
 
-    Ok(target.to_string())
+    if host.is_empty() {
+        anyhow::bail!("Host part cannot be empty");
+    }
+    Ok(if tls {
+        format!("https://{host}:{port}")
+    } else {
+        format!("{host}:{port}")
+    })
 }
 
 #[cfg(test)]
@@ -243,9 +2056,133 @@ mod tests {
             "192.168.1.1:8080"
         );
 
-        assert!(parse_upstream_target("localhost").is_err());
         assert!(parse_upstream_target("localhost:3000:9000").is_err());
         assert!(parse_upstream_target(":3000").is_err());
         assert!(parse_upstream_target("localhost:invalid").is_err());
     }
+
+    #[test]
+    fn test_parse_upstream_target_forms() {
+        // IPv6 literals
+        assert_eq!(parse_upstream_target("[::1]:3000").unwrap(), "[::1]:3000");
+        assert_eq!(parse_upstream_target("[::1]").unwrap(), "[::1]:80");
+        assert_eq!(parse_upstream_target("::1").unwrap(), "[::1]:80");
+        assert!(parse_upstream_target("[::1").is_err());
+        assert!(parse_upstream_target("[nope]:3000").is_err());
+        assert!(parse_upstream_target("[::1]3000").is_err());
+
+        // Bare hosts get the default port
+        assert_eq!(parse_upstream_target("localhost").unwrap(), "localhost:80");
+        assert_eq!(parse_upstream_target("10.0.0.2").unwrap(), "10.0.0.2:80");
+
+        // URLs
+        assert_eq!(
+            parse_upstream_target("http://localhost:3000/").unwrap(),
+            "localhost:3000"
+        );
+        assert_eq!(
+            parse_upstream_target("http://example.com").unwrap(),
+            "example.com:80"
+        );
+        assert_eq!(
+            parse_upstream_target("https://api.example.com").unwrap(),
+            "https://api.example.com:443"
+        );
+        assert_eq!(
+            parse_upstream_target("https://[::1]:8443").unwrap(),
+            "https://[::1]:8443"
+        );
+        assert!(parse_upstream_target("ftp://example.com").is_err());
+        assert!(parse_upstream_target("http://example.com/api").is_err());
+        assert!(parse_upstream_target("http://:3000").is_err());
+    }
+
+    #[test]
+    fn test_listen_addrs() {
+        let addrs = |argv: &[&str]| {
+            let args =
+                Args::try_parse_from([&["debug-proxy", "localhost:3000"], argv].concat()).unwrap();
+            listen_addrs(&args)
+                .unwrap()
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(addrs(&[]), ["0.0.0.0:8080"]);
+        assert_eq!(addrs(&["--host", "[::]", "-p", "9000"]), ["[::]:9000"]);
+        assert_eq!(
+            addrs(&["--listen", "127.0.0.1:8080", "--listen", "[::1]:8080"]),
+            ["127.0.0.1:8080", "[::1]:8080"]
+        );
+        // --port adds the --host listener to the --listen ones
+        assert_eq!(
+            addrs(&["--listen", "127.0.0.1:9000", "-p", "8081"]),
+            ["0.0.0.0:8081", "127.0.0.1:9000"]
+        );
+
+        assert!(Args::try_parse_from(["debug-proxy", "--listen", "localhost", "x:1"]).is_err());
+        assert_eq!(
+            admin_ui::local_authority("0.0.0.0:8080".parse().unwrap()),
+            "localhost:8080"
+        );
+        assert_eq!(
+            admin_ui::local_authority("[::1]:8080".parse().unwrap()),
+            "[::1]:8080"
+        );
+    }
+
+    #[test]
+    fn test_upstream_port_env() {
+        let args = Args::try_parse_from([
+            "debug-proxy",
+            "--upstream-port-env",
+            "PORT",
+            "--",
+            "python",
+            "-m",
+            "http.server",
+            "{port}",
+        ])
+        .unwrap();
+        assert!(args.upstream.is_none());
+        assert_eq!(args.upstream_port_env.as_deref(), Some("PORT"));
+        assert_eq!(
+            apply_port_template(&args.command, 4123),
+            vec!["python", "-m", "http.server", "4123"]
+        );
+
+        // Needs a command to hand the port to, and replaces the upstream
+        assert!(Args::try_parse_from(["debug-proxy", "--upstream-port-env", "PORT"]).is_err());
+        assert!(Args::try_parse_from([
+            "debug-proxy",
+            "localhost:3000",
+            "--upstream-port-env",
+            "PORT",
+            "--",
+            "npm",
+            "start"
+        ])
+        .is_err());
+        assert!(Args::try_parse_from(["debug-proxy"]).is_err());
+    }
+
+    #[test]
+    fn test_health_interval_range() {
+        let parse = |interval: &str| {
+            Args::try_parse_from([
+                "debug-proxy",
+                "localhost:3000",
+                "--instance",
+                "localhost:3001",
+                "--health-check",
+                "tcp",
+                "--health-interval",
+                interval,
+            ])
+        };
+        assert_eq!(parse("500").unwrap().health_interval, 500);
+        // Zero would time every probe out at once and spin the loop
+        assert!(parse("0").is_err());
+    }
 }
@@ -0,0 +1,149 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::recorder::RequestRecorder;
+use crate::usage::read_own_rss;
+
+/// How often the proxy's own memory is checked against the limit.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Body bytes kept in a record under [`Pressure::ShrinkPreviews`].
+pub const SHRUNK_PREVIEW: usize = 256;
+
+/// How far recording has backed off to keep the proxy under its memory
+/// limit, mildest first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pressure {
+    #[default]
+    Normal,
+    /// From 70% of the limit: body previews are cut to [`SHRUNK_PREVIEW`]
+    /// bytes.
+    ShrinkPreviews,
+    /// From 85%: bodies are no longer kept, only their size and hash.
+    NoBodies,
+    /// At the limit: new requests are proxied without being recorded.
+    Paused,
+}
+
+impl Pressure {
+    pub fn for_usage(rss_bytes: u64, limit_bytes: u64) -> Self {
+        match rss_bytes.saturating_mul(100) / limit_bytes.max(1) {
+            100.. => Self::Paused,
+            85.. => Self::NoBodies,
+            70.. => Self::ShrinkPreviews,
+            _ => Self::Normal,
+        }
+    }
+
+    /// Where a body is truncated under this pressure, instead of
+    /// `truncate_at`.
+    pub fn truncate_at(self, truncate_at: usize) -> usize {
+        match self {
+            Self::Normal => truncate_at,
+            Self::ShrinkPreviews => truncate_at.min(SHRUNK_PREVIEW),
+            Self::NoBodies | Self::Paused => 0,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::ShrinkPreviews,
+            2 => Self::NoBodies,
+            3 => Self::Paused,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// The memory section of `/_proxy/api/stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStatus {
+    /// The proxy's resident memory at the last check, `None` before the
+    /// first one or where it cannot be read.
+    pub rss_bytes: Option<u64>,
+    pub limit_bytes: u64,
+    pub pressure: Pressure,
+    /// Requests proxied but not recorded while paused.
+    pub unrecorded: u64,
+}
+
+/// Watches the proxy's resident memory and backs recording off as it nears
+/// the limit, so a long session degrades instead of being OOM-killed.
+#[derive(Debug, Clone)]
+pub struct MemoryMonitor {
+    limit_bytes: u64,
+    /// Zero until the first check.
+    rss_bytes: Arc<AtomicU64>,
+}
+
+impl MemoryMonitor {
+    pub fn new(limit_bytes: u64) -> Self {
+        Self {
+            limit_bytes,
+            rss_bytes: Arc::default(),
+        }
+    }
+
+    /// Checks the proxy's memory every second for as long as it runs.
+    pub async fn run(&self, recorder: RequestRecorder) {
+        if read_own_rss().is_none() {
+            warn!(
+                "Cannot read the proxy's memory use on this platform; --memory-limit has no effect"
+            );
+            return;
+        }
+        let mut ticks = tokio::time::interval(SAMPLE_INTERVAL);
+        loop {
+            ticks.tick().await;
+            if let Some(rss_bytes) = read_own_rss() {
+                self.update(rss_bytes, &recorder);
+            }
+        }
+    }
+
+    /// Sets the recorder's pressure for `rss_bytes` of resident memory.
+    pub fn update(&self, rss_bytes: u64, recorder: &RequestRecorder) {
+        self.rss_bytes.store(rss_bytes, Ordering::Relaxed);
+        let pressure = Pressure::for_usage(rss_bytes, self.limit_bytes);
+        let previous = recorder.pressure();
+        if pressure == previous {
+            return;
+        }
+        recorder.set_pressure(pressure);
+        let (used_mb, limit_mb) = (rss_bytes >> 20, self.limit_bytes >> 20);
+        match pressure {
+            Pressure::Normal => info!(
+                "Memory back to {used_mb} MB of {limit_mb} MB; recording bodies in full again"
+            ),
+            Pressure::ShrinkPreviews if pressure < previous => info!(
+                "Memory down to {used_mb} MB of {limit_mb} MB; keeping bodies again, cut to {SHRUNK_PREVIEW} bytes"
+            ),
+            Pressure::ShrinkPreviews => warn!(
+                "Memory at {used_mb} MB of {limit_mb} MB; cutting recorded bodies to {SHRUNK_PREVIEW} bytes"
+            ),
+            Pressure::NoBodies if pressure < previous => info!(
+                "Memory down to {used_mb} MB of {limit_mb} MB; recording requests again, without bodies"
+            ),
+            Pressure::NoBodies => warn!(
+                "Memory at {used_mb} MB of {limit_mb} MB; no longer recording bodies"
+            ),
+            Pressure::Paused => warn!(
+                "Memory at {used_mb} MB of {limit_mb} MB; recording paused, clear the history to free memory"
+            ),
+        }
+    }
+
+    pub fn status(&self, recorder: &RequestRecorder) -> MemoryStatus {
+        let rss_bytes = self.rss_bytes.load(Ordering::Relaxed);
+        MemoryStatus {
+            rss_bytes: (rss_bytes > 0).then_some(rss_bytes),
+            limit_bytes: self.limit_bytes,
+            pressure: recorder.pressure(),
+            unrecorded: recorder.unrecorded(),
+        }
+    }
+}
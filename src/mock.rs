@@ -0,0 +1,517 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Method, Request, Response, StatusCode, Uri};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Server};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::config::ProxyConfig;
+use crate::recorder::{sha256_hex, HttpTransaction};
+use crate::route::PathTemplate;
+use crate::snapshot::Snapshot;
+use crate::template::{Template, TemplateContext};
+use crate::upstream::{build_client, upstream_base_url, UpstreamClient};
+
+/// Names the recorded transaction a mocked response came from.
+pub const MOCK_HEADER: &str = "x-debug-proxy-mock";
+
+/// Answering proxied requests from the recorded history instead of the
+/// upstream, for when the upstream goes away mid-session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OfflineMode {
+    #[default]
+    Off,
+    /// Requests nothing recorded matches get `503`.
+    Strict,
+    /// Requests nothing recorded matches go to the upstream.
+    Passthrough,
+}
+
+impl std::str::FromStr for OfflineMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "strict" => Ok(Self::Strict),
+            "passthrough" => Ok(Self::Passthrough),
+            _ => anyhow::bail!("Expected off, strict or passthrough, got {s}"),
+        }
+    }
+}
+
+/// A response served for matching requests ahead of the recordings, with a
+/// body [`Template`] filled in from each request. A `sequence` serves its
+/// responses in turn instead, such as `202` while a job is pending and then
+/// `200`, counted separately for each value of `key` when it is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockRule {
+    /// Any method when absent.
+    #[serde(default)]
+    pub method: Option<String>,
+    /// A path template such as `/users/{id}`, whose `{id}` the body reads
+    /// as `path.id`.
+    pub path: String,
+    #[serde(flatten)]
+    pub response: MockResponse,
+    #[serde(default)]
+    pub sequence: Vec<MockResponse>,
+    /// What follows the end of the sequence.
+    #[serde(default)]
+    pub then: SequenceEnd,
+    /// A template such as `{{ path.id }}` that keeps a separate place in
+    /// the sequence for each value.
+    #[serde(default)]
+    pub key: Option<Template>,
+    /// Responses served so far for each key.
+    #[serde(skip)]
+    turns: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+/// One response of a [`MockRule`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockResponse {
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: Template,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// Where a [`MockRule`]'s sequence goes after its last response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SequenceEnd {
+    /// Keep serving the last response.
+    #[default]
+    Last,
+    /// Start over from the first.
+    Cycle,
+}
+
+#[derive(Debug, Deserialize)]
+struct MockRulesFile {
+    rules: Vec<MockRule>,
+}
+
+impl MockRule {
+    /// Loads rules from a YAML or JSON file with a `rules` list.
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read mock rules: {}", path.display()))?;
+        let file: MockRulesFile = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse mock rules: {}", path.display()))?;
+        Ok(file.rules)
+    }
+
+    /// The rule's response to a request, when it matches, taking the next
+    /// turn of its sequence.
+    pub fn respond(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Option<Response<Bytes>> {
+        if self
+            .method
+            .as_ref()
+            .is_some_and(|m| !m.eq_ignore_ascii_case(method.as_str()))
+        {
+            return None;
+        }
+        let params = PathTemplate::parse(&self.path).params(uri.path())?;
+        let query: Vec<(String, String)> =
+            url::form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        let json = serde_json::from_slice(body).ok();
+        let context = TemplateContext {
+            method: method.as_str(),
+            path: uri.path(),
+            params: &params,
+            query: &query,
+            headers,
+            body: json.as_ref(),
+        };
+
+        let response = match self.sequence.len() {
+            0 => &self.response,
+            len => {
+                let key = self
+                    .key
+                    .as_ref()
+                    .map(|key| key.render(&context))
+                    .unwrap_or_default();
+                let mut turns = self.turns.lock();
+                let turn = turns.entry(key).or_default();
+                let step = match self.then {
+                    SequenceEnd::Last => (*turn).min(len - 1),
+                    SequenceEnd::Cycle => *turn % len,
+                };
+                *turn += 1;
+                &self.sequence[step]
+            }
+        };
+        match response.render(&context) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                warn!("Mock rule for {} has an invalid header: {e}", self.path);
+                None
+            }
+        }
+    }
+}
+
+impl MockResponse {
+    /// Bodies that are JSON are served as such unless the response sets a
+    /// `Content-Type`.
+    fn render(&self, context: &TemplateContext) -> http::Result<Response<Bytes>> {
+        let rendered = self.body.render(context);
+        let mut response =
+            Response::builder().status(StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK));
+        if !self
+            .headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("content-type"))
+        {
+            let is_json = serde_json::from_str::<serde_json::Value>(&rendered).is_ok();
+            response = response.header(
+                header::CONTENT_TYPE,
+                if is_json {
+                    "application/json"
+                } else {
+                    "text/plain"
+                },
+            );
+        }
+        for (name, value) in &self.headers {
+            response = response.header(name.as_str(), value.as_str());
+        }
+        response.body(Bytes::from(rendered))
+    }
+}
+
+/// Serves recorded responses back for the requests that produced them, so a
+/// captured session works as an offline mock of the upstream.
+///
+/// A request matches recordings with the same method and path, query
+/// included; without one, recordings of the same path with another query
+/// match. Recordings of the same request body are preferred, and repeated
+/// requests get the matching responses in the order they were recorded,
+/// starting over after the last. Unmatched requests get `404`, or go to the
+/// passthrough upstream when there is one. [`MockRule`]s are checked before
+/// the recordings, in order.
+pub struct MockServer {
+    transactions: Vec<HttpTransaction>,
+    rules: Vec<MockRule>,
+    /// Responses served so far for each method and path, to take turns.
+    served: Mutex<HashMap<String, usize>>,
+    passthrough: Option<(String, UpstreamClient)>,
+}
+
+impl MockServer {
+    /// Keeps the transactions that have a response to serve.
+    pub fn new(transactions: Vec<HttpTransaction>) -> Self {
+        Self {
+            transactions: transactions
+                .into_iter()
+                .filter(|transaction| transaction.response.is_some())
+                .collect(),
+            rules: Vec::new(),
+            served: Mutex::default(),
+            passthrough: None,
+        }
+    }
+
+    /// Loads a snapshot, a saved `/_proxy/api/logs` response or JSON Lines
+    /// as written by `--save-traffic`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recorded traffic: {}", path.display()))?;
+        let transactions = parse_transactions(&content)
+            .with_context(|| format!("Failed to parse recorded traffic: {}", path.display()))?;
+        Ok(Self::new(transactions))
+    }
+
+    /// Sends requests no recording matches to `upstream` instead of
+    /// answering them with `404`.
+    pub fn with_passthrough(mut self, upstream: String) -> Self {
+        let client = build_client(&ProxyConfig::default());
+        self.passthrough = Some((upstream, client));
+        self
+    }
+
+    pub fn with_rules(mut self, rules: Vec<MockRule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn rules(&self) -> &[MockRule] {
+        &self.rules
+    }
+
+    /// Recorded responses available to serve.
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        let mock = Arc::new(self);
+        let make_svc = make_service_fn(move |_conn| {
+            let mock = Arc::clone(&mock);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let mock = Arc::clone(&mock);
+                    async move { Ok::<_, Infallible>(mock.handle(req).await) }
+                }))
+            }
+        });
+        Server::try_bind(&addr)
+            .with_context(|| format!("Failed to bind {addr}"))?
+            .serve(make_svc)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let (parts, body) = req.into_parts();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(e) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Failed to read request body: {e}")))
+                    .unwrap()
+            }
+        };
+        let path = parts
+            .uri
+            .path_and_query()
+            .map_or(parts.uri.path(), |pq| pq.as_str())
+            .to_string();
+
+        if let Some(response) = self
+            .rules
+            .iter()
+            .find_map(|rule| rule.respond(&parts.method, &parts.uri, &parts.headers, &body))
+        {
+            return response.map(Body::from);
+        }
+        if let Some(transaction) = self.find(&parts.method, &path, &body) {
+            return recorded_response(transaction, &parts.headers).map(Body::from);
+        }
+        match self.passthrough {
+            Some((ref upstream, ref client)) => {
+                info!(
+                    "No recording of {} {path}, passing it through",
+                    parts.method
+                );
+                passthrough(client, upstream, parts, &path, body).await
+            }
+            None => {
+                info!("No recording of {} {path}", parts.method);
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .header(header::CONTENT_TYPE, "text/plain")
+                    .body(Body::from(format!(
+                        "No recorded response for {} {path}",
+                        parts.method
+                    )))
+                    .unwrap()
+            }
+        }
+    }
+
+    fn find(&self, method: &Method, path: &str, body: &[u8]) -> Option<&HttpTransaction> {
+        let candidates = matching(&self.transactions, method, path, body);
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let mut served = self.served.lock();
+        let turn = served.entry(format!("{method} {path}")).or_default();
+        let transaction = candidates[*turn % candidates.len()];
+        *turn += 1;
+        Some(transaction)
+    }
+}
+
+/// Recorded transactions with a response that answer a request, in recorded
+/// order: the same method and path, query included, or failing that the same
+/// path with any query. Recordings of the same request body are preferred.
+pub fn matching<'a>(
+    transactions: impl IntoIterator<Item = &'a HttpTransaction>,
+    method: &Method,
+    path: &str,
+    body: &[u8],
+) -> Vec<&'a HttpTransaction> {
+    let without_query = |path: &str| path.split('?').next().unwrap_or_default().to_string();
+    let same_request: Vec<&HttpTransaction> = transactions
+        .into_iter()
+        .filter(|t| t.response.is_some() && t.request.method == method.as_str())
+        .filter(|t| without_query(&t.request.path) == without_query(path))
+        .collect();
+    let mut candidates: Vec<&HttpTransaction> = same_request
+        .iter()
+        .copied()
+        .filter(|t| t.request.path == path)
+        .collect();
+    if candidates.is_empty() {
+        candidates = same_request;
+    }
+    let sha256 = sha256_hex(body);
+    if candidates.iter().any(|t| t.request.body.sha256 == sha256) {
+        candidates.retain(|t| t.request.body.sha256 == sha256);
+    }
+    candidates
+}
+
+/// Reads transactions from a snapshot, a JSON array or JSON Lines.
+fn parse_transactions(content: &str) -> Result<Vec<HttpTransaction>> {
+    if let Ok(snapshot) = serde_json::from_str::<Snapshot>(content) {
+        return Ok(snapshot.transactions);
+    }
+    if content.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str(content)?);
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid transaction on line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Headers that describe the recorded connection or encoding rather than
+/// the response. The served body is the recorded preview, never the
+/// original encoded bytes, so neither the recorded `Content-Encoding` nor
+/// `Content-Length` describes it; hyper sets the length from the body.
+const SKIPPED_HEADERS: &[HeaderName] = &[
+    header::CONNECTION,
+    header::CONTENT_ENCODING,
+    header::CONTENT_LENGTH,
+    header::TRANSFER_ENCODING,
+];
+
+/// Rebuilds a recorded response, as `304` when the request's `If-None-Match`
+/// names the recorded `ETag`. `transaction` must have a response.
+pub fn recorded_response(
+    transaction: &HttpTransaction,
+    request_headers: &HeaderMap,
+) -> Response<Bytes> {
+    let recorded = transaction
+        .response
+        .as_ref()
+        .expect("replayed transactions have a response");
+    let etag = recorded
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("etag"))
+        .map(|(_, value)| value.as_str());
+    let not_modified = etag.is_some_and(|etag| {
+        request_headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|tags| {
+                tags.split(',')
+                    .any(|tag| tag.trim() == etag || tag.trim() == "*")
+            })
+    });
+
+    // Binary bodies were not recorded, so there is nothing to decode either
+    let body = if recorded.body.is_binary {
+        Bytes::new()
+    } else {
+        Bytes::copy_from_slice(recorded.body.preview.as_bytes())
+    };
+    if recorded.body.is_binary || recorded.body.truncated {
+        warn!(
+            "Serving {} {} with the recorded part of its {} body",
+            transaction.request.method,
+            transaction.request.path,
+            if recorded.body.is_binary {
+                "binary"
+            } else {
+                "truncated"
+            }
+        );
+    }
+
+    let status = if not_modified {
+        StatusCode::NOT_MODIFIED
+    } else {
+        StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::OK)
+    };
+    let mut response = Response::builder().status(status);
+    for (name, value) in &recorded.headers {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) else {
+            continue;
+        };
+        if SKIPPED_HEADERS.contains(&name) {
+            continue;
+        }
+        response = response.header(name, value);
+    }
+    response = response.header(MOCK_HEADER, transaction.request.id.as_str());
+    let body = if not_modified { Bytes::new() } else { body };
+    response.body(body).unwrap()
+}
+
+async fn passthrough(
+    client: &UpstreamClient,
+    upstream: &str,
+    parts: http::request::Parts,
+    path: &str,
+    body: Bytes,
+) -> Response<Body> {
+    let uri = match format!("{}{path}", upstream_base_url(upstream)).parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(Body::from(format!("Invalid upstream URL: {e}")))
+                .unwrap()
+        }
+    };
+    let mut req = Request::builder().method(parts.method).uri(uri);
+    for (name, value) in &parts.headers {
+        if name != header::HOST {
+            req = req.header(name, value);
+        }
+    }
+    match client.request(req.body(Body::from(body)).unwrap()).await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Passthrough to {upstream} failed: {e}");
+            Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(Body::from("Bad Gateway"))
+                .unwrap()
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{Context, Result};
+use http::{HeaderMap, Method, StatusCode};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::recorder::Violation;
+use crate::route::PathTemplate;
+use crate::schema;
+
+const SOURCE: &str = "openapi";
+
+/// One side of a proxied exchange, as seen by the spec validator.
+pub struct ExchangePart<'a> {
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+}
+
+/// An OpenAPI 3 document that proxied traffic is checked against.
+pub struct OpenApiSpec {
+    document: Value,
+    base_path: String,
+    paths: Vec<PathTemplate>,
+}
+
+impl OpenApiSpec {
+    /// Loads a spec from a YAML or JSON file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read OpenAPI spec: {}", path.display()))?;
+        Self::parse(&content)
+            .with_context(|| format!("Failed to parse OpenAPI spec: {}", path.display()))
+    }
+
+    /// Parses a spec from YAML or JSON text.
+    pub fn parse(content: &str) -> Result<Self> {
+        let document: Value = serde_yaml::from_str(content)?;
+        let paths = document
+            .get("paths")
+            .and_then(Value::as_object)
+            .context("OpenAPI spec has no 'paths' object")?
+            .keys()
+            .map(|template| PathTemplate::parse(template))
+            .collect();
+
+        let base_path = document
+            .pointer("/servers/0/url")
+            .and_then(Value::as_str)
+            .map(server_base_path)
+            .unwrap_or_default();
+
+        Ok(Self {
+            document,
+            base_path,
+            paths,
+        })
+    }
+
+    /// Checks a request, and its response when one was received, against the
+    /// spec. Returns an empty list when the exchange conforms.
+    pub fn validate(
+        &self,
+        method: &Method,
+        path: &str,
+        request: ExchangePart,
+        response: Option<(StatusCode, ExchangePart)>,
+    ) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        // The base path only counts up to a segment boundary, so `/api`
+        // does not take in `/apiv2`
+        let relative = path
+            .strip_prefix(&self.base_path)
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'));
+        let Some(relative) = relative else {
+            violations.push(violation(
+                "unknown_path",
+                format!("{path} is outside the spec's base path {}", self.base_path),
+            ));
+            return violations;
+        };
+        let Some(template) = self.find_path(relative) else {
+            violations.push(violation(
+                "unknown_path",
+                format!("{path} does not match any path in the spec"),
+            ));
+            return violations;
+        };
+
+        let operation = self
+            .document
+            .get("paths")
+            .and_then(|paths| paths.get(template.as_str()))
+            .and_then(|item| item.get(method.as_str().to_ascii_lowercase()));
+        let Some(operation) = operation else {
+            violations.push(violation(
+                "unknown_method",
+                format!("{method} is not defined for {}", template.as_str()),
+            ));
+            return violations;
+        };
+
+        if let Some(request_body) = operation.get("requestBody") {
+            let request_body = self.deref(request_body);
+            let required = request_body.get("required").and_then(Value::as_bool) == Some(true);
+            if request.body.is_empty() {
+                if required {
+                    violations.push(violation(
+                        "request_body",
+                        "request body is required but was empty".to_string(),
+                    ));
+                }
+            } else {
+                self.check_body(request_body, &request, "request_body", &mut violations);
+            }
+        }
+
+        if let Some((status, response)) = response {
+            match self.find_response(operation, status) {
+                Some(spec_response) => {
+                    if !response.body.is_empty() {
+                        self.check_body(spec_response, &response, "response_body", &mut violations);
+                    }
+                }
+                None => violations.push(violation(
+                    "unexpected_status",
+                    format!(
+                        "status {} is not documented for {method} {}",
+                        status.as_u16(),
+                        template.as_str()
+                    ),
+                )),
+            }
+        }
+
+        violations
+    }
+
+    fn find_path(&self, path: &str) -> Option<&PathTemplate> {
+        PathTemplate::best_match(&self.paths, path)
+    }
+
+    fn find_response<'a>(&'a self, operation: &'a Value, status: StatusCode) -> Option<&'a Value> {
+        let responses = operation.get("responses")?;
+        let code = status.as_u16();
+        responses
+            .get(code.to_string())
+            .or_else(|| responses.get(format!("{}XX", code / 100)))
+            .or_else(|| responses.get(format!("{}xx", code / 100)))
+            .or_else(|| responses.get("default"))
+            .map(|response| self.deref(response))
+    }
+
+    fn check_body(
+        &self,
+        container: &Value,
+        part: &ExchangePart,
+        kind: &str,
+        violations: &mut Vec<Violation>,
+    ) {
+        let Some(content) = container.get("content").and_then(Value::as_object) else {
+            return;
+        };
+
+        let content_type = part
+            .headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<mime::Mime>().ok());
+
+        let media = match &content_type {
+            Some(mime) => content
+                .get(mime.essence_str())
+                .or_else(|| content.get(&format!("{}/*", mime.type_())))
+                .or_else(|| content.get("*/*")),
+            None => content.get("*/*"),
+        };
+
+        let Some(media) = media else {
+            let actual = content_type
+                .map(|m| m.essence_str().to_string())
+                .unwrap_or_else(|| "no content type".to_string());
+            violations.push(violation(
+                kind,
+                format!(
+                    "{actual} is not one of the documented media types: {}",
+                    content.keys().cloned().collect::<Vec<_>>().join(", ")
+                ),
+            ));
+            return;
+        };
+
+        let is_json = content_type
+            .as_ref()
+            .is_some_and(|m| m.subtype() == mime::JSON || m.suffix() == Some(mime::JSON));
+        let Some(body_schema) = media.get("schema") else {
+            return;
+        };
+        if !is_json {
+            return;
+        }
+
+        match serde_json::from_slice::<Value>(part.body) {
+            Ok(instance) => {
+                for message in schema::validate(body_schema, &self.document, &instance) {
+                    violations.push(violation(kind, message));
+                }
+            }
+            Err(e) => violations.push(violation(kind, format!("body is not valid JSON: {e}"))),
+        }
+    }
+
+    fn deref<'a>(&'a self, value: &'a Value) -> &'a Value {
+        value
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|r| r.strip_prefix('#'))
+            .and_then(|pointer| self.document.pointer(pointer))
+            .unwrap_or(value)
+    }
+}
+
+fn server_base_path(url: &str) -> String {
+    let path = match url::Url::parse(url) {
+        Ok(parsed) => parsed.path().to_string(),
+        Err(_) => url.to_string(),
+    };
+    path.trim_end_matches('/').to_string()
+}
+
+fn violation(kind: &str, message: String) -> Violation {
+    Violation {
+        source: SOURCE.to_string(),
+        kind: kind.to_string(),
+        message,
+    }
+}
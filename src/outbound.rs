@@ -0,0 +1,262 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboundProxyKind {
+    Socks5,
+    Http,
+}
+
+/// A proxy that upstream connections are made through.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboundProxy {
+    pub kind: OutboundProxyKind,
+    pub host: String,
+    pub port: u16,
+    #[serde(skip_serializing)]
+    pub credentials: Option<(String, String)>,
+    /// Hosts reached directly, as in `NO_PROXY`.
+    pub no_proxy: Vec<String>,
+    /// Reach loopback hosts directly; set for proxies taken from the
+    /// environment, which are rarely meant for the local dev server.
+    pub bypass_loopback: bool,
+}
+
+impl OutboundProxy {
+    /// Parses `socks5://[user:pass@]host[:port]` or
+    /// `http://[user:pass@]host[:port]`.
+    pub fn parse(url: &str) -> Result<Self> {
+        let parsed =
+            url::Url::parse(url).with_context(|| format!("Invalid outbound proxy: {url}"))?;
+        let kind = match parsed.scheme() {
+            "socks5" | "socks5h" => OutboundProxyKind::Socks5,
+            "http" => OutboundProxyKind::Http,
+            scheme => bail!("Unsupported outbound proxy scheme: {scheme}"),
+        };
+        let Some(host) = parsed.host_str() else {
+            bail!("Outbound proxy has no host: {url}");
+        };
+        let port = parsed.port().unwrap_or(match kind {
+            OutboundProxyKind::Socks5 => 1080,
+            OutboundProxyKind::Http => 80,
+        });
+        let credentials = (!parsed.username().is_empty()).then(|| {
+            (
+                percent_decode(parsed.username()),
+                percent_decode(parsed.password().unwrap_or("")),
+            )
+        });
+
+        Ok(Self {
+            kind,
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            credentials,
+            no_proxy: Vec::new(),
+            bypass_loopback: false,
+        })
+    }
+
+    /// The proxy from `HTTP_PROXY` or `ALL_PROXY`, honouring `NO_PROXY`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let var = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+        };
+        let Some(url) = var(&["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]) else {
+            return Ok(None);
+        };
+        let mut proxy = Self::parse(&url)?;
+        proxy.no_proxy = var(&["NO_PROXY", "no_proxy"])
+            .map(|list| {
+                list.split(',')
+                    .map(|entry| entry.trim().to_ascii_lowercase())
+                    .filter(|entry| !entry.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        proxy.bypass_loopback = true;
+        Ok(Some(proxy))
+    }
+
+    /// Whether connections to `host` skip the proxy.
+    pub fn bypasses(&self, host: &str) -> bool {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        if self.bypass_loopback
+            && (host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback()))
+        {
+            return true;
+        }
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.');
+            entry == "*" || host == entry || host.ends_with(&format!(".{entry}"))
+        })
+    }
+
+    /// `Proxy-Authorization` value for requests sent through an HTTP proxy.
+    pub fn authorization(&self) -> Option<String> {
+        let (user, password) = self.credentials.as_ref()?;
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+        Some(format!("Basic {token}"))
+    }
+}
+
+/// Decodes the `%XX` escapes of a URL user name or password.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Asks a SOCKS5 proxy on `stream` to connect to `host:port`.
+pub async fn socks5_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&(String, String)>,
+) -> io::Result<()> {
+    let methods: &[u8] = if credentials.is_some() {
+        &[0x00, 0x02]
+    } else {
+        &[0x00]
+    };
+    let mut greeting = vec![0x05, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != 0x05 {
+        return Err(io::Error::other("Outbound proxy is not a SOCKS5 proxy"));
+    }
+    match (choice[1], credentials) {
+        (0x00, _) => {}
+        (0x02, Some((user, password))) => {
+            let mut auth = vec![0x01, user.len() as u8];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).await?;
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0x00 {
+                return Err(io::Error::other("SOCKS5 authentication failed"));
+            }
+        }
+        _ => {
+            return Err(io::Error::other(
+                "SOCKS5 proxy accepts none of the offered authentication methods",
+            ))
+        }
+    }
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let mut request = vec![0x05, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            request.extend_from_slice(&[0x03, host.len() as u8]);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 {
+        let reason = match reply[1] {
+            0x01 => "general failure",
+            0x02 => "connection not allowed by ruleset",
+            0x03 => "network unreachable",
+            0x04 => "host unreachable",
+            0x05 => "connection refused",
+            0x06 => "TTL expired",
+            0x07 => "command not supported",
+            0x08 => "address type not supported",
+            _ => "unknown error",
+        };
+        return Err(io::Error::other(format!(
+            "SOCKS5 proxy could not connect to {host}:{port}: {reason}"
+        )));
+    }
+    // Skip the bound address
+    let address_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await? as usize,
+        _ => return Err(io::Error::other("Invalid SOCKS5 reply")),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Opens a tunnel to `host:port` through an HTTP proxy on `stream`.
+pub async fn http_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    authorization: Option<&str>,
+) -> io::Result<()> {
+    let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if let Some(authorization) = authorization {
+        request.push_str(&format!("Proxy-Authorization: {authorization}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte so nothing after the headers is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(io::Error::other("Outbound proxy response headers too long"));
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(io::Error::other(format!(
+            "Outbound proxy refused CONNECT to {host}:{port}: {status_line}"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use anyhow::{bail, Context};
+use http::Method;
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::bench::parse_duration;
+use crate::recorder::HttpTransaction;
+
+/// Client address recorded for probe requests, to tell them from traffic.
+pub const PROBE_CLIENT: &str = "probe";
+
+/// A request the proxy sends through itself on a schedule, recorded like
+/// any other, for a latency and health timeline of an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Probe {
+    #[serde(serialize_with = "serialize_method")]
+    pub method: Method,
+    /// The upstream path with its query, if any.
+    pub path: String,
+    #[serde(rename = "interval_ms", serialize_with = "serialize_millis")]
+    pub interval: Duration,
+}
+
+impl std::str::FromStr for Probe {
+    type Err = anyhow::Error;
+
+    /// Parses `[METHOD ]PATH@INTERVAL`, such as `/health@5s` or
+    /// `POST /api/ping@500ms`. Intervals take `ms`, `s` or `m`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let Some((request, interval)) = s.rsplit_once('@') else {
+            bail!("Expected [METHOD ]PATH@INTERVAL, got {s}");
+        };
+        let (method, path) = match request.split_once(' ') {
+            Some((method, path)) => (
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .with_context(|| format!("Invalid method: {method}"))?,
+                path.trim(),
+            ),
+            None => (Method::GET, request),
+        };
+        if !path.starts_with('/') {
+            bail!("Probe path must start with '/', got {path}");
+        }
+        let interval = parse_duration(interval)?;
+        if interval.is_zero() {
+            bail!("Probe interval must be above zero");
+        }
+        Ok(Self {
+            method,
+            path: path.to_string(),
+            interval,
+        })
+    }
+}
+
+fn serialize_method<S: serde::Serializer>(
+    method: &Method,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(method.as_str())
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// One probe request, as the timeline shows it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub id: String,
+    pub timestamp: u64,
+    /// `None` when the probe got no response, see `error`.
+    pub status: Option<u16>,
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// A configured probe with its recorded results, in recorded order.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeTimeline {
+    #[serde(flatten)]
+    pub probe: Probe,
+    pub results: Vec<ProbeResult>,
+}
+
+impl ProbeTimeline {
+    /// Collects the recorded transactions `probe` sent.
+    pub fn collect<'a>(
+        probe: &Probe,
+        transactions: impl IntoIterator<Item = &'a HttpTransaction>,
+    ) -> Self {
+        let results = transactions
+            .into_iter()
+            .filter(|t| {
+                t.request.client_addr == PROBE_CLIENT
+                    && t.request.method == probe.method.as_str()
+                    && t.request.path == probe.path
+            })
+            .map(|t| ProbeResult {
+                id: t.request.id.clone(),
+                timestamp: t.request.timestamp,
+                status: t.response.as_ref().map(|response| response.status),
+                duration_ms: t.response.as_ref().map(|response| response.duration_ms),
+                error: t.error.clone(),
+            })
+            .collect();
+        Self {
+            probe: probe.clone(),
+            results,
+        }
+    }
+}
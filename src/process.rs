@@ -1,27 +1,357 @@
-use anyhow::{Context, Result};
-use parking_lot::Mutex;
-use std::process::{Child, Command, Stdio};
-use std::sync::Arc;
+use anyhow::{bail, Context, Result};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{broadcast, oneshot, watch};
+use tokio::task::JoinHandle;
 use tracing::{error, info, warn};
 
+use crate::usage::{ResourceUsage, UsageHistory};
+
+/// Default grace period between asking the command to exit (SIGTERM, or
+/// CTRL_BREAK on Windows) and killing it when stopping.
+pub const DEFAULT_KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default number of output lines kept from the managed command.
+pub const DEFAULT_MAX_LOG_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    /// Output of a pre-start or post-stop hook.
+    Hook,
+}
+
+impl LogStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+            LogStream::Hook => "hook",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+/// Ring buffer of the managed command's output. New lines are also
+/// broadcast to subscribers for live tailing.
+#[derive(Clone)]
+pub struct ProcessLogs {
+    lines: Arc<RwLock<VecDeque<LogLine>>>,
+    next_seq: Arc<Mutex<u64>>,
+    max_lines: usize,
+    sender: broadcast::Sender<LogLine>,
+}
+
+impl ProcessLogs {
+    pub fn new(max_lines: usize) -> Self {
+        let (sender, _) = broadcast::channel(256);
+        Self {
+            lines: Arc::new(RwLock::new(VecDeque::with_capacity(max_lines))),
+            next_seq: Arc::new(Mutex::new(1)),
+            max_lines,
+            sender,
+        }
+    }
+
+    pub fn push(&self, stream: LogStream, line: String) {
+        let mut next_seq = self.next_seq.lock();
+        let log_line = LogLine {
+            seq: *next_seq,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            stream,
+            line,
+        };
+        *next_seq += 1;
+
+        {
+            let mut lines = self.lines.write();
+            if lines.len() >= self.max_lines {
+                lines.pop_front();
+            }
+            lines.push_back(log_line.clone());
+        }
+        // No subscribers is fine
+        let _ = self.sender.send(log_line);
+    }
+
+    /// Returns the buffered lines with a sequence number above `since`.
+    pub fn get(&self, since: Option<u64>) -> Vec<LogLine> {
+        let since = since.unwrap_or(0);
+        self.lines
+            .read()
+            .iter()
+            .filter(|line| line.seq > since)
+            .cloned()
+            .collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogLine> {
+        self.sender.subscribe()
+    }
+
+    fn capture<R: AsyncRead + Unpin + Send + 'static>(
+        &self,
+        stream: LogStream,
+        reader: R,
+    ) -> JoinHandle<()> {
+        let logs = self.clone();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(reader);
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let line = String::from_utf8_lossy(&buf);
+                        logs.push(stream, line.trim_end_matches(['\r', '\n']).to_string());
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl Default for ProcessLogs {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_LOG_LINES)
+    }
+}
+
+/// How the managed command is restarted after it exits on its own.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    pub enabled: bool,
+    /// Consecutive crashes before giving up; `0` retries forever.
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// A process that stays up this long is considered healthy again and
+    /// its crash count is reset.
+    pub stable_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            stable_after: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Delay before the restart following the `failures`-th consecutive crash.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Snapshot of the managed command served at `/_proxy/api/process`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessStatus {
+    pub command: Vec<String>,
+    pub running: bool,
+    pub pid: Option<u32>,
+    pub uptime_ms: Option<u64>,
+    pub restart_count: u32,
+    pub last_exit_code: Option<i32>,
+    /// Stopped on purpose, so it is not restarted automatically.
+    pub stopped: bool,
+    pub consecutive_failures: u32,
+    pub next_restart_in_ms: Option<u64>,
+    /// Set when the restart policy gave up on a crash-looping command.
+    pub error: Option<String>,
+    /// Latest CPU and memory sample while running, where supported.
+    pub usage: Option<ResourceUsage>,
+}
+
+/// Sent to subscribers whenever the managed command exits.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessExit {
+    pub pid: u32,
+    pub code: Option<i32>,
+    /// Stopped on request rather than exiting on its own.
+    pub requested: bool,
+}
+
+#[derive(Default)]
+struct ProcessState {
+    started_at: Option<Instant>,
+    restart_count: u32,
+    last_exit_code: Option<i32>,
+    stopped: bool,
+    consecutive_failures: u32,
+    next_restart_at: Option<Instant>,
+    failed: Option<String>,
+}
+
+impl ProcessState {
+    /// Applies the restart policy to a process that exited on its own.
+    fn record_crash(&mut self, code: Option<i32>, policy: &RestartPolicy) {
+        let uptime = self.started_at.map(|at| at.elapsed());
+        self.record_exit(code);
+
+        if uptime.is_some_and(|uptime| uptime >= policy.stable_after) {
+            self.consecutive_failures = 0;
+        }
+        let code = code.map_or("a signal".to_string(), |c| format!("code {c}"));
+        self.record_failure(&format!("exited with {code}"), policy);
+    }
+
+    /// Counts a crash or failed restart and schedules the next attempt.
+    fn record_failure(&mut self, reason: &str, policy: &RestartPolicy) {
+        self.consecutive_failures += 1;
+
+        if !policy.enabled {
+            warn!("Upstream process {reason}; automatic restart is disabled");
+        } else if policy.max_retries > 0 && self.consecutive_failures > policy.max_retries {
+            let message = format!(
+                "Crash loop: failed {} times in a row, last {reason}; not restarting",
+                self.consecutive_failures
+            );
+            error!("{message}");
+            self.failed = Some(message);
+        } else {
+            let backoff = policy.backoff(self.consecutive_failures);
+            warn!("Upstream process {reason}; restarting in {backoff:?}");
+            self.next_restart_at = Some(Instant::now() + backoff);
+        }
+    }
+
+    fn record_exit(&mut self, code: Option<i32>) {
+        self.started_at = None;
+        self.last_exit_code = code;
+    }
+}
+
+/// The running command. The child itself is owned by the task waiting on it.
+struct RunningChild {
+    pid: u32,
+    /// Becomes `Some` once the child has exited and been reaped.
+    exited: watch::Receiver<Option<ProcessExit>>,
+    /// Sending on this makes the waiting task kill the child.
+    kill: Option<oneshot::Sender<()>>,
+    /// Holds the command and everything it spawns; closing it kills them.
+    #[cfg(windows)]
+    job: Option<windows::JobObject>,
+}
+
 #[derive(Clone)]
 pub struct ProcessManager {
-    child: Arc<Mutex<Option<Child>>>,
+    child: Arc<Mutex<Option<RunningChild>>>,
+    state: Arc<Mutex<ProcessState>>,
+    exits: broadcast::Sender<ProcessExit>,
     command: Vec<String>,
+    env: Vec<(String, String)>,
+    cwd: Option<PathBuf>,
+    logs: ProcessLogs,
+    /// Serializes start and stop, which may wait on hooks.
+    lifecycle: Arc<tokio::sync::Mutex<()>>,
+    pre_start: Vec<String>,
+    post_stop: Vec<String>,
+    usage: Arc<Mutex<UsageHistory>>,
+    restart_policy: RestartPolicy,
+    kill_timeout: Duration,
 }
 
 impl ProcessManager {
     pub fn new(command: Vec<String>) -> Self {
+        let (exits, _) = broadcast::channel(16);
         Self {
             child: Arc::new(Mutex::new(None)),
+            state: Arc::new(Mutex::new(ProcessState::default())),
+            exits,
             command,
+            env: Vec::new(),
+            cwd: None,
+            logs: ProcessLogs::default(),
+            lifecycle: Arc::new(tokio::sync::Mutex::new(())),
+            pre_start: Vec::new(),
+            post_stop: Vec::new(),
+            usage: Arc::new(Mutex::new(UsageHistory::default())),
+            restart_policy: RestartPolicy::default(),
+            kill_timeout: DEFAULT_KILL_TIMEOUT,
         }
     }
 
-    pub fn start(&self) -> Result<()> {
-        let mut child_lock = self.child.lock();
+    /// How long to wait after SIGTERM (CTRL_BREAK on Windows) before
+    /// killing the command on stop.
+    pub fn with_kill_timeout(mut self, timeout: Duration) -> Self {
+        self.kill_timeout = timeout;
+        self
+    }
+
+    pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Sets extra environment variables for the command. Later entries win
+    /// when a key appears more than once.
+    pub fn with_env(mut self, env: Vec<(String, String)>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// The captured stdout/stderr of the command.
+    pub fn logs(&self) -> ProcessLogs {
+        self.logs.clone()
+    }
+
+    /// Shell commands run in order before every start, e.g. migrations. The
+    /// command is not started when one of them fails.
+    pub fn with_pre_start_hooks(mut self, hooks: Vec<String>) -> Self {
+        self.pre_start = hooks;
+        self
+    }
+
+    /// Shell commands run in order after the command is stopped.
+    pub fn with_post_stop_hooks(mut self, hooks: Vec<String>) -> Self {
+        self.post_stop = hooks;
+        self
+    }
+
+    /// Runs the command in `cwd` instead of the proxy's working directory.
+    pub fn with_cwd(mut self, cwd: PathBuf) -> Self {
+        self.cwd = Some(cwd);
+        self
+    }
+
+    /// Notifies every exit of the command, whether requested or not.
+    pub fn subscribe(&self) -> broadcast::Receiver<ProcessExit> {
+        self.exits.subscribe()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let _lifecycle = self.lifecycle.lock().await;
 
-        if child_lock.is_some() {
+        if self.is_running() {
             return Ok(()); // Already running
         }
 
@@ -29,6 +359,12 @@ impl ProcessManager {
             return Err(anyhow::anyhow!("No command specified"));
         }
 
+        for hook in &self.pre_start {
+            self.run_hook("pre-start", hook).await?;
+        }
+
+        let mut child_lock = self.child.lock();
+
         info!("Starting command: {:?}", self.command);
 
         let mut cmd = Command::new(&self.command[0]);
@@ -37,122 +373,608 @@ impl ProcessManager {
             cmd.args(&self.command[1..]);
         }
 
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(ref cwd) = self.cwd {
+            cmd.current_dir(cwd);
+        }
+
+        #[cfg(unix)]
+        cmd.process_group(0);
+        // Its own console process group, so CTRL_BREAK reaches only the command
+        #[cfg(windows)]
+        cmd.creation_flags(windows::CREATE_NEW_PROCESS_GROUP);
+
         // Try to resolve the command if it's not found
-        let child = cmd
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
+        let mut child = cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .stdin(Stdio::null())
+            .kill_on_drop(true)
             .spawn()
             .with_context(|| {
                 format!("Failed to start command: {:?}. Make sure the command is in your PATH and executable.", self.command)
             })?;
+        let pid = child.id().context("Upstream process exited immediately")?;
 
-        info!("Started upstream process with PID: {}", child.id());
-        *child_lock = Some(child);
+        info!("Started upstream process with PID: {}", pid);
+        #[cfg(windows)]
+        let job = match child.raw_handle().map(windows::JobObject::assign) {
+            Some(Ok(job)) => Some(job),
+            Some(Err(e)) => {
+                warn!("Processes started by the command may outlive it: {e:#}");
+                None
+            }
+            None => None,
+        };
+        if let Some(stdout) = child.stdout.take() {
+            self.logs.capture(LogStream::Stdout, stdout);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            self.logs.capture(LogStream::Stderr, stderr);
+        }
+
+        {
+            let mut state = self.state.lock();
+            state.started_at = Some(Instant::now());
+            state.stopped = false;
+            state.next_restart_at = None;
+            if state.failed.take().is_some() {
+                state.consecutive_failures = 0;
+            }
+        }
+
+        // The state is up to date before the waiter can record an exit
+        let (exited_tx, exited) = watch::channel(None);
+        let (kill, kill_rx) = oneshot::channel();
+        tokio::spawn(wait_for_exit(
+            child,
+            pid,
+            kill_rx,
+            exited_tx,
+            ExitRecorder {
+                child: Arc::downgrade(&self.child),
+                state: Arc::clone(&self.state),
+                exits: self.exits.clone(),
+                restart_policy: self.restart_policy.clone(),
+            },
+        ));
+        *child_lock = Some(RunningChild {
+            pid,
+            exited,
+            kill: Some(kill),
+            #[cfg(windows)]
+            job,
+        });
 
         Ok(())
     }
 
-    pub fn stop(&self) -> Result<()> {
-        let mut child_lock = self.child.lock();
+    pub async fn stop(&self) -> Result<()> {
+        let _lifecycle = self.lifecycle.lock().await;
+        {
+            let mut state = self.state.lock();
+            state.stopped = true;
+            state.consecutive_failures = 0;
+            state.next_restart_at = None;
+        }
+        let Some(pid) = self.get_pid() else {
+            return Ok(());
+        };
+        info!("Stopping upstream process with PID: {}", pid);
 
-        if let Some(mut child) = child_lock.take() {
-            info!("Stopping upstream process with PID: {}", child.id());
+        #[cfg(unix)]
+        {
+            // The command runs in its own process group; signal all of it
+            // so grandchildren (e.g. node under `npm start`) go down too
+            let pgid = pid as i32;
+            unsafe {
+                libc::kill(-pgid, libc::SIGTERM);
+            }
 
-            #[cfg(unix)]
-            {
-                // Try graceful shutdown first
-                unsafe {
-                    libc::kill(child.id() as i32, libc::SIGTERM);
+            let deadline = Instant::now() + self.kill_timeout;
+            // The child is reaped as soon as it exits, so the group is gone
+            // once it has exited and nothing it spawned is left
+            while self.is_running() || unsafe { libc::kill(-pgid, 0) } == 0 {
+                if Instant::now() >= deadline {
+                    warn!(
+                        "Process group didn't exit within {:?}, force killing",
+                        self.kill_timeout
+                    );
+                    unsafe {
+                        libc::kill(-pgid, libc::SIGKILL);
+                    }
+                    break;
                 }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
 
-                // Wait a bit for graceful shutdown
-                std::thread::sleep(std::time::Duration::from_millis(100));
-
-                match child.try_wait() {
-                    Ok(Some(status)) => {
-                        info!("Process exited gracefully with status: {}", status);
-                        return Ok(());
-                    }
-                    Ok(None) => {
-                        warn!("Process didn't exit gracefully, force killing");
-                        // Force kill if still running
-                        unsafe {
-                            libc::kill(child.id() as i32, libc::SIGKILL);
-                        }
+        #[cfg(windows)]
+        {
+            windows::ctrl_break(pid);
+            if tokio::time::timeout(self.kill_timeout, self.wait())
+                .await
+                .is_err()
+            {
+                warn!(
+                    "Process didn't exit within {:?}, force killing",
+                    self.kill_timeout
+                );
+                let mut child = self.child.lock();
+                if let Some(ref mut child) = *child {
+                    if let Some(ref job) = child.job {
+                        job.terminate();
                     }
-                    Err(e) => {
-                        error!("Error checking process status: {}", e);
+                    if let Some(kill) = child.kill.take() {
+                        let _ = kill.send(());
                     }
                 }
             }
+        }
 
-            #[cfg(windows)]
-            {
-                let _ = child.kill();
+        #[cfg(not(any(unix, windows)))]
+        if let Some(kill) = self.child.lock().as_mut().and_then(|c| c.kill.take()) {
+            let _ = kill.send(());
+        }
+
+        if let Some(exit) = self.wait().await {
+            info!("Process exited with code: {:?}", exit.code);
+        }
+
+        for hook in &self.post_stop {
+            if let Err(e) = self.run_hook("post-stop", hook).await {
+                warn!("{e:#}");
             }
+        }
+        Ok(())
+    }
 
-            let _ = child.wait();
+    /// Runs `hook` through the shell with the command's environment and
+    /// working directory. Its output goes to the log buffer.
+    async fn run_hook(&self, stage: &str, hook: &str) -> Result<()> {
+        info!("Running {stage} hook: {hook}");
+        self.logs
+            .push(LogStream::Hook, format!("[{stage}] $ {hook}"));
+
+        let mut cmd = shell_command(hook);
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(ref cwd) = self.cwd {
+            cmd.current_dir(cwd);
         }
+        let mut child = cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run {stage} hook: {hook}"))?;
 
+        let mut output = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            output.push(self.logs.capture(LogStream::Hook, stdout));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            output.push(self.logs.capture(LogStream::Hook, stderr));
+        }
+        let status = child.wait().await?;
+        // Keep the hook's output ahead of whatever runs next
+        for task in output {
+            let _ = task.await;
+        }
+
+        if !status.success() {
+            bail!("The {stage} hook `{hook}` failed with {status}");
+        }
         Ok(())
     }
 
+    /// Waits for the running command to exit. Returns `None` right away
+    /// when nothing is running.
+    pub async fn wait(&self) -> Option<ProcessExit> {
+        let mut exited = self.child.lock().as_ref()?.exited.clone();
+        let exit = exited.wait_for(Option::is_some).await.ok()?;
+        exit.clone()
+    }
+
     pub fn is_running(&self) -> bool {
-        let mut child_lock = self.child.lock();
+        self.child.lock().is_some()
+    }
 
-        if let Some(child) = child_lock.as_mut() {
-            match child.try_wait() {
-                Ok(Some(_)) => {
-                    // Process has exited
-                    *child_lock = None;
-                    false
-                }
-                Ok(None) => {
-                    // Process is still running
-                    true
-                }
-                Err(_) => {
-                    // Error checking status, assume not running
-                    *child_lock = None;
-                    false
-                }
-            }
+    pub fn get_pid(&self) -> Option<u32> {
+        self.child.lock().as_ref().map(|child| child.pid)
+    }
+
+    pub async fn restart(&self) -> Result<()> {
+        self.stop().await?;
+        self.start().await?;
+        self.state.lock().restart_count += 1;
+        Ok(())
+    }
+
+    /// Restarts the command if it exited on its own and the restart policy
+    /// says it is time. Call this periodically; returns whether it restarted.
+    pub async fn supervise(&self) -> Result<bool> {
+        if self.is_running() {
+            return Ok(false);
+        }
+        let due = {
+            let state = self.state.lock();
+            !state.stopped
+                && state.failed.is_none()
+                && state.next_restart_at.is_some_and(|at| Instant::now() >= at)
+        };
+        if !due {
+            return Ok(false);
+        }
+
+        if let Err(e) = self.start().await {
+            // Back off as after a crash instead of retrying on every call
+            self.state
+                .lock()
+                .record_failure("failed to start", &self.restart_policy);
+            return Err(e);
+        }
+        self.state.lock().restart_count += 1;
+        Ok(true)
+    }
+
+    /// Sends `signal` to the command's process group.
+    #[cfg(unix)]
+    pub fn signal(&self, signal: i32) -> Result<()> {
+        let pid = self.get_pid().context("Upstream process is not running")?;
+        if unsafe { libc::kill(-(pid as i32), signal) } != 0 {
+            return Err(anyhow::anyhow!(
+                "Failed to send signal {signal}: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn signal(&self, _signal: i32) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "Signals are not supported on this platform"
+        ))
+    }
+
+    /// Samples the CPU and memory use of the command and its process group,
+    /// at most once per sample interval. Call this periodically.
+    pub fn sample_usage(&self) {
+        let pid = self.get_pid();
+        self.usage.lock().sample(pid);
+    }
+
+    /// Resource usage samples taken after `since` (Unix milliseconds).
+    pub fn usage(&self, since: Option<u64>) -> Vec<ResourceUsage> {
+        self.usage.lock().get(since)
+    }
+
+    /// Whether the command was stopped on purpose and should stay down.
+    pub fn is_stopped(&self) -> bool {
+        self.state.lock().stopped
+    }
+
+    pub fn status(&self) -> ProcessStatus {
+        let running = self.is_running();
+        let pid = self.get_pid();
+        let state = self.state.lock();
+        ProcessStatus {
+            command: self.command.clone(),
+            running,
+            pid,
+            uptime_ms: state
+                .started_at
+                .filter(|_| running)
+                .map(|started_at| started_at.elapsed().as_millis() as u64),
+            restart_count: state.restart_count,
+            last_exit_code: state.last_exit_code,
+            stopped: state.stopped,
+            consecutive_failures: state.consecutive_failures,
+            next_restart_in_ms: state
+                .next_restart_at
+                .map(|at| at.saturating_duration_since(Instant::now()).as_millis() as u64),
+            error: state.failed.clone(),
+            usage: self.usage.lock().current().filter(|_| running),
+        }
+    }
+}
+
+/// What the waiting task needs to record an exit. It holds the child slot
+/// weakly so it does not count as a [`ProcessManager`] clone.
+struct ExitRecorder {
+    child: Weak<Mutex<Option<RunningChild>>>,
+    state: Arc<Mutex<ProcessState>>,
+    exits: broadcast::Sender<ProcessExit>,
+    restart_policy: RestartPolicy,
+}
+
+/// Reaps the child, records how it exited and notifies waiters.
+async fn wait_for_exit(
+    mut child: Child,
+    pid: u32,
+    mut kill: oneshot::Receiver<()>,
+    exited: watch::Sender<Option<ProcessExit>>,
+    recorder: ExitRecorder,
+) {
+    let status = tokio::select! {
+        status = child.wait() => status,
+        _ = &mut kill => {
+            let _ = child.start_kill();
+            child.wait().await
+        }
+    };
+    let code = status.ok().and_then(|status| status.code());
+
+    // Same lock order as start(): the child slot, then the state
+    let slot = recorder.child.upgrade();
+    let mut running = slot.as_ref().map(|slot| slot.lock());
+    let requested = {
+        let mut state = recorder.state.lock();
+        if state.stopped {
+            state.record_exit(code);
         } else {
-            false
+            state.record_crash(code, &recorder.restart_policy);
+        }
+        state.stopped
+    };
+    if let Some(ref mut running) = running {
+        if running.as_ref().is_some_and(|c| c.pid == pid) {
+            **running = None;
         }
     }
+    drop(running);
 
-    pub fn get_pid(&self) -> Option<u32> {
-        self.child.lock().as_ref().map(|child| child.id())
+    let exit = ProcessExit {
+        pid,
+        code,
+        requested,
+    };
+    // No subscribers is fine
+    let _ = recorder.exits.send(exit.clone());
+    let _ = exited.send(Some(exit));
+}
+
+/// Runs `script` through the platform's shell.
+fn shell_command(script: &str) -> Command {
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
+    #[cfg(not(windows))]
+    let (shell, flag) = ("sh", "-c");
+
+    let mut cmd = Command::new(shell);
+    cmd.arg(flag).arg(script);
+    cmd
+}
+
+/// The command line that runs `script` through the platform's shell, for
+/// [`ProcessManager::new`].
+pub fn shell_argv(script: &str) -> Vec<String> {
+    let program = shell_command(script);
+    let program = program.as_std();
+    std::iter::once(program.get_program())
+        .chain(program.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Asks the OS for a currently unused local TCP port.
+pub fn free_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))
+        .context("Failed to find a free port for the upstream command")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Parses a `KEY=VALUE` pair as given to `--env`.
+pub fn parse_env_var(value: &str) -> Result<(String, String)> {
+    let (key, value) = value
+        .split_once('=')
+        .with_context(|| format!("Invalid environment variable {value:?}, expected KEY=VALUE"))?;
+    if key.is_empty() {
+        return Err(anyhow::anyhow!("Environment variable name cannot be empty"));
     }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Reads a `.env` file: `KEY=VALUE` lines with optional `export ` prefixes,
+/// `#` comments and single- or double-quoted values.
+pub fn load_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read env file: {}", path.display()))?;
 
-    pub fn restart(&self) -> Result<()> {
-        self.stop()?;
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        self.start()
+    let mut vars = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) =
+            parse_env_var(line).with_context(|| format!("{}:{}", path.display(), i + 1))?;
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&q| value.strip_prefix(q).and_then(|v| v.strip_suffix(q)))
+            .unwrap_or(value);
+        vars.push((key.trim().to_string(), value.to_string()));
     }
+    Ok(vars)
 }
 
 impl Drop for ProcessManager {
     fn drop(&mut self) {
-        if let Err(e) = self.stop() {
-            error!("Error stopping process in drop: {}", e);
+        // Clones share the child; only the last one stops it
+        if Arc::strong_count(&self.child) > 1 {
+            return;
+        }
+        // Nothing can wait for a graceful exit here; stop() is the clean way
+        if let Some(mut child) = self.child.lock().take() {
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(-(child.pid as i32), libc::SIGKILL);
+            }
+            if let Some(kill) = child.kill.take() {
+                let _ = kill.send(());
+            }
         }
     }
 }
 
-#[cfg(unix)]
-extern "C" {
-    fn kill(pid: i32, sig: i32) -> i32;
+/// Parses a signal name such as `HUP`, `SIGUSR2` or `usr1`.
+pub fn parse_signal(name: &str) -> Option<i32> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(signal_name, _)| *signal_name == name)
+        .map(|(_, number)| *number)
 }
 
+/// Signals that can be sent to the managed command by name.
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", 1),
+    ("INT", 2),
+    ("QUIT", 3),
+    ("KILL", 9),
+    ("TERM", 15),
+    ("USR1", SIGUSR1),
+    ("USR2", SIGUSR2),
+];
+
+// Unlike the others these vary between platforms, such as 30 and 31 on the
+// BSDs and macOS
+#[cfg(unix)]
+const SIGUSR1: i32 = libc::SIGUSR1;
 #[cfg(unix)]
-mod libc {
-    pub const SIGTERM: i32 = 15;
-    pub const SIGKILL: i32 = 9;
+const SIGUSR2: i32 = libc::SIGUSR2;
+#[cfg(not(unix))]
+const SIGUSR1: i32 = 10;
+#[cfg(not(unix))]
+const SIGUSR2: i32 = 12;
+
+/// Console control events and Job Objects, the Windows counterparts of
+/// SIGTERM and process groups.
+#[cfg(windows)]
+mod windows {
+    use anyhow::{bail, Result};
+    use std::ffi::c_void;
 
-    pub unsafe fn kill(pid: i32, sig: i32) -> i32 {
-        super::kill(pid, sig)
+    pub const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    const CTRL_BREAK_EVENT: u32 = 1;
+    const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x0000_2000;
+    const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS: i32 = 9;
+
+    type Handle = *mut c_void;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct BasicLimitInformation {
+        per_process_user_time_limit: i64,
+        per_job_user_time_limit: i64,
+        limit_flags: u32,
+        minimum_working_set_size: usize,
+        maximum_working_set_size: usize,
+        active_process_limit: u32,
+        affinity: usize,
+        priority_class: u32,
+        scheduling_class: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct IoCounters {
+        read_operation_count: u64,
+        write_operation_count: u64,
+        other_operation_count: u64,
+        read_transfer_count: u64,
+        write_transfer_count: u64,
+        other_transfer_count: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ExtendedLimitInformation {
+        basic_limit_information: BasicLimitInformation,
+        io_info: IoCounters,
+        process_memory_limit: usize,
+        job_memory_limit: usize,
+        peak_process_memory_used: usize,
+        peak_job_memory_used: usize,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GenerateConsoleCtrlEvent(event: u32, process_group_id: u32) -> i32;
+        fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> Handle;
+        fn SetInformationJobObject(
+            job: Handle,
+            class: i32,
+            information: *mut c_void,
+            length: u32,
+        ) -> i32;
+        fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+        fn TerminateJobObject(job: Handle, exit_code: u32) -> i32;
+        fn CloseHandle(handle: Handle) -> i32;
+    }
+
+    /// Asks the console process group led by `pid` to exit.
+    pub fn ctrl_break(pid: u32) {
+        unsafe {
+            GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid);
+        }
+    }
+
+    /// A Job Object that kills every process in it once it is closed.
+    pub struct JobObject(Handle);
+
+    // The handle is only passed to thread-safe kernel calls
+    unsafe impl Send for JobObject {}
+    unsafe impl Sync for JobObject {}
+
+    impl JobObject {
+        /// Puts `process` in a new job. Processes it starts join the job too.
+        pub fn assign(process: std::os::windows::io::RawHandle) -> Result<Self> {
+            unsafe {
+                let handle = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+                if handle.is_null() {
+                    bail!(
+                        "Failed to create job object: {}",
+                        std::io::Error::last_os_error()
+                    );
+                }
+                let job = Self(handle);
+
+                let mut info = ExtendedLimitInformation::default();
+                info.basic_limit_information.limit_flags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                if SetInformationJobObject(
+                    job.0,
+                    JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS,
+                    &mut info as *mut _ as *mut c_void,
+                    std::mem::size_of::<ExtendedLimitInformation>() as u32,
+                ) == 0
+                    || AssignProcessToJobObject(job.0, process as Handle) == 0
+                {
+                    bail!(
+                        "Failed to set up job object: {}",
+                        std::io::Error::last_os_error()
+                    );
+                }
+                Ok(job)
+            }
+        }
+
+        pub fn terminate(&self) {
+            unsafe {
+                TerminateJobObject(self.0, 1);
+            }
+        }
+    }
+
+    impl Drop for JobObject {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
     }
 }
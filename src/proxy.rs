@@ -1,315 +1,3183 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use http::{header, Method, Request, Response, StatusCode};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http::{header, HeaderMap, Method, Request, Response, StatusCode, Uri, Version};
+use hyper::body::HttpBody;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Client, Server};
-use hyper_rustls::HttpsConnectorBuilder;
+use hyper::upgrade::OnUpgrade;
+use hyper::{Body, Server};
 use tracing::{debug, error, info, warn};
 
-use crate::config::SharedConfig;
-use crate::recorder::{RequestInfo, RequestRecorder, ResponseInfo};
+use crate::admin_ui::fallback_page;
+use crate::assertions::{SchemaAssertion, SchemaAssertionSet, SchemaAssertions};
+use crate::audit::{config_changes, AuditLog};
+use crate::auth::{asset_key, request_cookie, tokens_match, TokenGuard, ASSET_COOKIE, LOCKOUT};
+use crate::balancer::{Balancer, Pick};
+use crate::baseline::{BaselineStore, DEFAULT_IGNORED_HEADERS};
+use crate::bench::Latency;
+use crate::body_size::{self, BodyPart, SizeWarning};
+use crate::cache;
+#[cfg(feature = "decoders")]
+use crate::compression::{compress_response, decode_response};
+use crate::config::{Decompression, SharedConfig};
+use crate::cors::CorsPolicy;
+use crate::diff::diff_transactions;
+use crate::endpoints;
+use crate::events::{upstream_failure, ConnectionEvents, EventKind};
+use crate::export::{to_bundle, to_har, to_hurl, to_jsonl, to_k6, to_mitmproxy, BundleContext};
+#[cfg(feature = "tls")]
+use crate::forward::CertificateAuthority;
+use crate::forward::ForwardProxy;
+use crate::health::{check_upstream, Health, Readiness, CONNECT_TIMEOUT};
+use crate::hold::{HoldQueue, HoldRule};
+use crate::inbox::Inbox;
+use crate::inflight::{InFlightRequests, InFlightState};
+use crate::memory::{MemoryMonitor, Pressure};
+use crate::mock::{self, OfflineMode, MOCK_HEADER};
+use crate::openapi::{ExchangePart, OpenApiSpec};
+use crate::outbound::OutboundProxyKind;
+use crate::probe::{Probe, ProbeTimeline, PROBE_CLIENT};
+use crate::process::{parse_signal, ProcessLogs, ProcessManager};
+use crate::public_tunnel::PublicTunnel;
+use crate::recorder::{
+    now_ms, HoldRecord, HttpTransaction, PreflightView, ProxyOverhead, RequestInfo,
+    RequestRecorder, ResponseInfo, SizeTotals, Violation,
+};
+use crate::script::{RouteScript, ScriptRequest};
+use crate::search;
+use crate::services::Services;
+use crate::snapshot::Snapshot;
+use crate::timeline::build_timeline;
+use crate::transform::{Exchange, ResponseTransforms, TransformRule, TransformSet};
+use crate::tunnel::{relay, Relayed};
+use crate::upstream::{
+    build_client, build_client_with_identity, upstream_base_url, with_raw_response, ClientIdentity,
+    ConnectionTag, UpstreamClient,
+};
+use crate::version::BuildInfo;
+#[cfg(feature = "ui")]
 use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
 
+/// The upstream's side of a connection that switched protocols, carried on
+/// the response until the client's side is ready to be joined to it.
+struct UpstreamUpgrade {
+    protocol: String,
+    upgrade: OnUpgrade,
+}
+
+/// Where a recorded request came from.
+#[derive(Debug, Clone)]
+struct Origin {
+    client_addr: String,
+    /// The listen address that accepted the connection.
+    listener: Option<SocketAddr>,
+    /// The inbox capture this one replays, which is not captured or held
+    /// again.
+    replay_of: Option<String>,
+    /// The held request this one delivers once released, which is not
+    /// captured or held again.
+    held_from: Option<String>,
+}
+
+/// A message body read in full, with the trailer fields that followed it.
+/// hyper only reads and writes trailers over HTTP/2; HTTP/1.1 chunked
+/// trailers are dropped.
+struct BufferedBody {
+    bytes: Bytes,
+    trailers: Option<HeaderMap>,
+}
+
+impl BufferedBody {
+    /// Reads `body` to the end. On failure returns what arrived before the
+    /// error with it.
+    async fn read(mut body: Body) -> Result<Self, (Bytes, hyper::Error)> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(chunk) => bytes.extend_from_slice(&chunk),
+                Err(e) => return Err((Bytes::from(bytes), e)),
+            }
+        }
+        match body.trailers().await {
+            Ok(trailers) => Ok(Self {
+                bytes: Bytes::from(bytes),
+                trailers,
+            }),
+            Err(e) => Err((Bytes::from(bytes), e)),
+        }
+    }
+
+    fn into_body(self) -> Body {
+        let Some(trailers) = self.trailers else {
+            return Body::from(self.bytes);
+        };
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            if sender.send_data(self.bytes).await.is_ok() {
+                let _ = sender.send_trailers(trailers).await;
+            }
+        });
+        body
+    }
+}
+
+/// When a proxied request was sent upstream and how long the upstream took
+/// to answer, carried in the response extensions for `Server-Timing` and the
+/// recorded overhead.
+#[derive(Debug, Clone, Copy)]
+struct UpstreamTiming {
+    sent_at: Instant,
+    /// Until the response headers arrived.
+    headers: Duration,
+    /// Until the whole response body arrived.
+    duration: Duration,
+}
+
+/// Marks a transaction as aborted by its client if dropped before
+/// [`AbortGuard::disarm`]. hyper drops a request's future when the client
+/// goes away, which also cancels the upstream request in flight.
+struct AbortGuard<'a> {
+    proxy: &'a DebugProxy,
+    request_id: &'a str,
+    client_addr: &'a str,
+    method: &'a Method,
+    path: &'a str,
+    start_time: Instant,
+    armed: bool,
+}
+
+impl AbortGuard<'_> {
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for AbortGuard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        info!(
+            "Client aborted {} {} while it was waiting on the upstream",
+            self.method, self.path
+        );
+        self.proxy.recorder.record_client_aborted(
+            self.request_id,
+            self.start_time.elapsed().as_millis() as u64,
+        );
+        self.proxy.events.push(
+            self.client_addr,
+            Some(self.request_id),
+            EventKind::ClientAborted {
+                method: self.method.to_string(),
+                path: self.path.to_string(),
+            },
+        );
+    }
+}
+
+#[cfg(feature = "ui")]
 #[derive(RustEmbed)]
 #[folder = "ui/dist/"]
 struct Assets;
 
+/// An embedded UI file with its content type.
+#[cfg(feature = "ui")]
+fn embedded_asset(path: &str) -> Option<(Vec<u8>, String)> {
+    Assets::get(path).map(|content| {
+        let content_type = content.metadata.mimetype().to_string();
+        (content.data.into_owned(), content_type)
+    })
+}
+
+/// Built without the `ui` feature, so the admin page is the fallback.
+#[cfg(not(feature = "ui"))]
+fn embedded_asset(_path: &str) -> Option<(Vec<u8>, String)> {
+    None
+}
+
 pub struct DebugProxy {
     config: SharedConfig,
     recorder: RequestRecorder,
     upstream_address: String,
-    client: Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
+    client: Arc<parking_lot::RwLock<UpstreamClient>>,
+    /// Clients for routes with their own client certificate, built on first
+    /// use.
+    identity_clients: Arc<parking_lot::Mutex<HashMap<ClientIdentity, UpstreamClient>>>,
+    openapi: Option<Arc<OpenApiSpec>>,
+    assertions: SchemaAssertions,
+    transforms: ResponseTransforms,
+    baseline: BaselineStore,
+    process_logs: Option<ProcessLogs>,
+    process: Option<ProcessManager>,
+    services: Services,
+    forward_proxy: Option<ForwardProxy>,
+    /// Requests routed so far by the upstream split.
+    split_requests: Arc<AtomicU64>,
+    balancer: Option<Arc<Balancer>>,
+    route_script: Option<Arc<RouteScript>>,
+    events: ConnectionEvents,
+    probes: Vec<Probe>,
+    inflight: InFlightRequests,
+    audit: AuditLog,
+    token_guard: TokenGuard,
+    memory: Option<MemoryMonitor>,
+    public_tunnel: Option<PublicTunnel>,
+    inbox: Option<Inbox>,
+    holds: HoldQueue,
 }
 
 impl DebugProxy {
     pub fn new(config: SharedConfig, recorder: RequestRecorder, upstream_address: String) -> Self {
-        let https = HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_or_http()
-            .enable_http1()
-            .build();
-
-        let client = Client::builder().build::<_, hyper::Body>(https);
+        let client = Arc::new(parking_lot::RwLock::new(build_client(&config.read())));
+        recorder.set_binary_detection(config.read().binary_detection.clone());
+        recorder.set_duplicate_window(config.read().duplicate_window);
 
         Self {
             config,
             recorder,
             upstream_address,
             client,
+            identity_clients: Arc::default(),
+            openapi: None,
+            assertions: SchemaAssertions::new(),
+            transforms: ResponseTransforms::new(),
+            baseline: BaselineStore::new(),
+            process_logs: None,
+            process: None,
+            services: Services::default(),
+            forward_proxy: None,
+            split_requests: Arc::new(AtomicU64::new(0)),
+            balancer: None,
+            route_script: None,
+            events: ConnectionEvents::default(),
+            probes: Vec::new(),
+            inflight: InFlightRequests::default(),
+            audit: AuditLog::default(),
+            token_guard: TokenGuard::default(),
+            memory: None,
+            public_tunnel: None,
+            inbox: None,
+            holds: HoldQueue::default(),
         }
     }
 
+    /// Validates every proxied transaction against the given OpenAPI spec.
+    pub fn with_openapi_spec(mut self, spec: OpenApiSpec) -> Self {
+        self.openapi = Some(Arc::new(spec));
+        self
+    }
+
+    /// Uses the given per-route schema assertions instead of an empty set.
+    pub fn with_schema_assertions(mut self, assertions: SchemaAssertions) -> Self {
+        self.assertions = assertions;
+        self
+    }
+
+    /// Rewrites upstream responses with the given transforms.
+    pub fn with_transforms(mut self, transforms: ResponseTransforms) -> Self {
+        self.transforms = transforms;
+        self
+    }
+
+    /// Uses the given baseline store, e.g. one backed by a file.
+    pub fn with_baseline(mut self, baseline: BaselineStore) -> Self {
+        self.baseline = baseline;
+        self
+    }
+
+    /// Serves the managed upstream command's output from the admin API.
+    #[allow(dead_code)]
+    pub fn with_process_logs(mut self, logs: ProcessLogs) -> Self {
+        self.process_logs = Some(logs);
+        self
+    }
+
+    /// Lets the admin API report on and control the managed upstream
+    /// command, and serves its output.
+    pub fn with_process_manager(mut self, process: ProcessManager) -> Self {
+        self.process_logs = Some(process.logs());
+        self.process = Some(process);
+        self
+    }
+
+    /// Routes requests to the managed services by path prefix. Paths no
+    /// service claims still go to the default upstream.
+    pub fn with_services(mut self, services: Services) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Lets the script pick the upstream for requests to the default one,
+    /// ahead of any split or balancer.
+    pub fn with_route_script(mut self, script: RouteScript) -> Self {
+        self.route_script = Some(Arc::new(script));
+        self
+    }
+
+    /// Balances requests for the default upstream across the balancer's
+    /// instances instead of sending them all to one.
+    pub fn with_balancer(mut self, balancer: Arc<Balancer>) -> Self {
+        self.balancer = Some(balancer);
+        self
+    }
+
+    /// Sends each probe through the proxy on its interval while serving.
+    pub fn with_probes(mut self, probes: Vec<Probe>) -> Self {
+        self.probes = probes;
+        self
+    }
+
+    /// Backs recording off as the proxy's resident memory nears
+    /// `limit_bytes`, while it serves.
+    pub fn with_memory_limit(mut self, limit_bytes: u64) -> Self {
+        self.memory = Some(MemoryMonitor::new(limit_bytes));
+        self
+    }
+
+    /// Marks the requests that arrived through `tunnel`'s public URL.
+    pub fn with_public_tunnel(mut self, tunnel: PublicTunnel) -> Self {
+        self.public_tunnel = Some(tunnel);
+        self
+    }
+
+    /// Answers and records the requests `inbox` captures instead of
+    /// forwarding them.
+    pub fn with_inbox(mut self, inbox: Inbox) -> Self {
+        self.inbox = Some(inbox);
+        self
+    }
+
+    /// Keeps requests matching the queue's rules from the upstream until
+    /// their delay runs out or they are released from the admin API.
+    pub fn with_holds(mut self, holds: HoldQueue) -> Self {
+        self.holds = holds;
+        self
+    }
+
+    /// Also serves as a forward proxy: absolute-form requests go to the host
+    /// they name and CONNECT opens tunnels, intercepted when `mitm` is set.
+    pub fn with_forward_proxy(mut self, forward_proxy: ForwardProxy) -> Self {
+        self.forward_proxy = Some(forward_proxy);
+        self
+    }
+
+    #[allow(dead_code)]
     pub async fn start_server(&self, listen_addr: SocketAddr) -> Result<()> {
+        self.start_servers(&[listen_addr]).await
+    }
+
+    /// Serves the proxy on every address in `listen_addrs`. All addresses are
+    /// bound before any is served, so one that is taken fails the whole call.
+    pub async fn start_servers(&self, listen_addrs: &[SocketAddr]) -> Result<()> {
+        self.serve(bind_listeners(listen_addrs, false)?).await
+    }
+
+    /// Serves the proxy on listeners from [`bind_listeners`].
+    pub async fn serve(&self, listeners: Vec<std::net::TcpListener>) -> Result<()> {
         let proxy = Arc::new(self.clone());
 
-        let make_svc = make_service_fn(move |_conn| {
+        let mut servers = tokio::task::JoinSet::new();
+        for listener in listeners {
+            let listen_addr = listener.local_addr()?;
+            let proxy = Arc::clone(&proxy);
+            let make_svc = make_service_fn(move |conn: &AddrStream| {
+                let proxy = Arc::clone(&proxy);
+                let origin = Origin {
+                    client_addr: conn.remote_addr().to_string(),
+                    listener: Some(listen_addr),
+                    replay_of: None,
+                    held_from: None,
+                };
+                let connection = proxy.events.track_connection(&origin.client_addr);
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        connection.count_request();
+                        let proxy = Arc::clone(&proxy);
+                        let origin = origin.clone();
+                        async move { proxy.handle_request(req, origin).await }
+                    }))
+                }
+            });
+
+            let server = Server::from_tcp(listener)?.serve(make_svc);
+
+            info!("Proxy server listening on {}", listen_addr);
+
+            servers.spawn(async move {
+                if let Err(e) = server.await {
+                    error!("Server error on {}: {}", listen_addr, e);
+                }
+            });
+        }
+        for probe in self.probes.clone() {
+            let proxy = Arc::clone(&proxy);
+            servers.spawn(async move { proxy.run_probe(probe).await });
+        }
+        if let Some(memory) = self.memory.clone() {
+            let recorder = self.recorder.clone();
+            servers.spawn(async move { memory.run(recorder).await });
+        }
+        while servers.join_next().await.is_some() {}
+
+        Ok(())
+    }
+
+    /// Serves the admin API alone on `listener`, without the token. Meant for
+    /// a loopback address, where only tools on the same machine reach it.
+    pub async fn serve_local_admin(&self, listener: std::net::TcpListener) -> Result<()> {
+        let proxy = Arc::new(self.clone());
+        let listen_addr = listener.local_addr()?;
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
             let proxy = Arc::clone(&proxy);
+            let client_addr = conn.remote_addr().to_string();
             async move {
                 Ok::<_, Infallible>(service_fn(move |req| {
                     let proxy = Arc::clone(&proxy);
-                    async move { proxy.handle_request(req).await }
+                    let client_addr = client_addr.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            proxy
+                                .handle_local_admin_request(req, &client_addr, listen_addr.port())
+                                .await,
+                        )
+                    }
                 }))
             }
         });
 
-        let server = Server::bind(&listen_addr).serve(make_svc);
-
-        info!("Proxy server listening on {}", listen_addr);
+        info!("Admin API listening on {} without a token", listen_addr);
+        Server::from_tcp(listener)?.serve(make_svc).await?;
+        Ok(())
+    }
 
-        if let Err(e) = server.await {
-            error!("Server error: {}", e);
+    /// The proxy as a [`ProxyService`], for serving it from another server
+    /// instead of [`serve`](Self::serve).
+    #[allow(dead_code)]
+    pub fn into_service(self) -> ProxyService {
+        ProxyService {
+            proxy: Arc::new(self),
         }
+    }
 
-        Ok(())
+    async fn run_probe(&self, probe: Probe) {
+        let uri = match probe.path.parse::<Uri>() {
+            Ok(uri) => uri,
+            Err(e) => {
+                warn!("Not probing {}: {e}", probe.path);
+                return;
+            }
+        };
+        let mut ticks = tokio::time::interval(probe.interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let (_, response) = self
+                .forward(
+                    &probe.method,
+                    &uri,
+                    Version::HTTP_11,
+                    &HeaderMap::new(),
+                    BufferedBody {
+                        bytes: Bytes::new(),
+                        trailers: None,
+                    },
+                    Origin {
+                        client_addr: PROBE_CLIENT.to_string(),
+                        listener: None,
+                        replay_of: None,
+                        held_from: None,
+                    },
+                )
+                .await;
+            debug!(
+                "Probe {} {}: {}",
+                probe.method,
+                probe.path,
+                response.status()
+            );
+        }
     }
 
-    async fn handle_request(&self, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    async fn handle_request(
+        &self,
+        req: Request<Body>,
+        origin: Origin,
+    ) -> Result<Response<Body>, Infallible> {
         let method = req.method().clone();
         let uri = req.uri().clone();
-        let version = req.version();
-        let headers = req.headers().clone();
+        #[cfg(feature = "decoders")]
+        let accept_encoding = req.headers().get(header::ACCEPT_ENCODING).cloned();
 
         debug!("Incoming request: {} {}", method, uri.path());
 
-        // Handle admin requests
-        let is_admin_request = self.should_handle_admin_request(uri.path());
+        if method == Method::CONNECT && self.forward_proxy.is_some() {
+            return Ok(self.handle_connect(req, origin).await);
+        }
+
+        // Handle admin requests, which absolute-form requests are not
+        let is_admin_request = self.should_handle_admin_request(uri.path())
+            && !(self.forward_proxy.is_some() && uri.authority().is_some());
         debug!(
             "Should handle as admin request? {} for path: {}",
             is_admin_request,
             uri.path()
         );
         if is_admin_request {
-            return Ok(self.handle_admin_request(req).await.unwrap_or_else(|e| {
-                error!("Error handling admin request: {}", e);
-                Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from("Internal Server Error"))
-                    .unwrap()
-            }));
+            let response = self
+                .handle_admin_request(req, &origin.client_addr)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Error handling admin request: {}", e);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Internal Server Error"))
+                        .unwrap()
+                });
+            #[cfg(feature = "decoders")]
+            let response = compress_response(response, accept_encoding.as_ref());
+            return Ok(response);
         }
 
-        // Handle proxy requests
-        let client_addr = "unknown".to_string(); // In a real implementation, extract from connection
-        let start_time = Instant::now();
+        self.proxy_request(req, origin).await
+    }
 
-        // Read request body
-        let (_parts, body) = req.into_parts();
-        let body_bytes = match hyper::body::to_bytes(body).await {
-            Ok(bytes) => bytes.to_vec(),
-            Err(e) => {
+    /// Reads the request body and forwards the request.
+    async fn proxy_request(
+        &self,
+        req: Request<Body>,
+        origin: Origin,
+    ) -> Result<Response<Body>, Infallible> {
+        let received_at = Instant::now();
+        // hyper sends `100 Continue` once the body is read, which is the only
+        // expectation there is; others must be refused before reading it
+        let expect_continue = match req.headers().get(header::EXPECT) {
+            None => false,
+            Some(expect) if expect.as_bytes().eq_ignore_ascii_case(b"100-continue") => {
+                req.version() == Version::HTTP_11
+            }
+            Some(_) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::EXPECTATION_FAILED)
+                    .body(Body::from("Expectation Failed"))
+                    .unwrap());
+            }
+        };
+        let (mut parts, body) = req.into_parts();
+        let client_upgrade = parts.extensions.remove::<OnUpgrade>();
+        let client_addr = origin.client_addr.clone();
+        let client_timeout = self
+            .config
+            .read()
+            .for_route(&parts.method, parts.uri.path())
+            .client_timeout;
+        let body = match tokio::time::timeout(client_timeout, BufferedBody::read(body)).await {
+            Ok(Ok(body)) => body,
+            Ok(Err((_, e))) => {
                 error!("Error reading request body: {}", e);
+                self.events.push(
+                    &origin.client_addr,
+                    None,
+                    EventKind::ClientReset {
+                        error: e.to_string(),
+                    },
+                );
                 return Ok(Response::builder()
                     .status(StatusCode::BAD_REQUEST)
                     .body(Body::from("Bad Request"))
                     .unwrap());
             }
+            Err(_) => {
+                warn!(
+                    "Client took longer than {:?} to send the request body",
+                    client_timeout
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::REQUEST_TIMEOUT)
+                    .body(Body::from("Request Timeout"))
+                    .unwrap());
+            }
         };
 
-        // Record the request
-        let (request_id, upstream_timeout) = {
+        // hyper sends nothing for an empty body
+        let sent_continue = expect_continue && !body.bytes.is_empty();
+        let forwarded_at = Instant::now();
+        let (request_id, mut response) = self
+            .forward(
+                &parts.method,
+                &parts.uri,
+                parts.version,
+                &parts.headers,
+                body,
+                origin,
+            )
+            .await;
+        if sent_continue {
+            self.recorder
+                .record_interim(&request_id, StatusCode::CONTINUE);
+        }
+        if let Some(upstream) = response.extensions_mut().remove::<UpstreamUpgrade>() {
+            match client_upgrade {
+                Some(client) => self.tunnel_upgrade(&request_id, &client_addr, client, upstream),
+                None => {
+                    self.recorder.record_error(
+                        &request_id,
+                        "The upstream switched protocols, which the client connection cannot"
+                            .to_string(),
+                    );
+                    response = Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(Body::from("Bad Gateway"))
+                        .unwrap();
+                }
+            }
+        }
+        let (debug_headers, cors) = {
             let config = self.config.read();
-            let request_info = RequestInfo {
-                method: &method,
-                path: uri.path(),
-                version,
-                headers: &headers,
-                body: &body_bytes,
-                client_addr,
-                truncate_at: config.truncate_body_at,
-            };
-            let request_id = self.recorder.record_request(request_info);
-            let upstream_timeout = config.upstream_timeout;
-            (request_id, upstream_timeout)
+            (config.debug_headers, config.cors.clone())
         };
-
-        // Forward to upstream
-        let upstream_uri = format!(
-            "http://{}{}",
-            self.upstream_address,
-            uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("")
+        if let Some(cors) = cors {
+            if !CorsPolicy::is_preflight(&parts.method, &parts.headers) {
+                cors.apply(&parts.headers, response.headers_mut());
+            }
+        }
+        if debug_headers {
+            add_debug_headers(&mut response, &request_id, received_at);
+        }
+        self.recorder.record_overhead(
+            &request_id,
+            proxy_overhead(
+                received_at,
+                forwarded_at,
+                response.extensions().get::<UpstreamTiming>(),
+            ),
         );
+        Ok(response)
+    }
 
-        let upstream_req = Request::builder()
-            .method(&method)
-            .uri(&upstream_uri)
-            .version(version);
-
-        let upstream_req = headers
-            .into_iter()
-            .fold(upstream_req, |req, (name, value)| {
-                if let Some(name) = name {
-                    req.header(name, value)
-                } else {
-                    req
-                }
-            });
-
-        let upstream_req = upstream_req.body(Body::from(body_bytes)).unwrap();
+    /// Relays the connection byte for byte once the upstream has switched
+    /// protocols, whatever the new protocol is, and records how much passed
+    /// each way when it closes.
+    fn tunnel_upgrade(
+        &self,
+        request_id: &str,
+        client_addr: &str,
+        client: OnUpgrade,
+        upstream: UpstreamUpgrade,
+    ) {
+        let UpstreamUpgrade { protocol, upgrade } = upstream;
+        debug!("Tunnelling {protocol} for {request_id}");
+        self.recorder.record_tunnel_opened(request_id, &protocol);
+        self.events.push(
+            client_addr,
+            Some(request_id),
+            EventKind::Upgraded {
+                protocol: protocol.clone(),
+            },
+        );
+        let (recorder, events) = (self.recorder.clone(), self.events.clone());
+        let (request_id, client_addr) = (request_id.to_string(), client_addr.to_string());
+        tokio::spawn(async move {
+            let opened = Instant::now();
+            let relayed = match tokio::try_join!(client, upgrade) {
+                Ok((client, upstream)) => relay(client, upstream).await,
+                Err(e) => Relayed {
+                    error: Some(format!("Upgrade failed: {e}")),
+                    ..Relayed::default()
+                },
+            };
+            events.push(
+                &client_addr,
+                Some(&request_id),
+                EventKind::TunnelClosed {
+                    protocol: protocol.clone(),
+                    bytes_to_upstream: relayed.bytes_to_upstream,
+                    bytes_to_client: relayed.bytes_to_client,
+                    duration_ms: opened.elapsed().as_millis() as u64,
+                    error: relayed.error.clone(),
+                },
+            );
+            recorder.record_tunnel_closed(&request_id, &protocol, relayed);
+        });
+    }
 
-        // Make upstream request with timeout
-        let upstream_result =
-            tokio::time::timeout(upstream_timeout, self.client.request(upstream_req)).await;
+    /// Answers a CONNECT request in forward proxy mode. The tunnel is either
+    /// intercepted, recording the HTTPS requests inside, or passed through and
+    /// recorded as one transaction.
+    async fn handle_connect(&self, req: Request<Body>, origin: Origin) -> Response<Body> {
+        let Some(authority) = req.uri().authority().cloned() else {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("CONNECT needs a host:port target"))
+                .unwrap();
+        };
+        let host = authority
+            .host()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = authority.port_u16().unwrap_or(443);
 
-        match upstream_result {
-            Ok(Ok(upstream_response)) => {
-                let (parts, body) = upstream_response.into_parts();
-                let response_bytes = match hyper::body::to_bytes(body).await {
-                    Ok(bytes) => bytes.to_vec(),
+        #[cfg(feature = "tls")]
+        if let Some(ca) = self
+            .forward_proxy
+            .as_ref()
+            .and_then(|forward_proxy| forward_proxy.mitm.clone())
+        {
+            let proxy = self.clone();
+            tokio::spawn(async move {
+                let upgraded = match hyper::upgrade::on(req).await {
+                    Ok(upgraded) => upgraded,
                     Err(e) => {
-                        error!("Error reading response body: {e}");
-                        self.recorder
-                            .record_error(&request_id, format!("Error reading response: {e}"));
-                        return Ok(Response::builder()
-                            .status(StatusCode::BAD_GATEWAY)
-                            .body(Body::from("Bad Gateway"))
-                            .unwrap());
+                        error!("CONNECT upgrade failed: {}", e);
+                        return;
                     }
                 };
+                if let Err(e) = proxy.intercept(upgraded, ca, &host, port, origin).await {
+                    debug!("Intercepted tunnel to {}:{} closed: {}", host, port, e);
+                }
+            });
+            return Response::new(Body::empty());
+        }
 
-                let duration = start_time.elapsed();
-                let truncate_at = {
-                    let config = self.config.read();
-                    config.truncate_body_at
-                };
-
-                let response_info = ResponseInfo {
-                    request_id: &request_id,
-                    status: parts.status,
-                    version: parts.version,
-                    headers: &parts.headers,
-                    body: &response_bytes,
-                    duration_ms: duration.as_millis() as u64,
-                    truncate_at,
-                };
-                self.recorder.record_response(response_info);
-
-                let mut response = Response::builder()
-                    .status(parts.status)
-                    .version(parts.version);
-
-                response = parts
-                    .headers
-                    .into_iter()
-                    .fold(response, |resp, (name, value)| {
-                        if let Some(name) = name {
-                            resp.header(name, value)
-                        } else {
-                            resp
-                        }
-                    });
-
-                Ok(response.body(Body::from(response_bytes)).unwrap())
-            }
+        // Connect before answering so a failure reaches the client
+        let start_time = Instant::now();
+        let (request_id, upstream_timeout, truncate_at) = {
+            let config = self.config.read();
+            let request_id = self.recorder.record_request(RequestInfo {
+                method: &Method::CONNECT,
+                path: authority.as_str(),
+                version: req.version(),
+                headers: req.headers(),
+                body: &[],
+                client_addr: origin.client_addr.clone(),
+                listener: origin.listener,
+                target: None,
+                trailers: None,
+                truncate_at: config.truncate_body_at,
+            });
+            (request_id, config.upstream_timeout, config.truncate_body_at)
+        };
+        let connected = tokio::time::timeout(
+            upstream_timeout,
+            tokio::net::TcpStream::connect((host.as_str(), port)),
+        )
+        .await;
+        let mut upstream = match connected {
+            Ok(Ok(stream)) => stream,
             Ok(Err(e)) => {
-                error!("Upstream request failed: {}", e);
+                self.events.push(
+                    &origin.client_addr,
+                    Some(&request_id),
+                    EventKind::UpstreamConnectFailed {
+                        upstream: authority.to_string(),
+                        error: e.to_string(),
+                    },
+                );
                 self.recorder
                     .record_error(&request_id, format!("Upstream error: {e}"));
-                Ok(Response::builder()
+                return Response::builder()
                     .status(StatusCode::BAD_GATEWAY)
                     .body(Body::from("Bad Gateway"))
-                    .unwrap())
+                    .unwrap();
             }
             Err(_) => {
-                // Timeout occurred
-                warn!("Upstream request timed out after {:?}", upstream_timeout);
                 self.recorder
                     .record_error(&request_id, "Upstream timeout".to_string());
-                Ok(Response::builder()
-                    .status(StatusCode::SERVICE_UNAVAILABLE)
-                    .body(Body::from("Service Unavailable - Upstream Timeout"))
-                    .unwrap())
+                return Response::builder()
+                    .status(StatusCode::GATEWAY_TIMEOUT)
+                    .body(Body::from("Gateway Timeout"))
+                    .unwrap();
             }
-        }
-    }
+        };
+        self.recorder.record_response(ResponseInfo {
+            request_id: &request_id,
+            status: StatusCode::OK,
+            version: req.version(),
+            headers: &HeaderMap::new(),
+            body: &[],
+            duration_ms: start_time.elapsed().as_millis() as u64,
+            trailers: None,
+            truncate_at,
+        });
 
-    fn should_handle_admin_request(&self, path: &str) -> bool {
-        path.starts_with("/_proxy")
+        tokio::spawn(async move {
+            match hyper::upgrade::on(req).await {
+                Ok(mut upgraded) => {
+                    let _ = tokio::io::copy_bidirectional(&mut upgraded, &mut upstream).await;
+                }
+                Err(e) => error!("CONNECT upgrade failed: {}", e),
+            }
+        });
+        Response::new(Body::empty())
     }
 
-    async fn handle_admin_request(&self, req: Request<Body>) -> Result<Response<Body>> {
-        let method = req.method();
-        let uri = req.uri();
-        let path = uri.path();
-        let query = uri.query().unwrap_or("");
-
-        let query_params: std::collections::HashMap<String, String> =
-            url::form_urlencoded::parse(query.as_bytes())
-                .into_owned()
-                .collect();
-
-        // Check token authentication
-        let is_static_asset = path.starts_with("/_proxy/assets/");
-        if !is_static_asset {
-            let expected_token = self.config.get_access_token();
-            let provided_token = query_params.get("token");
+    /// Terminates TLS on a CONNECT tunnel with a certificate from `ca` and
+    /// proxies the requests inside to `https://host:port`.
+    #[cfg(feature = "tls")]
+    async fn intercept(
+        &self,
+        upgraded: hyper::upgrade::Upgraded,
+        ca: Arc<CertificateAuthority>,
+        host: &str,
+        port: u16,
+        origin: Origin,
+    ) -> Result<()> {
+        let acceptor = tokio_rustls::TlsAcceptor::from(ca.server_config(host));
+        let tls = match acceptor.accept(upgraded).await {
+            Ok(tls) => tls,
+            Err(e) => {
+                self.events.push(
+                    &origin.client_addr,
+                    None,
+                    EventKind::ClientTlsError {
+                        host: host.to_string(),
+                        error: e.to_string(),
+                    },
+                );
+                return Err(e.into());
+            }
+        };
 
-            debug!(
-                "Token check - expected: {}, provided: {:?}",
-                expected_token, provided_token
+        let base_url = match port {
+            443 => format!("https://{}", host_authority(host)),
+            port => format!("https://{}:{port}", host_authority(host)),
+        };
+        let proxy = self.clone();
+        let service = service_fn(move |mut req: Request<Body>| {
+            let proxy = proxy.clone();
+            let origin = origin.clone();
+            let uri = format!(
+                "{base_url}{}",
+                req.uri()
+                    .path_and_query()
+                    .map(|pq| pq.as_str())
+                    .unwrap_or("/")
             );
-
-            if provided_token != Some(&expected_token) {
-                return Ok(Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .body(Body::from("Unauthorized - Invalid or missing token"))
-                    .unwrap());
+            async move {
+                match uri.parse() {
+                    Ok(uri) => *req.uri_mut() = uri,
+                    Err(_) => {
+                        return Ok(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from("Bad Request"))
+                            .unwrap())
+                    }
+                }
+                proxy.proxy_request(req, origin).await
             }
-        }
+        });
+        hyper::server::conn::Http::new()
+            .http1_only(true)
+            .serve_connection(tls, service)
+            .await?;
+        Ok(())
+    }
 
-        let path_without_query = path;
-        debug!("Admin request routing: {} {}", method, path_without_query);
+    /// The latest recorded upstream response to the same request, preferring
+    /// ones below `500` so an upstream already failing is not replayed.
+    fn offline_response(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Option<Response<Bytes>> {
+        let history = self.recorder.snapshot();
+        let recorded = history
+            .iter()
+            .map(Arc::as_ref)
+            .filter(|t| t.blocked.is_none() && t.served_from.is_none() && !t.inbox);
+        let candidates = mock::matching(recorded, method, path, body);
+        let transaction = candidates
+            .iter()
+            .rev()
+            .find(|t| t.response.as_ref().is_some_and(|r| r.status < 500))
+            .or(candidates.last())?;
+        Some(mock::recorded_response(transaction, headers))
+    }
 
-        match (method, path_without_query) {
-            (&Method::GET, "/_proxy") | (&Method::GET, "/_proxy/") => self.serve_admin_ui().await,
-            (&Method::GET, "/_proxy/api/config") => self.serve_config().await,
-            (&Method::POST, "/_proxy/api/config") => {
-                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
-                self.update_config(&body_bytes).await
-            }
-            (&Method::GET, "/_proxy/api/logs") => self.serve_logs().await,
-            (&Method::DELETE, "/_proxy/api/logs") => self.clear_logs().await,
-            (&Method::GET, path) if path.starts_with("/_proxy/assets/") => {
-                self.serve_static_asset(path).await
-            }
-            _ => Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::from("Not Found"))
-                .unwrap()),
-        }
+    /// Waits out the hold on a request answered `202`, then sends it to the
+    /// upstream as a transaction of its own. Boxed, since it forwards from
+    /// within [`Self::forward`].
+    fn deliver_held(
+        &self,
+        request_id: String,
+        rule: HoldRule,
+        request: Request<Bytes>,
+        origin: Origin,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let proxy = self.clone();
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let held_at = Instant::now();
+            let released = proxy
+                .holds
+                .hold(&request_id, &parts.method, parts.uri.path(), &rule)
+                .await;
+            info!(
+                "Delivering held {} {} after release by {}",
+                parts.method,
+                parts.uri.path(),
+                released.as_str()
+            );
+            let (delivered_as, _) = proxy
+                .forward(
+                    &parts.method,
+                    &parts.uri,
+                    parts.version,
+                    &parts.headers,
+                    BufferedBody {
+                        bytes: body,
+                        trailers: None,
+                    },
+                    Origin {
+                        replay_of: None,
+                        held_from: Some(request_id.clone()),
+                        ..origin
+                    },
+                )
+                .await;
+            proxy.recorder.record_held(
+                &request_id,
+                HoldRecord {
+                    rule: rule.to_string(),
+                    held_ms: held_at.elapsed().as_millis() as u64,
+                    released_by: released.as_str().to_string(),
+                    delivered_as: Some(delivered_as),
+                },
+            );
+        })
     }
 
-    async fn serve_admin_ui(&self) -> Result<Response<Body>> {
-        // Serve the embedded React app
-        let body = match Assets::get("index.html") {
-            Some(content) => Body::from(content.data.as_ref().to_vec()),
-            None => {
-                let fallback = "<!DOCTYPE html><html><head><title>Debug Proxy</title></head><body><h1>Admin Interface</h1><p>page not found</p></body></html>";
-                Body::from(fallback)
+    /// Records the request, forwards it to the upstream and records the
+    /// outcome. Returns the transaction id and the response for the client.
+    async fn forward(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        version: Version,
+        headers: &HeaderMap,
+        body: BufferedBody,
+        origin: Origin,
+    ) -> (String, Response<Body>) {
+        let start_time = Instant::now();
+        let BufferedBody {
+            bytes: body_bytes,
+            trailers: request_trailers,
+        } = body;
+
+        // In forward proxy mode an absolute-form URI names the upstream, as
+        // it does for an inbox replay sent to a chosen upstream
+        let forward_target = match (uri.scheme(), uri.authority()) {
+            (Some(scheme), Some(authority))
+                if self.forward_proxy.is_some() || origin.replay_of.is_some() =>
+            {
+                Some(format!("{scheme}://{authority}"))
             }
+            _ => None,
         };
+        let service = match forward_target {
+            Some(_) => None,
+            None => self.services.route(uri.path()),
+        };
+        let mut route_script_error = None;
+        let (default_upstream, sticky_cookie, failover_from) = match (&forward_target, service) {
+            (None, None) => {
+                let scripted = self.route_script.as_ref().and_then(|script| {
+                    let request = ScriptRequest {
+                        method,
+                        uri,
+                        headers,
+                        client_addr: &origin.client_addr,
+                    };
+                    script.route(&request).unwrap_or_else(|e| {
+                        warn!("Route script failed, using the default upstream: {e:#}");
+                        route_script_error = Some(format!("{e:#}"));
+                        None
+                    })
+                });
+                match scripted {
+                    Some(upstream) => (Some(upstream), None, None),
+                    None => match self.pick_default_upstream(headers, &origin.client_addr) {
+                        Some(pick) => (Some(pick.instance), pick.set_cookie, pick.failover_from),
+                        None => (None, None, None),
+                    },
+                }
+            }
+            _ => (None, None, None),
+        };
+        let routed =
+            service.is_some() || default_upstream.is_some() || !self.upstream_address.is_empty();
+        let delivery = origin.replay_of.is_some() || origin.held_from.is_some();
+        let captured = !delivery
+            && forward_target.is_none()
+            && self
+                .inbox
+                .as_ref()
+                .is_some_and(|inbox| inbox.captures(uri.path(), routed));
+        let target = forward_target
+            .clone()
+            .or_else(|| default_upstream.as_deref().map(upstream_base_url))
+            .filter(|_| !captured);
+
+        // Record the request
+        let (request_id, route, cors, blocked, max_body_size) = {
+            let config = self.config.read();
+            let route = config.for_route(method, uri.path());
+            let request_info = RequestInfo {
+                method,
+                path: &uri.to_string(),
+                version,
+                headers,
+                body: &body_bytes,
+                client_addr: origin.client_addr.clone(),
+                listener: origin.listener,
+                target,
+                trailers: request_trailers.as_ref(),
+                truncate_at: config.request_truncate_at(&route, headers),
+            };
+            let request_id = self.recorder.record_request(request_info);
+            (
+                request_id,
+                route,
+                config.cors.clone(),
+                config.safe_mode.check(method, uri.path()),
+                config.max_body_size,
+            )
+        };
+        self.record_size_warnings(
+            &request_id,
+            &origin.client_addr,
+            method,
+            uri.path(),
+            body_size::check(
+                BodyPart::Request,
+                headers,
+                body_bytes.len(),
+                max_body_size,
+                true,
+            ),
+        );
+        if self
+            .public_tunnel
+            .as_ref()
+            .is_some_and(|tunnel| tunnel.received(headers))
+        {
+            self.recorder.record_via_public_tunnel(&request_id);
+        }
+        if let Some(ref source_id) = origin.replay_of {
+            self.recorder.record_replayed_from(&request_id, source_id);
+        }
+        if let Some(ref source_id) = origin.held_from {
+            self.recorder.record_held_from(&request_id, source_id);
+        }
+        if let Some(from) = failover_from {
+            self.recorder.record_failover(&request_id, from);
+        }
+        if let Some(error) = route_script_error {
+            self.recorder.record_route_script_error(&request_id, error);
+        }
+        let response_truncate_at =
+            |headers: &HeaderMap| self.config.read().response_truncate_at(&route, headers);
+
+        if captured {
+            info!("Captured {method} {} in the inbox", uri.path());
+            let body = serde_json::json!({ "captured": request_id }).to_string();
+            let response = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .unwrap();
+            self.recorder.record_response(ResponseInfo {
+                request_id: &request_id,
+                status: response.status(),
+                version,
+                headers: response.headers(),
+                body: body.as_bytes(),
+                duration_ms: start_time.elapsed().as_millis() as u64,
+                trailers: None,
+                truncate_at: response_truncate_at(response.headers()),
+            });
+            self.recorder.record_inbox(&request_id);
+            return (request_id, response.map(Body::from));
+        }
+
+        if let Some(reason) = blocked {
+            info!("Blocked {method} {}: {reason}", uri.path());
+            let response = Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .header(header::CONTENT_TYPE, "text/plain")
+                .body(Body::from(format!(
+                    "Blocked by debug-proxy safe mode: {reason}"
+                )))
+                .unwrap();
+            self.recorder.record_response(ResponseInfo {
+                request_id: &request_id,
+                status: response.status(),
+                version,
+                headers: response.headers(),
+                body: &[],
+                duration_ms: start_time.elapsed().as_millis() as u64,
+                trailers: None,
+                truncate_at: response_truncate_at(response.headers()),
+            });
+            self.recorder.record_blocked(&request_id, reason);
+            return (request_id, response);
+        }
+
+        // Preflights are answered here so the upstream needs no CORS support
+        if let Some(cors) = cors.filter(|_| CorsPolicy::is_preflight(method, headers)) {
+            let response = cors.preflight_response(headers);
+            self.recorder.record_response(ResponseInfo {
+                request_id: &request_id,
+                status: response.status(),
+                version,
+                headers: response.headers(),
+                body: &[],
+                duration_ms: start_time.elapsed().as_millis() as u64,
+                trailers: None,
+                truncate_at: response_truncate_at(response.headers()),
+            });
+            return (request_id, response);
+        }
+
+        let request_violations =
+            self.assertions
+                .check_request(method, uri.path(), headers, &body_bytes);
+        if !request_violations.is_empty() {
+            self.recorder
+                .record_violations(&request_id, request_violations.clone());
+            if self.assertions.is_strict() {
+                self.recorder.record_error(
+                    &request_id,
+                    "Request rejected by schema assertions".to_string(),
+                );
+                return (
+                    request_id,
+                    schema_violation_response(&request_violations).map(Body::from),
+                );
+            }
+        }
+
+        let offline = self.config.read().offline;
+        if offline != OfflineMode::Off {
+            let path = uri.path_and_query().map_or(uri.path(), |pq| pq.as_str());
+            if let Some(response) = self.offline_response(method, path, headers, &body_bytes) {
+                let source_id = response
+                    .headers()
+                    .get(MOCK_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                self.recorder.record_response(ResponseInfo {
+                    request_id: &request_id,
+                    status: response.status(),
+                    version,
+                    headers: response.headers(),
+                    body: response.body(),
+                    duration_ms: start_time.elapsed().as_millis() as u64,
+                    trailers: None,
+                    truncate_at: response_truncate_at(response.headers()),
+                });
+                self.recorder.record_served_from(&request_id, &source_id);
+                return (request_id, response.map(Body::from));
+            }
+            if offline == OfflineMode::Strict {
+                info!("No recording of {method} {path}, offline");
+                self.recorder
+                    .record_error(&request_id, "Offline: no recorded response".to_string());
+                let response = Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .header(header::CONTENT_TYPE, "text/plain")
+                    .body(Body::from(format!(
+                        "debug-proxy is offline and has no recorded response for {method} {path}"
+                    )))
+                    .unwrap();
+                return (request_id, response);
+            }
+        }
+
+        // Deliveries of held requests and replays are not held again
+        let hold_rule = if delivery {
+            None
+        } else {
+            self.holds.rule_for(method, uri.path()).cloned()
+        };
+        if let Some(rule) = hold_rule {
+            if !rule.is_open() {
+                let body = serde_json::json!({ "queued": request_id }).to_string();
+                let response = Response::builder()
+                    .status(StatusCode::ACCEPTED)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+                    .unwrap();
+                self.recorder.record_response(ResponseInfo {
+                    request_id: &request_id,
+                    status: response.status(),
+                    version,
+                    headers: response.headers(),
+                    body: body.as_bytes(),
+                    duration_ms: start_time.elapsed().as_millis() as u64,
+                    trailers: None,
+                    truncate_at: response_truncate_at(response.headers()),
+                });
+                let mut held = Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .version(version)
+                    .body(body_bytes)
+                    .unwrap();
+                *held.headers_mut() = headers.clone();
+                tokio::spawn(self.deliver_held(request_id.clone(), rule, held, origin));
+                return (request_id, response.map(Body::from));
+            }
+            let held_at = Instant::now();
+            let released = self
+                .holds
+                .hold(&request_id, method, uri.path(), &rule)
+                .await;
+            info!(
+                "Released held {method} {} by {}",
+                uri.path(),
+                released.as_str()
+            );
+            self.recorder.record_held(
+                &request_id,
+                HoldRecord {
+                    rule: rule.to_string(),
+                    held_ms: held_at.elapsed().as_millis() as u64,
+                    released_by: released.as_str().to_string(),
+                    delivered_as: None,
+                },
+            );
+        }
+
+        // Forward to upstream, or to the service that owns the path
+        let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("");
+        let upstream_uri = match service {
+            _ if forward_target.is_some() => uri.to_string(),
+            Some(service) => format!(
+                "{}{}",
+                upstream_base_url(&service.upstream),
+                service.upstream_path(path_and_query)
+            ),
+            None => format!(
+                "{}{}",
+                upstream_base_url(
+                    default_upstream
+                        .as_deref()
+                        .unwrap_or(&self.upstream_address)
+                ),
+                path_and_query
+            ),
+        };
+
+        // The upstream connection decides the version, not the client's
+        let upstream_version = if self.config.read().upstream_http2 {
+            Version::HTTP_2
+        } else if version == Version::HTTP_2 {
+            Version::HTTP_11
+        } else {
+            version
+        };
+        let upstream_req = Request::builder()
+            .method(method)
+            .uri(&upstream_uri)
+            .version(upstream_version);
+
+        let upstream_req = headers
+            .iter()
+            .filter(|(name, _)| {
+                // The body is already here, so there is nothing to wait for
+                *name != header::EXPECT
+            })
+            .filter(|(name, _)| {
+                // Meant for this proxy, not passed on
+                forward_target.is_none()
+                    || !(*name == header::PROXY_AUTHORIZATION || *name == "proxy-connection")
+            })
+            .fold(upstream_req, |req, (name, value)| req.header(name, value));
+
+        let mut upstream_req = upstream_req
+            .body(
+                BufferedBody {
+                    bytes: body_bytes.clone(),
+                    trailers: request_trailers,
+                }
+                .into_body(),
+            )
+            .unwrap();
+        let (no_cache, decompression) = {
+            let config = self.config.read();
+            (config.no_cache, config.decompression)
+        };
+        if no_cache {
+            cache::strip_conditional_headers(upstream_req.headers_mut());
+        }
+        // A missing Accept-Encoding means any coding is acceptable
+        if decompression == Decompression::Identity {
+            upstream_req.headers_mut().insert(
+                header::ACCEPT_ENCODING,
+                header::HeaderValue::from_static("identity"),
+            );
+        }
+        if let Some(authorization) = self.outbound_proxy_authorization(upstream_req.uri()) {
+            upstream_req
+                .headers_mut()
+                .insert(header::PROXY_AUTHORIZATION, authorization);
+        }
+        if forward_target.is_none() {
+            let mut injected = Vec::new();
+            for header in &self.config.read().upstream_headers {
+                upstream_req
+                    .headers_mut()
+                    .insert(header.name.clone(), header.value.clone());
+                injected.push(header.name.to_string());
+            }
+            self.recorder.record_injected_headers(&request_id, injected);
+        }
+
+        // Make upstream request with timeout
+        let identity = self.config.read().route_client_identity(method, uri.path());
+        let client = match identity {
+            Some(identity) => self.identity_client(identity),
+            None => self.client.read().clone(),
+        };
+        let upstream_authority = upstream_req
+            .uri()
+            .authority()
+            .map(|authority| authority.to_string())
+            .unwrap_or_default();
+        let upstream_target = upstream_req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| "/".to_string(), |target| target.to_string());
+        // Which connection carried the request, to show the raw response
+        // hyper failed to parse
+        let captured = hyper::client::connect::capture_connection(&mut upstream_req);
+        let abort_guard = AbortGuard {
+            proxy: self,
+            request_id: &request_id,
+            client_addr: &origin.client_addr,
+            method,
+            path: uri.path(),
+            start_time,
+            armed: true,
+        };
+        let mut inflight = self.inflight.track(
+            &request_id,
+            method.as_str(),
+            path_and_query,
+            &origin.client_addr,
+            &upstream_authority,
+            start_time,
+        );
+        let sent_at = Instant::now();
+        let upstream_result = tokio::select! {
+            result = tokio::time::timeout(route.upstream_timeout, client.request(upstream_req)) => result,
+            status = inflight.cancelled() => {
+                abort_guard.disarm();
+                let response = self.cancelled_response(&request_id, status);
+                return (request_id, response);
+            }
+        };
+
+        let response = match upstream_result {
+            Ok(Ok(upstream_response)) => {
+                let headers_duration = sent_at.elapsed();
+                let (mut parts, body) = upstream_response.into_parts();
+                let upstream_upgrade = parts
+                    .extensions
+                    .remove::<OnUpgrade>()
+                    .filter(|_| parts.status == StatusCode::SWITCHING_PROTOCOLS)
+                    .map(|upgrade| UpstreamUpgrade {
+                        protocol: parts
+                            .headers
+                            .get(header::UPGRADE)
+                            .and_then(|value| value.to_str().ok())
+                            .unwrap_or("unknown")
+                            .to_string(),
+                        upgrade,
+                    });
+                inflight.set_state(InFlightState::StreamingResponse);
+                if let Some(tag) = parts.extensions.get::<ConnectionTag>() {
+                    self.recorder
+                        .record_connection(&request_id, tag.record_use());
+                }
+                let read = tokio::select! {
+                    read = BufferedBody::read(body) => read,
+                    status = inflight.cancelled() => {
+                        abort_guard.disarm();
+                        let response = self.cancelled_response(&request_id, status);
+                        return (request_id, response);
+                    }
+                };
+                let (mut response_bytes, response_trailers) = match read {
+                    Ok(body) => (body.bytes, body.trailers),
+                    Err((received, e)) => {
+                        error!("Error reading response body: {e}");
+                        let raw = parts
+                            .extensions
+                            .get::<ConnectionTag>()
+                            .and_then(|tag| tag.raw_response(method, &upstream_target, sent_at));
+                        self.events.push(
+                            &origin.client_addr,
+                            Some(&request_id),
+                            EventKind::UpstreamReset {
+                                upstream: upstream_authority,
+                                error: e.to_string(),
+                            },
+                        );
+                        self.recorder.record_partial_response(
+                            ResponseInfo {
+                                request_id: &request_id,
+                                status: parts.status,
+                                version: parts.version,
+                                headers: &parts.headers,
+                                body: &received,
+                                duration_ms: start_time.elapsed().as_millis() as u64,
+                                trailers: None,
+                                truncate_at: response_truncate_at(&parts.headers),
+                            },
+                            with_raw_response(
+                                format!("Error reading response: {e}"),
+                                raw.as_deref(),
+                            ),
+                        );
+                        self.record_size_warnings(
+                            &request_id,
+                            &origin.client_addr,
+                            method,
+                            uri.path(),
+                            body_size::check(
+                                BodyPart::Response,
+                                &parts.headers,
+                                received.len(),
+                                max_body_size,
+                                body_size::response_has_body(method, parts.status),
+                            ),
+                        );
+                        abort_guard.disarm();
+                        return (
+                            request_id,
+                            Response::builder()
+                                .status(StatusCode::BAD_GATEWAY)
+                                .body(Body::from("Bad Gateway"))
+                                .unwrap(),
+                        );
+                    }
+                };
+
+                self.record_size_warnings(
+                    &request_id,
+                    &origin.client_addr,
+                    method,
+                    uri.path(),
+                    body_size::check(
+                        BodyPart::Response,
+                        &parts.headers,
+                        response_bytes.len(),
+                        max_body_size,
+                        body_size::response_has_body(method, parts.status),
+                    ),
+                );
+                #[cfg(feature = "decoders")]
+                if decompression == Decompression::Decode {
+                    decode_response(&mut parts.headers, &mut response_bytes, max_body_size);
+                }
+                let upstream_timing = UpstreamTiming {
+                    sent_at,
+                    headers: headers_duration,
+                    duration: sent_at.elapsed(),
+                };
+                let duration = start_time.elapsed();
+
+                let response_info = ResponseInfo {
+                    request_id: &request_id,
+                    status: parts.status,
+                    version: parts.version,
+                    headers: &parts.headers,
+                    body: &response_bytes,
+                    duration_ms: duration.as_millis() as u64,
+                    trailers: response_trailers.as_ref(),
+                    truncate_at: response_truncate_at(&parts.headers),
+                };
+                self.recorder.record_response(response_info);
+
+                if let Some(spec) = &self.openapi {
+                    let violations = spec.validate(
+                        method,
+                        uri.path(),
+                        ExchangePart {
+                            headers,
+                            body: &body_bytes,
+                        },
+                        Some((
+                            parts.status,
+                            ExchangePart {
+                                headers: &parts.headers,
+                                body: &response_bytes,
+                            },
+                        )),
+                    );
+                    self.recorder.record_violations(&request_id, violations);
+                }
+
+                let response_violations = self.assertions.check_response(
+                    method,
+                    uri.path(),
+                    &parts.headers,
+                    &response_bytes,
+                );
+                if !response_violations.is_empty() {
+                    self.recorder
+                        .record_violations(&request_id, response_violations.clone());
+                    if self.assertions.is_strict() {
+                        abort_guard.disarm();
+                        // Record the 502 the client gets in place of the
+                        // upstream's response
+                        let rejected = schema_violation_response(&response_violations);
+                        self.recorder.record_response(ResponseInfo {
+                            request_id: &request_id,
+                            status: rejected.status(),
+                            version: rejected.version(),
+                            headers: rejected.headers(),
+                            body: rejected.body(),
+                            duration_ms: start_time.elapsed().as_millis() as u64,
+                            trailers: None,
+                            truncate_at: response_truncate_at(rejected.headers()),
+                        });
+                        self.recorder.record_error(
+                            &request_id,
+                            "Response rejected by schema assertions".to_string(),
+                        );
+                        return (request_id, rejected.map(Body::from));
+                    }
+                }
+
+                let applied = self.transforms.apply(
+                    &Exchange {
+                        method,
+                        path: uri.path(),
+                        status: parts.status,
+                    },
+                    &mut parts.status,
+                    &mut parts.headers,
+                    &mut response_bytes,
+                );
+                self.recorder.record_transforms(&request_id, applied);
+                if no_cache {
+                    cache::disable_caching(&mut parts.headers);
+                }
+
+                let mut response = Response::builder()
+                    .status(parts.status)
+                    .version(parts.version);
+
+                response = parts
+                    .headers
+                    .into_iter()
+                    .fold(response, |resp, (name, value)| {
+                        if let Some(name) = name {
+                            resp.header(name, value)
+                        } else {
+                            resp
+                        }
+                    });
+
+                let mut response = response
+                    .body(
+                        BufferedBody {
+                            bytes: response_bytes,
+                            trailers: response_trailers,
+                        }
+                        .into_body(),
+                    )
+                    .unwrap();
+                if let Some(cookie) = sticky_cookie {
+                    response.headers_mut().append(header::SET_COOKIE, cookie);
+                }
+                response.extensions_mut().insert(upstream_timing);
+                if let Some(upgrade) = upstream_upgrade {
+                    response.extensions_mut().insert(upgrade);
+                }
+                response
+            }
+            Ok(Err(e)) => {
+                error!("Upstream request failed: {}", e);
+                self.events.push(
+                    &origin.client_addr,
+                    Some(&request_id),
+                    upstream_failure(&upstream_authority, &e),
+                );
+                let raw = ConnectionTag::captured(&captured)
+                    .and_then(|tag| tag.raw_response(method, &upstream_target, sent_at));
+                self.recorder.record_error(
+                    &request_id,
+                    with_raw_response(format!("Upstream error: {e}"), raw.as_deref()),
+                );
+                Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::from("Bad Gateway"))
+                    .unwrap()
+            }
+            Err(_) => {
+                // Timeout occurred
+                warn!(
+                    "Upstream request timed out after {:?}",
+                    route.upstream_timeout
+                );
+                self.recorder
+                    .record_error(&request_id, "Upstream timeout".to_string());
+                Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from("Service Unavailable - Upstream Timeout"))
+                    .unwrap()
+            }
+        };
+        abort_guard.disarm();
+        (request_id, response)
+    }
+
+    /// Logs each warning, adds it to the event log and records them all on
+    /// the transaction.
+    fn record_size_warnings(
+        &self,
+        request_id: &str,
+        client_addr: &str,
+        method: &Method,
+        path: &str,
+        warnings: Vec<SizeWarning>,
+    ) {
+        for warning in &warnings {
+            warn!("{method} {path}: {warning}");
+            self.events.push(
+                client_addr,
+                Some(request_id),
+                EventKind::BodySize {
+                    method: method.to_string(),
+                    path: path.to_string(),
+                    warning: warning.clone(),
+                },
+            );
+        }
+        self.recorder.record_size_warnings(request_id, warnings);
+    }
+
+    /// Answers a request cancelled through the admin API while it waited on
+    /// the upstream.
+    fn cancelled_response(&self, request_id: &str, status: StatusCode) -> Response<Body> {
+        info!("Cancelled request {request_id}, answering {status}");
+        self.recorder
+            .record_error(request_id, "Cancelled through the admin API".to_string());
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from("Cancelled by debug-proxy"))
+            .unwrap()
+    }
+
+    /// The client presenting a route's own certificate.
+    fn identity_client(&self, identity: ClientIdentity) -> UpstreamClient {
+        self.identity_clients
+            .lock()
+            .entry(identity)
+            .or_insert_with_key(|identity| {
+                build_client_with_identity(&self.config.read(), Some(identity))
+            })
+            .clone()
+    }
+
+    /// Picks where a request for the default upstream goes when that varies:
+    /// to `upstream_b` under a split, or to one of the balancer's instances.
+    /// `None` leaves it on the fixed upstream.
+    fn pick_default_upstream(&self, headers: &HeaderMap, client_addr: &str) -> Option<Pick> {
+        let split = {
+            let config = self.config.read();
+            config
+                .upstream_b
+                .clone()
+                .map(|upstream_b| (upstream_b, u64::from(config.split_percent.min(100))))
+        };
+        if let Some((ref upstream_b, percent)) = split {
+            // Requests are counted rather than sampled, so `split_percent`
+            // of every hundred go to `upstream_b`, evenly spaced
+            let n = self.split_requests.fetch_add(1, Ordering::Relaxed);
+            if (n + 1) * percent / 100 > n * percent / 100 {
+                return Some(Pick {
+                    instance: upstream_b.clone(),
+                    set_cookie: None,
+                    failover_from: None,
+                });
+            }
+        }
+        match self.balancer {
+            Some(ref balancer) => Some(balancer.pick(headers, client_addr)),
+            None => split.is_some().then(|| Pick {
+                instance: self.upstream_address.clone(),
+                set_cookie: None,
+                failover_from: None,
+            }),
+        }
+    }
+
+    /// Credentials for plain HTTP requests sent through an HTTP outbound
+    /// proxy; tunnels carry them in the `CONNECT` instead.
+    fn outbound_proxy_authorization(&self, uri: &Uri) -> Option<header::HeaderValue> {
+        let config = self.config.read();
+        let proxy = config.outbound_proxy.as_ref()?;
+        if proxy.kind != OutboundProxyKind::Http
+            || uri.scheme_str() != Some("http")
+            || proxy.bypasses(uri.host().unwrap_or_default())
+        {
+            return None;
+        }
+        proxy.authorization()?.parse().ok()
+    }
+
+    /// The forward proxy's interception CA, for installing in clients.
+    async fn serve_ca_certificate(&self) -> Result<Response<Body>> {
+        #[cfg(feature = "tls")]
+        let cert_pem = self
+            .forward_proxy
+            .as_ref()
+            .and_then(|forward_proxy| forward_proxy.mitm.as_ref())
+            .map(|ca| ca.cert_pem().to_string());
+        #[cfg(not(feature = "tls"))]
+        let cert_pem: Option<String> = None;
+        let Some(cert_pem) = cert_pem else {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("HTTPS interception is not enabled"))
+                .unwrap());
+        };
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/x-x509-ca-cert")
+            .header(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"debug-proxy-ca.pem\"",
+            )
+            .body(Body::from(cert_pem))
+            .unwrap())
+    }
+
+    fn should_handle_admin_request(&self, path: &str) -> bool {
+        path.starts_with("/_proxy")
+    }
+
+    /// Routes an admin request from `client_addr` once it has the token.
+    async fn handle_admin_request(
+        &self,
+        req: Request<Body>,
+        client_addr: &str,
+    ) -> Result<Response<Body>> {
+        if let Some(rejected) = self.check_token(&req, client_addr) {
+            return Ok(rejected);
+        }
+        self.audited_admin_request(req, client_addr).await
+    }
+
+    /// An admin request on the local admin listener on `port`, which needs
+    /// no token and serves nothing else. Requests must name the loopback
+    /// listener as their `Host` and any `Origin`, so web pages open in a
+    /// local browser cannot reach it through cross-site requests or DNS
+    /// rebinding.
+    async fn handle_local_admin_request(
+        &self,
+        req: Request<Body>,
+        client_addr: &str,
+        port: u16,
+    ) -> Response<Body> {
+        let is_local = |authority: &str| {
+            ["127.0.0.1", "localhost", "[::1]"]
+                .iter()
+                .any(|host| authority.eq_ignore_ascii_case(&format!("{host}:{port}")))
+        };
+        let host_ok = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(is_local);
+        let origin_ok = req.headers().get(header::ORIGIN).is_none_or(|origin| {
+            origin
+                .to_str()
+                .ok()
+                .and_then(|origin| origin.strip_prefix("http://"))
+                .is_some_and(is_local)
+        });
+        if !host_ok || !origin_ok {
+            warn!(
+                "Rejected local admin request from {} with a foreign Host or Origin",
+                client_addr
+            );
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from(
+                    "Forbidden - the local admin listener only serves its own loopback address",
+                ))
+                .unwrap();
+        }
+        if !self.should_handle_admin_request(req.uri().path()) {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not Found - this listener only serves /_proxy"))
+                .unwrap();
+        }
+        self.audited_admin_request(req, client_addr)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Error handling admin request: {}", e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("Internal Server Error"))
+                    .unwrap()
+            })
+    }
+
+    /// Routes an admin request that passed the token check, adding every
+    /// change it makes to the audit log.
+    async fn audited_admin_request(
+        &self,
+        req: Request<Body>,
+        client_addr: &str,
+    ) -> Result<Response<Body>> {
+        if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+            return self.route_admin_request(req).await;
+        }
+        let method = req.method().to_string();
+        let path = req.uri().path().to_string();
+        let before = self.config_json();
+        let response = self.route_admin_request(req).await?;
+        let changes = config_changes(&before, &self.config_json());
+        info!(
+            "Admin {} {} from {}: {}",
+            method,
+            path,
+            client_addr,
+            response.status()
+        );
+        self.audit.record(
+            client_addr,
+            &method,
+            &path,
+            response.status().as_u16(),
+            changes,
+        );
+        Ok(response)
+    }
+
+    /// The answer to a request without the admin token, or to any request
+    /// from an address locked out for guessing it.
+    fn check_token(&self, req: &Request<Body>, client_addr: &str) -> Option<Response<Body>> {
+        // Health checks come from tools that have no token
+        let path = req.uri().path();
+        if path == "/_proxy/healthz" || path == "/_proxy/readyz" {
+            return None;
+        }
+
+        if let Some(remaining) = self.token_guard.locked_for(client_addr) {
+            return Some(
+                Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header(header::RETRY_AFTER, remaining.as_secs().max(1))
+                    .body(Body::from("Too many invalid tokens, try again later"))
+                    .unwrap(),
+            );
+        }
+
+        let expected_token = self.config.get_access_token();
+        let provided_token =
+            url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .filter(|(name, _)| name == "token")
+                .map(|(_, value)| value.into_owned())
+                .last();
+
+        // The admin page's scripts and styles come with its cookie instead.
+        // Without either, refuse without counting a guess, or a page with a
+        // stale cookie would lock its own address out
+        if path.starts_with("/_proxy/assets/") {
+            let cookie = request_cookie(req.headers(), ASSET_COOKIE);
+            if cookie.is_some_and(|key| tokens_match(key, &asset_key(&expected_token))) {
+                return None;
+            }
+            if provided_token.is_none() {
+                return Some(
+                    Response::builder()
+                        .status(StatusCode::FORBIDDEN)
+                        .body(Body::from(
+                            "Forbidden - open the admin page with its token first",
+                        ))
+                        .unwrap(),
+                );
+            }
+        }
+        // The provided token may be the admin token itself, so neither is logged
+        debug!(
+            "Token check from {} - token provided: {}",
+            client_addr,
+            provided_token.is_some()
+        );
+        if provided_token.is_some_and(|token| tokens_match(&token, &expected_token)) {
+            self.token_guard.record_success(client_addr);
+            return None;
+        }
+
+        if self.token_guard.record_failure(client_addr) {
+            warn!(
+                "Locking out {} for {:?} after repeated invalid admin tokens",
+                client_addr, LOCKOUT
+            );
+        }
+        Some(
+            Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::from("Unauthorized - Invalid or missing token"))
+                .unwrap(),
+        )
+    }
+
+    async fn route_admin_request(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let method = req.method();
+        let uri = req.uri();
+        let path = uri.path();
+        let query = uri.query().unwrap_or("");
+
+        let query_params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+
+        // Let the admin API see everything proxied before it was asked
+        self.recorder.flush().await;
+
+        let path_without_query = path;
+        debug!("Admin request routing: {} {}", method, path_without_query);
+
+        match (method, path_without_query) {
+            (&Method::GET, "/_proxy") | (&Method::GET, "/_proxy/") => self.serve_admin_ui().await,
+            (&Method::GET, "/_proxy/healthz") => self.serve_health().await,
+            (&Method::GET, "/_proxy/readyz") => self.serve_readiness().await,
+            (&Method::GET, "/_proxy/api/version") => self.serve_version().await,
+            (&Method::GET, "/_proxy/api/audit") => self.serve_audit(&query_params).await,
+            (&Method::GET, "/_proxy/api/config") => self.serve_config().await,
+            (&Method::POST, "/_proxy/api/config") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.update_config(&body_bytes).await
+            }
+            (&Method::GET, "/_proxy/api/logs") => {
+                self.serve_logs(req.headers(), &query_params).await
+            }
+            (&Method::POST, "/_proxy/api/send") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.send_request(&body_bytes).await
+            }
+            (&Method::GET, "/_proxy/api/held") => self.serve_held().await,
+            (&Method::POST, "/_proxy/api/held/release") => self.release_held(&query_params).await,
+            (&Method::GET, "/_proxy/api/inbox") => self.serve_inbox().await,
+            (&Method::POST, "/_proxy/api/inbox/replay") => self.replay_inbox(&query_params).await,
+            (&Method::DELETE, "/_proxy/api/logs") => self.clear_logs().await,
+            (&Method::GET, "/_proxy/api/events") => self.serve_events(&query_params).await,
+            (&Method::DELETE, "/_proxy/api/events") => self.clear_events().await,
+            (&Method::GET, "/_proxy/api/probes") => self.serve_probes().await,
+            (&Method::GET, "/_proxy/api/inflight") => self.serve_inflight().await,
+            (&Method::POST, path)
+                if path.starts_with("/_proxy/api/inflight/") && path.ends_with("/cancel") =>
+            {
+                let id = path
+                    .trim_start_matches("/_proxy/api/inflight/")
+                    .trim_end_matches("/cancel");
+                self.cancel_inflight(id, &query_params).await
+            }
+            (&Method::GET, "/_proxy/api/timeline") => self.serve_timeline(&query_params).await,
+            (&Method::GET, "/_proxy/api/diff") => self.serve_diff(&query_params).await,
+            (&Method::GET, "/_proxy/api/export") => self.serve_export(&query_params).await,
+            (&Method::GET, "/_proxy/api/export/bundle") => self.serve_bundle(&query_params).await,
+            (&Method::GET, "/_proxy/api/baseline") => self.serve_baseline().await,
+            (&Method::POST, "/_proxy/api/baseline") => self.capture_baseline().await,
+            (&Method::DELETE, "/_proxy/api/baseline") => self.clear_baseline().await,
+            (&Method::GET, "/_proxy/api/sessions") => self.serve_sessions().await,
+            (&Method::POST, "/_proxy/api/sessions") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.control_session(&body_bytes).await
+            }
+            (&Method::POST, "/_proxy/api/snapshot") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.save_snapshot(&body_bytes).await
+            }
+            (&Method::GET, "/_proxy/api/regressions") => {
+                self.serve_regressions(&query_params).await
+            }
+            (&Method::GET, "/_proxy/api/ca.pem") => self.serve_ca_certificate().await,
+            (&Method::GET, "/_proxy/api/stats") => self.serve_stats(&query_params).await,
+            (&Method::GET, "/_proxy/api/endpoints") => self.serve_endpoints().await,
+            (&Method::GET, "/_proxy/api/search") => self.serve_search(&query_params).await,
+            (&Method::GET, "/_proxy/api/process") => self.serve_process_status().await,
+            (&Method::POST, "/_proxy/api/process/signal") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.signal_process(self.process.as_ref(), &body_bytes)
+                    .await
+            }
+            (&Method::POST, path) if path.starts_with("/_proxy/api/process/") => {
+                let action = path.trim_start_matches("/_proxy/api/process/");
+                self.control_process(self.process.as_ref(), action).await
+            }
+            (&Method::GET, "/_proxy/api/process/logs") => {
+                self.serve_process_logs(self.process_logs.as_ref(), &query_params)
+                    .await
+            }
+            (&Method::GET, "/_proxy/api/process/logs/stream") => {
+                self.stream_process_logs(self.process_logs.as_ref(), &query_params)
+                    .await
+            }
+            (&Method::GET, "/_proxy/api/services") => self.serve_services().await,
+            (method, path) if path.starts_with("/_proxy/api/services/") => {
+                let route = path.trim_start_matches("/_proxy/api/services/").to_string();
+                let method = method.clone();
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.handle_service_request(&method, &route, &body_bytes, &query_params)
+                    .await
+            }
+            (&Method::GET, "/_proxy/api/violations") => self.serve_violations().await,
+            (&Method::GET, "/_proxy/api/assertions") => self.serve_assertions().await,
+            (&Method::PUT, "/_proxy/api/assertions") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.replace_assertions(&body_bytes).await
+            }
+            (&Method::POST, "/_proxy/api/assertions") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.add_assertion(&body_bytes).await
+            }
+            (&Method::DELETE, "/_proxy/api/assertions") => self.clear_assertions().await,
+            (&Method::GET, "/_proxy/api/transforms") => self.serve_transforms().await,
+            (&Method::PUT, "/_proxy/api/transforms") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.replace_transforms(&body_bytes).await
+            }
+            (&Method::POST, "/_proxy/api/transforms") => {
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+                self.add_transform(&body_bytes).await
+            }
+            (&Method::DELETE, "/_proxy/api/transforms") => self.clear_transforms().await,
+            (&Method::GET, path) if path.starts_with("/_proxy/assets/") => {
+                self.serve_static_asset(path).await
+            }
+            (&Method::GET, path) if !path.starts_with("/_proxy/api/") => {
+                self.serve_ui_route(path).await
+            }
+            _ => Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not Found"))
+                .unwrap()),
+        }
+    }
+
+    async fn serve_admin_ui(&self) -> Result<Response<Body>> {
+        // Serve the embedded React app, or a plain listing when it was not built
+        let body = match embedded_asset("index.html") {
+            Some((content, _)) => Body::from(content),
+            None => {
+                let history = self.recorder.snapshot();
+                Body::from(fallback_page(
+                    history.iter().map(AsRef::as_ref),
+                    &self.config.get_access_token(),
+                ))
+            }
+        };
+
+        // The page carries the token in its URL, so it is never cached
+        let cookie = format!(
+            "{ASSET_COOKIE}={}; Path=/_proxy/assets; HttpOnly; SameSite=Strict",
+            asset_key(&self.config.get_access_token())
+        );
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/html")
+            .header(header::CACHE_CONTROL, "no-store")
+            .header(header::SET_COOKIE, cookie)
+            .body(body)
+            .unwrap())
+    }
+
+    /// A GET under `/_proxy/` the admin API does not answer: a file at the
+    /// root of the UI build, such as `manifest.json`, or otherwise a route of
+    /// the UI's history routing like `/_proxy/requests/<id>`, which loads
+    /// the UI to show it.
+    async fn serve_ui_route(&self, path: &str) -> Result<Response<Body>> {
+        let name = path.strip_prefix("/_proxy/").unwrap_or(path);
+        let is_file = name.rsplit('/').next().unwrap_or_default().contains('.');
+        if !is_file {
+            return self.serve_admin_ui().await;
+        }
+
+        let embedded = if name.contains('/') || name == "index.html" {
+            None
+        } else {
+            embedded_asset(name)
+        };
+        match embedded {
+            // Unlike the hashed assets these keep their names across builds
+            Some((content, content_type)) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CACHE_CONTROL, "no-cache")
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .body(Body::from(content))
+                .unwrap()),
+            None => Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not Found"))
+                .unwrap()),
+        }
+    }
+
+    async fn serve_config(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&self.config_json())?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// The runtime config as `GET /_proxy/api/config` shows it.
+    fn config_json(&self) -> serde_json::Value {
+        let config = self.config.read();
+        serde_json::json!({
+            "client_timeout_ms": config.client_timeout.as_millis(),
+            "upstream_timeout_ms": config.upstream_timeout.as_millis(),
+            "max_history_size": config.max_history_size,
+            "max_body_size": config.max_body_size,
+            "truncate_body_at": config.truncate_body_at,
+            "truncate_request_at": config.truncate_request_at,
+            "truncate_response_at": config.truncate_response_at,
+            "truncate_by_content_type": config.truncate_by_content_type,
+            "binary_detection": config.binary_detection,
+            "pool_max_idle_per_host": config.pool_max_idle_per_host,
+            "pool_idle_timeout_ms": config.pool_idle_timeout.as_millis(),
+            "http1_keep_alive": config.http1_keep_alive,
+            "resolve": config.resolve,
+            "dns_cache_ttl_ms": config.dns_cache_ttl.as_millis(),
+            "outbound_proxy": config.outbound_proxy,
+            "upstream_b": config.upstream_b,
+            "split_percent": config.split_percent,
+            "instances": self.balancer.as_ref().map(|balancer| balancer.instances()),
+            "sticky": self.balancer.as_ref().and_then(|balancer| balancer.stickiness()),
+            "debug_headers": config.debug_headers,
+            "no_cache": config.no_cache,
+            "decompression": config.decompression,
+            "duplicate_window_ms": config.duplicate_window.as_millis(),
+            "offline": config.offline,
+            "cors": config.cors,
+            "preflights": config.preflights,
+            "safe_mode": config.safe_mode,
+            "routes": config.routes,
+            "alerts": config.alerts,
+            "upstream_headers": config.upstream_headers,
+        })
+    }
+
+    async fn update_config(&self, body: &[u8]) -> Result<Response<Body>> {
+        match serde_json::from_slice::<crate::config::ConfigUpdate>(body) {
+            Ok(update) => {
+                self.config.update(|config| {
+                    update.apply_to(config);
+                });
+
+                // Update recorder size if changed
+                if let Some(new_size) = update.max_history_size {
+                    self.recorder.resize(new_size);
+                }
+                if update.binary_detection.is_some() {
+                    self.recorder
+                        .set_binary_detection(self.config.read().binary_detection.clone());
+                }
+                if update.duplicate_window_ms.is_some() {
+                    self.recorder
+                        .set_duplicate_window(self.config.read().duplicate_window);
+                }
+
+                // New pool settings apply to a fresh client; requests in
+                // flight finish on the old one
+                if update.changes_pool() {
+                    *self.client.write() = build_client(&self.config.read());
+                }
+                if update.changes_pool() || update.routes.is_some() {
+                    self.identity_clients.lock().clear();
+                }
+
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .body(Body::from("Configuration updated"))
+                    .unwrap())
+            }
+            Err(e) => {
+                let error_msg = format!("Invalid configuration: {e}");
+                Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(error_msg))
+                    .unwrap())
+            }
+        }
+    }
+
+    /// Serves the history, or `304 Not Modified` when the client's
+    /// `If-None-Match` still matches it.
+    /// `?after=<seq>` lists only transactions recorded after the one with
+    /// that `seq`, for polling without relying on the clock.
+    async fn serve_logs(
+        &self,
+        headers: &HeaderMap,
+        params: &HashMap<String, String>,
+    ) -> Result<Response<Body>> {
+        let after = match params.get("after").map(|after| after.parse::<u64>()) {
+            Some(Ok(after)) => Some(after),
+            Some(Err(_)) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Invalid 'after', expected a transaction seq"))
+                    .unwrap())
+            }
+            None => None,
+        };
+        let preflights = self.config.read().preflights;
+        let etag = match preflights {
+            PreflightView::Show => self.recorder.etag(),
+            // The list changes with the view, so the tag must too
+            view => format!(
+                "{}-{}\"",
+                self.recorder.etag().trim_end_matches('"'),
+                serde_json::to_value(view)?.as_str().unwrap_or_default()
+            ),
+        };
+        let unchanged = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim().trim_start_matches("W/"))
+                    .any(|tag| tag == etag || tag == "*")
+            });
+        if unchanged {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .body(Body::empty())
+                .unwrap());
+        }
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ETAG, etag)
+            .body(json_array_body(
+                self.recorder.listed_after(preflights, after),
+            ))
+            .unwrap())
+    }
+
+    async fn send_request(&self, body: &[u8]) -> Result<Response<Body>> {
+        let request = match serde_json::from_slice::<SendRequest>(body) {
+            Ok(request) => request,
+            Err(e) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Invalid request: {e}")))
+                    .unwrap())
+            }
+        };
+
+        let parsed = (
+            Method::from_bytes(request.method.as_bytes()),
+            request.path.parse::<Uri>(),
+        );
+        let (Ok(method), Ok(uri)) = parsed else {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Invalid method or path"))
+                .unwrap());
+        };
+        if uri.authority().is_some()
+            || !request.path.starts_with('/')
+            || self.should_handle_admin_request(uri.path())
+        {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(
+                    "Path must be an upstream path starting with '/'",
+                ))
+                .unwrap());
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &request.headers {
+            let (Ok(name), Ok(value)) = (
+                header::HeaderName::from_bytes(name.as_bytes()),
+                header::HeaderValue::from_str(value),
+            ) else {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Invalid header: {name}")))
+                    .unwrap());
+            };
+            headers.append(name, value);
+        }
+
+        let (id, response) = self
+            .forward(
+                &method,
+                &uri,
+                Version::HTTP_11,
+                &headers,
+                BufferedBody {
+                    bytes: Bytes::from(request.body),
+                    trailers: None,
+                },
+                Origin {
+                    client_addr: "admin".to_string(),
+                    listener: None,
+                    replay_of: None,
+                    held_from: None,
+                },
+            )
+            .await;
+        let response_body =
+            serde_json::json!({ "id": id, "status": response.status().as_u16() }).to_string();
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_held(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&self.holds.list())?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// `?id=<id>` releases one held request, and no id releases them all.
+    async fn release_held(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let released = match params.get("id") {
+            Some(id) if self.holds.release(id) => 1,
+            Some(_) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::from("No held request with that id"))
+                    .unwrap())
+            }
+            None => self.holds.release_all(),
+        };
+        let response_body = serde_json::json!({ "released": released }).to_string();
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// The requests the inbox captured, oldest first.
+    async fn serve_inbox(&self) -> Result<Response<Body>> {
+        let captured: Vec<HttpTransaction> = self
+            .recorder
+            .get_transactions()
+            .into_iter()
+            .filter(|t| t.inbox)
+            .collect();
+        let response_body = serde_json::to_string(&captured)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// Sends an inbox capture on to where the proxy would route it, or to
+    /// `?upstream=`, and records it like proxied traffic.
+    async fn replay_inbox(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let Some(id) = params.get("id") else {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Missing 'id' transaction id"))
+                .unwrap());
+        };
+        let Some(captured) = self.recorder.get_transaction(id) else {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Transaction not found"))
+                .unwrap());
+        };
+        if !captured.inbox {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(
+                    "Invalid 'id', expected a transaction captured in the inbox",
+                ))
+                .unwrap());
+        }
+        let request = captured.request;
+        if request.body.truncated || request.body.is_binary {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(
+                    "The captured body was not recorded in full, so it cannot be replayed",
+                ))
+                .unwrap());
+        }
+
+        let target = match params.get("upstream") {
+            Some(upstream) => format!("{}{}", upstream_base_url(upstream), request.path),
+            None if self.upstream_address.is_empty()
+                && self.services.route(&request.path).is_none() =>
+            {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(
+                        "No upstream to replay to; give one with 'upstream'",
+                    ))
+                    .unwrap());
+            }
+            None => request.path.clone(),
+        };
+        let (Ok(method), Ok(uri)) = (
+            Method::from_bytes(request.method.as_bytes()),
+            target.parse::<Uri>(),
+        ) else {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(
+                    "Invalid 'upstream', expected host:port or an http:// or https:// URL",
+                ))
+                .unwrap());
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &request.headers {
+            if let (Ok(name), Ok(value)) = (
+                header::HeaderName::from_bytes(name.as_bytes()),
+                header::HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+
+        let (id, response) = self
+            .forward(
+                &method,
+                &uri,
+                Version::HTTP_11,
+                &headers,
+                BufferedBody {
+                    bytes: Bytes::copy_from_slice(request.body.preview.as_bytes()),
+                    trailers: None,
+                },
+                Origin {
+                    client_addr: "admin".to_string(),
+                    listener: None,
+                    replay_of: Some(request.id),
+                    held_from: None,
+                },
+            )
+            .await;
+        let response_body =
+            serde_json::json!({ "id": id, "status": response.status().as_u16() }).to_string();
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_diff(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let (Some(a_id), Some(b_id)) = (params.get("a"), params.get("b")) else {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Missing 'a' or 'b' transaction id"))
+                .unwrap());
+        };
+
+        let (Some(a), Some(b)) = (
+            self.recorder.get_transaction(a_id),
+            self.recorder.get_transaction(b_id),
+        ) else {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Transaction not found"))
+                .unwrap());
+        };
+
+        let response_body = serde_json::to_string(&diff_transactions(&a, &b))?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_export(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let transactions = self.exported_transactions(params);
+        let base_url = params
+            .get("base_url")
+            .cloned()
+            .unwrap_or_else(|| upstream_base_url(&self.upstream_address));
+
+        match params.get("format").map(String::as_str) {
+            Some("k6") => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/javascript")
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"script.js\"",
+                )
+                .body(Body::from(to_k6(&transactions, &base_url)))
+                .unwrap()),
+            Some("hurl") => {
+                let response_body = serde_json::to_string(&to_hurl(&transactions))?;
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(response_body))
+                    .unwrap())
+            }
+            Some("har") => {
+                let response_body = serde_json::to_string(&to_har(&transactions, &base_url))?;
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(
+                        header::CONTENT_DISPOSITION,
+                        "attachment; filename=\"traffic.har\"",
+                    )
+                    .body(Body::from(response_body))
+                    .unwrap())
+            }
+            Some("mitmproxy") => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"traffic.flows\"",
+                )
+                .body(Body::from(to_mitmproxy(&transactions, &base_url)))
+                .unwrap()),
+            Some("jsonl") => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/x-ndjson")
+                .body(Body::from(to_jsonl(&transactions)))
+                .unwrap()),
+            _ => Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(
+                    "Missing or unknown 'format', expected 'k6', 'hurl', 'har', 'jsonl' or 'mitmproxy'",
+                ))
+                .unwrap()),
+        }
+    }
+
+    /// The transactions an export covers: those in `ids` and `session`,
+    /// when given.
+    fn exported_transactions(&self, params: &HashMap<String, String>) -> Vec<HttpTransaction> {
+        let mut transactions = self.recorder.get_transactions();
+        if let Some(ids) = params.get("ids") {
+            let ids: Vec<&str> = ids.split(',').map(str::trim).collect();
+            transactions.retain(|t| ids.contains(&t.request.id.as_str()));
+        }
+        if let Some(session) = params.get("session") {
+            transactions.retain(|t| t.request.session.as_ref() == Some(session));
+        }
+        transactions
+    }
+
+    /// A zip of the transactions started between `since` and `until`, with
+    /// the managed command's output over the same range, the config and
+    /// the stats, for attaching to a bug report.
+    async fn serve_bundle(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let mut range = [None, None];
+        for (bound, name) in range.iter_mut().zip(["since", "until"]) {
+            match params.get(name).map(|value| value.parse::<u64>()) {
+                Some(Ok(timestamp)) => *bound = Some(timestamp),
+                Some(Err(_)) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!(
+                            "Invalid '{name}', expected Unix milliseconds"
+                        )))
+                        .unwrap())
+                }
+                None => {}
+            }
+        }
+        let [since, until] = range;
+        let in_range = |timestamp: u64| {
+            since.is_none_or(|since| timestamp >= since)
+                && until.is_none_or(|until| timestamp <= until)
+        };
+
+        let mut transactions = self.exported_transactions(params);
+        transactions.retain(|t| in_range(t.request.timestamp));
+        let process_logs = self.process_logs.as_ref().map(|logs| {
+            let mut lines = logs.get(None);
+            lines.retain(|line| in_range(line.timestamp));
+            lines
+        });
+        let base_url = params
+            .get("base_url")
+            .cloned()
+            .unwrap_or_else(|| upstream_base_url(&self.upstream_address));
+        let bundle = to_bundle(
+            &transactions,
+            &BundleContext {
+                base_url: &base_url,
+                config: &self.config_json(),
+                stats: &self.stats_json(since),
+                process_logs: process_logs.as_deref(),
+                since,
+                until,
+                utc_offset: self.recorder.utc_offset(),
+            },
+        )?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/zip")
+            .header(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"debug-proxy-bundle.zip\"",
+            )
+            .body(Body::from(bundle))
+            .unwrap())
+    }
+
+    async fn serve_baseline(&self) -> Result<Response<Body>> {
+        match self.baseline.get() {
+            Some(baseline) => {
+                let response_body = serde_json::to_string(&baseline)?;
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(response_body))
+                    .unwrap())
+            }
+            None => Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("No baseline captured"))
+                .unwrap()),
+        }
+    }
+
+    async fn capture_baseline(&self) -> Result<Response<Body>> {
+        let captured = self.baseline.capture(self.recorder.get_transactions())?;
+        let response_body = serde_json::json!({ "captured": captured }).to_string();
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// The capture sessions with the number of their transactions still in
+    /// the history.
+    async fn serve_sessions(&self) -> Result<Response<Body>> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for transaction in self.recorder.snapshot() {
+            if let Some(ref session) = transaction.request.session {
+                *counts.entry(session.clone()).or_default() += 1;
+            }
+        }
+        let sessions: Vec<_> = self
+            .recorder
+            .sessions()
+            .into_iter()
+            .map(|session| {
+                let transactions = counts.get(&session.name).copied().unwrap_or(0);
+                serde_json::json!({
+                    "name": session.name,
+                    "started_at": session.started_at,
+                    "stopped_at": session.stopped_at,
+                    "transactions": transactions,
+                })
+            })
+            .collect();
+        let response_body = serde_json::to_string(&sessions)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// `{"action": "start", "name": ...}` or `{"action": "stop"}`.
+    async fn control_session(&self, body: &[u8]) -> Result<Response<Body>> {
+        let request = match serde_json::from_slice::<SessionRequest>(body) {
+            Ok(request) => request,
+            Err(e) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Invalid request: {e}")))
+                    .unwrap())
+            }
+        };
+        let session = match request.action.as_str() {
+            "start" => {
+                let name = request
+                    .name
+                    .unwrap_or_else(|| format!("session-{}", self.recorder.sessions().len() + 1));
+                match self.recorder.start_session(name) {
+                    Ok(session) => session,
+                    Err(e) => {
+                        return Ok(Response::builder()
+                            .status(StatusCode::CONFLICT)
+                            .body(Body::from(e.to_string()))
+                            .unwrap())
+                    }
+                }
+            }
+            "stop" => match self.recorder.stop_session() {
+                Some(session) => session,
+                None => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::CONFLICT)
+                        .body(Body::from("No session is running"))
+                        .unwrap())
+                }
+            },
+            action => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!(
+                        "Unknown action {action:?}, expected start or stop"
+                    )))
+                    .unwrap())
+            }
+        };
+        let response_body = serde_json::to_string(&session)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// Writes the history to the file named by `{"path": ...}`, or to a
+    /// timestamped file in the working directory.
+    async fn save_snapshot(&self, body: &[u8]) -> Result<Response<Body>> {
+        let request = if body.is_empty() {
+            SnapshotRequest::default()
+        } else {
+            match serde_json::from_slice::<SnapshotRequest>(body) {
+                Ok(request) => request,
+                Err(e) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!("Invalid request: {e}")))
+                        .unwrap())
+                }
+            }
+        };
+        let snapshot = Snapshot::new(self.recorder.get_transactions());
+        let path = request
+            .path
+            .unwrap_or_else(|| PathBuf::from(snapshot.default_file_name()));
+        if let Err(e) = snapshot.save(&path) {
+            return Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(format!("{e:#}")))
+                .unwrap());
+        }
+        info!(
+            "Saved {} transactions to {}",
+            snapshot.transactions.len(),
+            path.display()
+        );
+        let response_body = serde_json::json!({
+            "path": path,
+            "transactions": snapshot.transactions.len(),
+        })
+        .to_string();
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn clear_baseline(&self) -> Result<Response<Body>> {
+        self.baseline.clear();
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from("Baseline cleared"))
+            .unwrap())
+    }
+
+    async fn serve_regressions(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let ignored_headers: Vec<String> = match params.get("ignore_headers") {
+            Some(list) => list
+                .split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            None => DEFAULT_IGNORED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        };
+        let regressions = self
+            .baseline
+            .regressions(&self.recorder.get_transactions(), &ignored_headers);
+        let response_body = serde_json::to_string(&regressions)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// Summarizes the recorded traffic next to the managed commands' CPU and
+    /// memory use over time, so latency spikes can be lined up with a busy
+    /// upstream. `since` limits the usage samples to those after it.
+    async fn serve_stats(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let since = params.get("since").and_then(|s| s.parse().ok());
+        let response_body = serde_json::to_string(&self.stats_json(since))?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// The stats as `GET /_proxy/api/stats` shows them, with resource usage
+    /// samples taken after `since`.
+    fn stats_json(&self, since: Option<u64>) -> serde_json::Value {
+        let transactions = self.recorder.snapshot();
+        let errors = transactions
+            .iter()
+            .filter(|t| t.error.is_some() || t.response.as_ref().is_some_and(|r| r.status >= 500))
+            .count();
+        let durations = transactions
+            .iter()
+            .filter_map(|t| t.response.as_ref().map(|r| r.duration_ms))
+            .collect();
+        let overheads = transactions
+            .iter()
+            .filter_map(|t| t.overhead.map(|o| o.proxy_us))
+            .collect();
+        let (reused, opened): (Vec<_>, Vec<_>) = transactions
+            .iter()
+            .filter_map(|t| t.connection.as_ref())
+            .partition(|c| c.reused);
+        let (mut request_sizes, mut response_sizes) =
+            (SizeTotals::default(), SizeTotals::default());
+        for t in &transactions {
+            request_sizes.add(t.request.header_bytes, &t.request.body);
+            if let Some(ref response) = t.response {
+                response_sizes.add(response.header_bytes, &response.body);
+            }
+        }
+        // The requests carrying the most header bytes, usually cookies
+        let mut largest_headers: Vec<_> = transactions.iter().collect();
+        largest_headers.sort_by_key(|t| std::cmp::Reverse(t.request.header_bytes));
+        let largest_headers: Vec<_> = largest_headers
+            .into_iter()
+            .take(5)
+            .map(|t| {
+                serde_json::json!({
+                    "id": t.request.id,
+                    "method": t.request.method,
+                    "path": t.request.path,
+                    "header_bytes": t.request.header_bytes,
+                })
+            })
+            .collect();
+
+        let alert = self
+            .config
+            .read()
+            .alerts
+            .evaluate(transactions.iter().map(AsRef::as_ref), now_ms());
+
+        let services: serde_json::Map<String, serde_json::Value> = self
+            .services
+            .iter()
+            .map(|service| {
+                (
+                    service.name.clone(),
+                    serde_json::json!(service.process.usage(since)),
+                )
+            })
+            .collect();
+        serde_json::json!({
+            "requests": transactions.len(),
+            "preflights": transactions.iter().filter(|t| t.request.preflight).count(),
+            "blocked": transactions.iter().filter(|t| t.blocked.is_some()).count(),
+            "client_aborted": transactions.iter().filter(|t| t.client_aborted.is_some()).count(),
+            "duplicates": transactions.iter().filter(|t| t.duplicate_of.is_some()).count(),
+            "served_offline": transactions.iter().filter(|t| t.served_from.is_some()).count(),
+            "via_public_tunnel": transactions.iter().filter(|t| t.via_public_tunnel).count(),
+            "public_url": self.public_tunnel.as_ref().and_then(PublicTunnel::url),
+            "in_flight": self.inflight.list().len(),
+            "errors": errors,
+            "alert": alert,
+            "latency_ms": Latency::from_durations(durations),
+            "proxy_overhead_us": Latency::from_durations(overheads),
+            "dropped_records": self.recorder.dropped(),
+            "recording_paused": self.recorder.pressure() == Pressure::Paused,
+            "memory": self.memory.as_ref().map(|memory| memory.status(&self.recorder)),
+            "upstream_connections": { "opened": opened.len(), "reused": reused.len() },
+            "sizes": {
+                "requests": request_sizes,
+                "responses": response_sizes,
+                "largest_request_headers": largest_headers,
+            },
+            "process_usage": self.process.as_ref().map(|process| process.usage(since)),
+            "services_usage": services,
+            "upstream_health": self.balancer.as_ref().map(|balancer| balancer.health()),
+        })
+    }
+
+    async fn serve_endpoints(&self) -> Result<Response<Body>> {
+        let transactions = self.recorder.snapshot();
+        let endpoints = endpoints::summarize(transactions.iter().map(AsRef::as_ref));
+        let response_body = serde_json::to_string(&endpoints)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// `?q=REGEX`: the transactions whose recorded bodies match, newest
+    /// first, at most `limit` of them.
+    async fn serve_search(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let pattern = match params.get("q").map(|q| regex::Regex::new(q)) {
+            Some(Ok(pattern)) => pattern,
+            Some(Err(e)) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Invalid pattern: {e}")))
+                    .unwrap())
+            }
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Missing q parameter"))
+                    .unwrap())
+            }
+        };
+        let limit = params
+            .get("limit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(100);
+        let transactions = self.recorder.snapshot();
+        let hits = search::search(transactions.iter().map(AsRef::as_ref), &pattern, limit);
+        let response_body = serde_json::to_string(&hits)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_process_status(&self) -> Result<Response<Body>> {
+        let Some(ref process) = self.process else {
+            return Ok(no_managed_process_response());
+        };
+        let response_body = serde_json::to_string(&process.status())?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_services(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&self.services.status())?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// `/_proxy/api/services/<name>/...`: the process endpoints, scoped to
+    /// one service.
+    async fn handle_service_request(
+        &self,
+        method: &Method,
+        route: &str,
+        body: &[u8],
+        params: &HashMap<String, String>,
+    ) -> Result<Response<Body>> {
+        let (name, action) = route.split_once('/').unwrap_or((route, ""));
+        let Some(service) = self.services.get(name) else {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(format!("Unknown service: {name}")))
+                .unwrap());
+        };
+        let logs = service.process.logs();
+
+        match (method, action) {
+            (&Method::GET, "") => {
+                let response_body = serde_json::to_string(&service.status())?;
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(response_body))
+                    .unwrap())
+            }
+            (&Method::GET, "logs") => self.serve_process_logs(Some(&logs), params).await,
+            (&Method::GET, "logs/stream") => self.stream_process_logs(Some(&logs), params).await,
+            (&Method::POST, "signal") => self.signal_process(Some(&service.process), body).await,
+            (&Method::POST, action) => self.control_process(Some(&service.process), action).await,
+            _ => Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not Found"))
+                .unwrap()),
+        }
+    }
+
+    async fn control_process(
+        &self,
+        process: Option<&ProcessManager>,
+        action: &str,
+    ) -> Result<Response<Body>> {
+        let Some(process) = process else {
+            return Ok(no_managed_process_response());
+        };
+        if !matches!(action, "start" | "stop" | "restart") {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from(
+                    "Unknown action, expected start, stop or restart",
+                ))
+                .unwrap());
+        }
+
+        info!("Process {} requested from admin API", action);
+        let result = match action {
+            "start" => process.start().await,
+            "stop" => process.stop().await,
+            _ => process.restart().await,
+        }
+        .map(|_| process.status());
+
+        match result {
+            Ok(status) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(&status)?))
+                .unwrap()),
+            Err(e) => Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(format!("{e:#}")))
+                .unwrap()),
+        }
+    }
+
+    async fn signal_process(
+        &self,
+        process: Option<&ProcessManager>,
+        body: &[u8],
+    ) -> Result<Response<Body>> {
+        let Some(process) = process else {
+            return Ok(no_managed_process_response());
+        };
+        let request = match serde_json::from_slice::<SignalRequest>(body) {
+            Ok(request) => request,
+            Err(e) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Invalid request: {e}")))
+                    .unwrap())
+            }
+        };
+        let Some(signal) = parse_signal(&request.signal) else {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Unknown signal: {}", request.signal)))
+                .unwrap());
+        };
+
+        info!("Sending {} to upstream process", request.signal);
+        match process.signal(signal) {
+            Ok(()) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .body(Body::from("Signal sent"))
+                .unwrap()),
+            Err(e) => Ok(Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body(Body::from(format!("{e:#}")))
+                .unwrap()),
+        }
+    }
+
+    /// Connection events with a sequence number above `since`.
+    async fn serve_events(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let since = params.get("since").and_then(|s| s.parse().ok());
+        let response_body = serde_json::to_string(&self.events.get(since))?;
 
         Ok(Response::builder()
             .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "text/html")
-            .body(body)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
             .unwrap())
     }
 
-    async fn serve_config(&self) -> Result<Response<Body>> {
-        let config = self.config.read();
-        let config_json = serde_json::json!({
-            "client_timeout_ms": config.client_timeout.as_millis(),
-            "upstream_timeout_ms": config.upstream_timeout.as_millis(),
-            "max_history_size": config.max_history_size,
-            "max_body_size": config.max_body_size,
-            "truncate_body_at": config.truncate_body_at,
-        });
+    /// `?after=<id>` lists only newer entries.
+    async fn serve_audit(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let after = match params.get("after").map(|after| after.parse::<u64>()) {
+            Some(Ok(after)) => Some(after),
+            Some(Err(_)) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Invalid 'after', expected an entry id"))
+                    .unwrap())
+            }
+            None => None,
+        };
+        let response_body = serde_json::to_string(&self.audit.list(after))?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
 
-        let response_body = serde_json::to_string(&config_json)?;
+    async fn serve_version(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&BuildInfo::current())?;
 
         Ok(Response::builder()
             .status(StatusCode::OK)
@@ -318,36 +3186,283 @@ impl DebugProxy {
             .unwrap())
     }
 
-    async fn update_config(&self, body: &[u8]) -> Result<Response<Body>> {
-        match serde_json::from_slice::<crate::config::ConfigUpdate>(body) {
-            Ok(update) => {
-                self.config.update(|config| {
-                    update.apply_to(config);
-                });
+    async fn serve_health(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&Health::current())?;
 
-                // Update recorder size if changed
-                if let Some(new_size) = update.max_history_size {
-                    self.recorder.resize(new_size);
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// `503` until the upstream is reachable and the managed commands run.
+    async fn serve_readiness(&self) -> Result<Response<Body>> {
+        let upstreams = match self.balancer {
+            Some(ref balancer) => balancer.instances().into_iter().map(String::from).collect(),
+            None => vec![self.upstream_address.clone()],
+        };
+        let checks: Vec<_> = upstreams
+            .into_iter()
+            .map(|upstream| {
+                tokio::spawn(async move { check_upstream(&upstream, CONNECT_TIMEOUT).await })
+            })
+            .collect();
+        let mut upstreams = Vec::with_capacity(checks.len());
+        for check in checks {
+            upstreams.push(check.await?);
+        }
+        let readiness = Readiness::new(
+            upstreams,
+            self.process.as_ref().map(ProcessManager::status),
+            self.services.status(),
+        );
+
+        let status = if readiness.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&readiness)?))
+            .unwrap())
+    }
+
+    async fn serve_inflight(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&self.inflight.list())?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn cancel_inflight(
+        &self,
+        id: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<Response<Body>> {
+        let status = match params.get("status") {
+            Some(status) => match status.parse::<u16>().map(StatusCode::from_u16) {
+                Ok(Ok(status)) => status,
+                _ => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!("Invalid status: {status}")))
+                        .unwrap())
+                }
+            },
+            None => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        if !self.inflight.cancel(id, status) {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Request not in flight"))
+                .unwrap());
+        }
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({ "cancelled": id, "status": status.as_u16() }).to_string(),
+            ))
+            .unwrap())
+    }
+
+    async fn serve_probes(&self) -> Result<Response<Body>> {
+        let transactions = self.recorder.snapshot();
+        let timelines: Vec<ProbeTimeline> = self
+            .probes
+            .iter()
+            .map(|probe| ProbeTimeline::collect(probe, transactions.iter().map(Arc::as_ref)))
+            .collect();
+        let response_body = serde_json::to_string(&timelines)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_timeline(&self, params: &HashMap<String, String>) -> Result<Response<Body>> {
+        let since = params
+            .get("since")
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(0);
+        let preflights = self.config.read().preflights;
+        let transactions = self.recorder.listed(preflights);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis() as u64;
+        let timeline = build_timeline(
+            transactions
+                .iter()
+                .map(Arc::as_ref)
+                .filter(|t| t.request.timestamp >= since),
+            now,
+        );
+        let response_body = serde_json::to_string(&timeline)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_process_logs(
+        &self,
+        logs: Option<&ProcessLogs>,
+        params: &HashMap<String, String>,
+    ) -> Result<Response<Body>> {
+        let Some(logs) = logs else {
+            return Ok(no_managed_process_response());
+        };
+        let since = params.get("since").and_then(|s| s.parse().ok());
+        let response_body = serde_json::to_string(&logs.get(since))?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    /// Server-sent events: the buffered lines after `since`, then new lines
+    /// as they are written.
+    async fn stream_process_logs(
+        &self,
+        logs: Option<&ProcessLogs>,
+        params: &HashMap<String, String>,
+    ) -> Result<Response<Body>> {
+        let Some(logs) = logs else {
+            return Ok(no_managed_process_response());
+        };
+        let since = params.get("since").and_then(|s| s.parse().ok());
+
+        // Subscribe before taking the backlog so no line falls in between
+        let mut receiver = logs.subscribe();
+        let backlog = logs.get(since);
+        let (mut sender, body) = Body::channel();
+
+        tokio::spawn(async move {
+            let mut last_seq = since.unwrap_or(0);
+            for line in backlog {
+                last_seq = line.seq;
+                if sender.send_data(sse_event(&line)).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                match receiver.recv().await {
+                    Ok(line) if line.seq <= last_seq => {}
+                    Ok(line) => {
+                        last_seq = line.seq;
+                        if sender.send_data(sse_event(&line)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Process log stream lagged, skipped {skipped} lines");
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
                 }
+            }
+        });
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(body)
+            .unwrap())
+    }
+
+    async fn serve_violations(&self) -> Result<Response<Body>> {
+        let violations: Vec<_> = self
+            .recorder
+            .snapshot()
+            .into_iter()
+            .filter(|t| !t.violations.is_empty())
+            .map(|t| {
+                serde_json::json!({
+                    "id": t.request.id,
+                    "timestamp": t.request.timestamp,
+                    "method": t.request.method,
+                    "path": t.request.path,
+                    "status": t.response.as_ref().map(|r| r.status),
+                    "violations": t.violations,
+                })
+            })
+            .collect();
+        let response_body = serde_json::to_string(&violations)?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
+
+    async fn serve_assertions(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&self.assertions.snapshot())?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_body))
+            .unwrap())
+    }
 
+    async fn replace_assertions(&self, body: &[u8]) -> Result<Response<Body>> {
+        match serde_json::from_slice::<SchemaAssertionSet>(body) {
+            Ok(set) => {
+                self.assertions.replace(set);
                 Ok(Response::builder()
                     .status(StatusCode::OK)
-                    .body(Body::from("Configuration updated"))
+                    .body(Body::from("Schema assertions updated"))
                     .unwrap())
             }
-            Err(e) => {
-                let error_msg = format!("Invalid configuration: {e}");
+            Err(e) => Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid schema assertions: {e}")))
+                .unwrap()),
+        }
+    }
+
+    async fn add_assertion(&self, body: &[u8]) -> Result<Response<Body>> {
+        match serde_json::from_slice::<SchemaAssertion>(body) {
+            Ok(assertion) => {
+                self.assertions.add(assertion);
                 Ok(Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(Body::from(error_msg))
+                    .status(StatusCode::OK)
+                    .body(Body::from("Schema assertion added"))
                     .unwrap())
             }
+            Err(e) => Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid schema assertion: {e}")))
+                .unwrap()),
         }
     }
 
-    async fn serve_logs(&self) -> Result<Response<Body>> {
-        let transactions = self.recorder.get_transactions();
-        let response_body = serde_json::to_string(&transactions)?;
+    async fn clear_assertions(&self) -> Result<Response<Body>> {
+        self.assertions.clear();
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from("Schema assertions cleared"))
+            .unwrap())
+    }
+
+    async fn serve_transforms(&self) -> Result<Response<Body>> {
+        let response_body = serde_json::to_string(&TransformSet {
+            rules: self.transforms.snapshot(),
+        })?;
 
         Ok(Response::builder()
             .status(StatusCode::OK)
@@ -356,6 +3471,46 @@ impl DebugProxy {
             .unwrap())
     }
 
+    async fn replace_transforms(&self, body: &[u8]) -> Result<Response<Body>> {
+        match serde_json::from_slice::<TransformSet>(body) {
+            Ok(set) => {
+                self.transforms.replace(set);
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .body(Body::from("Transforms updated"))
+                    .unwrap())
+            }
+            Err(e) => Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid transforms: {e}")))
+                .unwrap()),
+        }
+    }
+
+    async fn add_transform(&self, body: &[u8]) -> Result<Response<Body>> {
+        match serde_json::from_slice::<TransformRequest>(body) {
+            Ok(request) => {
+                self.transforms.add(request.rule);
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .body(Body::from("Transform added"))
+                    .unwrap())
+            }
+            Err(e) => Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Invalid transform: {e}")))
+                .unwrap()),
+        }
+    }
+
+    async fn clear_transforms(&self) -> Result<Response<Body>> {
+        self.transforms.clear();
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from("Transforms cleared"))
+            .unwrap())
+    }
+
     async fn clear_logs(&self) -> Result<Response<Body>> {
         self.recorder.clear();
         Ok(Response::builder()
@@ -364,21 +3519,40 @@ impl DebugProxy {
             .unwrap())
     }
 
+    async fn clear_events(&self) -> Result<Response<Body>> {
+        self.events.clear();
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from("Events cleared"))
+            .unwrap())
+    }
+
     async fn serve_static_asset(&self, path: &str) -> Result<Response<Body>> {
         // Convert /_proxy/assets/... to relative path
         let asset_path = path.strip_prefix("/_proxy/").unwrap_or(path);
         debug!("Serving embedded asset: {}", asset_path);
 
-        match Assets::get(asset_path) {
-            Some(content) => {
-                let content_type = content.metadata.mimetype();
-
-                Ok(Response::builder()
-                    .status(StatusCode::OK)
-                    .header(header::CONTENT_TYPE, content_type)
-                    .body(Body::from(content.data.as_ref().to_vec()))
-                    .unwrap())
-            }
+        let embedded = if asset_path
+            .split('/')
+            .any(|part| part.is_empty() || part == "..")
+        {
+            None
+        } else {
+            embedded_asset(asset_path)
+        };
+        match embedded {
+            // The UI build puts a content hash in every asset's name, so a
+            // name never changes content
+            Some((content, content_type)) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(
+                    header::CACHE_CONTROL,
+                    "private, max-age=31536000, immutable",
+                )
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .body(Body::from(content))
+                .unwrap()),
             None => {
                 debug!("Embedded asset not found: {}", asset_path);
                 Ok(Response::builder()
@@ -397,6 +3571,299 @@ impl Clone for DebugProxy {
             recorder: self.recorder.clone(),
             upstream_address: self.upstream_address.clone(),
             client: self.client.clone(),
+            identity_clients: self.identity_clients.clone(),
+            openapi: self.openapi.clone(),
+            assertions: self.assertions.clone(),
+            transforms: self.transforms.clone(),
+            baseline: self.baseline.clone(),
+            process_logs: self.process_logs.clone(),
+            process: self.process.clone(),
+            services: self.services.clone(),
+            forward_proxy: self.forward_proxy.clone(),
+            split_requests: self.split_requests.clone(),
+            balancer: self.balancer.clone(),
+            route_script: self.route_script.clone(),
+            events: self.events.clone(),
+            probes: self.probes.clone(),
+            inflight: self.inflight.clone(),
+            audit: self.audit.clone(),
+            token_guard: self.token_guard.clone(),
+            memory: self.memory.clone(),
+            public_tunnel: self.public_tunnel.clone(),
+            inbox: self.inbox.clone(),
+            holds: self.holds.clone(),
+        }
+    }
+}
+
+/// The proxy as a tower [`Service`](hyper::service::Service), for mounting
+/// in another server, such as with axum's `Router::fallback_service`, or
+/// wrapping in middleware. Requests are handled as [`DebugProxy::serve`]
+/// handles them, but probes are not sent.
+///
+/// The client address is recorded from a [`SocketAddr`] in the request's
+/// extensions, which the hosting server or a middleware can insert.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct ProxyService {
+    proxy: Arc<DebugProxy>,
+}
+
+impl hyper::service::Service<Request<Body>> for ProxyService {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = std::pin::Pin<
+        Box<dyn std::future::Future<Output = Result<Response<Body>, Infallible>> + Send>,
+    >;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Infallible>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let proxy = Arc::clone(&self.proxy);
+        let origin = Origin {
+            client_addr: req
+                .extensions()
+                .get::<SocketAddr>()
+                .map_or_else(|| "unknown".to_string(), ToString::to_string),
+            listener: None,
+            replay_of: None,
+            held_from: None,
+        };
+        Box::pin(async move { proxy.handle_request(req, origin).await })
+    }
+}
+
+/// Body of `POST /_proxy/api/send`. Headers use the same `[name, value]`
+/// pairs as recorded transactions so a request can be edited and resent.
+#[derive(Deserialize)]
+struct SendRequest {
+    #[serde(default = "default_send_method")]
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: String,
+}
+
+/// Body of `POST /_proxy/api/sessions`.
+#[derive(Deserialize)]
+struct SessionRequest {
+    action: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// Body of `POST /_proxy/api/snapshot`.
+#[derive(Default, Deserialize)]
+struct SnapshotRequest {
+    path: Option<PathBuf>,
+}
+
+/// Body of `POST /_proxy/api/transforms`.
+#[derive(Deserialize)]
+struct TransformRequest {
+    rule: TransformRule,
+}
+
+/// Body of `POST /_proxy/api/process/signal`.
+#[derive(Deserialize)]
+struct SignalRequest {
+    signal: String,
+}
+
+fn default_send_method() -> String {
+    "GET".to_string()
+}
+
+/// `host` as it appears in a URL authority, with IPv6 addresses bracketed.
+#[cfg(feature = "tls")]
+fn host_authority(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    }
+}
+
+/// Ports tried above a taken one before `--port-auto` gives up.
+const PORT_AUTO_ATTEMPTS: u16 = 100;
+
+/// Binds every address in `listen_addrs`. With `port_auto`, an address whose
+/// port is already in use moves to the next free port above it.
+pub fn bind_listeners(
+    listen_addrs: &[SocketAddr],
+    port_auto: bool,
+) -> Result<Vec<std::net::TcpListener>> {
+    listen_addrs
+        .iter()
+        .map(|&requested| {
+            let mut addr = requested;
+            loop {
+                match bind_listener(addr) {
+                    Err(e)
+                        if port_auto
+                            && e.kind() == std::io::ErrorKind::AddrInUse
+                            && addr.port() != 0
+                            && addr.port() < u16::MAX
+                            && addr.port() - requested.port() < PORT_AUTO_ATTEMPTS =>
+                    {
+                        addr.set_port(addr.port() + 1);
+                    }
+                    Ok(listener) => {
+                        if addr != requested {
+                            warn!("{} is in use, listening on {} instead", requested, addr);
+                        }
+                        return Ok(listener);
+                    }
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Failed to listen on {addr}"));
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Binds the proxy's listening socket. IPv6 sockets also accept IPv4, so
+/// `::` serves both stacks whatever the OS default.
+fn bind_listener(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    // As std does, so a restarted proxy can rebind at once
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Streams `items` as a JSON array, serializing a chunk at a time so large
+/// histories are neither cloned nor held as one string.
+fn json_array_body<T: Serialize + Send + Sync + 'static>(items: Vec<Arc<T>>) -> Body {
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        chunk.push(b'[');
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                chunk.push(b',');
+            }
+            if let Err(e) = serde_json::to_writer(&mut chunk, item.as_ref()) {
+                error!("Failed to serialize response: {e}");
+                sender.abort();
+                return;
+            }
+            if chunk.len() >= CHUNK_SIZE {
+                let full = std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE));
+                if sender.send_data(full.into()).await.is_err() {
+                    return;
+                }
+            }
+        }
+        chunk.push(b']');
+        let _ = sender.send_data(chunk.into()).await;
+    });
+    body
+}
+
+fn no_managed_process_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("No managed upstream process"))
+        .unwrap()
+}
+
+fn sse_event(line: &crate::process::LogLine) -> Bytes {
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        line.seq,
+        line.stream.as_str(),
+        serde_json::to_string(line).unwrap()
+    ))
+}
+
+fn schema_violation_response(violations: &[Violation]) -> Response<Bytes> {
+    let body = serde_json::json!({
+        "error": "Schema assertion failed",
+        "violations": violations,
+    });
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Bytes::from(body.to_string()))
+        .unwrap()
+}
+
+/// Splits the time since a request arrived into the client's, the
+/// upstream's and the proxy's own share.
+fn proxy_overhead(
+    received_at: Instant,
+    forwarded_at: Instant,
+    upstream: Option<&UpstreamTiming>,
+) -> ProxyOverhead {
+    let us = |duration: Duration| duration.as_micros() as u64;
+    let request_body_us = us(forwarded_at.saturating_duration_since(received_at));
+    let Some(upstream) = upstream else {
+        // Answered without the upstream, so all of it was the proxy's
+        let proxy_us = us(forwarded_at.elapsed());
+        return ProxyOverhead {
+            request_body_us,
+            before_upstream_us: proxy_us,
+            proxy_us,
+            ..Default::default()
+        };
+    };
+    let before_upstream_us = us(upstream.sent_at.saturating_duration_since(forwarded_at));
+    let after_upstream_us = us((upstream.sent_at + upstream.duration).elapsed());
+    ProxyOverhead {
+        request_body_us,
+        before_upstream_us,
+        upstream_us: Some(us(upstream.headers)),
+        response_body_us: Some(us(upstream.duration.saturating_sub(upstream.headers))),
+        after_upstream_us,
+        proxy_us: before_upstream_us + after_upstream_us,
+    }
+}
+
+/// Adds `X-Debug-Proxy-Id`, naming the transaction, and a `Server-Timing`
+/// breakdown that browser DevTools shows next to the request: `queue` until
+/// the request was sent upstream, `upstream` until its response arrived and
+/// `proxy` for the rest.
+fn add_debug_headers(response: &mut Response<Body>, request_id: &str, received_at: Instant) {
+    let ms = |duration: Duration| format!("{:.1}", duration.as_secs_f64() * 1000.0);
+    let total = received_at.elapsed();
+    let timing = match response.extensions().get::<UpstreamTiming>() {
+        Some(upstream) => {
+            let queue = upstream.sent_at.saturating_duration_since(received_at);
+            let proxy = total.saturating_sub(queue + upstream.duration);
+            format!(
+                "queue;dur={}, upstream;dur={}, proxy;dur={}",
+                ms(queue),
+                ms(upstream.duration),
+                ms(proxy)
+            )
         }
+        // No upstream response, so the proxy spent all the time
+        None => format!("proxy;dur={}", ms(total)),
+    };
+
+    let headers = response.headers_mut();
+    if let Ok(value) = header::HeaderValue::from_str(request_id) {
+        headers.insert("x-debug-proxy-id", value);
+    }
+    if let Ok(value) = header::HeaderValue::from_str(&timing) {
+        // Keeps any Server-Timing the upstream sent
+        headers.append("server-timing", value);
     }
 }
@@ -0,0 +1,137 @@
+use http::{header, HeaderMap};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::info;
+
+use crate::process::{shell_argv, ProcessManager};
+
+/// What exposes the proxy publicly: a known tunneling client, or any command
+/// that prints the public URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TunnelProvider {
+    /// `ngrok http <port>`, which needs ngrok installed and signed in.
+    Ngrok,
+    /// A Cloudflare quick tunnel, which needs no account.
+    Cloudflared,
+    /// A shell command, with `{port}` replaced by the proxy's port. The
+    /// first `https://` URL it prints is taken as the public one.
+    Command(String),
+}
+
+impl std::str::FromStr for TunnelProvider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim() {
+            "" => anyhow::bail!("Expected ngrok, cloudflared or a command"),
+            "ngrok" => Ok(Self::Ngrok),
+            "cloudflared" => Ok(Self::Cloudflared),
+            command => Ok(Self::Command(command.to_string())),
+        }
+    }
+}
+
+impl TunnelProvider {
+    /// The command line that exposes `http://localhost:<port>`.
+    pub fn command(&self, port: u16) -> Vec<String> {
+        match self {
+            Self::Ngrok => ["ngrok", "http", &port.to_string(), "--log", "stdout"]
+                .map(str::to_string)
+                .to_vec(),
+            Self::Cloudflared => vec![
+                "cloudflared".to_string(),
+                "tunnel".to_string(),
+                "--url".to_string(),
+                format!("http://localhost:{port}"),
+            ],
+            Self::Command(command) => shell_argv(&command.replace("{port}", &port.to_string())),
+        }
+    }
+
+    /// The public URL in a line of the tunnel's output, if it has one.
+    /// cloudflared also prints links to its docs, so only its tunnel domain
+    /// counts.
+    pub fn public_url(&self, line: &str) -> Option<String> {
+        line.split(|c: char| c.is_whitespace() || matches!(c, '"' | '|' | '='))
+            .filter(|word| word.starts_with("https://"))
+            .map(|word| word.trim_end_matches(['/', ',', '.']))
+            .find(|url| {
+                let host = url["https://".len()..]
+                    .split(['/', ':'])
+                    .next()
+                    .unwrap_or_default();
+                match self {
+                    Self::Ngrok => line.contains("url="),
+                    Self::Cloudflared => host.ends_with(".trycloudflare.com"),
+                    Self::Command(_) => host.contains('.') && host != "localhost",
+                }
+            })
+            .map(str::to_string)
+    }
+}
+
+/// The public URL the tunnel reported, shared with the proxy so it can mark
+/// the requests that came through it.
+#[derive(Clone, Default)]
+pub struct PublicTunnel {
+    url: Arc<RwLock<Option<String>>>,
+}
+
+impl PublicTunnel {
+    pub fn url(&self) -> Option<String> {
+        self.url.read().clone()
+    }
+
+    pub fn set_url(&self, url: String) {
+        *self.url.write() = Some(url);
+    }
+
+    /// Whether a request was addressed to the public URL. Tunnels pass the
+    /// public host on in `Host`, or in `X-Forwarded-Host` when they rewrite
+    /// it.
+    pub fn received(&self, headers: &HeaderMap) -> bool {
+        let url = self.url.read();
+        let Some(host) = url
+            .as_deref()
+            .and_then(|url| url.strip_prefix("https://"))
+            .and_then(|rest| rest.split('/').next())
+        else {
+            return false;
+        };
+        [header::HOST.as_str(), "x-forwarded-host"]
+            .into_iter()
+            .filter_map(|name| headers.get(name)?.to_str().ok())
+            .any(|value| {
+                value
+                    .split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case(host))
+            })
+    }
+
+    /// Waits for `process` to print the public URL, and records it.
+    pub async fn discover(
+        &self,
+        provider: &TunnelProvider,
+        process: &ProcessManager,
+    ) -> Option<String> {
+        let logs = process.logs();
+        let mut lines = logs.subscribe();
+        // Lines printed before subscribing
+        let mut url = logs
+            .get(None)
+            .iter()
+            .find_map(|line| provider.public_url(&line.line));
+        while url.is_none() {
+            match lines.recv().await {
+                Ok(line) => url = provider.public_url(&line.line),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+        let url = url?;
+        info!("Public tunnel ready at {url}");
+        self.set_url(url.clone());
+        Some(url)
+    }
+}
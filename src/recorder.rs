@@ -1,18 +1,41 @@
-use http::{HeaderMap, Method, StatusCode, Version};
+use bytes::Bytes;
+use http::{HeaderMap, Method, StatusCode, Uri, Version};
 use mime::Mime;
-use parking_lot::RwLock;
-use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "decoders")]
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::body_size::SizeWarning;
+use crate::cors::CorsPolicy;
+use crate::datetime::{rfc3339, UtcOffset};
+use crate::memory::Pressure;
+use crate::tunnel::Relayed;
 
 pub struct RequestInfo<'a> {
     pub method: &'a Method,
+    /// The request target as received: a path with its query, or an
+    /// absolute URL.
     pub path: &'a str,
     pub version: Version,
     pub headers: &'a HeaderMap,
     pub body: &'a [u8],
     pub client_addr: String,
+    pub listener: Option<SocketAddr>,
+    pub target: Option<String>,
+    /// Trailer fields sent after the body, which only HTTP/2 carries here.
+    pub trailers: Option<&'a HeaderMap>,
     pub truncate_at: usize,
 }
 
@@ -23,251 +46,1772 @@ pub struct ResponseInfo<'a> {
     pub headers: &'a HeaderMap,
     pub body: &'a [u8],
     pub duration_ms: u64,
+    pub trailers: Option<&'a HeaderMap>,
     pub truncate_at: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequestRecord {
     pub id: String,
+    /// Counts up from 1 in the order requests arrived, which timestamps
+    /// alone cannot tell within a millisecond. 0 in records from before it
+    /// was kept.
+    #[serde(default)]
+    pub seq: u64,
     pub timestamp: u64,
+    /// `timestamp` as RFC 3339, in the timezone set with `--timezone`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub time: String,
     pub method: String,
+    /// The path with its query string, if any.
     pub path: String,
+    /// The decoded query parameters, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub query: Vec<(String, String)>,
+    /// The absolute URL, when the request named its scheme and host as
+    /// forward proxy and HTTP/2 requests do.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
     pub version: String,
     pub headers: Vec<(String, String)>,
+    /// Size of the header fields as HTTP/1.1 sends them.
+    #[serde(default)]
+    pub header_bytes: usize,
     pub body: BodyRecord,
     pub client_addr: String,
+    /// The listen address the request arrived on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listener: Option<String>,
+    /// The upstream base URL, such as `https://example.com`, when it varies
+    /// per request: the host a forward proxy request named, or the upstream
+    /// picked by a split or route script. Absent for requests to the fixed
+    /// upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Whether this is a CORS preflight.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preflight: bool,
+    /// The capture session running when the request arrived.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Trailer fields that followed the body.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailers: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseRecord {
     pub id: String,
     pub timestamp: u64,
+    /// `timestamp` as RFC 3339, in the timezone set with `--timezone`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub time: String,
     pub status: u16,
     pub version: String,
     pub headers: Vec<(String, String)>,
+    /// Size of the header fields as HTTP/1.1 sends them.
+    #[serde(default)]
+    pub header_bytes: usize,
     pub body: BodyRecord,
     pub duration_ms: u64,
+    /// Trailer fields that followed the body, such as gRPC's `grpc-status`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailers: Vec<(String, String)>,
+    /// Whether reading the body failed part way; `body` then describes what
+    /// arrived before the transaction's `error`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BodyRecord {
     pub content_type: Option<String>,
+    /// Bytes on the wire, before any `Content-Encoding` is removed.
     pub size: usize,
-    pub preview: String,
+    /// Bytes once a gzip or deflate `Content-Encoding` is removed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded_size: Option<usize>,
+    /// Hex SHA-256 of the whole body as it was on the wire, including any
+    /// part past the truncation point. Empty in records from before it was
+    /// computed.
+    #[serde(default)]
+    pub sha256: String,
+    /// Stored once for all records of the same body.
+    pub preview: BodyText,
     pub is_binary: bool,
     pub truncated: bool,
 }
 
+/// Recorded body text, shared between the records of identical bodies so
+/// repeated responses, like those to polling, take no extra memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BodyText(Arc<str>);
+
+impl BodyText {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for BodyText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for BodyText {
+    fn from(text: String) -> Self {
+        Self(text.into())
+    }
+}
+
+impl From<&str> for BodyText {
+    fn from(text: &str) -> Self {
+        Self(text.into())
+    }
+}
+
+impl PartialEq<str> for BodyText {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for BodyText {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Serialize for BodyText {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for BodyText {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Recorded body text by the SHA-256 of the body it came from, so each
+/// distinct body is kept once however many transactions carry it.
+#[derive(Default)]
+struct BodyStore {
+    bodies: HashMap<String, Weak<str>>,
+    /// Entries after the last sweep of bodies no record holds any more.
+    swept_len: usize,
+}
+
+impl BodyStore {
+    /// Points `body` at the stored copy of its text, storing it if this is
+    /// the first record of the body.
+    fn intern(&mut self, body: &mut BodyRecord) {
+        if body.preview.is_empty() {
+            return;
+        }
+        // The same body recorded with other truncation or binary rules has
+        // different text, which replaces the stored copy
+        if let Some(stored) = self.bodies.get(&body.sha256).and_then(Weak::upgrade) {
+            if *stored == *body.preview.0 {
+                body.preview = BodyText(stored);
+                return;
+            }
+        }
+        self.bodies
+            .insert(body.sha256.clone(), Arc::downgrade(&body.preview.0));
+        if self.bodies.len() > 2 * self.swept_len.max(64) {
+            self.bodies.retain(|_, text| text.strong_count() > 0);
+            self.swept_len = self.bodies.len();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.bodies.clear();
+        self.swept_len = 0;
+    }
+}
+
+/// Byte counts over recorded requests or responses.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SizeTotals {
+    pub count: usize,
+    pub header_bytes: usize,
+    pub max_header_bytes: usize,
+    /// Body bytes on the wire.
+    pub body_bytes: usize,
+    /// Body bytes once decoded, the same as `body_bytes` where there was no
+    /// encoding to remove.
+    pub decoded_body_bytes: usize,
+}
+
+impl SizeTotals {
+    pub fn add(&mut self, header_bytes: usize, body: &BodyRecord) {
+        self.count += 1;
+        self.header_bytes += header_bytes;
+        self.max_header_bytes = self.max_header_bytes.max(header_bytes);
+        self.body_bytes += body.size;
+        self.decoded_body_bytes += body.decoded_size.unwrap_or(body.size);
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HttpTransaction {
     pub request: RequestRecord,
     pub response: Option<ResponseRecord>,
     pub error: Option<String>,
+    #[serde(default)]
+    pub violations: Vec<Violation>,
+    /// The upstream connection the response arrived on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<UpstreamConnection>,
+    /// The unhealthy upstream instance the request was routed away from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failover_from: Option<String>,
+    /// Why safe mode kept the request from the upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<String>,
+    /// Statuses of interim responses sent to the client before the final
+    /// one, such as `100` for a request with `Expect: 100-continue`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interim_responses: Vec<u16>,
+    /// The preflight folded into this transaction when preflights are
+    /// collapsed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preflight_id: Option<String>,
+    /// Response transforms that changed what the client got. The recorded
+    /// response is the one the upstream sent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<String>,
+    /// Why the route script failed, sending the request to the default
+    /// upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route_script_error: Option<String>,
+    /// Where the time went, to tell the proxy's own share from the client's
+    /// and the upstream's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overhead: Option<ProxyOverhead>,
+    /// Names of the configured headers added to the request on its way
+    /// upstream. Their values are not recorded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub injected_headers: Vec<String>,
+    /// Bodies over `max_body_size`, or with a `Content-Length` that did not
+    /// match what arrived.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub size_warnings: Vec<SizeWarning>,
+    /// The client went away before its response was ready. The upstream
+    /// request was cancelled, so there is no response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_aborted: Option<ClientAbort>,
+    /// The first of a run of identical requests (same method, path and
+    /// body) that this one repeated within the duplicate window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+    /// The requests that repeated this one, on the first of a run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duplicates: Vec<String>,
+    /// The recorded transaction whose response answered this one in offline
+    /// mode, without contacting the upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub served_from: Option<String>,
+    /// When the transaction finished, in milliseconds since the epoch: its
+    /// response was recorded, it failed, or its client went away. `None`
+    /// while it is in flight. It started at the request's `timestamp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<u64>,
+    /// The request arrived through the public tunnel's URL rather than
+    /// locally.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub via_public_tunnel: bool,
+    /// Captured by the webhook inbox and answered without contacting the
+    /// upstream.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inbox: bool,
+    /// The inbox capture this one delivered to the upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replayed_from: Option<String>,
+    /// The connection tunnelled after the upstream switched protocols.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelRecord>,
+    /// How long a hold rule kept the request from the upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held: Option<HoldRecord>,
+    /// The held request this one delivered to the upstream once released.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_from: Option<String>,
+}
+
+/// How CORS preflights appear in the transaction list. They are recorded and
+/// counted in the stats either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreflightView {
+    #[default]
+    Show,
+    /// Fold each preflight into the request it preceded, keeping only those
+    /// no request followed, such as rejected ones.
+    Collapse,
+    Hide,
+}
+
+impl std::str::FromStr for PreflightView {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "show" => Ok(Self::Show),
+            "collapse" => Ok(Self::Collapse),
+            "hide" => Ok(Self::Hide),
+            _ => anyhow::bail!("Expected show, collapse or hide, got {s}"),
+        }
+    }
+}
+
+/// How recorded bodies are told apart from text. Content types are matched
+/// on their essence, like `application/x-ndjson`, or as `type/*`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BinaryDetection {
+    /// Treat any body containing a null byte as binary.
+    pub null_bytes: bool,
+    /// Percentage of control characters other than tabs and line breaks
+    /// above which a body is binary.
+    pub non_printable_percent: u8,
+    /// Content types always shown as text.
+    pub text_types: Vec<String>,
+    /// Content types always treated as binary, ahead of `text_types`.
+    pub binary_types: Vec<String>,
+}
+
+impl Default for BinaryDetection {
+    fn default() -> Self {
+        Self {
+            null_bytes: true,
+            non_printable_percent: 30,
+            text_types: Vec::new(),
+            binary_types: Vec::new(),
+        }
+    }
+}
+
+impl BinaryDetection {
+    pub fn is_binary(&self, data: &[u8], content_type: Option<&str>) -> bool {
+        if data.is_empty() {
+            return false;
+        }
+
+        // Check content type first
+        if let Some(mime) = content_type.and_then(|ct| ct.parse::<Mime>().ok()) {
+            let listed = |types: &[String]| {
+                types.iter().any(|listed| {
+                    let listed = listed.trim();
+                    listed.eq_ignore_ascii_case(mime.essence_str())
+                        || listed
+                            .strip_suffix("/*")
+                            .is_some_and(|kind| kind.eq_ignore_ascii_case(mime.type_().as_str()))
+                })
+            };
+            if listed(&self.binary_types) {
+                return true;
+            }
+            if listed(&self.text_types) {
+                return false;
+            }
+            match (mime.type_(), mime.subtype()) {
+                (mime::TEXT, _) => return false,
+                (mime::APPLICATION, mime::JSON) => return false,
+                (mime::APPLICATION, mime::JAVASCRIPT) => return false,
+                (mime::APPLICATION, subtype) if subtype == "xml" => return false,
+                (mime::APPLICATION, subtype) if subtype.as_str().ends_with("+json") => {
+                    return false
+                }
+                (mime::APPLICATION, subtype) if subtype.as_str().ends_with("+xml") => return false,
+                _ => {}
+            }
+        }
+
+        // Heuristic: check for null bytes or high ratio of non-printable characters
+        if self.null_bytes && data.contains(&0) {
+            return true;
+        }
+
+        let non_printable_count = data
+            .iter()
+            .filter(|&&b| b < 32 && b != b'\t' && b != b'\n' && b != b'\r')
+            .count();
+
+        non_printable_count * 100 / data.len() > usize::from(self.non_printable_percent)
+    }
+}
+
+/// A named capture that groups the requests recorded while it ran, so
+/// separate repro attempts can be told apart and exported on their own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureSession {
+    pub name: String,
+    pub started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stopped_at: Option<u64>,
+}
+
+/// When a client gave up on its request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientAbort {
+    pub timestamp: u64,
+    /// How long after the request arrived.
+    pub duration_ms: u64,
+}
+
+/// A connection passed through byte for byte after a `101 Switching
+/// Protocols`, since the proxy does not understand what follows.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelRecord {
+    /// The protocol from the upstream's `Upgrade` header.
+    pub protocol: String,
+    pub bytes_to_upstream: u64,
+    pub bytes_to_client: u64,
+    /// `None` while the tunnel is open.
+    pub closed_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A request a hold rule kept from the upstream until it was released.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HoldRecord {
+    pub rule: String,
+    pub held_ms: u64,
+    /// `delay` when the rule's delay ran out, `admin` when released from
+    /// the admin API.
+    pub released_by: String,
+    /// The transaction that delivered it to the upstream, when the client
+    /// was answered `202` rather than kept waiting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivered_as: Option<String>,
+}
+
+/// Time spent on a proxied transaction, in microseconds. `proxy_us` is what
+/// debug-proxy itself added: recording, checks and rewriting headers and
+/// bodies, before and after the upstream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProxyOverhead {
+    /// Reading the request body from the client.
+    pub request_body_us: u64,
+    /// From the body being read until the request went upstream.
+    pub before_upstream_us: u64,
+    /// Waiting for the upstream's response headers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_us: Option<u64>,
+    /// Reading the response body from the upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_body_us: Option<u64>,
+    /// From the response body arriving until the response was handed to
+    /// the client.
+    pub after_upstream_us: u64,
+    pub proxy_us: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamConnection {
+    /// Numbers connections in the order they were opened.
+    pub id: u64,
+    /// Whether an earlier response already used the connection.
+    pub reused: bool,
+    /// Time spent resolving the upstream host before connecting; absent
+    /// when it was an IP address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<u64>,
+    /// What was negotiated with an HTTPS upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTls>,
+}
+
+/// The TLS session of an upstream connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpstreamTls {
+    /// Such as `TLSv1.3`.
+    pub version: String,
+    /// Such as `TLS13_AES_128_GCM_SHA256`.
+    pub cipher: String,
+    /// The protocol agreed through ALPN, such as `h2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<String>,
+    /// The certificate the upstream presented, absent if it could not be
+    /// parsed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<CertificateInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    /// DNS names and IP addresses the certificate is valid for.
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// Validity period, in Unix milliseconds.
+    pub not_before: i64,
+    pub not_after: i64,
+    /// Whether the certificate was outside its validity period when the
+    /// connection was opened.
+    pub expired: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Violation {
+    pub source: String,
+    pub kind: String,
+    pub message: String,
+}
+
+/// Default number of records that may wait for the background writer.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// A change to the history, taken on the request path and applied by
+/// [`apply`], inline or on the background writer.
+enum RecordEvent {
+    Request {
+        id: String,
+        seq: u64,
+        timestamp: u64,
+        time: String,
+        method: Method,
+        path: String,
+        version: Version,
+        headers: HeaderMap,
+        body: Bytes,
+        client_addr: String,
+        listener: Option<SocketAddr>,
+        target: Option<String>,
+        trailers: Option<HeaderMap>,
+        truncate_at: usize,
+        session: Option<String>,
+    },
+    Response {
+        request_id: String,
+        timestamp: u64,
+        time: String,
+        status: StatusCode,
+        version: Version,
+        headers: HeaderMap,
+        body: Bytes,
+        duration_ms: u64,
+        trailers: Option<HeaderMap>,
+        truncate_at: usize,
+        /// Why the body was cut short.
+        error: Option<String>,
+    },
+    Error {
+        request_id: String,
+        timestamp: u64,
+        error: String,
+    },
+    Violations {
+        request_id: String,
+        violations: Vec<Violation>,
+    },
+    Connection {
+        request_id: String,
+        connection: UpstreamConnection,
+    },
+    Failover {
+        request_id: String,
+        from: String,
+    },
+    Interim {
+        request_id: String,
+        status: StatusCode,
+    },
+    Blocked {
+        request_id: String,
+        reason: String,
+    },
+    ServedFrom {
+        request_id: String,
+        source_id: String,
+    },
+    Transforms {
+        request_id: String,
+        rules: Vec<String>,
+    },
+    RouteScriptError {
+        request_id: String,
+        error: String,
+    },
+    Overhead {
+        request_id: String,
+        overhead: ProxyOverhead,
+    },
+    InjectedHeaders {
+        request_id: String,
+        names: Vec<String>,
+    },
+    SizeWarnings {
+        request_id: String,
+        warnings: Vec<SizeWarning>,
+    },
+    ClientAborted {
+        request_id: String,
+        abort: ClientAbort,
+    },
+    Tunnel {
+        request_id: String,
+        tunnel: TunnelRecord,
+    },
+    ViaPublicTunnel {
+        request_id: String,
+    },
+    Inbox {
+        request_id: String,
+    },
+    Held {
+        request_id: String,
+        held: HoldRecord,
+    },
+    ReplayedFrom {
+        request_id: String,
+        source_id: String,
+    },
+    HeldFrom {
+        request_id: String,
+        source_id: String,
+    },
+    Flush(oneshot::Sender<()>),
+}
+
+/// Transactions in each history segment.
+const SEGMENT_LEN: u64 = 256;
+/// Independently locked parts of the request id index.
+const INDEX_SHARDS: usize = 16;
+
+/// A run of [`SEGMENT_LEN`] consecutive history slots under its own lock,
+/// so updates to transactions in different segments never wait on each
+/// other. Evicted slots are emptied.
+struct Segment {
+    /// Slot number of the first transaction.
+    start: u64,
+    transactions: RwLock<Vec<Option<Arc<HttpTransaction>>>>,
+}
+
+impl Segment {
+    fn new(start: u64) -> Self {
+        Self {
+            start,
+            transactions: RwLock::new(vec![None; SEGMENT_LEN as usize]),
+        }
+    }
+}
+
+/// Recorded transactions, oldest first, in append-only segments. Each
+/// transaction sits behind an `Arc` so readers copy pointers rather than
+/// whole transactions. Only appends and evictions are serialized; updates
+/// lock the one segment and index shard they touch, and readers hold the
+/// segment list just long enough to copy it.
+#[derive(Default)]
+struct History {
+    /// Held while appending or evicting, which move the ends of the history.
+    append: Mutex<()>,
+    segments: RwLock<VecDeque<Arc<Segment>>>,
+    /// Slot number of the oldest transaction.
+    first: AtomicU64,
+    /// Slot number the next transaction takes.
+    end: AtomicU64,
+    /// Request id to slot number, sharded by id.
+    index: [Mutex<HashMap<String, u64>>; INDEX_SHARDS],
+    /// Bumped on every change, for ETags.
+    generation: AtomicU64,
+    duplicates: Mutex<Duplicates>,
+    bodies: Mutex<BodyStore>,
+}
+
+#[derive(Default)]
+struct Duplicates {
+    /// How close identical requests must follow each other to count as
+    /// duplicates; zero turns detection off.
+    window: Duration,
+    /// The latest run of identical requests for each request fingerprint.
+    recent: HashMap<u64, RecentRequest>,
 }
 
+struct RecentRequest {
+    first_id: String,
+    last_seen: u64,
+}
+
+impl History {
+    fn len(&self) -> u64 {
+        self.end.load(Ordering::Relaxed) - self.first.load(Ordering::Relaxed)
+    }
+
+    fn index_shard(&self, id: &str) -> &Mutex<HashMap<String, u64>> {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        &self.index[hasher.finish() as usize % INDEX_SHARDS]
+    }
+
+    /// The segment holding `slot`, if it is still held.
+    fn segment(&self, slot: u64) -> Option<Arc<Segment>> {
+        let segments = self.segments.read();
+        let front = segments.front()?.start;
+        let segment = segments.get(slot.checked_sub(front)? as usize / SEGMENT_LEN as usize)?;
+        (segment.start + SEGMENT_LEN > slot).then(|| Arc::clone(segment))
+    }
+
+    /// Interns the request body, links the request to an identical one
+    /// recorded within the duplicate window and appends it, evicting the
+    /// oldest transaction when `max_size` are held.
+    fn record(&self, mut transaction: HttpTransaction, fingerprint: u64, max_size: usize) {
+        let append = self.append.lock();
+        self.bodies.lock().intern(&mut transaction.request.body);
+        if !transaction.request.preflight {
+            transaction.duplicate_of = self.link_duplicate(
+                fingerprint,
+                transaction.request.timestamp,
+                &transaction.request.id,
+            );
+        }
+        self.push(&append, transaction, max_size);
+    }
+
+    /// Appends previously recorded transactions as they are.
+    fn extend(&self, transactions: Vec<HttpTransaction>, max_size: usize) {
+        let append = self.append.lock();
+        for transaction in transactions {
+            self.push(&append, transaction, max_size);
+        }
+    }
+
+    fn push(&self, append: &MutexGuard<'_, ()>, transaction: HttpTransaction, max_size: usize) {
+        if self.len() >= max_size as u64 {
+            self.pop_front(append);
+        }
+        let slot = self.end.load(Ordering::Relaxed);
+        let start = slot - slot % SEGMENT_LEN;
+        let tail = self
+            .segments
+            .read()
+            .back()
+            .filter(|segment| segment.start == start)
+            .cloned();
+        let tail = tail.unwrap_or_else(|| {
+            let segment = Arc::new(Segment::new(start));
+            self.segments.write().push_back(Arc::clone(&segment));
+            segment
+        });
+        let id = transaction.request.id.clone();
+        tail.transactions.write()[(slot - start) as usize] = Some(Arc::new(transaction));
+        self.index_shard(&id).lock().insert(id, slot);
+        self.end.store(slot + 1, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn pop_front(&self, _append: &MutexGuard<'_, ()>) {
+        let first = self.first.load(Ordering::Relaxed);
+        if first == self.end.load(Ordering::Relaxed) {
+            return;
+        }
+        let evicted = self.segment(first).and_then(|segment| {
+            segment.transactions.write()[(first - segment.start) as usize].take()
+        });
+        if let Some(transaction) = evicted {
+            let id = &transaction.request.id;
+            self.index_shard(id).lock().remove(id);
+        }
+        self.first.store(first + 1, Ordering::Relaxed);
+        if (first + 1).is_multiple_of(SEGMENT_LEN) {
+            self.segments.write().pop_front();
+        }
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Evicts the oldest transactions until at most `len` are held.
+    fn truncate(&self, len: usize) {
+        let append = self.append.lock();
+        while self.len() > len as u64 {
+            self.pop_front(&append);
+        }
+    }
+
+    fn clear(&self) {
+        let _append = self.append.lock();
+        self.segments.write().clear();
+        for shard in &self.index {
+            shard.lock().clear();
+        }
+        self.duplicates.lock().recent.clear();
+        self.bodies.lock().clear();
+        self.first
+            .store(self.end.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Notes a request recorded at `timestamp`. When an identical one came
+    /// within the duplicate window, links the two and returns the id of the
+    /// first of their run.
+    fn link_duplicate(&self, fingerprint: u64, timestamp: u64, id: &str) -> Option<String> {
+        let first_id = {
+            let mut duplicates = self.duplicates.lock();
+            let window = duplicates.window.as_millis() as u64;
+            if window == 0 {
+                return None;
+            }
+            duplicates
+                .recent
+                .retain(|_, recent| timestamp.saturating_sub(recent.last_seen) <= window);
+            match duplicates.recent.entry(fingerprint) {
+                Entry::Occupied(mut entry) => {
+                    entry.get_mut().last_seen = timestamp;
+                    entry.get().first_id.clone()
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(RecentRequest {
+                        first_id: id.to_string(),
+                        last_seen: timestamp,
+                    });
+                    return None;
+                }
+            }
+        };
+        self.update(&first_id, |first| first.duplicates.push(id.to_string()));
+        Some(first_id)
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<HttpTransaction>> {
+        let slot = *self.index_shard(id).lock().get(id)?;
+        self.get_slot(slot)
+    }
+
+    fn get_slot(&self, slot: u64) -> Option<Arc<HttpTransaction>> {
+        let segment = self.segment(slot)?;
+        let transactions = segment.transactions.read();
+        transactions[(slot - segment.start) as usize].clone()
+    }
+
+    /// Applies `update` to the transaction recorded under `id`, copying it
+    /// first if a reader still holds it.
+    fn update(&self, id: &str, update: impl FnOnce(&mut HttpTransaction)) {
+        let Some(slot) = self.index_shard(id).lock().get(id).copied() else {
+            return;
+        };
+        let Some(segment) = self.segment(slot) else {
+            return;
+        };
+        let mut transactions = segment.transactions.write();
+        if let Some(transaction) = transactions[(slot - segment.start) as usize].as_mut() {
+            update(Arc::make_mut(transaction));
+            self.generation.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Every held transaction, oldest first. Segments are read one at a
+    /// time, so changes made meanwhile may show in later segments only.
+    fn snapshot(&self) -> Vec<Arc<HttpTransaction>> {
+        let segments: Vec<Arc<Segment>> = self.segments.read().iter().cloned().collect();
+        let mut transactions = Vec::with_capacity(self.len() as usize);
+        for segment in segments {
+            transactions.extend(segment.transactions.read().iter().flatten().cloned());
+        }
+        transactions
+    }
+}
+
+type SharedHistory = Arc<History>;
+
 pub struct RequestRecorder {
-    transactions: Arc<RwLock<VecDeque<HttpTransaction>>>,
+    history: SharedHistory,
     max_size: usize,
+    binary: Arc<RwLock<BinaryDetection>>,
+    sessions: Arc<RwLock<Vec<CaptureSession>>>,
+    queue: Option<mpsc::Sender<RecordEvent>>,
+    dropped: Arc<AtomicU64>,
+    pressure: Arc<AtomicU8>,
+    unrecorded: Arc<AtomicU64>,
+    next_seq: Arc<AtomicU64>,
+    utc_offset: Arc<RwLock<UtcOffset>>,
 }
 
 impl RequestRecorder {
     pub fn new(max_size: usize) -> Self {
         Self {
-            transactions: Arc::new(RwLock::new(VecDeque::with_capacity(max_size))),
+            history: Arc::default(),
             max_size,
+            binary: Arc::default(),
+            sessions: Arc::default(),
+            queue: None,
+            dropped: Arc::new(AtomicU64::new(0)),
+            pressure: Arc::default(),
+            unrecorded: Arc::default(),
+            next_seq: Arc::default(),
+            utc_offset: Arc::default(),
         }
     }
 
-    pub fn record_request(&self, info: RequestInfo) -> String {
-        let id = uuid::Uuid::new_v4().to_string();
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+    /// Moves body analysis and the history locks off the caller onto a
+    /// background task fed by a queue of `capacity` records. Records that
+    /// find the queue full are dropped and counted in [`Self::dropped`].
+    /// Must be called within a tokio runtime.
+    pub fn with_background_writer(mut self, capacity: usize) -> Self {
+        let (tx, mut rx) = mpsc::channel(capacity.max(1));
+        let history = Arc::clone(&self.history);
+        let max_size = self.max_size;
+        let binary = Arc::clone(&self.binary);
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                apply(&history, max_size, &binary, event);
+            }
+        });
+        self.queue = Some(tx);
+        self
+    }
 
-        let body_record = Self::analyze_body(info.body, info.headers, info.truncate_at);
+    /// Records dropped because the background writer fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
 
-        let request = RequestRecord {
+    /// Waits until everything recorded so far is in the history.
+    pub async fn flush(&self) {
+        let Some(ref queue) = self.queue else {
+            return;
+        };
+        let (tx, rx) = oneshot::channel();
+        if queue.send(RecordEvent::Flush(tx)).await.is_ok() {
+            let _ = rx.await;
+        }
+    }
+
+    /// How far recording backs off to save memory.
+    pub fn pressure(&self) -> Pressure {
+        Pressure::from_u8(self.pressure.load(Ordering::Relaxed))
+    }
+
+    pub fn set_pressure(&self, pressure: Pressure) {
+        self.pressure.store(pressure as u8, Ordering::Relaxed);
+    }
+
+    /// The timezone of the `time` of records from now on.
+    pub fn utc_offset(&self) -> UtcOffset {
+        *self.utc_offset.read()
+    }
+
+    pub fn set_utc_offset(&self, offset: UtcOffset) {
+        *self.utc_offset.write() = offset;
+    }
+
+    /// Requests not recorded because recording was paused.
+    pub fn unrecorded(&self) -> u64 {
+        self.unrecorded.load(Ordering::Relaxed)
+    }
+
+    /// Records a request and returns its id. While recording is paused the
+    /// id is returned but nothing recorded under it.
+    pub fn record_request(&self, info: RequestInfo) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let pressure = self.pressure();
+        if pressure == Pressure::Paused {
+            self.unrecorded.fetch_add(1, Ordering::Relaxed);
+            return id;
+        }
+        let timestamp = now_ms();
+        self.submit(RecordEvent::Request {
             id: id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed) + 1,
             timestamp,
-            method: info.method.to_string(),
+            time: rfc3339(timestamp, self.utc_offset()),
+            method: info.method.clone(),
             path: info.path.to_string(),
-            version: format!("{:?}", info.version),
-            headers: info
-                .headers
-                .iter()
-                .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("<invalid>").to_string()))
-                .collect(),
-            body: body_record,
+            version: info.version,
+            headers: info.headers.clone(),
+            body: Bytes::copy_from_slice(info.body),
             client_addr: info.client_addr,
-        };
+            listener: info.listener,
+            target: info.target,
+            trailers: info.trailers.cloned(),
+            truncate_at: pressure.truncate_at(info.truncate_at),
+            session: self.current_session().map(|session| session.name),
+        });
+        id
+    }
 
-        let transaction = HttpTransaction {
-            request,
-            response: None,
-            error: None,
-        };
+    pub fn record_response(&self, info: ResponseInfo) {
+        self.submit(response_event(
+            info,
+            None,
+            self.pressure(),
+            self.utc_offset(),
+        ));
+    }
 
-        let mut transactions = self.transactions.write();
-        if transactions.len() >= self.max_size {
-            transactions.pop_front();
+    /// Records a response whose body failed part way through `info.body`,
+    /// keeping its status and headers next to the error.
+    pub fn record_partial_response(&self, info: ResponseInfo, error: String) {
+        self.submit(response_event(
+            info,
+            Some(error),
+            self.pressure(),
+            self.utc_offset(),
+        ));
+    }
+
+    pub fn record_error(&self, request_id: &str, error: String) {
+        self.submit(RecordEvent::Error {
+            request_id: request_id.to_string(),
+            timestamp: now_ms(),
+            error,
+        });
+    }
+
+    pub fn record_violations(&self, request_id: &str, violations: Vec<Violation>) {
+        if violations.is_empty() {
+            return;
         }
-        transactions.push_back(transaction);
+        self.submit(RecordEvent::Violations {
+            request_id: request_id.to_string(),
+            violations,
+        });
+    }
 
-        id
+    pub fn record_connection(&self, request_id: &str, connection: UpstreamConnection) {
+        self.submit(RecordEvent::Connection {
+            request_id: request_id.to_string(),
+            connection,
+        });
     }
 
-    pub fn record_response(&self, info: ResponseInfo) {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+    pub fn record_failover(&self, request_id: &str, from: String) {
+        self.submit(RecordEvent::Failover {
+            request_id: request_id.to_string(),
+            from,
+        });
+    }
 
-        let body_record = Self::analyze_body(info.body, info.headers, info.truncate_at);
+    pub fn record_interim(&self, request_id: &str, status: StatusCode) {
+        self.submit(RecordEvent::Interim {
+            request_id: request_id.to_string(),
+            status,
+        });
+    }
 
-        let response = ResponseRecord {
-            id: info.request_id.to_string(),
-            timestamp,
-            status: info.status.as_u16(),
-            version: format!("{:?}", info.version),
-            headers: info
-                .headers
-                .iter()
-                .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("<invalid>").to_string()))
-                .collect(),
-            body: body_record,
-            duration_ms: info.duration_ms,
-        };
+    pub fn record_blocked(&self, request_id: &str, reason: String) {
+        self.submit(RecordEvent::Blocked {
+            request_id: request_id.to_string(),
+            reason,
+        });
+    }
+
+    pub fn record_served_from(&self, request_id: &str, source_id: &str) {
+        self.submit(RecordEvent::ServedFrom {
+            request_id: request_id.to_string(),
+            source_id: source_id.to_string(),
+        });
+    }
 
-        let mut transactions = self.transactions.write();
-        if let Some(transaction) = transactions
-            .iter_mut()
-            .find(|t| t.request.id == info.request_id)
-        {
-            transaction.response = Some(response);
+    pub fn record_transforms(&self, request_id: &str, rules: Vec<String>) {
+        if rules.is_empty() {
+            return;
         }
+        self.submit(RecordEvent::Transforms {
+            request_id: request_id.to_string(),
+            rules,
+        });
     }
 
-    pub fn record_error(&self, request_id: &str, error: String) {
-        let mut transactions = self.transactions.write();
-        if let Some(transaction) = transactions.iter_mut().find(|t| t.request.id == request_id) {
-            transaction.error = Some(error);
+    pub fn record_route_script_error(&self, request_id: &str, error: String) {
+        self.submit(RecordEvent::RouteScriptError {
+            request_id: request_id.to_string(),
+            error,
+        });
+    }
+
+    pub fn record_overhead(&self, request_id: &str, overhead: ProxyOverhead) {
+        self.submit(RecordEvent::Overhead {
+            request_id: request_id.to_string(),
+            overhead,
+        });
+    }
+
+    pub fn record_injected_headers(&self, request_id: &str, names: Vec<String>) {
+        if names.is_empty() {
+            return;
+        }
+        self.submit(RecordEvent::InjectedHeaders {
+            request_id: request_id.to_string(),
+            names,
+        });
+    }
+
+    pub fn record_size_warnings(&self, request_id: &str, warnings: Vec<SizeWarning>) {
+        if warnings.is_empty() {
+            return;
         }
+        self.submit(RecordEvent::SizeWarnings {
+            request_id: request_id.to_string(),
+            warnings,
+        });
+    }
+
+    pub fn record_via_public_tunnel(&self, request_id: &str) {
+        self.submit(RecordEvent::ViaPublicTunnel {
+            request_id: request_id.to_string(),
+        });
+    }
+
+    pub fn record_inbox(&self, request_id: &str) {
+        self.submit(RecordEvent::Inbox {
+            request_id: request_id.to_string(),
+        });
+    }
+
+    pub fn record_replayed_from(&self, request_id: &str, source_id: &str) {
+        self.submit(RecordEvent::ReplayedFrom {
+            request_id: request_id.to_string(),
+            source_id: source_id.to_string(),
+        });
+    }
+
+    pub fn record_held_from(&self, request_id: &str, source_id: &str) {
+        self.submit(RecordEvent::HeldFrom {
+            request_id: request_id.to_string(),
+            source_id: source_id.to_string(),
+        });
+    }
+
+    pub fn record_held(&self, request_id: &str, held: HoldRecord) {
+        self.submit(RecordEvent::Held {
+            request_id: request_id.to_string(),
+            held,
+        });
+    }
+
+    pub fn record_tunnel_opened(&self, request_id: &str, protocol: &str) {
+        self.submit(RecordEvent::Tunnel {
+            request_id: request_id.to_string(),
+            tunnel: TunnelRecord {
+                protocol: protocol.to_string(),
+                bytes_to_upstream: 0,
+                bytes_to_client: 0,
+                closed_at: None,
+                error: None,
+            },
+        });
+    }
+
+    pub fn record_tunnel_closed(&self, request_id: &str, protocol: &str, relayed: Relayed) {
+        self.submit(RecordEvent::Tunnel {
+            request_id: request_id.to_string(),
+            tunnel: TunnelRecord {
+                protocol: protocol.to_string(),
+                bytes_to_upstream: relayed.bytes_to_upstream,
+                bytes_to_client: relayed.bytes_to_client,
+                closed_at: Some(now_ms()),
+                error: relayed.error,
+            },
+        });
+    }
+
+    pub fn record_client_aborted(&self, request_id: &str, duration_ms: u64) {
+        self.submit(RecordEvent::ClientAborted {
+            request_id: request_id.to_string(),
+            abort: ClientAbort {
+                timestamp: now_ms(),
+                duration_ms,
+            },
+        });
+    }
+
+    fn submit(&self, event: RecordEvent) {
+        let Some(ref queue) = self.queue else {
+            apply(&self.history, self.max_size, &self.binary, event);
+            return;
+        };
+        if queue.try_send(event).is_err() && self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+            warn!("Recording queue is full; dropping records until the writer catches up");
+        }
+    }
+
+    pub fn get_transaction(&self, request_id: &str) -> Option<HttpTransaction> {
+        self.history.get(request_id).map(Arc::unwrap_or_clone)
     }
 
     pub fn get_transactions(&self) -> Vec<HttpTransaction> {
-        self.transactions.read().iter().cloned().collect()
+        self.snapshot()
+            .into_iter()
+            .map(Arc::unwrap_or_clone)
+            .collect()
+    }
+
+    /// The recorded transactions, oldest first, sharing storage with the
+    /// history. Prefer this over [`Self::get_transactions`] for read-only use.
+    pub fn snapshot(&self) -> Vec<Arc<HttpTransaction>> {
+        self.history.snapshot()
+    }
+
+    /// The transactions to list with preflights shown as `view` says, oldest
+    /// first.
+    pub fn listed(&self, view: PreflightView) -> Vec<Arc<HttpTransaction>> {
+        let transactions = self.snapshot();
+        match view {
+            PreflightView::Show => transactions,
+            PreflightView::Hide => transactions
+                .into_iter()
+                .filter(|t| !t.request.preflight)
+                .collect(),
+            PreflightView::Collapse => {
+                // A preflight belongs to the next request for its path from
+                // the same client IP; the port changes between connections
+                let key = |t: &HttpTransaction| {
+                    let ip = t
+                        .request
+                        .client_addr
+                        .parse::<SocketAddr>()
+                        .map(|addr| addr.ip().to_string())
+                        .unwrap_or_else(|_| t.request.client_addr.clone());
+                    (ip, t.request.path.clone())
+                };
+                let mut pending = HashMap::new();
+                let mut listed: Vec<Option<Arc<HttpTransaction>>> = Vec::new();
+                for transaction in transactions {
+                    if transaction.request.preflight {
+                        pending.insert(key(&transaction), listed.len());
+                        listed.push(Some(transaction));
+                        continue;
+                    }
+                    let preflight = pending
+                        .remove(&key(&transaction))
+                        .and_then(|index| listed[index].take());
+                    listed.push(Some(match preflight {
+                        Some(preflight) => {
+                            let mut transaction = Arc::unwrap_or_clone(transaction);
+                            transaction.preflight_id = Some(preflight.request.id.clone());
+                            Arc::new(transaction)
+                        }
+                        None => transaction,
+                    }));
+                }
+                listed.into_iter().flatten().collect()
+            }
+        }
+    }
+
+    /// The transactions [`listed`](Self::listed) after the one numbered
+    /// `after`, or all of them.
+    pub fn listed_after(
+        &self,
+        view: PreflightView,
+        after: Option<u64>,
+    ) -> Vec<Arc<HttpTransaction>> {
+        let mut transactions = self.listed(view);
+        if let Some(after) = after {
+            transactions.retain(|t| t.request.seq > after);
+        }
+        transactions
+    }
+
+    /// An entity tag for the history that changes whenever anything is
+    /// recorded, updated or cleared.
+    pub fn etag(&self) -> String {
+        let history = &self.history;
+        let mut hasher = DefaultHasher::new();
+        history.len().hash(&mut hasher);
+        let end = history.end.load(Ordering::Relaxed);
+        end.checked_sub(1)
+            .and_then(|last| history.get_slot(last))
+            .map(|t| t.request.id.clone())
+            .hash(&mut hasher);
+        history.generation.load(Ordering::Relaxed).hash(&mut hasher);
+        format!("\"{:016x}\"", hasher.finish())
     }
 
     #[allow(dead_code)]
     pub fn get_recent_transactions(&self, count: usize) -> Vec<HttpTransaction> {
-        let transactions = self.transactions.read();
-        transactions
-            .iter()
-            .rev()
-            .take(count)
-            .cloned()
-            .collect::<Vec<_>>()
+        let recent = self.history.snapshot();
+        let skip = recent.len().saturating_sub(count);
+        recent
             .into_iter()
-            .rev()
+            .skip(skip)
+            .map(Arc::unwrap_or_clone)
             .collect()
     }
 
     pub fn clear(&self) {
-        self.transactions.write().clear();
+        self.history.clear();
     }
 
-    pub fn resize(&self, new_size: usize) {
-        let mut transactions = self.transactions.write();
-        while transactions.len() > new_size {
-            transactions.pop_front();
+    /// Starts tagging new requests with `name`, stopping the running
+    /// session first.
+    pub fn start_session(&self, name: String) -> anyhow::Result<CaptureSession> {
+        let mut sessions = self.sessions.write();
+        if sessions.iter().any(|session| session.name == name) {
+            anyhow::bail!("A session named {name:?} already exists");
+        }
+        let now = now_ms();
+        if let Some(running) = sessions.last_mut().filter(|s| s.stopped_at.is_none()) {
+            running.stopped_at = Some(now);
         }
-        transactions.reserve(new_size);
+        let session = CaptureSession {
+            name,
+            started_at: now,
+            stopped_at: None,
+        };
+        sessions.push(session.clone());
+        Ok(session)
     }
 
-    fn analyze_body(body: &[u8], headers: &HeaderMap, truncate_at: usize) -> BodyRecord {
-        let size = body.len();
-        let content_type = headers
-            .get("content-type")
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_string());
+    /// Stops the running session, returning it.
+    pub fn stop_session(&self) -> Option<CaptureSession> {
+        let mut sessions = self.sessions.write();
+        let running = sessions.last_mut().filter(|s| s.stopped_at.is_none())?;
+        running.stopped_at = Some(now_ms());
+        Some(running.clone())
+    }
 
-        let is_binary = Self::is_binary_content(body, content_type.as_deref());
-        let truncated = size > truncate_at;
+    pub fn current_session(&self) -> Option<CaptureSession> {
+        self.sessions
+            .read()
+            .last()
+            .filter(|session| session.stopped_at.is_none())
+            .cloned()
+    }
 
-        let preview = if is_binary {
-            if size == 0 {
-                String::new()
-            } else {
-                format!("<binary data: {size} bytes>")
-            }
-        } else {
-            let preview_bytes = if truncated {
-                &body[..truncate_at.min(size)]
-            } else {
-                body
-            };
+    /// Every session started, oldest first.
+    pub fn sessions(&self) -> Vec<CaptureSession> {
+        self.sessions.read().clone()
+    }
 
-            match std::str::from_utf8(preview_bytes) {
-                Ok(s) => s.to_string(),
-                Err(_) => format!("<invalid UTF-8: {size} bytes>"),
-            }
-        };
+    /// Adds previously recorded transactions, such as a loaded snapshot,
+    /// oldest first. Only the newest fit when there are more than the
+    /// history holds.
+    pub fn restore(&self, transactions: Vec<HttpTransaction>) {
+        // Number new requests after the restored ones
+        if let Some(seq) = transactions.iter().map(|t| t.request.seq).max() {
+            self.next_seq.fetch_max(seq, Ordering::Relaxed);
+        }
+        self.history.extend(transactions, self.max_size);
+    }
 
-        BodyRecord {
-            content_type,
-            size,
-            preview,
-            is_binary,
-            truncated,
+    /// Replaces the rules for telling binary bodies from text in records
+    /// from now on.
+    pub fn set_binary_detection(&self, binary: BinaryDetection) {
+        *self.binary.write() = binary;
+    }
+
+    /// Flags requests identical to one recorded less than `window` before;
+    /// zero turns this off.
+    pub fn set_duplicate_window(&self, window: Duration) {
+        self.history.duplicates.lock().window = window;
+    }
+
+    pub fn resize(&self, new_size: usize) {
+        self.history.truncate(new_size);
+    }
+}
+
+impl Clone for RequestRecorder {
+    fn clone(&self) -> Self {
+        Self {
+            history: Arc::clone(&self.history),
+            max_size: self.max_size,
+            binary: Arc::clone(&self.binary),
+            sessions: Arc::clone(&self.sessions),
+            queue: self.queue.clone(),
+            dropped: Arc::clone(&self.dropped),
+            pressure: Arc::clone(&self.pressure),
+            unrecorded: Arc::clone(&self.unrecorded),
+            next_seq: Arc::clone(&self.next_seq),
+            utc_offset: Arc::clone(&self.utc_offset),
         }
     }
+}
 
-    fn is_binary_content(data: &[u8], content_type: Option<&str>) -> bool {
-        if data.is_empty() {
-            return false;
+fn apply(history: &History, max_size: usize, binary: &RwLock<BinaryDetection>, event: RecordEvent) {
+    match event {
+        RecordEvent::Request {
+            id,
+            seq,
+            timestamp,
+            time,
+            method,
+            path,
+            version,
+            headers,
+            body,
+            client_addr,
+            listener,
+            target,
+            trailers,
+            truncate_at,
+            session,
+        } => {
+            let mut hasher = DefaultHasher::new();
+            (method.as_str(), &path, &body[..]).hash(&mut hasher);
+            let fingerprint = hasher.finish();
+            let (path, url) = split_request_target(path);
+            let transaction = HttpTransaction {
+                request: RequestRecord {
+                    preflight: CorsPolicy::is_preflight(&method, &headers),
+                    id,
+                    seq,
+                    timestamp,
+                    time,
+                    method: method.to_string(),
+                    query: query_params(&path),
+                    path,
+                    url,
+                    version: format!("{version:?}"),
+                    headers: header_pairs(&headers),
+                    header_bytes: header_bytes(&headers),
+                    body: analyze_body(&body, &headers, truncate_at, &binary.read()),
+                    client_addr,
+                    listener: listener.map(|addr| addr.to_string()),
+                    target,
+                    session,
+                    trailers: trailers.as_ref().map(header_pairs).unwrap_or_default(),
+                },
+                response: None,
+                error: None,
+                violations: Vec::new(),
+                connection: None,
+                failover_from: None,
+                blocked: None,
+                interim_responses: Vec::new(),
+                preflight_id: None,
+                transforms: Vec::new(),
+                route_script_error: None,
+                overhead: None,
+                injected_headers: Vec::new(),
+                size_warnings: Vec::new(),
+                client_aborted: None,
+                duplicate_of: None,
+                duplicates: Vec::new(),
+                served_from: None,
+                ended_at: None,
+                via_public_tunnel: false,
+                inbox: false,
+                replayed_from: None,
+                tunnel: None,
+                held: None,
+                held_from: None,
+            };
+
+            history.record(transaction, fingerprint, max_size);
         }
+        RecordEvent::Response {
+            request_id,
+            timestamp,
+            time,
+            status,
+            version,
+            headers,
+            body,
+            duration_ms,
+            trailers,
+            truncate_at,
+            error,
+        } => {
+            let mut response = ResponseRecord {
+                id: request_id,
+                timestamp,
+                time,
+                status: status.as_u16(),
+                version: format!("{version:?}"),
+                headers: header_pairs(&headers),
+                header_bytes: header_bytes(&headers),
+                body: analyze_body(&body, &headers, truncate_at, &binary.read()),
+                duration_ms,
+                trailers: trailers.as_ref().map(header_pairs).unwrap_or_default(),
+                incomplete: error.is_some(),
+            };
 
-        // Check content type first
-        if let Some(ct) = content_type {
-            if let Ok(mime) = ct.parse::<Mime>() {
-                match (mime.type_(), mime.subtype()) {
-                    (mime::TEXT, _) => return false,
-                    (mime::APPLICATION, mime::JSON) => return false,
-                    (mime::APPLICATION, mime::JAVASCRIPT) => return false,
-                    (mime::APPLICATION, subtype) if subtype == "xml" => return false,
-                    (mime::APPLICATION, subtype) if subtype.as_str().ends_with("+json") => {
-                        return false
-                    }
-                    (mime::APPLICATION, subtype) if subtype.as_str().ends_with("+xml") => {
-                        return false
-                    }
-                    _ => {}
+            history.bodies.lock().intern(&mut response.body);
+            let request_id = response.id.clone();
+            history.update(&request_id, |transaction| {
+                transaction.ended_at = Some(timestamp);
+                transaction.response = Some(response);
+                if error.is_some() {
+                    transaction.error = error;
                 }
-            }
+            });
         }
-
-        // Heuristic: check for null bytes or high ratio of non-printable characters
-        let null_count = data.iter().filter(|&&b| b == 0).count();
-        if null_count > 0 {
-            return true;
+        RecordEvent::Error {
+            request_id,
+            timestamp,
+            error,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.ended_at.get_or_insert(timestamp);
+                transaction.error = Some(error);
+            });
+        }
+        RecordEvent::Violations {
+            request_id,
+            violations,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.violations.extend(violations);
+            });
+        }
+        RecordEvent::Connection {
+            request_id,
+            connection,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.connection = Some(connection);
+            });
+        }
+        RecordEvent::Failover { request_id, from } => {
+            history.update(&request_id, |transaction| {
+                transaction.failover_from = Some(from);
+            });
+        }
+        RecordEvent::Interim { request_id, status } => {
+            history.update(&request_id, |transaction| {
+                transaction.interim_responses.push(status.as_u16());
+            });
+        }
+        RecordEvent::Blocked { request_id, reason } => {
+            history.update(&request_id, |transaction| {
+                transaction.blocked = Some(reason);
+            });
+        }
+        RecordEvent::ServedFrom {
+            request_id,
+            source_id,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.served_from = Some(source_id);
+            });
+        }
+        RecordEvent::Transforms { request_id, rules } => {
+            history.update(&request_id, |transaction| {
+                transaction.transforms = rules;
+            });
+        }
+        RecordEvent::RouteScriptError { request_id, error } => {
+            history.update(&request_id, |transaction| {
+                transaction.route_script_error = Some(error);
+            });
+        }
+        RecordEvent::Overhead {
+            request_id,
+            overhead,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.overhead = Some(overhead);
+            });
+        }
+        RecordEvent::InjectedHeaders { request_id, names } => {
+            history.update(&request_id, |transaction| {
+                transaction.injected_headers = names;
+            });
+        }
+        RecordEvent::SizeWarnings {
+            request_id,
+            warnings,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.size_warnings.extend(warnings);
+            });
         }
+        RecordEvent::ViaPublicTunnel { request_id } => {
+            history.update(&request_id, |transaction| {
+                transaction.via_public_tunnel = true;
+            });
+        }
+        RecordEvent::Inbox { request_id } => {
+            history.update(&request_id, |transaction| {
+                transaction.inbox = true;
+            });
+        }
+        RecordEvent::Held { request_id, held } => {
+            history.update(&request_id, |transaction| {
+                transaction.held = Some(held);
+            });
+        }
+        RecordEvent::ReplayedFrom {
+            request_id,
+            source_id,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.replayed_from = Some(source_id);
+            });
+        }
+        RecordEvent::HeldFrom {
+            request_id,
+            source_id,
+        } => {
+            history.update(&request_id, |transaction| {
+                transaction.held_from = Some(source_id);
+            });
+        }
+        RecordEvent::Tunnel { request_id, tunnel } => {
+            history.update(&request_id, |transaction| {
+                transaction.tunnel = Some(tunnel);
+            });
+        }
+        RecordEvent::ClientAborted { request_id, abort } => {
+            history.update(&request_id, |transaction| {
+                transaction.ended_at.get_or_insert(abort.timestamp);
+                transaction.client_aborted = Some(abort);
+                transaction
+                    .error
+                    .get_or_insert_with(|| "Client aborted".to_string());
+            });
+        }
+        RecordEvent::Flush(done) => {
+            let _ = done.send(());
+        }
+    }
+}
 
-        let non_printable_count = data
-            .iter()
-            .filter(|&&b| b < 32 && b != b'\t' && b != b'\n' && b != b'\r')
-            .count();
+fn response_event(
+    info: ResponseInfo,
+    error: Option<String>,
+    pressure: Pressure,
+    utc_offset: UtcOffset,
+) -> RecordEvent {
+    let timestamp = now_ms();
+    RecordEvent::Response {
+        request_id: info.request_id.to_string(),
+        timestamp,
+        time: rfc3339(timestamp, utc_offset),
+        status: info.status,
+        version: info.version,
+        headers: info.headers.clone(),
+        body: Bytes::copy_from_slice(info.body),
+        duration_ms: info.duration_ms,
+        trailers: info.trailers.cloned(),
+        truncate_at: pressure.truncate_at(info.truncate_at),
+        error,
+    }
+}
+
+/// The hex SHA-256 of `bytes`, as recorded in [`BodyRecord::sha256`].
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
 
-        // If more than 30% non-printable, consider it binary
-        non_printable_count * 100 / data.len() > 30
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("<invalid>").to_string()))
+        .collect()
+}
+
+/// Header bytes as `name: value\r\n` lines.
+fn header_bytes(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum()
+}
+
+/// Most bytes a body is decoded to for its decoded size, so a small
+/// compression bomb cannot stall the recorder.
+#[cfg(feature = "decoders")]
+const DECODED_SIZE_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// The body's size with its `Content-Encoding` removed, for the codings
+/// that can be decoded here. `None` past [`DECODED_SIZE_LIMIT`].
+#[cfg(feature = "decoders")]
+fn decoded_size(body: &[u8], headers: &HeaderMap) -> Option<usize> {
+    let encoding = headers
+        .get(http::header::CONTENT_ENCODING)?
+        .to_str()
+        .ok()?
+        .trim();
+    if body.is_empty() {
+        return None;
     }
+    let mut decoder = crate::compression::decoder(encoding, body)?.take(DECODED_SIZE_LIMIT + 1);
+    std::io::copy(&mut decoder, &mut std::io::sink())
+        .ok()
+        .filter(|&size| size <= DECODED_SIZE_LIMIT)
+        .map(|size| size as usize)
 }
 
-impl Clone for RequestRecorder {
-    fn clone(&self) -> Self {
-        Self {
-            transactions: Arc::clone(&self.transactions),
-            max_size: self.max_size,
+/// Without the `decoders` feature compressed bodies are left undecoded.
+#[cfg(not(feature = "decoders"))]
+fn decoded_size(_body: &[u8], _headers: &HeaderMap) -> Option<usize> {
+    None
+}
+
+/// Splits an absolute-form request target into its path with query and the
+/// full URL; other targets are already a path.
+fn split_request_target(target: String) -> (String, Option<String>) {
+    match target.parse::<Uri>() {
+        Ok(uri) if uri.scheme().is_some() && uri.authority().is_some() => {
+            let path = uri.path_and_query().map_or("/", |pq| pq.as_str());
+            (path.to_string(), Some(target))
+        }
+        _ => (target, None),
+    }
+}
+
+fn query_params(path: &str) -> Vec<(String, String)> {
+    path.split_once('?')
+        .map(|(_, query)| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn analyze_body(
+    body: &[u8],
+    headers: &HeaderMap,
+    truncate_at: usize,
+    binary: &BinaryDetection,
+) -> BodyRecord {
+    let size = body.len();
+    let content_type = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+
+    let is_binary = binary.is_binary(body, content_type.as_deref());
+    let truncated = size > truncate_at;
+
+    let preview = if is_binary {
+        if size == 0 {
+            String::new()
+        } else {
+            format!("<binary data: {size} bytes>")
         }
+    } else {
+        let preview_bytes = if truncated {
+            &body[..truncate_at.min(size)]
+        } else {
+            body
+        };
+
+        match std::str::from_utf8(preview_bytes) {
+            Ok(s) => s.to_string(),
+            Err(_) => format!("<invalid UTF-8: {size} bytes>"),
+        }
+    };
+
+    BodyRecord {
+        content_type,
+        size,
+        decoded_size: decoded_size(body, headers),
+        sha256: sha256_hex(body),
+        preview: preview.into(),
+        is_binary,
+        truncated,
     }
 }
@@ -0,0 +1,97 @@
+/// A path pattern such as `/users/{id}/posts`, where `{...}` segments match
+/// any single non-empty segment and a trailing `*` matches the rest of the path.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    /// `{name}`, holding the name.
+    Param(String),
+    Rest,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Self {
+        let segments = split_path(template)
+            .map(|segment| {
+                if segment == "*" {
+                    Segment::Rest
+                } else if segment.starts_with('{') && segment.ends_with('}') {
+                    Segment::Param(segment[1..segment.len() - 1].to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+        Self {
+            template: template.to_string(),
+            segments,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Returns how generic the match was when `path` matches: whether a
+    /// trailing `*` took the rest of the path, then the number of `{name}`
+    /// segments. Lower orders first, so a trailing `*` is the least specific.
+    pub fn matches(&self, path: &str) -> Option<(bool, usize)> {
+        let mut parts = split_path(path);
+        let mut wildcards = 0;
+        for segment in &self.segments {
+            match segment {
+                Segment::Rest => return Some((true, wildcards)),
+                Segment::Literal(literal) => {
+                    if parts.next() != Some(literal.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(_) => {
+                    parts.next()?;
+                    wildcards += 1;
+                }
+            }
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some((false, wildcards))
+    }
+
+    /// The values of the `{name}` segments in `path`, in template order,
+    /// when it matches.
+    pub fn params(&self, path: &str) -> Option<Vec<(String, String)>> {
+        self.matches(path)?;
+        Some(
+            self.segments
+                .iter()
+                .zip(split_path(path))
+                .filter_map(|(segment, part)| match segment {
+                    Segment::Param(name) => Some((name.clone(), part.to_string())),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Picks the most specific template matching `path`.
+    pub fn best_match<'a, I>(templates: I, path: &str) -> Option<&'a PathTemplate>
+    where
+        I: IntoIterator<Item = &'a PathTemplate>,
+    {
+        templates
+            .into_iter()
+            .filter_map(|template| template.matches(path).map(|score| (score, template)))
+            .min_by_key(|(score, _)| *score)
+            .map(|(_, template)| template)
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
@@ -0,0 +1,44 @@
+use http::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::route::PathTemplate;
+
+/// Keeps requests that could change state away from the upstream, for
+/// observing a shared environment without touching it. Patterns are path
+/// templates such as `/admin/*` or `/users/{id}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SafeMode {
+    /// Block `POST`, `PUT`, `PATCH` and `DELETE`.
+    pub block_writes: bool,
+    /// Paths where writes still go through, such as a GraphQL endpoint used
+    /// for queries.
+    #[serde(default)]
+    pub allow_paths: Vec<String>,
+    /// Paths blocked for every method.
+    #[serde(default)]
+    pub block_paths: Vec<String>,
+}
+
+impl SafeMode {
+    pub fn is_enabled(&self) -> bool {
+        self.block_writes || !self.block_paths.is_empty()
+    }
+
+    /// Why a request is kept from the upstream, or `None` to let it through.
+    pub fn check(&self, method: &Method, path: &str) -> Option<String> {
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .find(|pattern| PathTemplate::parse(pattern).matches(path).is_some())
+                .cloned()
+        };
+        if let Some(pattern) = matches(&self.block_paths) {
+            return Some(format!("{path} matches blocked path {pattern}"));
+        }
+        let is_write = [Method::POST, Method::PUT, Method::PATCH, Method::DELETE].contains(method);
+        if self.block_writes && is_write && matches(&self.allow_paths).is_none() {
+            return Some(format!("{method} requests are blocked"));
+        }
+        None
+    }
+}
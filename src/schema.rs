@@ -0,0 +1,216 @@
+use serde_json::Value;
+
+/// Validates `instance` against a JSON Schema (the subset used by OpenAPI 3).
+///
+/// `root` is the document local `$ref`s (`#/components/schemas/...`) are
+/// resolved against. Returns one message per violation, prefixed with the
+/// location of the offending value (`$.items[0].name`).
+pub fn validate(schema: &Value, root: &Value, instance: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at(schema, root, instance, "$", &mut errors, 0);
+    errors
+}
+
+const MAX_REF_DEPTH: usize = 32;
+
+fn validate_at(
+    schema: &Value,
+    root: &Value,
+    instance: &Value,
+    location: &str,
+    errors: &mut Vec<String>,
+    depth: usize,
+) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            errors.push(format!("{location}: no value is allowed here"));
+            return;
+        }
+        Value::Object(obj) => obj,
+        _ => return,
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        if depth >= MAX_REF_DEPTH {
+            errors.push(format!("{location}: $ref nesting too deep at {reference}"));
+            return;
+        }
+        match resolve_ref(root, reference) {
+            Some(target) => validate_at(target, root, instance, location, errors, depth + 1),
+            None => errors.push(format!("{location}: unresolvable $ref {reference}")),
+        }
+        return;
+    }
+
+    if instance.is_null() && schema.get("nullable").and_then(Value::as_bool) == Some(true) {
+        return;
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|t| matches_type(t, instance)) {
+            errors.push(format!(
+                "{location}: expected {}, got {}",
+                allowed.join(" or "),
+                type_name(instance)
+            ));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(instance) {
+            errors.push(format!(
+                "{location}: value {instance} is not one of {options:?}"
+            ));
+        }
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if expected != instance {
+            errors.push(format!("{location}: expected constant {expected}"));
+        }
+    }
+
+    match instance {
+        Value::Object(map) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !map.contains_key(key) {
+                        errors.push(format!("{location}: missing required property '{key}'"));
+                    }
+                }
+            }
+
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, value) in map {
+                let child = format!("{location}.{key}");
+                match properties.and_then(|p| p.get(key)) {
+                    Some(prop_schema) => {
+                        validate_at(prop_schema, root, value, &child, errors, depth)
+                    }
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{location}: unexpected property '{key}'"))
+                        }
+                        Some(extra) => validate_at(extra, root, value, &child, errors, depth),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    errors.push(format!("{location}: expected at least {min} items"));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max {
+                    errors.push(format!("{location}: expected at most {max} items"));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    let child = format!("{location}[{i}]");
+                    validate_at(item_schema, root, item, &child, errors, depth);
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if len < min {
+                    errors.push(format!("{location}: string shorter than {min}"));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if len > max {
+                    errors.push(format!("{location}: string longer than {max}"));
+                }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if n < min {
+                    errors.push(format!("{location}: {n} is less than minimum {min}"));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if n > max {
+                    errors.push(format!("{location}: {n} is greater than maximum {max}"));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        for sub in all {
+            validate_at(sub, root, instance, location, errors, depth);
+        }
+    }
+
+    if let Some(Value::Array(any)) = schema.get("anyOf") {
+        let matched = any
+            .iter()
+            .any(|sub| validate(sub, root, instance).is_empty());
+        if !matched {
+            errors.push(format!(
+                "{location}: value does not match any of the anyOf schemas"
+            ));
+        }
+    }
+
+    if let Some(Value::Array(one)) = schema.get("oneOf") {
+        let matched = one
+            .iter()
+            .filter(|sub| validate(sub, root, instance).is_empty())
+            .count();
+        if matched != 1 {
+            errors.push(format!(
+                "{location}: value matches {matched} of the oneOf schemas, expected exactly 1"
+            ));
+        }
+    }
+}
+
+fn resolve_ref<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+fn matches_type(expected: &str, instance: &Value) -> bool {
+    match expected {
+        "null" => instance.is_null(),
+        "boolean" => instance.is_boolean(),
+        "object" => instance.is_object(),
+        "array" => instance.is_array(),
+        "string" => instance.is_string(),
+        "number" => instance.is_number(),
+        "integer" => match instance {
+            Value::Number(n) => {
+                n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+            }
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{anyhow, Context, Result};
+use http::{HeaderMap, Method, Uri};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::path::Path;
+
+use crate::upstream::upstream_base_url;
+
+/// Operations a script may run per request, so a runaway loop fails the
+/// request over to the default upstream instead of hanging it.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A [Rhai](https://rhai.rs) script that picks the upstream for each request
+/// to the default upstream. The script sees a `request` map with `method`,
+/// `path`, `query`, `client_addr` and `headers` (lowercase names), and its
+/// last expression is the upstream to use, or `()` to keep the default:
+///
+/// ```text
+/// let tenant = request.headers["x-tenant"];
+/// if tenant == "acme" { "localhost:4001" } else if tenant == "globex" { "localhost:4002" }
+/// ```
+pub struct RouteScript {
+    engine: Engine,
+    ast: AST,
+}
+
+/// What a routing script sees of a request.
+pub struct ScriptRequest<'a> {
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub headers: &'a HeaderMap,
+    pub client_addr: &'a str,
+}
+
+impl RouteScript {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read route script: {}", path.display()))?;
+        Self::compile(&source)
+            .with_context(|| format!("Failed to compile route script: {}", path.display()))
+    }
+
+    pub fn compile(source: &str) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(source).map_err(|e| anyhow!("{e}"))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script for a request, returning the upstream it picked or
+    /// `None` for the default one.
+    pub fn route(&self, request: &ScriptRequest) -> Result<Option<String>> {
+        let mut headers = Map::new();
+        for (name, value) in request.headers {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            // Repeated headers are joined the way HTTP allows folding them
+            headers
+                .entry(name.as_str().into())
+                .and_modify(|existing: &mut Dynamic| {
+                    *existing = format!("{existing}, {value}").into();
+                })
+                .or_insert_with(|| value.clone().into());
+        }
+        let mut map = Map::new();
+        map.insert("method".into(), request.method.to_string().into());
+        map.insert("path".into(), request.uri.path().into());
+        map.insert(
+            "query".into(),
+            request.uri.query().unwrap_or_default().into(),
+        );
+        map.insert("client_addr".into(), request.client_addr.into());
+        map.insert("headers".into(), headers.into());
+
+        let mut scope = Scope::new();
+        scope.push("request", map);
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("{e}"))?;
+
+        if result.is_unit() {
+            return Ok(None);
+        }
+        let upstream = result
+            .into_string()
+            .map_err(|kind| anyhow!("Route script returned {kind}, expected a string or ()"))?;
+        if upstream.is_empty() {
+            return Ok(None);
+        }
+        format!("{}/", upstream_base_url(&upstream))
+            .parse::<Uri>()
+            .with_context(|| format!("Route script returned an invalid upstream {upstream:?}"))?;
+        Ok(Some(upstream))
+    }
+}
@@ -0,0 +1,100 @@
+use regex::Regex;
+use serde::Serialize;
+
+use crate::recorder::{BodyRecord, HttpTransaction};
+
+/// Characters of body shown on each side of a match.
+const CONTEXT_CHARS: usize = 40;
+
+/// Matches reported per body; the count still includes the rest.
+const MAX_MATCHES_PER_BODY: usize = 5;
+
+/// A transaction with a body matching the search.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    pub status: Option<u16>,
+    pub matches: Vec<BodyMatch>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BodyMatch {
+    /// `request` or `response`.
+    pub part: &'static str,
+    /// Matches in the body, of which the first few are in `context`.
+    pub count: usize,
+    /// The text around each match.
+    pub context: Vec<String>,
+    /// Whether only the recorded start of a longer body was searched.
+    pub truncated: bool,
+}
+
+/// Searches the recorded request and response bodies, newest transactions
+/// first. Binary bodies are skipped.
+pub fn search<'a>(
+    transactions: impl DoubleEndedIterator<Item = &'a HttpTransaction>,
+    pattern: &Regex,
+    limit: usize,
+) -> Vec<SearchHit> {
+    transactions
+        .rev()
+        .filter_map(|transaction| {
+            let matches: Vec<BodyMatch> = [
+                ("request", Some(&transaction.request.body)),
+                (
+                    "response",
+                    transaction.response.as_ref().map(|response| &response.body),
+                ),
+            ]
+            .into_iter()
+            .filter_map(|(part, body)| search_body(part, body?, pattern))
+            .collect();
+            (!matches.is_empty()).then(|| SearchHit {
+                id: transaction.request.id.clone(),
+                method: transaction.request.method.clone(),
+                path: transaction.request.path.clone(),
+                status: transaction
+                    .response
+                    .as_ref()
+                    .map(|response| response.status),
+                matches,
+            })
+        })
+        .take(limit)
+        .collect()
+}
+
+fn search_body(part: &'static str, body: &BodyRecord, pattern: &Regex) -> Option<BodyMatch> {
+    if body.is_binary {
+        return None;
+    }
+    let text = &body.preview;
+    let found: Vec<_> = pattern.find_iter(text).collect();
+    if found.is_empty() {
+        return None;
+    }
+    let context = found
+        .iter()
+        .take(MAX_MATCHES_PER_BODY)
+        .map(|m| {
+            let start = text[..m.start()]
+                .char_indices()
+                .rev()
+                .nth(CONTEXT_CHARS - 1)
+                .map_or(0, |(i, _)| i);
+            let end = text[m.end()..]
+                .char_indices()
+                .nth(CONTEXT_CHARS)
+                .map_or(text.len(), |(i, _)| m.end() + i);
+            text[start..end].to_string()
+        })
+        .collect();
+    Some(BodyMatch {
+        part,
+        count: found.len(),
+        context,
+        truncated: body.truncated,
+    })
+}
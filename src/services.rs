@@ -0,0 +1,343 @@
+use anyhow::{bail, Context, Result};
+use hyper::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::process::{self, ProcessManager, ProcessStatus, RestartPolicy};
+use crate::upstream::upstream_base_url;
+
+/// Contents of a `--services` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServicesFile {
+    pub services: Vec<ServiceConfig>,
+}
+
+/// One managed command and the route prefix it serves.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceConfig {
+    pub name: String,
+    pub prefix: String,
+    pub command: Vec<String>,
+    /// Where the command listens; omit and set `port_env` to pick a free port.
+    #[serde(default)]
+    pub upstream: Option<String>,
+    #[serde(default)]
+    pub port_env: Option<String>,
+    /// Remove `prefix` from the path before forwarding.
+    #[serde(default)]
+    pub strip_prefix: bool,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Shell commands run before every start and after every stop.
+    #[serde(default)]
+    pub pre_start: Vec<String>,
+    #[serde(default)]
+    pub post_stop: Vec<String>,
+    #[serde(default)]
+    pub restart: RestartConfig,
+    #[serde(default)]
+    pub ready: Option<ReadinessConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RestartConfig {
+    pub enabled: bool,
+    pub max_retries: u32,
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        let policy = RestartPolicy::default();
+        Self {
+            enabled: policy.enabled,
+            max_retries: policy.max_retries,
+            backoff_ms: policy.initial_backoff.as_millis() as u64,
+            max_backoff_ms: policy.max_backoff.as_millis() as u64,
+        }
+    }
+}
+
+/// An HTTP path that answers with a non-5xx status once the service is up.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadinessConfig {
+    pub path: String,
+    #[serde(default = "default_ready_interval_ms")]
+    pub interval_ms: u64,
+}
+
+fn default_ready_interval_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub prefix: String,
+    pub upstream: String,
+    /// `None` when the service has no readiness check.
+    pub ready: Option<bool>,
+    pub process: ProcessStatus,
+}
+
+pub struct Service {
+    pub name: String,
+    pub prefix: String,
+    pub upstream: String,
+    pub strip_prefix: bool,
+    pub process: ProcessManager,
+    readiness: Option<ReadinessConfig>,
+    ready: Arc<AtomicBool>,
+}
+
+impl Service {
+    /// Whether `path` falls under this service's prefix.
+    pub fn matches(&self, path: &str) -> bool {
+        let prefix = self.prefix.trim_end_matches('/');
+        match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.is_empty(),
+            None => false,
+        }
+    }
+
+    /// The path and query to request from this service's upstream.
+    pub fn upstream_path(&self, path_and_query: &str) -> String {
+        if !self.strip_prefix {
+            return path_and_query.to_string();
+        }
+        let rest = path_and_query
+            .strip_prefix(self.prefix.trim_end_matches('/'))
+            .unwrap_or(path_and_query);
+        if rest.starts_with('/') {
+            rest.to_string()
+        } else {
+            format!("/{rest}")
+        }
+    }
+
+    pub fn status(&self) -> ServiceStatus {
+        ServiceStatus {
+            name: self.name.clone(),
+            prefix: self.prefix.clone(),
+            upstream: self.upstream.clone(),
+            ready: self
+                .readiness
+                .as_ref()
+                .map(|_| self.ready.load(Ordering::Relaxed)),
+            process: self.process.status(),
+        }
+    }
+}
+
+/// The managed services fronted by the proxy, routed by path prefix.
+#[derive(Clone, Default)]
+pub struct Services {
+    services: Arc<Vec<Service>>,
+}
+
+impl Services {
+    /// Loads service definitions from a YAML or JSON file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read services: {}", path.display()))?;
+        let file: ServicesFile = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse services: {}", path.display()))?;
+        Self::from_config(file.services)
+    }
+
+    pub fn from_config(configs: Vec<ServiceConfig>) -> Result<Self> {
+        let mut names = HashSet::new();
+        let mut services = Vec::with_capacity(configs.len());
+        for config in configs {
+            if !names.insert(config.name.clone()) {
+                bail!("Duplicate service name: {}", config.name);
+            }
+            if !config.prefix.starts_with('/') {
+                bail!(
+                    "Service {} prefix must start with '/': {}",
+                    config.name,
+                    config.prefix
+                );
+            }
+            if config.command.is_empty() {
+                bail!("Service {} has no command", config.name);
+            }
+
+            let mut env: Vec<(String, String)> = config.env.into_iter().collect();
+            let mut command = config.command;
+            let upstream = match (config.upstream, config.port_env) {
+                (Some(upstream), None) => upstream,
+                (None, Some(var)) => {
+                    let port = process::free_port()?;
+                    env.push((var, port.to_string()));
+                    command = command
+                        .iter()
+                        .map(|arg| arg.replace("{port}", &port.to_string()))
+                        .collect();
+                    format!("127.0.0.1:{port}")
+                }
+                _ => bail!(
+                    "Service {} needs exactly one of `upstream` or `port_env`",
+                    config.name
+                ),
+            };
+
+            let restart = config.restart;
+            let mut manager = ProcessManager::new(command)
+                .with_env(env)
+                .with_pre_start_hooks(config.pre_start)
+                .with_post_stop_hooks(config.post_stop)
+                .with_restart_policy(RestartPolicy {
+                    enabled: restart.enabled,
+                    max_retries: restart.max_retries,
+                    initial_backoff: Duration::from_millis(restart.backoff_ms),
+                    max_backoff: Duration::from_millis(restart.max_backoff_ms),
+                    ..Default::default()
+                });
+            if let Some(cwd) = config.cwd {
+                manager = manager.with_cwd(cwd);
+            }
+
+            services.push(Service {
+                name: config.name,
+                prefix: config.prefix,
+                upstream,
+                strip_prefix: config.strip_prefix,
+                process: manager,
+                readiness: config.ready,
+                ready: Arc::new(AtomicBool::new(false)),
+            });
+        }
+
+        Ok(Self {
+            services: Arc::new(services),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Service> {
+        self.services.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Service> {
+        self.services.iter().find(|s| s.name == name)
+    }
+
+    /// The service with the longest prefix matching `path`.
+    pub fn route(&self, path: &str) -> Option<&Service> {
+        self.services
+            .iter()
+            .filter(|s| s.matches(path))
+            .max_by_key(|s| s.prefix.trim_end_matches('/').len())
+    }
+
+    pub async fn start_all(&self) -> Result<()> {
+        for service in self.services.iter() {
+            info!("Starting service {}", service.name);
+            service
+                .process
+                .start()
+                .await
+                .with_context(|| format!("Failed to start service {}", service.name))?;
+        }
+        Ok(())
+    }
+
+    pub async fn stop_all(&self) {
+        for service in self.services.iter() {
+            if let Err(e) = service.process.stop().await {
+                error!("Error stopping service {}: {}", service.name, e);
+            }
+        }
+    }
+
+    /// Applies each service's restart policy; see [`ProcessManager::supervise`].
+    pub async fn supervise(&self) {
+        for service in self.services.iter() {
+            match service.process.supervise().await {
+                Ok(true) => info!("Service {} restarted", service.name),
+                Ok(false) => {}
+                Err(e) => error!("Failed to restart service {}: {}", service.name, e),
+            }
+        }
+    }
+
+    /// Samples each service's resource usage; see [`ProcessManager::sample_usage`].
+    pub fn sample_usage(&self) {
+        for service in self.services.iter() {
+            service.process.sample_usage();
+        }
+    }
+
+    /// Probes every service's readiness path in the background.
+    pub fn spawn_readiness_checks(&self) {
+        for index in 0..self.services.len() {
+            let services = Arc::clone(&self.services);
+            tokio::spawn(async move {
+                let service = &services[index];
+                let Some(ref readiness) = service.readiness else {
+                    return;
+                };
+                let client = Client::new();
+                let uri = format!("{}{}", upstream_base_url(&service.upstream), readiness.path);
+                loop {
+                    let ready = match uri.parse() {
+                        Ok(uri) => client
+                            .get(uri)
+                            .await
+                            .is_ok_and(|response| !response.status().is_server_error()),
+                        Err(_) => false,
+                    };
+                    if ready != service.ready.swap(ready, Ordering::Relaxed) {
+                        info!(
+                            "Service {} is {}",
+                            service.name,
+                            if ready { "ready" } else { "not ready" }
+                        );
+                    }
+                    tokio::time::sleep(Duration::from_millis(readiness.interval_ms)).await;
+                }
+            });
+        }
+    }
+
+    /// Waits until every service with a readiness check reports ready.
+    pub async fn wait_ready(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pending: Vec<&str> = self
+                .services
+                .iter()
+                .filter(|s| s.readiness.is_some() && !s.ready.load(Ordering::Relaxed))
+                .map(|s| s.name.as_str())
+                .collect();
+            if pending.is_empty() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    "Services not ready after {timeout:?}: {}",
+                    pending.join(", ")
+                );
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn status(&self) -> Vec<ServiceStatus> {
+        self.services.iter().map(Service::status).collect()
+    }
+}
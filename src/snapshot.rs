@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::recorder::HttpTransaction;
+
+/// The recorded history saved to a file, to be browsed again later with
+/// `--load-snapshot`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub saved_at: u64,
+    pub transactions: Vec<HttpTransaction>,
+}
+
+impl Snapshot {
+    pub fn new(transactions: Vec<HttpTransaction>) -> Self {
+        Self {
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            transactions,
+        }
+    }
+
+    /// A file name for a snapshot taken now, such as
+    /// `debug-proxy-snapshot-1700000000000.json`.
+    pub fn default_file_name(&self) -> String {
+        format!("debug-proxy-snapshot-{}.json", self.saved_at)
+    }
+
+    /// Writes the snapshot to a new file at `path`, refusing to overwrite
+    /// one that exists.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| format!("Failed to create snapshot: {}", path.display()))?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)
+            .with_context(|| format!("Failed to write snapshot: {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read snapshot: {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse snapshot: {}", path.display()))
+    }
+}
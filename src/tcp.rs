@@ -0,0 +1,184 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::events::{ConnectionEvents, EventKind};
+use crate::tunnel::{hex_dump, relay_sampled};
+
+/// Totals across the connections forwarded so far.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TcpStats {
+    pub connections: u64,
+    /// Connections still open.
+    pub active: u64,
+    /// Connections the upstream refused or did not accept in time.
+    pub failed: u64,
+    pub bytes_to_upstream: u64,
+    pub bytes_to_client: u64,
+    /// The slowest upstream connect, in milliseconds.
+    pub max_connect_ms: u64,
+    /// The longest a connection stayed open, in milliseconds.
+    pub max_duration_ms: u64,
+}
+
+impl fmt::Display for TcpStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "  Connections: {} ({} failed, {} open)",
+            self.connections, self.failed, self.active
+        )?;
+        writeln!(f, "  Sent:        {} bytes", self.bytes_to_upstream)?;
+        writeln!(f, "  Received:    {} bytes", self.bytes_to_client)?;
+        write!(
+            f,
+            "  Longest:     connect {}ms, open {}ms",
+            self.max_connect_ms, self.max_duration_ms
+        )
+    }
+}
+
+/// Forwards plain TCP connections to an upstream without reading what they
+/// carry, for protocols like PostgreSQL or Redis that the HTTP proxy cannot
+/// record. Each connection's byte counts and timing become a connection
+/// event.
+#[derive(Clone)]
+pub struct TcpForwarder {
+    upstream: String,
+    connect_timeout: Duration,
+    /// Bytes logged as a hex dump from the start of each direction.
+    hex_dump: usize,
+    events: ConnectionEvents,
+    stats: Arc<Mutex<TcpStats>>,
+}
+
+impl TcpForwarder {
+    pub fn new(upstream: String) -> Self {
+        Self {
+            upstream,
+            connect_timeout: Duration::from_secs(5),
+            hex_dump: 0,
+            events: ConnectionEvents::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Logs the first `bytes` sent each way on every connection.
+    pub fn with_hex_dump(mut self, bytes: usize) -> Self {
+        self.hex_dump = bytes;
+        self
+    }
+
+    pub fn events(&self) -> &ConnectionEvents {
+        &self.events
+    }
+
+    pub fn stats(&self) -> TcpStats {
+        self.stats.lock().clone()
+    }
+
+    /// Accepts connections on `listener` and forwards each one until the
+    /// listener fails.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (client, client_addr) = listener.accept().await?;
+            let forwarder = self.clone();
+            tokio::spawn(async move {
+                forwarder.forward(client, client_addr.to_string()).await;
+            });
+        }
+    }
+
+    async fn forward(&self, client: TcpStream, client_addr: String) {
+        self.events
+            .push(&client_addr, None, EventKind::ClientConnected);
+        self.stats.lock().connections += 1;
+        let opened = Instant::now();
+        let connected =
+            tokio::time::timeout(self.connect_timeout, TcpStream::connect(&self.upstream)).await;
+        let connect_ms = opened.elapsed().as_millis() as u64;
+        let upstream = match connected {
+            Ok(Ok(upstream)) => upstream,
+            Ok(Err(e)) => return self.connect_failed(&client_addr, e.to_string()),
+            Err(_) => {
+                let error = format!("No connection within {:?}", self.connect_timeout);
+                return self.connect_failed(&client_addr, error);
+            }
+        };
+        let _ = client.set_nodelay(true);
+        let _ = upstream.set_nodelay(true);
+        {
+            let mut stats = self.stats.lock();
+            stats.active += 1;
+            stats.max_connect_ms = stats.max_connect_ms.max(connect_ms);
+        }
+
+        let relayed = relay_sampled(client, upstream, self.hex_dump).await;
+        let duration_ms = opened.elapsed().as_millis() as u64;
+        {
+            let mut stats = self.stats.lock();
+            stats.active -= 1;
+            stats.bytes_to_upstream += relayed.bytes_to_upstream;
+            stats.bytes_to_client += relayed.bytes_to_client;
+            stats.max_duration_ms = stats.max_duration_ms.max(duration_ms);
+        }
+        info!(
+            "{client_addr} closed after {duration_ms}ms (connect {connect_ms}ms): {} bytes sent, {} received{}",
+            relayed.bytes_to_upstream,
+            relayed.bytes_to_client,
+            relayed
+                .error
+                .as_ref()
+                .map_or(String::new(), |e| format!(", {e}"))
+        );
+        if !relayed.upstream_sample.is_empty() {
+            info!(
+                "{client_addr} sent:\n{}",
+                hex_dump(&relayed.upstream_sample)
+            );
+        }
+        if !relayed.client_sample.is_empty() {
+            info!(
+                "{client_addr} received:\n{}",
+                hex_dump(&relayed.client_sample)
+            );
+        }
+        self.events.push(
+            &client_addr,
+            None,
+            EventKind::TunnelClosed {
+                protocol: "tcp".to_string(),
+                bytes_to_upstream: relayed.bytes_to_upstream,
+                bytes_to_client: relayed.bytes_to_client,
+                duration_ms,
+                error: relayed.error,
+            },
+        );
+    }
+
+    fn connect_failed(&self, client_addr: &str, error: String) {
+        warn!(
+            "Could not connect {client_addr} to {}: {error}",
+            self.upstream
+        );
+        self.stats.lock().failed += 1;
+        self.events.push(
+            client_addr,
+            None,
+            EventKind::UpstreamConnectFailed {
+                upstream: self.upstream.clone(),
+                error,
+            },
+        );
+    }
+}
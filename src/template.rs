@@ -0,0 +1,196 @@
+use anyhow::{bail, Result};
+use http::HeaderMap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::datetime::{rfc3339, UtcOffset};
+use crate::recorder::now_ms;
+
+/// A response body with `{{ EXPRESSION }}` placeholders filled in from the
+/// request:
+///
+/// ```text
+/// {"id": "{{ path.id }}", "page": {{ query.page }}, "trace": "{{ header.x-request-id }}"}
+/// {"name": "{{ body.user.name }}", "ref": "{{ uuid }}", "at": "{{ now }}"}
+/// ```
+///
+/// `path.NAME` is a `{NAME}` segment of the rule's path, `body.a.0.b` a field
+/// of a JSON request body, and `method` and `path` the request's own. The
+/// helpers `uuid`, `now` (RFC 3339, UTC) and `timestamp` (Unix milliseconds)
+/// give new values every time. Missing values render as nothing, strings
+/// render without quotes and other JSON values as JSON. In a template that
+/// is JSON, strings are escaped so quotes and backslashes in them keep the
+/// result valid JSON.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+    /// Whether the template is JSON once its placeholders are filled in.
+    json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Value(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Method,
+    Path,
+    Param(String),
+    Query(String),
+    Header(String),
+    Body(Vec<String>),
+    Uuid,
+    Now,
+    Timestamp,
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (root, rest) = match s.split_once('.') {
+            Some((root, rest)) => (root, Some(rest)),
+            None => (s, None),
+        };
+        Ok(match (root, rest) {
+            ("method", None) => Self::Method,
+            ("path", None) => Self::Path,
+            ("uuid", None) => Self::Uuid,
+            ("now", None) => Self::Now,
+            ("timestamp", None) => Self::Timestamp,
+            ("body", None) => Self::Body(Vec::new()),
+            ("path", Some(name)) => Self::Param(name.to_string()),
+            ("query", Some(name)) => Self::Query(name.to_string()),
+            ("header", Some(name)) => Self::Header(name.to_ascii_lowercase()),
+            ("body", Some(fields)) => Self::Body(fields.split('.').map(str::to_string).collect()),
+            _ => bail!(
+                "Unknown template value {s}, expected method, path, path.NAME, query.NAME, header.NAME, body[.FIELD...], uuid, now or timestamp"
+            ),
+        })
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find("}}") else {
+                bail!("Unclosed {{{{ in template: {s}");
+            };
+            parts.push(Part::Value(rest[start + 2..start + end].trim().parse()?));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        // A placeholder stands in as `0`, valid both as a value and inside
+        // a string
+        let sample: String = parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.as_str(),
+                Part::Value(_) => "0",
+            })
+            .collect();
+        Ok(Self {
+            source: s.to_string(),
+            parts,
+            json: serde_json::from_str::<Value>(&sample).is_ok(),
+        })
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// What a template can read from the request it answers.
+pub struct TemplateContext<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// Values of the `{name}` segments of the matching path template.
+    pub params: &'a [(String, String)],
+    pub query: &'a [(String, String)],
+    pub headers: &'a HeaderMap,
+    /// The request body, when it is JSON.
+    pub body: Option<&'a Value>,
+}
+
+impl Template {
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let lookup = |pairs: &[(String, String)], name: &str| {
+            pairs
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default()
+        };
+        let text = |value: String| {
+            if !self.json {
+                return value;
+            }
+            let quoted = Value::String(value).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        };
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value(expr) => match expr {
+                    Expr::Method => context.method.to_string(),
+                    Expr::Path => text(context.path.to_string()),
+                    Expr::Param(name) => text(lookup(context.params, name)),
+                    Expr::Query(name) => text(lookup(context.query, name)),
+                    Expr::Header(name) => text(
+                        context
+                            .headers
+                            .get(name.as_str())
+                            .and_then(|value| value.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string(),
+                    ),
+                    Expr::Body(fields) => {
+                        let mut value = context.body;
+                        for field in fields {
+                            value = value.and_then(|value| match field.parse::<usize>() {
+                                Ok(index) if value.is_array() => value.get(index),
+                                _ => value.get(field.as_str()),
+                            });
+                        }
+                        match value {
+                            Some(Value::String(value)) => text(value.clone()),
+                            Some(value) => value.to_string(),
+                            None => String::new(),
+                        }
+                    }
+                    Expr::Uuid => uuid::Uuid::new_v4().to_string(),
+                    Expr::Now => rfc3339(now_ms(), UtcOffset::UTC),
+                    Expr::Timestamp => now_ms().to_string(),
+                },
+            })
+            .collect()
+    }
+}
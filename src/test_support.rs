@@ -0,0 +1,232 @@
+use anyhow::{bail, Result};
+use http::{Method, StatusCode};
+use hyper::Client;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::config::{ProxyConfig, SharedConfig};
+use crate::proxy::{bind_listeners, DebugProxy};
+use crate::recorder::{HttpTransaction, RequestRecorder};
+
+/// Admin token of proxies started with [`TestProxy::start`].
+pub const TEST_TOKEN: &str = "test-token";
+
+/// How long [`TestProxy`] waits for the admin API to answer after starting.
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often [`TestProxy::wait_for`] looks at the history again.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A proxy serving on an ephemeral port for the length of a test. Dropping
+/// it stops the server.
+pub struct TestProxy {
+    addr: SocketAddr,
+    token: String,
+    config: SharedConfig,
+    recorder: RequestRecorder,
+    server: JoinHandle<()>,
+}
+
+impl TestProxy {
+    /// Starts a proxy to `upstream` with the default config and
+    /// [`TEST_TOKEN`].
+    pub async fn start(upstream: &str) -> Result<Self> {
+        let config = ProxyConfig {
+            access_token: TEST_TOKEN.to_string(),
+            ..Default::default()
+        };
+        Self::start_with(upstream, config, |proxy| proxy).await
+    }
+
+    /// Starts a proxy to `upstream` with `config`, passing it through `build`
+    /// first for the `with_*` settings. Returns once the admin API answers.
+    pub async fn start_with(
+        upstream: &str,
+        config: ProxyConfig,
+        build: impl FnOnce(DebugProxy) -> DebugProxy,
+    ) -> Result<Self> {
+        let token = config.access_token.clone();
+        let recorder = RequestRecorder::new(config.max_history_size);
+        let config = SharedConfig::new(config);
+        let proxy = build(DebugProxy::new(
+            config.clone(),
+            recorder.clone(),
+            upstream.to_string(),
+        ));
+
+        let listeners = bind_listeners(&[SocketAddr::from(([127, 0, 0, 1], 0))], false)?;
+        let addr = listeners[0].local_addr()?;
+        let server = tokio::spawn(async move {
+            if let Err(e) = proxy.serve(listeners).await {
+                tracing::error!("Test proxy on {} failed: {}", addr, e);
+            }
+        });
+
+        let proxy = Self {
+            addr,
+            token,
+            config,
+            recorder,
+            server,
+        };
+        proxy.wait_ready().await?;
+        Ok(proxy)
+    }
+
+    async fn wait_ready(&self) -> Result<()> {
+        let client = Client::new();
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            let uri = self.admin_url("/_proxy/api/stats").parse()?;
+            match client.get(uri).await {
+                Ok(response) if response.status() == StatusCode::OK => return Ok(()),
+                _ if self.server.is_finished() => bail!("Test proxy on {} exited", self.addr),
+                _ if Instant::now() > deadline => {
+                    bail!(
+                        "Test proxy on {} not ready after {READY_TIMEOUT:?}",
+                        self.addr
+                    )
+                }
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of `path` through the proxy.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    /// The URL of an admin API `path`, with the token added to its query.
+    pub fn admin_url(&self, path: &str) -> String {
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let token = url::form_urlencoded::byte_serialize(self.token.as_bytes()).collect::<String>();
+        format!("{}{separator}token={token}", self.url(path))
+    }
+
+    pub fn config(&self) -> &SharedConfig {
+        &self.config
+    }
+
+    pub fn recorder(&self) -> &RequestRecorder {
+        &self.recorder
+    }
+
+    /// The recorded transactions matching `filter`, oldest first.
+    pub async fn transactions(&self, filter: &TransactionFilter) -> Vec<HttpTransaction> {
+        self.recorder.flush().await;
+        self.recorder
+            .snapshot()
+            .iter()
+            .filter(|t| filter.matches(t))
+            .map(|t| HttpTransaction::clone(t))
+            .collect()
+    }
+
+    /// Waits up to `timeout` for `count` finished transactions matching
+    /// `filter`, for requests whose client does not wait for them, and
+    /// returns every match.
+    pub async fn wait_for(
+        &self,
+        filter: &TransactionFilter,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<Vec<HttpTransaction>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let transactions = self.transactions(filter).await;
+            let finished = transactions.iter().filter(|t| t.ended_at.is_some()).count();
+            if finished >= count {
+                return Ok(transactions);
+            }
+            if Instant::now() > deadline {
+                bail!("Expected {count} matching transactions within {timeout:?}, got {finished}");
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Stops the server and waits for it to let go of the port.
+    pub async fn shutdown(mut self) {
+        self.server.abort();
+        let _ = (&mut self.server).await;
+    }
+}
+
+impl Drop for TestProxy {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Which recorded transactions to return. Every criterion set must match;
+/// an empty filter matches all.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    method: Option<Method>,
+    path: Option<String>,
+    path_prefix: Option<String>,
+    status: Option<u16>,
+    failed: Option<bool>,
+}
+
+impl TransactionFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Matches the path exactly, ignoring the query.
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn with_path_prefix(mut self, prefix: &str) -> Self {
+        self.path_prefix = Some(prefix.to_string());
+        self
+    }
+
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Only transactions that ended in an error, or only those that did
+    /// not.
+    pub fn with_failed(mut self, failed: bool) -> Self {
+        self.failed = Some(failed);
+        self
+    }
+
+    pub fn matches(&self, transaction: &HttpTransaction) -> bool {
+        let request = &transaction.request;
+        let path = request.path.split('?').next().unwrap_or_default();
+        self.method
+            .as_ref()
+            .is_none_or(|method| request.method == method.as_str())
+            && self.path.as_deref().is_none_or(|expected| path == expected)
+            && self
+                .path_prefix
+                .as_deref()
+                .is_none_or(|prefix| path.starts_with(prefix))
+            && self.status.is_none_or(|status| {
+                transaction
+                    .response
+                    .as_ref()
+                    .is_some_and(|response| response.status == status)
+            })
+            && self
+                .failed
+                .is_none_or(|failed| transaction.error.is_some() == failed)
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+use crate::recorder::HttpTransaction;
+
+/// Transactions laid out in time for a DevTools-style waterfall.
+#[derive(Debug, Clone, Serialize)]
+pub struct Timeline {
+    /// When the earliest transaction started, in milliseconds since the
+    /// epoch. Entry offsets count from here.
+    pub start: u64,
+    /// When the latest transaction ended, or now while any is in flight.
+    pub end: u64,
+    /// The most transactions in flight at once.
+    pub max_concurrency: usize,
+    /// Ordered by start.
+    pub entries: Vec<TimelineEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEntry {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub start: u64,
+    /// `None` while the transaction is in flight.
+    pub end: Option<u64>,
+    /// Milliseconds from the timeline's start.
+    pub offset_ms: u64,
+    /// Until the end, or until now while in flight.
+    pub duration_ms: u64,
+    /// The lowest row this entry fits in without overlapping another, for
+    /// drawing concurrent transactions side by side.
+    pub lane: usize,
+    /// Transactions in flight when this one started, itself included.
+    pub concurrency: usize,
+}
+
+/// Lays out `transactions`, treating those still in flight as ending at
+/// `now`.
+pub fn build_timeline<'a>(
+    transactions: impl IntoIterator<Item = &'a HttpTransaction>,
+    now: u64,
+) -> Timeline {
+    let mut transactions: Vec<&HttpTransaction> = transactions.into_iter().collect();
+    // Requests in the same millisecond keep the order they arrived in
+    transactions.sort_by_key(|t| (t.request.timestamp, t.request.seq));
+
+    let start = transactions.first().map_or(now, |t| t.request.timestamp);
+    let mut end = start;
+    let mut max_concurrency = 0;
+    // When the entry in each lane ends
+    let mut lanes: Vec<u64> = Vec::new();
+    let mut entries = Vec::with_capacity(transactions.len());
+    for t in transactions {
+        let entry_start = t.request.timestamp;
+        let entry_end = t.ended_at.unwrap_or(now).max(entry_start);
+        end = end.max(entry_end);
+
+        let concurrency = lanes.iter().filter(|&&ends| ends > entry_start).count() + 1;
+        max_concurrency = max_concurrency.max(concurrency);
+        let lane = match lanes.iter().position(|&ends| ends <= entry_start) {
+            Some(lane) => {
+                lanes[lane] = entry_end;
+                lane
+            }
+            None => {
+                lanes.push(entry_end);
+                lanes.len() - 1
+            }
+        };
+
+        entries.push(TimelineEntry {
+            id: t.request.id.clone(),
+            method: t.request.method.clone(),
+            path: t.request.path.clone(),
+            status: t.response.as_ref().map(|response| response.status),
+            error: t.error.clone(),
+            start: entry_start,
+            end: t.ended_at,
+            offset_ms: entry_start - start,
+            duration_ms: entry_end - entry_start,
+            lane,
+            concurrency,
+        });
+    }
+
+    Timeline {
+        start,
+        end,
+        max_concurrency,
+        entries,
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Method, StatusCode};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::route::PathTemplate;
+
+/// A change to upstream responses before they reach the client, written as
+/// `TARGET = VALUE [when CONDITION [and CONDITION]...]`:
+///
+/// ```text
+/// response.body.json.user.email = "test@example.com" when path == "/api/me"
+/// response.header.cache-control = "no-store" when path matches "/api/*"
+/// response.status = 503 when method == "POST" and path matches "/orders/{id}"
+/// ```
+///
+/// Values are JSON literals. A `null` header value removes the header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TransformRule {
+    source: String,
+    target: Target,
+    value: Value,
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Status,
+    Header(HeaderName),
+    Json(Vec<JsonStep>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum JsonStep {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    field: Field,
+    op: Op,
+    value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Path,
+    Method,
+    Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Matches,
+}
+
+/// What a rule sees of an exchange.
+pub struct Exchange<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub status: StatusCode,
+}
+
+impl TransformRule {
+    fn applies_to(&self, exchange: &Exchange) -> bool {
+        self.conditions.iter().all(|condition| {
+            let actual = match condition.field {
+                Field::Path => Value::from(exchange.path),
+                Field::Method => Value::from(exchange.method.as_str()),
+                Field::Status => Value::from(exchange.status.as_u16()),
+            };
+            match condition.op {
+                Op::Eq => actual == condition.value,
+                Op::Ne => actual != condition.value,
+                Op::Matches => condition.value.as_str().is_some_and(|pattern| {
+                    PathTemplate::parse(pattern)
+                        .matches(actual.as_str().unwrap_or_default())
+                        .is_some()
+                }),
+            }
+        })
+    }
+
+    /// Applies the rule to a response, returning whether it changed it.
+    fn apply(&self, status: &mut StatusCode, headers: &mut HeaderMap, body: &mut Bytes) -> bool {
+        match self.target {
+            Target::Status => {
+                let Some(code) = self
+                    .value
+                    .as_u64()
+                    .and_then(|code| u16::try_from(code).ok())
+                else {
+                    return false;
+                };
+                match StatusCode::from_u16(code) {
+                    Ok(code) => {
+                        *status = code;
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            Target::Header(ref name) => {
+                let value = match self.value {
+                    Value::Null => return headers.remove(name).is_some(),
+                    Value::String(ref value) => value.clone(),
+                    ref other => other.to_string(),
+                };
+                match HeaderValue::from_str(&value) {
+                    Ok(value) => {
+                        headers.insert(name.clone(), value);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            Target::Json(ref steps) => {
+                // An encoded body would have to be decoded and encoded again
+                if headers.contains_key(header::CONTENT_ENCODING) {
+                    return false;
+                }
+                let Ok(mut json) = serde_json::from_slice::<Value>(body) else {
+                    return false;
+                };
+                let Some(slot) = json_slot(&mut json, steps) else {
+                    return false;
+                };
+                *slot = self.value.clone();
+                *body = Bytes::from(json.to_string());
+                headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+                true
+            }
+        }
+    }
+}
+
+/// The value at `steps`, adding missing object keys along the way. Array
+/// indexes must exist.
+fn json_slot<'a>(mut value: &'a mut Value, steps: &[JsonStep]) -> Option<&'a mut Value> {
+    for step in steps {
+        value = match step {
+            JsonStep::Key(key) => value
+                .as_object_mut()?
+                .entry(key.clone())
+                .or_insert(Value::Null),
+            JsonStep::Index(index) => value.as_array_mut()?.get_mut(*index)?,
+        };
+    }
+    Some(value)
+}
+
+impl std::str::FromStr for TransformRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let source = s.trim();
+        let (target, rest) = source
+            .split_once('=')
+            .context("Expected TARGET = VALUE [when CONDITION]")?;
+        let target = parse_target(target.trim())?;
+        let (value, mut rest) = json_literal(rest)?;
+
+        let mut conditions = Vec::new();
+        if !rest.is_empty() {
+            rest = rest
+                .strip_prefix("when")
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .with_context(|| format!("Expected 'when' after the value, got {rest:?}"))?;
+            loop {
+                let (condition, after) = parse_condition(rest)?;
+                conditions.push(condition);
+                if after.is_empty() {
+                    break;
+                }
+                rest = after
+                    .strip_prefix("and")
+                    .filter(|rest| rest.starts_with(char::is_whitespace))
+                    .with_context(|| format!("Expected 'and' between conditions, got {after:?}"))?;
+            }
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            target,
+            value,
+            conditions,
+        })
+    }
+}
+
+fn parse_target(target: &str) -> Result<Target> {
+    if target == "response.status" {
+        return Ok(Target::Status);
+    }
+    if let Some(name) = target.strip_prefix("response.header.") {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name {name:?}"))?;
+        return Ok(Target::Header(name));
+    }
+    let Some(mut path) = target.strip_prefix("response.body.json") else {
+        bail!(
+            "Unknown target {target:?}, expected response.status, response.header.NAME or response.body.json..."
+        );
+    };
+    let mut steps = Vec::new();
+    while !path.is_empty() {
+        if let Some(rest) = path.strip_prefix('.') {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            if end == 0 {
+                bail!("Empty key in {target:?}");
+            }
+            steps.push(JsonStep::Key(rest[..end].to_string()));
+            path = &rest[end..];
+        } else if let Some(rest) = path.strip_prefix('[') {
+            let (index, rest) = rest
+                .split_once(']')
+                .with_context(|| format!("Unclosed '[' in {target:?}"))?;
+            let index = index
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid index {index:?} in {target:?}"))?;
+            steps.push(JsonStep::Index(index));
+            path = rest;
+        } else {
+            bail!("Unexpected {path:?} in {target:?}");
+        }
+    }
+    Ok(Target::Json(steps))
+}
+
+fn parse_condition(s: &str) -> Result<(Condition, &str)> {
+    let s = s.trim_start();
+    let (field, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    let field = match field {
+        "path" => Field::Path,
+        "method" => Field::Method,
+        "status" => Field::Status,
+        _ => bail!("Unknown field {field:?}, expected path, method or status"),
+    };
+    let rest = rest.trim_start();
+    let (op, rest) = if let Some(rest) = rest.strip_prefix("==") {
+        (Op::Eq, rest)
+    } else if let Some(rest) = rest.strip_prefix("!=") {
+        (Op::Ne, rest)
+    } else if let Some(rest) = rest.strip_prefix("matches") {
+        (Op::Matches, rest)
+    } else {
+        bail!("Expected ==, != or matches, got {rest:?}");
+    };
+    let (value, rest) = json_literal(rest)?;
+    if op == Op::Matches && !value.is_string() {
+        bail!("matches needs a path template string, got {value}");
+    }
+    Ok((Condition { field, op, value }, rest))
+}
+
+/// Reads one JSON value from the start of `s`, returning it and the trimmed
+/// text after it.
+fn json_literal(s: &str) -> Result<(Value, &str)> {
+    let s = s.trim_start();
+    let mut values = serde_json::Deserializer::from_str(s).into_iter::<Value>();
+    let value = values
+        .next()
+        .with_context(|| format!("Expected a JSON value, got {s:?}"))?
+        .with_context(|| format!("Invalid JSON value in {s:?}"))?;
+    Ok((value, s[values.byte_offset()..].trim()))
+}
+
+impl TryFrom<String> for TransformRule {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<TransformRule> for String {
+    fn from(rule: TransformRule) -> Self {
+        rule.source
+    }
+}
+
+/// Contents of a `--transforms` file, also accepted by the admin API.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TransformSet {
+    #[serde(default)]
+    pub rules: Vec<TransformRule>,
+}
+
+/// Runtime-editable list of response transformations, applied in order.
+#[derive(Clone, Default)]
+pub struct ResponseTransforms {
+    rules: Arc<RwLock<Vec<TransformRule>>>,
+}
+
+impl ResponseTransforms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads rules from a YAML or JSON file with a `rules` list.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read transforms: {}", path.display()))?;
+        let set: TransformSet = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse transforms: {}", path.display()))?;
+        let transforms = Self::new();
+        transforms.replace(set);
+        Ok(transforms)
+    }
+
+    pub fn replace(&self, set: TransformSet) {
+        *self.rules.write() = set.rules;
+    }
+
+    pub fn add(&self, rule: TransformRule) {
+        self.rules.write().push(rule);
+    }
+
+    pub fn clear(&self) {
+        self.rules.write().clear();
+    }
+
+    pub fn snapshot(&self) -> Vec<TransformRule> {
+        self.rules.read().clone()
+    }
+
+    /// Applies every matching rule to the response, returning those that
+    /// changed it.
+    pub fn apply(
+        &self,
+        exchange: &Exchange,
+        status: &mut StatusCode,
+        headers: &mut HeaderMap,
+        body: &mut Bytes,
+    ) -> Vec<String> {
+        let rules = self.rules.read();
+        rules
+            .iter()
+            .filter(|rule| rule.applies_to(exchange))
+            .filter(|rule| rule.apply(status, headers, body))
+            .map(|rule| rule.source.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// What passed through a tunnel once it closed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Relayed {
+    pub bytes_to_upstream: u64,
+    pub bytes_to_client: u64,
+    /// Why the tunnel closed, when it was not a clean shutdown.
+    pub error: Option<String>,
+    /// The first bytes sent each way, up to the sample size asked for.
+    pub upstream_sample: Vec<u8>,
+    pub client_sample: Vec<u8>,
+}
+
+/// Copies bytes both ways between `client` and `upstream` without reading
+/// them, until both sides are done or either fails.
+pub async fn relay<C, U>(client: C, upstream: U) -> Relayed
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    relay_sampled(client, upstream, 0).await
+}
+
+/// [`relay`], keeping the first `sample_bytes` sent each way.
+pub async fn relay_sampled<C, U>(client: C, upstream: U, sample_bytes: usize) -> Relayed
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut client = Counted::new(client, sample_bytes);
+    let mut upstream = Counted::new(upstream, sample_bytes);
+    let result = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    // Counted as read, so a failure part way keeps what got through
+    Relayed {
+        bytes_to_upstream: client.read,
+        bytes_to_client: upstream.read,
+        error: result.err().map(|e| e.to_string()),
+        upstream_sample: client.sample,
+        client_sample: upstream.sample,
+    }
+}
+
+/// A stream that counts the bytes read from it and keeps the first few.
+struct Counted<S> {
+    inner: S,
+    read: u64,
+    sample: Vec<u8>,
+    sample_bytes: usize,
+}
+
+impl<S> Counted<S> {
+    fn new(inner: S, sample_bytes: usize) -> Self {
+        Self {
+            inner,
+            read: 0,
+            sample: Vec::new(),
+            sample_bytes,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = &buf.filled()[filled..];
+        self.read += read.len() as u64;
+        let room = self.sample_bytes.saturating_sub(self.sample.len());
+        self.sample.extend_from_slice(&read[..room.min(read.len())]);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// `bytes` as lines of 16 in hex, next to their printable ASCII, in the
+/// layout of `hexdump -C`.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex: Vec<String> = (0..16)
+                .map(|i| {
+                    chunk
+                        .get(i)
+                        .map_or("  ".to_string(), |b| format!("{b:02x}"))
+                })
+                .collect();
+            let text: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}  {}  {}  |{text}|",
+                line * 16,
+                hex[..8].join(" "),
+                hex[8..].join(" ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,323 @@
+use anyhow::{Context as _, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::tunnel::hex_dump;
+
+/// The largest datagram UDP can carry.
+const MAX_DATAGRAM: usize = 65_535;
+
+/// Datagrams sampled from the start of each direction of a flow.
+pub const SAMPLED_DATAGRAMS: usize = 3;
+
+/// Closed flows kept for [`UdpRelay::flows`], oldest dropped first.
+const MAX_CLOSED_FLOWS: usize = 1000;
+
+/// Datagrams relayed one way in a flow.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DatagramStats {
+    pub datagrams: u64,
+    pub bytes: u64,
+    pub min_size: Option<usize>,
+    pub max_size: usize,
+    /// The first [`SAMPLED_DATAGRAMS`] datagrams in hex, cut to the sample
+    /// size asked for.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<String>,
+}
+
+impl DatagramStats {
+    fn count(&mut self, datagram: &[u8], sample_bytes: usize) {
+        self.datagrams += 1;
+        self.bytes += datagram.len() as u64;
+        self.min_size = Some(
+            self.min_size
+                .map_or(datagram.len(), |min| min.min(datagram.len())),
+        );
+        self.max_size = self.max_size.max(datagram.len());
+        if sample_bytes > 0 && self.samples.len() < SAMPLED_DATAGRAMS {
+            let sample = &datagram[..sample_bytes.min(datagram.len())];
+            self.samples
+                .push(sample.iter().map(|b| format!("{b:02x}")).collect());
+        }
+    }
+}
+
+/// The datagrams exchanged between one client address and the upstream.
+#[derive(Debug, Clone, Serialize)]
+pub struct UdpFlow {
+    pub client_addr: String,
+    /// When the first datagram arrived, in milliseconds since the epoch.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub to_upstream: DatagramStats,
+    pub to_client: DatagramStats,
+    /// Closed after going idle for the relay's idle timeout.
+    pub closed: bool,
+}
+
+/// Totals across every flow so far.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UdpStats {
+    pub flows: u64,
+    /// Flows that have not gone idle yet.
+    pub active: u64,
+    pub datagrams_to_upstream: u64,
+    pub datagrams_to_client: u64,
+    pub bytes_to_upstream: u64,
+    pub bytes_to_client: u64,
+}
+
+impl fmt::Display for UdpStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Flows:       {} ({} open)", self.flows, self.active)?;
+        writeln!(
+            f,
+            "  Sent:        {} datagrams, {} bytes",
+            self.datagrams_to_upstream, self.bytes_to_upstream
+        )?;
+        write!(
+            f,
+            "  Received:    {} datagrams, {} bytes",
+            self.datagrams_to_client, self.bytes_to_client
+        )
+    }
+}
+
+/// A flow's own socket to the upstream, so replies can be told apart by
+/// client.
+struct Flow {
+    upstream: UdpSocket,
+    started: Instant,
+    last_active: Mutex<Instant>,
+    record: Mutex<UdpFlow>,
+}
+
+impl Flow {
+    fn touch(&self) {
+        *self.last_active.lock() = Instant::now();
+    }
+
+    fn snapshot(&self) -> UdpFlow {
+        let mut record = self.record.lock().clone();
+        record.duration_ms = self.started.elapsed().as_millis() as u64;
+        record
+    }
+}
+
+/// Relays UDP datagrams to an upstream, keeping a flow per client address
+/// with the count, sizes and a hex sample of the datagrams each way. A flow
+/// closes once neither side has sent anything for the idle timeout.
+#[derive(Clone)]
+pub struct UdpRelay {
+    upstream: String,
+    idle_timeout: Duration,
+    /// Bytes sampled from the start of each of a flow's first datagrams.
+    hex_dump: usize,
+    active: Arc<Mutex<HashMap<SocketAddr, Arc<Flow>>>>,
+    closed: Arc<Mutex<VecDeque<UdpFlow>>>,
+}
+
+impl UdpRelay {
+    pub fn new(upstream: String) -> Self {
+        Self {
+            upstream,
+            idle_timeout: Duration::from_secs(30),
+            hex_dump: 0,
+            active: Arc::default(),
+            closed: Arc::default(),
+        }
+    }
+
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Logs and keeps the first `bytes` of each flow's first datagrams.
+    pub fn with_hex_dump(mut self, bytes: usize) -> Self {
+        self.hex_dump = bytes;
+        self
+    }
+
+    /// Closed flows, oldest first, then the open ones.
+    pub fn flows(&self) -> Vec<UdpFlow> {
+        let mut flows: Vec<UdpFlow> = self.closed.lock().iter().cloned().collect();
+        let mut open: Vec<UdpFlow> = self.active.lock().values().map(|f| f.snapshot()).collect();
+        open.sort_by_key(|flow| flow.started_at);
+        flows.extend(open);
+        flows
+    }
+
+    pub fn stats(&self) -> UdpStats {
+        let flows = self.flows();
+        let mut stats = UdpStats::default();
+        for flow in &flows {
+            stats.flows += 1;
+            stats.active += u64::from(!flow.closed);
+            stats.datagrams_to_upstream += flow.to_upstream.datagrams;
+            stats.datagrams_to_client += flow.to_client.datagrams;
+            stats.bytes_to_upstream += flow.to_upstream.bytes;
+            stats.bytes_to_client += flow.to_client.bytes;
+        }
+        stats
+    }
+
+    /// Relays datagrams arriving on `socket` until it fails.
+    pub async fn serve(self, socket: UdpSocket) -> Result<()> {
+        let socket = Arc::new(socket);
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let (len, client) = socket.recv_from(&mut buf).await?;
+            let datagram = &buf[..len];
+            let existing = {
+                let active = self.active.lock();
+                active.get(&client).inspect(|flow| flow.touch()).cloned()
+            };
+            let flow = match existing {
+                Some(flow) => flow,
+                None => match self.open_flow(&socket, client).await {
+                    Ok(flow) => flow,
+                    Err(e) => {
+                        warn!("Dropped a datagram from {client}: {e:#}");
+                        continue;
+                    }
+                },
+            };
+            self.sampled(&flow, client, "sent", datagram, |record| {
+                &mut record.to_upstream
+            });
+            if let Err(e) = flow.upstream.send(datagram).await {
+                warn!("Could not relay a datagram from {client}: {e}");
+            }
+        }
+    }
+
+    async fn open_flow(&self, socket: &Arc<UdpSocket>, client: SocketAddr) -> Result<Arc<Flow>> {
+        let upstream_addr = tokio::net::lookup_host(&self.upstream)
+            .await?
+            .next()
+            .with_context(|| format!("{} did not resolve", self.upstream))?;
+        let local: SocketAddr = if upstream_addr.is_ipv6() {
+            "[::]:0".parse()?
+        } else {
+            "0.0.0.0:0".parse()?
+        };
+        let upstream = UdpSocket::bind(local).await?;
+        upstream.connect(upstream_addr).await?;
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let flow = Arc::new(Flow {
+            upstream,
+            started: Instant::now(),
+            last_active: Mutex::new(Instant::now()),
+            record: Mutex::new(UdpFlow {
+                client_addr: client.to_string(),
+                started_at,
+                duration_ms: 0,
+                to_upstream: DatagramStats::default(),
+                to_client: DatagramStats::default(),
+                closed: false,
+            }),
+        });
+        self.active.lock().insert(client, Arc::clone(&flow));
+        info!("New UDP flow from {client} to {upstream_addr}");
+
+        let (relay, socket, flow_for_replies) =
+            (self.clone(), Arc::clone(socket), Arc::clone(&flow));
+        tokio::spawn(async move {
+            relay
+                .relay_replies(&socket, client, &flow_for_replies)
+                .await;
+        });
+        Ok(flow)
+    }
+
+    /// Sends the upstream's replies on to `client` until the flow goes
+    /// idle, then closes it.
+    async fn relay_replies(&self, socket: &UdpSocket, client: SocketAddr, flow: &Flow) {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let idle_for = flow.last_active.lock().elapsed();
+            let wait = self.idle_timeout.saturating_sub(idle_for);
+            if wait.is_zero() {
+                // Checked again under the lock, which the listener holds
+                // while it picks a flow for a new datagram
+                let mut active = self.active.lock();
+                if flow.last_active.lock().elapsed() >= self.idle_timeout {
+                    active.remove(&client);
+                    break;
+                }
+                continue;
+            }
+            match tokio::time::timeout(wait, flow.upstream.recv(&mut buf)).await {
+                Ok(Ok(len)) => {
+                    flow.touch();
+                    let datagram = &buf[..len];
+                    self.sampled(flow, client, "received", datagram, |record| {
+                        &mut record.to_client
+                    });
+                    if let Err(e) = socket.send_to(datagram, client).await {
+                        warn!("Could not relay a datagram to {client}: {e}");
+                    }
+                }
+                // Refused by the upstream, as ICMP port unreachable reports
+                Ok(Err(e)) => {
+                    warn!("UDP flow from {client} failed: {e}");
+                    self.active.lock().remove(&client);
+                    break;
+                }
+                Err(_) => {}
+            }
+        }
+
+        let mut record = flow.snapshot();
+        record.closed = true;
+        info!(
+            "UDP flow from {client} closed after {}ms: {} datagrams ({} bytes) sent, {} ({} bytes) received",
+            record.duration_ms,
+            record.to_upstream.datagrams,
+            record.to_upstream.bytes,
+            record.to_client.datagrams,
+            record.to_client.bytes
+        );
+        let mut closed = self.closed.lock();
+        if closed.len() == MAX_CLOSED_FLOWS {
+            closed.pop_front();
+        }
+        closed.push_back(record);
+    }
+
+    /// Counts a datagram in the direction `stats` picks, logging it as a hex
+    /// dump while that direction is still being sampled.
+    fn sampled(
+        &self,
+        flow: &Flow,
+        client: SocketAddr,
+        direction: &str,
+        datagram: &[u8],
+        stats: impl FnOnce(&mut UdpFlow) -> &mut DatagramStats,
+    ) {
+        let mut record = flow.record.lock();
+        let stats = stats(&mut record);
+        let sampled = stats.samples.len();
+        stats.count(datagram, self.hex_dump);
+        if stats.samples.len() > sampled {
+            let sample = &datagram[..self.hex_dump.min(datagram.len())];
+            info!(
+                "{client} {direction} {} bytes:\n{}",
+                datagram.len(),
+                hex_dump(sample)
+            );
+        }
+    }
+}
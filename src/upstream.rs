@@ -0,0 +1,1025 @@
+use anyhow::{bail, Context as _, Result};
+use base64::Engine as _;
+use http::{HeaderName, HeaderValue};
+use hyper::client::connect::dns::Name;
+use hyper::client::connect::{CaptureConnection, Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Body, Client, Method, Uri};
+#[cfg(feature = "tls")]
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder, MaybeHttpsStream};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, IoSlice};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+#[cfg(feature = "tls")]
+use x509_parser::extensions::GeneralName;
+
+use crate::config::ProxyConfig;
+use crate::outbound::{http_connect, socks5_handshake, OutboundProxy, OutboundProxyKind};
+#[cfg(feature = "tls")]
+use crate::recorder::CertificateInfo;
+use crate::recorder::{UpstreamConnection, UpstreamTls};
+
+pub type UpstreamClient = Client<TrackingConnector>;
+
+/// The scheme and authority to send requests for an upstream to: `host:port`
+/// means plain HTTP, and `https://host:port` is kept as is.
+pub fn upstream_base_url(upstream: &str) -> String {
+    if upstream.contains("://") {
+        upstream.trim_end_matches('/').to_string()
+    } else {
+        format!("http://{upstream}")
+    }
+}
+
+/// A client certificate and key presented to upstreams that require mutual
+/// TLS. Both files are PEM and are read when the identity is created, so a
+/// config naming a missing or unusable file is rejected up front.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "IdentityFiles", into = "IdentityFiles")]
+pub struct ClientIdentity {
+    files: IdentityFiles,
+    #[cfg(feature = "tls")]
+    chain: Arc<Vec<rustls::Certificate>>,
+    #[cfg(feature = "tls")]
+    key: Arc<rustls::PrivateKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct IdentityFiles {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl ClientIdentity {
+    pub fn load(cert: &Path, key: &Path) -> Result<Self> {
+        let files = IdentityFiles {
+            cert: cert.to_path_buf(),
+            key: key.to_path_buf(),
+        };
+        files.try_into()
+    }
+
+    pub fn cert_path(&self) -> &Path {
+        &self.files.cert
+    }
+}
+
+#[cfg(feature = "tls")]
+impl TryFrom<IdentityFiles> for ClientIdentity {
+    type Error = anyhow::Error;
+
+    fn try_from(files: IdentityFiles) -> Result<Self> {
+        let pem = std::fs::read(&files.cert).with_context(|| {
+            format!(
+                "Failed to read client certificate: {}",
+                files.cert.display()
+            )
+        })?;
+        let chain: Vec<rustls::Certificate> = rustls_pemfile::certs(&mut pem.as_slice())
+            .with_context(|| format!("Invalid client certificate: {}", files.cert.display()))?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect();
+        if chain.is_empty() {
+            bail!("No certificate in {}", files.cert.display());
+        }
+
+        let pem = std::fs::read(&files.key)
+            .with_context(|| format!("Failed to read client key: {}", files.key.display()))?;
+        let key = rustls_pemfile::read_all(&mut pem.as_slice())
+            .with_context(|| format!("Invalid client key: {}", files.key.display()))?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+                _ => None,
+            })
+            .with_context(|| format!("No private key in {}", files.key.display()))?;
+        rustls::sign::any_supported_type(&key)
+            .map_err(|e| anyhow::anyhow!("Unsupported client key {}: {e}", files.key.display()))?;
+
+        Ok(Self {
+            files,
+            chain: Arc::new(chain),
+            key: Arc::new(key),
+        })
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+impl TryFrom<IdentityFiles> for ClientIdentity {
+    type Error = anyhow::Error;
+
+    fn try_from(files: IdentityFiles) -> Result<Self> {
+        bail!(
+            "Client certificate {} needs debug-proxy built with the tls feature",
+            files.cert.display()
+        )
+    }
+}
+
+impl From<ClientIdentity> for IdentityFiles {
+    fn from(identity: ClientIdentity) -> Self {
+        identity.files
+    }
+}
+
+impl std::fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientIdentity")
+            .field("cert", &self.files.cert)
+            .field("key", &self.files.key)
+            .finish()
+    }
+}
+
+impl PartialEq for ClientIdentity {
+    fn eq(&self, other: &Self) -> bool {
+        self.files == other.files
+    }
+}
+
+impl Eq for ClientIdentity {}
+
+impl std::hash::Hash for ClientIdentity {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.files.hash(state);
+    }
+}
+
+/// Builds the client for upstream requests with the pool settings of
+/// `config`. With keep-alive off no connection is kept for reuse.
+pub fn build_client(config: &ProxyConfig) -> UpstreamClient {
+    build_client_with_identity(config, config.upstream_client_identity.as_ref())
+}
+
+/// Builds a client like [`build_client`] that presents `identity` to
+/// upstreams asking for a client certificate.
+pub fn build_client_with_identity(
+    config: &ProxyConfig,
+    identity: Option<&ClientIdentity>,
+) -> UpstreamClient {
+    let mut http = HttpConnector::new_with_resolver(UpstreamResolver::new(
+        config.resolve.clone(),
+        config.dns_cache_ttl,
+    ));
+    http.enforce_http(false);
+    let connector = OutboundConnector {
+        direct: http,
+        proxy: config.outbound_proxy.clone().map(Arc::new),
+    };
+
+    let max_idle = if config.http1_keep_alive {
+        config.pool_max_idle_per_host
+    } else {
+        0
+    };
+    Client::builder()
+        .pool_max_idle_per_host(max_idle)
+        .pool_idle_timeout(config.pool_idle_timeout)
+        .http2_only(config.upstream_http2)
+        .build::<_, Body>(TrackingConnector::new(wrap_tls(
+            config, identity, connector,
+        )))
+}
+
+/// Layers TLS over `connector` for `https://` upstreams, leaving plain HTTP
+/// as is.
+#[cfg(feature = "tls")]
+fn wrap_tls(
+    config: &ProxyConfig,
+    identity: Option<&ClientIdentity>,
+    connector: OutboundConnector,
+) -> InnerConnector {
+    let builder = if identity.is_none() && config.upstream_ca.is_empty() {
+        HttpsConnectorBuilder::new().with_native_roots()
+    } else {
+        HttpsConnectorBuilder::new().with_tls_config(tls_config(&config.upstream_ca, identity))
+    }
+    .https_or_http();
+    if config.upstream_http2 {
+        builder.enable_http2().wrap_connector(connector)
+    } else {
+        builder.enable_http1().wrap_connector(connector)
+    }
+}
+
+/// Built without the `tls` feature, so only plain HTTP upstreams are
+/// reachable and [`TrackingConnector`] turns `https://` ones away.
+#[cfg(not(feature = "tls"))]
+fn wrap_tls(
+    _config: &ProxyConfig,
+    _identity: Option<&ClientIdentity>,
+    connector: OutboundConnector,
+) -> InnerConnector {
+    connector
+}
+
+/// The connector for requests made outside the upstream settings, such as
+/// alert webhooks and benchmarks.
+#[cfg(feature = "tls")]
+pub type WebConnector = HttpsConnector<HttpConnector>;
+#[cfg(not(feature = "tls"))]
+pub type WebConnector = HttpConnector;
+
+/// A client for any URL, trusting the system roots for HTTPS.
+pub fn web_client() -> Client<WebConnector> {
+    #[cfg(feature = "tls")]
+    let connector = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    #[cfg(not(feature = "tls"))]
+    let connector = HttpConnector::new();
+    Client::builder().build::<_, Body>(connector)
+}
+
+/// TLS settings trusting the system roots plus `cas`, and presenting
+/// `identity` when the upstream asks for a client certificate.
+#[cfg(feature = "tls")]
+fn tls_config(cas: &[UpstreamCa], identity: Option<&ClientIdentity>) -> rustls::ClientConfig {
+    let native: Vec<Vec<u8>> = rustls_native_certs::load_native_certs()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|cert| cert.0)
+        .collect();
+    let roots_for = |host: Option<&str>| {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_parsable_certificates(&native);
+        for ca in cas
+            .iter()
+            .filter(|ca| ca.host.is_none() || ca.host.as_deref() == host)
+        {
+            roots.add_parsable_certificates(&ca.certs);
+        }
+        roots
+    };
+    let verifier = UpstreamVerifier {
+        default: rustls::client::WebPkiVerifier::new(roots_for(None), None),
+        hosts: cas
+            .iter()
+            .filter_map(|ca| ca.host.clone())
+            .map(|host| {
+                let verifier = rustls::client::WebPkiVerifier::new(roots_for(Some(&host)), None);
+                (host, verifier)
+            })
+            .collect(),
+    };
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    match identity {
+        Some(identity) => builder
+            .with_client_auth_cert(identity.chain.to_vec(), (*identity.key).clone())
+            .expect("client key was checked when the identity was loaded"),
+        None => builder.with_no_client_auth(),
+    }
+}
+
+/// A CA bundle trusted for upstream certificates in addition to the system
+/// roots, for every upstream or only for `host`. Parsed from `FILE` or
+/// `HOST=FILE`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UpstreamCa {
+    pub host: Option<String>,
+    pub file: PathBuf,
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    certs: Arc<Vec<Vec<u8>>>,
+}
+
+impl std::str::FromStr for UpstreamCa {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // A Windows path such as C:\ca.pem has no host
+        let (host, file) = match s.split_once('=') {
+            Some((host, file)) if !host.is_empty() => (Some(host.to_ascii_lowercase()), file),
+            _ => (None, s),
+        };
+        let file = PathBuf::from(file);
+        let certs = read_ca_bundle(&file)?;
+        Ok(Self {
+            host,
+            file,
+            certs: Arc::new(certs),
+        })
+    }
+}
+
+/// The DER certificates in a PEM bundle, of which at least one must be
+/// usable as a root.
+#[cfg(feature = "tls")]
+fn read_ca_bundle(file: &Path) -> Result<Vec<Vec<u8>>> {
+    let pem = std::fs::read(file)
+        .with_context(|| format!("Failed to read CA bundle: {}", file.display()))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .with_context(|| format!("Invalid CA bundle: {}", file.display()))?;
+    let mut roots = rustls::RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(&certs);
+    if added == 0 {
+        bail!("No usable CA certificate in {}", file.display());
+    }
+    Ok(certs)
+}
+
+#[cfg(not(feature = "tls"))]
+fn read_ca_bundle(file: &Path) -> Result<Vec<Vec<u8>>> {
+    bail!(
+        "CA bundle {} needs debug-proxy built with the tls feature",
+        file.display()
+    )
+}
+
+impl TryFrom<String> for UpstreamCa {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<UpstreamCa> for String {
+    fn from(ca: UpstreamCa) -> Self {
+        match ca.host {
+            Some(host) => format!("{host}={}", ca.file.display()),
+            None => ca.file.display().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Debug for UpstreamCa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpstreamCa")
+            .field("host", &self.host)
+            .field("file", &self.file)
+            .finish()
+    }
+}
+
+/// Verifies upstream certificates against the roots trusted for the host
+/// being connected to.
+#[cfg(feature = "tls")]
+struct UpstreamVerifier {
+    default: rustls::client::WebPkiVerifier,
+    hosts: HashMap<String, rustls::client::WebPkiVerifier>,
+}
+
+#[cfg(feature = "tls")]
+impl rustls::client::ServerCertVerifier for UpstreamVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            rustls::ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            rustls::ServerName::IpAddress(addr) => addr.to_string(),
+            _ => String::new(),
+        };
+        self.hosts
+            .get(&host)
+            .unwrap_or(&self.default)
+            .verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )
+    }
+}
+
+/// Identifies the upstream connection a response arrived on. Hyper copies it
+/// into the extensions of every response received over that connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionTag {
+    id: u64,
+    dns_ms: Option<u64>,
+    tls: Option<Arc<UpstreamTls>>,
+    responses: Arc<AtomicU64>,
+    raw: Arc<Mutex<RawExchange>>,
+}
+
+impl ConnectionTag {
+    /// Counts a response on this connection; every one after the first was
+    /// sent over a reused connection.
+    pub fn record_use(&self) -> UpstreamConnection {
+        let previous = self.responses.fetch_add(1, Ordering::Relaxed);
+        UpstreamConnection {
+            id: self.id,
+            reused: previous > 0,
+            dns_ms: self.dns_ms,
+            tls: self.tls.as_deref().cloned(),
+        }
+    }
+
+    /// The tag of the connection hyper picked for a request, once it has
+    /// picked one.
+    pub fn captured(captured: &CaptureConnection) -> Option<Self> {
+        let mut extensions = http::Extensions::new();
+        captured
+            .connection_metadata()
+            .as_ref()?
+            .get_extras(&mut extensions);
+        extensions.remove()
+    }
+
+    /// The first bytes the upstream answered `method target` with, when that
+    /// request was the latest written to this connection over HTTP/1, no
+    /// earlier than `since`. An HTTP/1 connection carries one request at a
+    /// time, so that is the request it was picked for.
+    pub fn raw_response(&self, method: &Method, target: &str, since: Instant) -> Option<Vec<u8>> {
+        let exchange = self.raw.lock();
+        exchange
+            .answered(method, target, since)
+            .then(|| exchange.response.clone())
+    }
+}
+
+/// Response bytes kept from each upstream connection, so a response hyper
+/// cannot parse can be shown as it was sent.
+pub const RAW_RESPONSE_CAPTURE: usize = 512;
+
+/// The request line of the latest request written to a connection and the
+/// first bytes read back for it.
+#[derive(Debug, Default)]
+struct RawExchange {
+    started: Option<Instant>,
+    request_line: Vec<u8>,
+    response: Vec<u8>,
+    reading: bool,
+}
+
+impl RawExchange {
+    fn wrote(&mut self, buf: &[u8]) {
+        if buf.is_empty() || (self.started.is_some() && !self.reading) {
+            return;
+        }
+        // The first write after a response starts the next request
+        let line_end = buf.windows(2).position(|pair| pair == b"\r\n");
+        self.started = Some(Instant::now());
+        self.request_line = buf[..line_end.unwrap_or(buf.len()).min(2048)].to_vec();
+        self.response.clear();
+        self.reading = false;
+    }
+
+    fn read(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.reading = true;
+        let room = RAW_RESPONSE_CAPTURE.saturating_sub(self.response.len());
+        self.response
+            .extend_from_slice(&bytes[..room.min(bytes.len())]);
+    }
+
+    fn answered(&self, method: &Method, target: &str, since: Instant) -> bool {
+        let mut parts = self.request_line.split(|&b| b == b' ');
+        self.started.is_some_and(|started| started >= since)
+            && !self.response.is_empty()
+            && parts.next() == Some(method.as_str().as_bytes())
+            && parts
+                .next()
+                .is_some_and(|sent| sent.ends_with(target.as_bytes()))
+    }
+}
+
+/// `error` followed by the start of the raw response, escaped, when there is
+/// one.
+pub fn with_raw_response(error: String, raw: Option<&[u8]>) -> String {
+    match raw {
+        Some(raw) => format!(
+            "{error}. The response began with {} bytes: \"{}\"",
+            raw.len(),
+            raw.escape_ascii()
+        ),
+        None => error,
+    }
+}
+
+/// Wraps the HTTPS connector to number the connections it opens.
+#[derive(Clone)]
+pub struct TrackingConnector {
+    inner: InnerConnector,
+    next_id: Arc<AtomicU64>,
+}
+
+impl TrackingConnector {
+    pub fn new(inner: InnerConnector) -> Self {
+        Self {
+            inner,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+type InnerConnector = HttpsConnector<OutboundConnector>;
+#[cfg(not(feature = "tls"))]
+type InnerConnector = OutboundConnector;
+type InnerStream = <InnerConnector as Service<Uri>>::Response;
+type InnerError = <InnerConnector as Service<Uri>>::Error;
+
+impl Service<Uri> for TrackingConnector {
+    type Response = TrackedStream;
+    type Error = InnerError;
+    type Future = Pin<Box<dyn Future<Output = Result<TrackedStream, InnerError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        #[cfg(not(feature = "tls"))]
+        if uri.scheme_str() == Some("https") {
+            return Box::pin(async {
+                Err("HTTPS upstreams need debug-proxy built with the tls feature".into())
+            });
+        }
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+        let connecting = self.inner.call(uri);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        Box::pin(async move {
+            // The resolver only sees the host name, so the port and the time
+            // taken to resolve travel through a task-local
+            let lookup = Arc::new(Lookup {
+                port,
+                duration: Mutex::new(None),
+            });
+            let inner = LOOKUP.scope(Arc::clone(&lookup), connecting).await?;
+            let dns_ms = lookup.duration.lock().map(|d| d.as_millis() as u64);
+            #[cfg(feature = "tls")]
+            let tls = match inner {
+                MaybeHttpsStream::Https(ref stream) => {
+                    Some(Arc::new(tls_details(stream.get_ref().1)))
+                }
+                MaybeHttpsStream::Http(_) => None,
+            };
+            #[cfg(not(feature = "tls"))]
+            let tls = None;
+            let raw = Arc::new(Mutex::new(RawExchange::default()));
+            Ok(TrackedStream {
+                inner,
+                raw: Arc::clone(&raw),
+                tag: ConnectionTag {
+                    id,
+                    dns_ms,
+                    tls,
+                    responses: Arc::new(AtomicU64::new(0)),
+                    raw,
+                },
+            })
+        })
+    }
+}
+
+/// What the TLS session with the upstream negotiated and the certificate it
+/// presented.
+#[cfg(feature = "tls")]
+fn tls_details(session: &rustls::ClientConnection) -> UpstreamTls {
+    let version = match session.protocol_version() {
+        Some(rustls::ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+        Some(rustls::ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+        Some(other) => format!("{other:?}"),
+        None => "unknown".to_string(),
+    };
+    let cipher = session.negotiated_cipher_suite().map_or_else(
+        || "unknown".to_string(),
+        |suite| format!("{:?}", suite.suite()),
+    );
+    UpstreamTls {
+        version,
+        cipher,
+        alpn: session
+            .alpn_protocol()
+            .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        certificate: session
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .and_then(|certificate| certificate_info(&certificate.0)),
+    }
+}
+
+/// The subject, issuer, names and validity of a DER certificate.
+#[cfg(feature = "tls")]
+pub fn certificate_info(der: &[u8]) -> Option<CertificateInfo> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+    let names = certificate
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|extension| {
+            extension
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(name.to_string()),
+                    GeneralName::IPAddress(bytes) => match bytes.len() {
+                        4 => Some(IpAddr::from(<[u8; 4]>::try_from(*bytes).ok()?).to_string()),
+                        16 => Some(IpAddr::from(<[u8; 16]>::try_from(*bytes).ok()?).to_string()),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let validity = certificate.validity();
+    Some(CertificateInfo {
+        subject: certificate.subject().to_string(),
+        issuer: certificate.issuer().to_string(),
+        names,
+        not_before: validity.not_before.timestamp() * 1000,
+        not_after: validity.not_after.timestamp() * 1000,
+        expired: !validity.is_valid(),
+    })
+}
+
+/// An upstream connection that reports its [`ConnectionTag`] to hyper.
+pub struct TrackedStream {
+    inner: InnerStream,
+    tag: ConnectionTag,
+    raw: Arc<Mutex<RawExchange>>,
+}
+
+impl Connection for TrackedStream {
+    fn connected(&self) -> Connected {
+        self.inner.connected().extra(self.tag.clone())
+    }
+}
+
+impl AsyncRead for TrackedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.raw.lock().read(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for TrackedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.raw.lock().wrote(buf);
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(buf) = bufs.iter().find(|buf| !buf.is_empty()) {
+            self.raw.lock().wrote(buf);
+        }
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Opens TCP connections to the upstream, directly or through the outbound
+/// proxy. TLS is layered on top by the HTTPS connector.
+#[derive(Clone)]
+pub struct OutboundConnector {
+    direct: HttpConnector<UpstreamResolver>,
+    proxy: Option<Arc<OutboundProxy>>,
+}
+
+impl Service<Uri> for OutboundConnector {
+    type Response = OutboundStream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<OutboundStream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.direct.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let host = uri.host().unwrap_or_default().to_string();
+        let proxy = self.proxy.clone().filter(|proxy| !proxy.bypasses(&host));
+        let Some(proxy) = proxy else {
+            let connecting = self.direct.call(uri);
+            return Box::pin(async move {
+                Ok(OutboundStream {
+                    stream: connecting.await?,
+                    absolute_form: false,
+                })
+            });
+        };
+
+        let tls = uri.scheme_str() == Some("https");
+        let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+        Box::pin(async move {
+            let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port)).await?;
+            stream.set_nodelay(true)?;
+            let absolute_form = match proxy.kind {
+                OutboundProxyKind::Socks5 => {
+                    socks5_handshake(&mut stream, &host, port, proxy.credentials.as_ref()).await?;
+                    false
+                }
+                OutboundProxyKind::Http if tls => {
+                    let authorization = proxy.authorization();
+                    http_connect(&mut stream, &host, port, authorization.as_deref()).await?;
+                    false
+                }
+                // Plain HTTP goes to the proxy as absolute-form requests
+                OutboundProxyKind::Http => true,
+            };
+            Ok(OutboundStream {
+                stream,
+                absolute_form,
+            })
+        })
+    }
+}
+
+/// A TCP connection to the upstream or, when `absolute_form` is set, to an
+/// HTTP proxy that expects absolute request URIs.
+pub struct OutboundStream {
+    stream: TcpStream,
+    absolute_form: bool,
+}
+
+impl Connection for OutboundStream {
+    fn connected(&self) -> Connected {
+        self.stream.connected().proxy(self.absolute_form)
+    }
+}
+
+impl AsyncRead for OutboundStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for OutboundStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// A header set on every request sent to the upstream, such as credentials
+/// the clients should not need to know. Parsed from `Name: value`; the value
+/// is left out of logs and serialized config.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct UpstreamHeader {
+    pub name: HeaderName,
+    pub value: HeaderValue,
+}
+
+impl UpstreamHeader {
+    /// `Authorization: Basic` for `user:password`.
+    pub fn basic_auth(credentials: &str) -> Result<Self> {
+        if !credentials.contains(':') {
+            bail!("Expected USER:PASSWORD");
+        }
+        let token = base64::engine::general_purpose::STANDARD.encode(credentials);
+        format!("Authorization: Basic {token}").parse()
+    }
+}
+
+impl std::str::FromStr for UpstreamHeader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, value)) = s.split_once(':') else {
+            bail!("Expected 'Name: value', got {s:?}");
+        };
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("Invalid header name {:?}", name.trim()))?;
+        let mut value = HeaderValue::from_str(value.trim())
+            .with_context(|| format!("Invalid value for header {name}"))?;
+        value.set_sensitive(true);
+        Ok(Self { name, value })
+    }
+}
+
+impl TryFrom<String> for UpstreamHeader {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl Serialize for UpstreamHeader {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}: [redacted]", self.name))
+    }
+}
+
+impl std::fmt::Debug for UpstreamHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: [redacted]", self.name)
+    }
+}
+
+/// Answers upstream lookups for `host:port` with a fixed address, like
+/// curl's `--resolve`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub port: u16,
+    pub addr: IpAddr,
+}
+
+impl std::str::FromStr for ResolveOverride {
+    type Err = anyhow::Error;
+
+    /// Parses `host:port:addr`; IPv6 addresses may be bracketed.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(host), Some(port), Some(addr)) = (parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Expected HOST:PORT:ADDR, got {s}");
+        };
+        if host.is_empty() {
+            bail!("Missing host in {s}");
+        }
+        let port = port
+            .parse()
+            .with_context(|| format!("Invalid port in {s}"))?;
+        let addr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .with_context(|| format!("Invalid address in {s}"))?;
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            port,
+            addr,
+        })
+    }
+}
+
+/// What the connector knows about the connection being resolved.
+struct Lookup {
+    port: u16,
+    duration: Mutex<Option<Duration>>,
+}
+
+tokio::task_local! {
+    static LOOKUP: Arc<Lookup>;
+}
+
+/// Resolves upstream host names, applying `--resolve` overrides and caching
+/// answers for the configured TTL. A TTL of zero resolves every new
+/// connection, as hyper does by default.
+#[derive(Clone)]
+pub struct UpstreamResolver {
+    overrides: Arc<Vec<ResolveOverride>>,
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, CachedAddrs>>>,
+}
+
+/// Addresses of a host and when they were resolved.
+type CachedAddrs = (Vec<IpAddr>, Instant);
+
+impl UpstreamResolver {
+    pub fn new(overrides: Vec<ResolveOverride>, ttl: Duration) -> Self {
+        Self {
+            overrides: Arc::new(overrides),
+            ttl,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Addresses for `host` when connecting to `port`, if an override or a
+    /// fresh cache entry answers without a lookup.
+    fn lookup_local(&self, host: &str, port: Option<u16>) -> Option<Vec<IpAddr>> {
+        let host = host.to_ascii_lowercase();
+        let overridden: Vec<IpAddr> = self
+            .overrides
+            .iter()
+            .filter(|o| o.host == host && port.is_none_or(|port| o.port == port))
+            .map(|o| o.addr)
+            .collect();
+        if !overridden.is_empty() {
+            return Some(overridden);
+        }
+
+        let cache = self.cache.lock();
+        cache
+            .get(&host)
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(addrs, _)| addrs.clone())
+    }
+}
+
+impl Service<Name> for UpstreamResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let lookup = LOOKUP.try_with(Arc::clone).ok();
+        let resolver = self.clone();
+        Box::pin(async move {
+            let started = Instant::now();
+            let host = name.as_str().to_string();
+            let addrs = match resolver.lookup_local(&host, lookup.as_ref().map(|l| l.port)) {
+                Some(addrs) => addrs,
+                None => {
+                    let addrs: Vec<IpAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                        .await?
+                        .map(|addr| addr.ip())
+                        .collect();
+                    if !resolver.ttl.is_zero() {
+                        resolver
+                            .cache
+                            .lock()
+                            .insert(host.to_ascii_lowercase(), (addrs.clone(), Instant::now()));
+                    }
+                    addrs
+                }
+            };
+            if let Some(lookup) = lookup {
+                *lookup.duration.lock() = Some(started.elapsed());
+            }
+            // The connector fills in the port
+            Ok(addrs
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, 0))
+                .collect::<Vec<_>>()
+                .into_iter())
+        })
+    }
+}
@@ -0,0 +1,178 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the managed command's resource usage is sampled.
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default number of samples kept, five minutes at the default interval.
+pub const DEFAULT_MAX_SAMPLES: usize = 300;
+
+/// CPU and memory use of the managed command and everything in its process
+/// group at one point in time.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceUsage {
+    pub timestamp: u64,
+    /// Percent of one core since the previous sample; above 100 when
+    /// several cores are busy.
+    pub cpu_percent: f64,
+    /// Resident memory in bytes.
+    pub memory_bytes: u64,
+    pub processes: usize,
+}
+
+/// Totals read from the OS for a process group.
+#[derive(Debug, Clone, Copy)]
+pub struct GroupUsage {
+    pub cpu_time: Duration,
+    pub memory_bytes: u64,
+    pub processes: usize,
+}
+
+/// Recent resource usage samples of the managed command.
+pub struct UsageHistory {
+    samples: VecDeque<ResourceUsage>,
+    max_samples: usize,
+    interval: Duration,
+    /// The previous reading, to turn CPU time into a rate.
+    last: Option<(u32, Instant, Duration)>,
+}
+
+impl UsageHistory {
+    pub fn new(max_samples: usize, interval: Duration) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(max_samples),
+            max_samples,
+            interval,
+            last: None,
+        }
+    }
+
+    /// Reads the usage of the process group led by `pid`, at most once per
+    /// interval. Pass `None` while nothing is running.
+    pub fn sample(&mut self, pid: Option<u32>) {
+        let Some(pid) = pid else {
+            self.last = None;
+            return;
+        };
+        let now = Instant::now();
+        if let Some((last_pid, at, _)) = self.last {
+            if last_pid == pid && now.duration_since(at) < self.interval {
+                return;
+            }
+        }
+        let Some(usage) = read_group(pid) else {
+            self.last = None;
+            return;
+        };
+
+        // The first reading of a process only sets the baseline
+        if let Some((last_pid, at, cpu_time)) = self.last {
+            if last_pid == pid {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                let busy = usage.cpu_time.saturating_sub(cpu_time).as_secs_f64();
+                if self.samples.len() >= self.max_samples {
+                    self.samples.pop_front();
+                }
+                self.samples.push_back(ResourceUsage {
+                    timestamp: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u64,
+                    cpu_percent: if elapsed > 0.0 {
+                        busy / elapsed * 100.0
+                    } else {
+                        0.0
+                    },
+                    memory_bytes: usage.memory_bytes,
+                    processes: usage.processes,
+                });
+            }
+        }
+        self.last = Some((pid, now, usage.cpu_time));
+    }
+
+    /// The latest sample, unless the process it belongs to is gone.
+    pub fn current(&self) -> Option<ResourceUsage> {
+        self.last.and(self.samples.back().cloned())
+    }
+
+    /// Samples taken after `since` (a Unix timestamp in milliseconds).
+    pub fn get(&self, since: Option<u64>) -> Vec<ResourceUsage> {
+        let since = since.unwrap_or(0);
+        self.samples
+            .iter()
+            .filter(|sample| sample.timestamp > since)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for UsageHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SAMPLES, DEFAULT_SAMPLE_INTERVAL)
+    }
+}
+
+/// Sums CPU time and resident memory over the processes in group `pgid`.
+#[cfg(target_os = "linux")]
+pub fn read_group(pgid: u32) -> Option<GroupUsage> {
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+
+    let mut ticks = 0;
+    let mut pages = 0;
+    let mut processes = 0;
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        // The command name is in parentheses and may itself contain spaces,
+        // so count fields from the closing one: state is field 3 of stat(5)
+        let Some(end) = stat.rfind(')') else {
+            continue;
+        };
+        let fields: Vec<&str> = stat[end + 1..].split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3).and_then(|f| f.parse::<u64>().ok());
+        // Exited processes linger as zombies until reaped
+        if field(5) != Some(pgid as u64) || fields.first() == Some(&"Z") {
+            continue;
+        }
+        ticks += field(14).unwrap_or(0) + field(15).unwrap_or(0);
+        pages += field(24).unwrap_or(0);
+        processes += 1;
+    }
+
+    (processes > 0).then(|| GroupUsage {
+        cpu_time: Duration::from_secs_f64(ticks as f64 / ticks_per_second as f64),
+        memory_bytes: pages * page_size,
+        processes,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_group(_pgid: u32) -> Option<GroupUsage> {
+    None
+}
+
+/// Resident memory of this process, in bytes.
+#[cfg(target_os = "linux")]
+pub fn read_own_rss() -> Option<u64> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * page_size)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_own_rss() -> Option<u64> {
+    None
+}
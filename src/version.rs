@@ -0,0 +1,61 @@
+use serde::Serialize;
+
+/// Bumped when the admin API changes in a way clients must adapt to.
+pub const ADMIN_API_VERSION: u32 = 1;
+
+/// Body of `GET /_proxy/api/version`: what this binary is and what it can
+/// do, for the web interface and tools to adapt to.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// The commit built, when built from a git checkout.
+    pub git_hash: Option<&'static str>,
+    pub admin_api_version: u32,
+    /// The cargo features compiled in.
+    pub features: Vec<&'static str>,
+    /// Admin API and proxy functions available in this build.
+    pub capabilities: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let mut features = Vec::new();
+        let mut capabilities = vec![
+            "export-har",
+            "export-hurl",
+            "export-jsonl",
+            "export-k6",
+            "export-mitmproxy",
+            "forward-proxy",
+            "health",
+            "inflight",
+            "offline",
+            "probes",
+            "search",
+            "timeline",
+        ];
+        if cfg!(feature = "ui") {
+            features.push("ui");
+            capabilities.push("web-ui");
+        }
+        if cfg!(feature = "tls") {
+            features.push("tls");
+            capabilities.extend(["https-upstream", "client-certificates", "mitm"]);
+        }
+        if cfg!(feature = "decoders") {
+            features.push("decoders");
+            capabilities.extend(["decoded-sizes", "compressed-responses"]);
+        }
+        capabilities.sort_unstable();
+
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("GIT_HASH"),
+            admin_api_version: ADMIN_API_VERSION,
+            features,
+            capabilities,
+        }
+    }
+}
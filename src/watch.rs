@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info};
+
+use crate::process::ProcessManager;
+
+/// Paths that change constantly during development and never warrant a restart.
+pub const DEFAULT_IGNORES: &[&str] = &[
+    "**/.git/**",
+    "**/node_modules/**",
+    "**/target/**",
+    "*.swp",
+    "*~",
+];
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub paths: Vec<PathBuf>,
+    /// Glob patterns; ones without a `/` match file names anywhere.
+    pub ignore: Vec<String>,
+    pub debounce: Duration,
+}
+
+/// Decides which changed paths are ignored.
+pub struct WatchFilter {
+    paths: GlobSet,
+    names: GlobSet,
+}
+
+impl WatchFilter {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let mut paths = GlobSetBuilder::new();
+        let mut names = GlobSetBuilder::new();
+        for pattern in DEFAULT_IGNORES
+            .iter()
+            .copied()
+            .chain(patterns.iter().map(String::as_str))
+        {
+            let glob =
+                Glob::new(pattern).with_context(|| format!("Invalid ignore pattern: {pattern}"))?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        Ok(Self {
+            paths: paths.build()?,
+            names: names.build()?,
+        })
+    }
+
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let relative = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+            .unwrap_or_else(|| path.to_path_buf());
+        self.paths.is_match(&relative)
+            || self.paths.is_match(path)
+            || path
+                .file_name()
+                .is_some_and(|name| self.names.is_match(name))
+    }
+}
+
+/// Restarts the managed command whenever watched files change. Watching stops
+/// when this is dropped.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn start(process: ProcessManager, options: WatchOptions) -> Result<Self> {
+        let filter = WatchFilter::new(&options.ignore)?;
+        let (sender, mut receiver) = mpsc::unbounded_channel::<PathBuf>();
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            if let Some(changed) = event.paths.into_iter().find(|p| !filter.is_ignored(p)) {
+                let _ = sender.send(changed);
+            }
+        })
+        .context("Failed to create file watcher")?;
+        for path in &options.paths {
+            watcher
+                .watch(path, RecursiveMode::Recursive)
+                .with_context(|| format!("Failed to watch {}", path.display()))?;
+        }
+        info!("Watching {:?} for changes", options.paths);
+
+        // Ends when the watcher, and with it the sender, is dropped
+        tokio::spawn(async move {
+            while let Some(changed) = receiver.recv().await {
+                // Wait for the burst of events from a save or checkout to settle
+                loop {
+                    match tokio::time::timeout(options.debounce, receiver.recv()).await {
+                        Ok(Some(_)) => {}
+                        Ok(None) => return,
+                        Err(_) => break,
+                    }
+                }
+
+                if process.is_stopped() {
+                    debug!("{} changed, but the process was stopped", changed.display());
+                    continue;
+                }
+                info!("{} changed, restarting upstream process", changed.display());
+                if let Err(e) = process.restart().await {
+                    error!("Failed to restart subprocess: {}", e);
+                }
+            }
+        });
+
+        Ok(Self { _watcher: watcher })
+    }
+}
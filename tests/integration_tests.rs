@@ -1,5 +1,9 @@
-use debug_proxy::{DebugProxy, ProxyConfig, RequestRecorder, SharedConfig};
+use debug_proxy::{
+    DebugProxy, ProcessManager, ProxyConfig, RequestRecorder, SchemaAssertion, SchemaAssertionSet,
+    SchemaAssertions, Services, SharedConfig,
+};
 use reqwest::Client;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
 
@@ -16,6 +20,7 @@ async fn test_proxy_basic_functionality() {
         max_body_size: 1024,
         truncate_body_at: 256,
         access_token: "test-token".to_string(),
+        ..Default::default()
     };
 
     let shared_config = SharedConfig::new(config);
@@ -193,23 +198,4606 @@ async fn test_configuration_updates() {
     proxy_server.abort();
 }
 
-async fn start_test_server(port: u16) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async move {
-        use hyper::service::{make_service_fn, service_fn};
-        use hyper::{Body, Request, Response, Server};
-        use std::convert::Infallible;
+#[tokio::test]
+async fn test_strict_schema_assertions() {
+    let upstream_server = start_test_server(3005).await;
 
-        let make_svc = make_service_fn(|_conn| async {
-            Ok::<_, Infallible>(service_fn(|_req: Request<Body>| async {
-                Ok::<_, Infallible>(Response::new(Body::from("Hello from test server")))
-            }))
-        });
+    let shared_config = SharedConfig::default();
+    let recorder = RequestRecorder::new(10);
+    let assertions = SchemaAssertions::new();
+    assertions.replace(SchemaAssertionSet {
+        strict: true,
+        routes: vec![SchemaAssertion {
+            path: "/*".to_string(),
+            method: None,
+            request: None,
+            response: Some(serde_json::json!({ "type": "object" })),
+        }],
+    });
+    let proxy = DebugProxy::new(
+        shared_config.clone(),
+        recorder.clone(),
+        "127.0.0.1:3005".to_string(),
+    )
+    .with_schema_assertions(assertions.clone());
 
-        let addr = ([127, 0, 0, 1], port).into();
-        let server = Server::bind(&addr).serve(make_svc);
+    let proxy_server = start_proxy_server(proxy, 8085).await;
 
-        if let Err(e) = server.await {
-            eprintln!("Test server error: {e}");
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    // The plain-text upstream response is not a JSON object
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8085/anything")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 502);
+
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].violations.len(), 1);
+    // The 502 sent is recorded, not the upstream's response
+    assert_eq!(transactions[0].response.as_ref().unwrap().status, 502);
+    assert!(transactions[0].error.is_some());
+
+    // Bodiless responses have nothing to validate
+    let response = client
+        .head("http://localhost:8085/anything")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    // Non-strict mode records the violation but passes the response through
+    assertions.set_strict(false);
+    let response = client
+        .get("http://localhost:8085/anything")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    // Violations are listed by the admin API
+    let token = shared_config.get_access_token();
+    let response = client
+        .get(format!(
+            "http://localhost:8085/_proxy/api/violations?token={token}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let violations: serde_json::Value = response.json().await.expect("Failed to parse JSON");
+    assert_eq!(violations.as_array().unwrap().len(), 2);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_send_endpoint() {
+    let upstream_server = start_test_server(3006).await;
+
+    let shared_config = SharedConfig::default();
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        shared_config.clone(),
+        recorder.clone(),
+        "127.0.0.1:3006".to_string(),
+    );
+
+    let proxy_server = start_proxy_server(proxy, 8086).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let token = shared_config.get_access_token();
+    let send_url = format!("http://localhost:8086/_proxy/api/send?token={token}");
+    let response = client
+        .post(&send_url)
+        .json(&serde_json::json!({
+            "method": "POST",
+            "path": "/items?draft=true",
+            "headers": [["content-type", "application/json"]],
+            "body": "{\"name\": \"widget\"}",
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    let sent: serde_json::Value = response.json().await.expect("Failed to parse JSON");
+    assert_eq!(sent["status"], 200);
+
+    // The composed request is recorded like any proxied one
+    let transaction = recorder
+        .get_transaction(sent["id"].as_str().unwrap())
+        .expect("Sent request was not recorded");
+    assert_eq!(transaction.request.method, "POST");
+    assert_eq!(transaction.request.path, "/items?draft=true");
+    assert_eq!(transaction.request.body.preview, "{\"name\": \"widget\"}");
+    assert_eq!(
+        transaction.response.unwrap().body.preview,
+        "Hello from test server"
+    );
+
+    // Admin paths cannot be targeted
+    let response = client
+        .post(&send_url)
+        .json(&serde_json::json!({ "path": "/_proxy/api/logs" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 400);
+    assert_eq!(recorder.get_transactions().len(), 1);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_process_logs_endpoints() {
+    use debug_proxy::process::LogStream;
+    use debug_proxy::ProcessLogs;
+
+    let logs = ProcessLogs::default();
+    logs.push(LogStream::Stdout, "listening on 3000".to_string());
+    logs.push(LogStream::Stderr, "deprecation warning".to_string());
+
+    let shared_config = SharedConfig::default();
+    let proxy = DebugProxy::new(
+        shared_config.clone(),
+        RequestRecorder::new(10),
+        "127.0.0.1:3007".to_string(),
+    )
+    .with_process_logs(logs.clone());
+
+    let proxy_server = start_proxy_server(proxy, 8087).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let token = shared_config.get_access_token();
+    let response = client
+        .get(format!(
+            "http://localhost:8087/_proxy/api/process/logs?token={token}&since=1"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let lines: serde_json::Value = response.json().await.expect("Failed to parse JSON");
+    assert_eq!(lines.as_array().unwrap().len(), 1);
+    assert_eq!(lines[0]["stream"], "stderr");
+    assert_eq!(lines[0]["line"], "deprecation warning");
+
+    // The stream replays the backlog and then follows new lines
+    let mut response = client
+        .get(format!(
+            "http://localhost:8087/_proxy/api/process/logs/stream?token={token}&since=1"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let chunk = response.chunk().await.unwrap().unwrap();
+    let event = String::from_utf8_lossy(&chunk);
+    assert!(event.starts_with("id: 2\nevent: stderr\ndata: "));
+
+    logs.push(LogStream::Stdout, "GET /health 200".to_string());
+    let chunk = tokio::time::timeout(Duration::from_secs(2), response.chunk())
+        .await
+        .expect("Timed out waiting for live log line")
+        .unwrap()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&chunk).contains("GET /health 200"));
+
+    proxy_server.abort();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_process_control_endpoints() {
+    let process_manager = ProcessManager::new(vec!["sleep".to_string(), "30".to_string()]);
+    process_manager
+        .start()
+        .await
+        .expect("Failed to start process");
+
+    let shared_config = SharedConfig::default();
+    let proxy = DebugProxy::new(
+        shared_config.clone(),
+        RequestRecorder::new(10),
+        "127.0.0.1:3008".to_string(),
+    )
+    .with_process_manager(process_manager.clone());
+
+    let proxy_server = start_proxy_server(proxy, 8088).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let token = shared_config.get_access_token();
+    let url = |path: &str| format!("http://localhost:8088/_proxy/api/process{path}?token={token}");
+
+    let status: serde_json::Value = client
+        .get(url(""))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(status["running"], true);
+    assert_eq!(status["restart_count"], 0);
+    let first_pid = status["pid"].as_u64().unwrap();
+
+    let status: serde_json::Value = client
+        .post(url("/restart"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(status["running"], true);
+    assert_eq!(status["restart_count"], 1);
+    assert_ne!(status["pid"].as_u64().unwrap(), first_pid);
+
+    let status: serde_json::Value = client
+        .post(url("/stop"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(status["running"], false);
+    assert_eq!(status["stopped"], true);
+    assert!(status["pid"].is_null());
+    assert!(!process_manager.is_running());
+
+    let status: serde_json::Value = client
+        .post(url("/start"))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(status["running"], true);
+    assert_eq!(status["stopped"], false);
+
+    let response = client
+        .post(url("/signal"))
+        .json(&serde_json::json!({ "signal": "SIGUSR1" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .post(url("/signal"))
+        .json(&serde_json::json!({ "signal": "SIGNOPE" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 400);
+
+    let stats: serde_json::Value = client
+        .get(format!(
+            "http://localhost:8088/_proxy/api/stats?token={token}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(stats["requests"], 0);
+    assert!(stats["process_usage"].is_array());
+
+    let response = client
+        .post(url("/explode"))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 404);
+
+    process_manager
+        .stop()
+        .await
+        .expect("Failed to stop process");
+    proxy_server.abort();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_services_routing() {
+    let api_server = start_test_server(3009).await;
+    let web_server = start_slow_test_server(3010, Duration::from_millis(0)).await;
+
+    let services = Services::from_config(
+        serde_json::from_value(serde_json::json!([
+            { "name": "web", "prefix": "/", "upstream": "127.0.0.1:3010", "command": ["sleep", "30"] },
+            { "name": "api", "prefix": "/api", "upstream": "127.0.0.1:3009", "command": ["sleep", "30"] }
+        ]))
+        .expect("Failed to parse services"),
+    )
+    .expect("Failed to build services");
+    services
+        .start_all()
+        .await
+        .expect("Failed to start services");
+
+    let shared_config = SharedConfig::default();
+    let proxy = DebugProxy::new(
+        shared_config.clone(),
+        RequestRecorder::new(10),
+        "127.0.0.1:3010".to_string(),
+    )
+    .with_services(services.clone());
+
+    let proxy_server = start_proxy_server(proxy, 8089).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let token = shared_config.get_access_token();
+
+    let body = client
+        .get("http://localhost:8089/api/users")
+        .send()
+        .await
+        .expect("Failed to send request")
+        .text()
+        .await
+        .expect("Failed to read body");
+    assert_eq!(body, "Hello from test server");
+
+    let body = client
+        .get("http://localhost:8089/apiary")
+        .send()
+        .await
+        .expect("Failed to send request")
+        .text()
+        .await
+        .expect("Failed to read body");
+    assert_eq!(body, "Slow response");
+
+    let status: serde_json::Value = client
+        .get(format!(
+            "http://localhost:8089/_proxy/api/services?token={token}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(status.as_array().unwrap().len(), 2);
+    assert_eq!(status[1]["name"], "api");
+    assert_eq!(status[1]["process"]["running"], true);
+
+    let status: serde_json::Value = client
+        .post(format!(
+            "http://localhost:8089/_proxy/api/services/api/stop?token={token}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(status["stopped"], true);
+    assert!(!services.get("api").unwrap().process.is_running());
+    assert!(services.get("web").unwrap().process.is_running());
+
+    let response = client
+        .get(format!(
+            "http://localhost:8089/_proxy/api/services/nope?token={token}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 404);
+
+    services.stop_all().await;
+    api_server.abort();
+    web_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_logs_endpoint_streams_history() {
+    let upstream_server = start_test_server(3011).await;
+
+    let config = ProxyConfig {
+        truncate_body_at: 4096,
+        ..Default::default()
+    };
+    let shared_config = SharedConfig::new(config);
+    let recorder = RequestRecorder::new(100).with_background_writer(16);
+    let proxy = DebugProxy::new(
+        shared_config.clone(),
+        recorder.clone(),
+        "127.0.0.1:3011".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8090).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    // Enough recorded bodies to span several streamed chunks
+    let client = Client::new();
+    let body = "x".repeat(4096);
+    for i in 0..40 {
+        let response = client
+            .post(format!("http://localhost:8090/items/{i}"))
+            .body(body.clone())
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), 200);
+    }
+
+    let token = shared_config.get_access_token();
+    let response = client
+        .get(format!(
+            "http://localhost:8090/_proxy/api/logs?token={token}"
+        ))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    let logs: Vec<serde_json::Value> = response.json().await.expect("Failed to parse JSON");
+    assert_eq!(logs.len(), 40);
+    assert_eq!(logs[0]["request"]["path"], "/items/0");
+    assert_eq!(logs[39]["request"]["path"], "/items/39");
+    assert_eq!(logs[39]["request"]["body"]["preview"], body);
+    assert_eq!(logs[39]["response"]["status"], 200);
+
+    // Unchanged polls are answered with 304 until something new is recorded
+    let logs_url = format!("http://localhost:8090/_proxy/api/logs?token={token}");
+    let etag = response_etag(&client, &logs_url).await;
+    let response = client
+        .get(&logs_url)
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 304);
+    client
+        .get("http://localhost:8090/items/new")
+        .send()
+        .await
+        .expect("Failed to send request");
+    let response = client
+        .get(&logs_url)
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    // Compressed on request, and still the same history
+    #[cfg(feature = "decoders")]
+    {
+        let response = client
+            .get(&logs_url)
+            .header("accept-encoding", "br;q=1, gzip;q=0.8")
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        let compressed = response.bytes().await.expect("Failed to read body");
+        let mut decoder = flate2::read::GzDecoder::new(&compressed[..]);
+        let mut json = String::new();
+        std::io::Read::read_to_string(&mut decoder, &mut json).expect("Invalid gzip body");
+        let logs: Vec<serde_json::Value> =
+            serde_json::from_str(&json).expect("Failed to parse JSON");
+        assert_eq!(logs.len(), 41);
+        assert!(compressed.len() < json.len() / 10);
+    }
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_upstream_connection_reuse() {
+    let upstream_server = start_test_server(3012).await;
+
+    let shared_config = SharedConfig::default();
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        shared_config.clone(),
+        recorder.clone(),
+        "127.0.0.1:3012".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8091).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let send = |path: &'static str| {
+        let client = client.clone();
+        async move {
+            client
+                .get(format!("http://localhost:8091{path}"))
+                .send()
+                .await
+                .expect("Failed to send request")
+        }
+    };
+    send("/first").await;
+    send("/second").await;
+
+    let transactions = recorder.get_transactions();
+    let first = transactions[0]
+        .connection
+        .clone()
+        .expect("No connection recorded");
+    let second = transactions[1]
+        .connection
+        .clone()
+        .expect("No connection recorded");
+    assert!(!first.reused);
+    assert!(second.reused);
+    assert_eq!(first.id, second.id);
+
+    // Without keep-alive every request gets a connection of its own
+    let token = shared_config.get_access_token();
+    let response = client
+        .post(format!(
+            "http://localhost:8091/_proxy/api/config?token={token}"
+        ))
+        .json(&serde_json::json!({ "http1_keep_alive": false }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    send("/third").await;
+    send("/fourth").await;
+
+    let transactions = recorder.get_transactions();
+    let third = transactions[2].connection.clone().unwrap();
+    let fourth = transactions[3].connection.clone().unwrap();
+    assert!(!third.reused && !fourth.reused);
+    assert_ne!(third.id, fourth.id);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_resolve_override() {
+    let upstream_server = start_test_server(3013).await;
+
+    let config = ProxyConfig {
+        resolve: vec!["upstream.invalid:3013:127.0.0.1".parse().unwrap()],
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "upstream.invalid:3013".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8092).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let response = Client::new()
+        .get("http://localhost:8092/resolved")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+
+    let connection = recorder.get_transactions()[0]
+        .connection
+        .clone()
+        .expect("No connection recorded");
+    assert!(connection.dns_ms.is_some());
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_dual_stack_listener() {
+    let upstream_server = start_test_server(3016).await;
+
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3016".to_string(),
+    );
+    let proxy_server = tokio::spawn(async move {
+        let addr = (std::net::Ipv6Addr::UNSPECIFIED, 8095).into();
+        if let Err(e) = proxy.start_server(addr).await {
+            eprintln!("Proxy server error: {e}");
+        }
+    });
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    for url in ["http://127.0.0.1:8095/v4", "http://[::1]:8095/v6"] {
+        let response = Client::new()
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), 200);
+    }
+    assert_eq!(recorder.get_transactions().len(), 2);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_multiple_listeners() {
+    let upstream_server = start_test_server(3017).await;
+
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3017".to_string(),
+    );
+    let listen_addrs = [
+        "127.0.0.1:8096".parse().unwrap(),
+        "[::1]:8097".parse().unwrap(),
+    ];
+    let proxy_server = tokio::spawn(async move {
+        if let Err(e) = proxy.start_servers(&listen_addrs).await {
+            eprintln!("Proxy server error: {e}");
+        }
+    });
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    for url in ["http://127.0.0.1:8096/first", "http://[::1]:8097/second"] {
+        let response = Client::new()
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), 200);
+    }
+
+    let mut transactions = recorder.get_transactions();
+    transactions.sort_by_key(|t| t.request.path.clone());
+    assert_eq!(
+        transactions[0].request.listener.as_deref(),
+        Some("127.0.0.1:8096")
+    );
+    assert_eq!(
+        transactions[1].request.listener.as_deref(),
+        Some("[::1]:8097")
+    );
+    assert!(transactions[0]
+        .request
+        .client_addr
+        .starts_with("127.0.0.1:"));
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_port_auto() {
+    use debug_proxy::proxy::bind_listeners;
+
+    let upstream_server = start_test_server(3018).await;
+    let taken = std::net::TcpListener::bind("127.0.0.1:8098").unwrap();
+
+    let requested = ["127.0.0.1:8098".parse().unwrap()];
+    assert!(bind_listeners(&requested, false).is_err());
+    let listeners = bind_listeners(&requested, true).unwrap();
+    let port = listeners[0].local_addr().unwrap().port();
+    assert!(port > 8098);
+
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        RequestRecorder::new(10),
+        "127.0.0.1:3018".to_string(),
+    );
+    let proxy_server = tokio::spawn(async move {
+        if let Err(e) = proxy.serve(listeners).await {
+            eprintln!("Proxy server error: {e}");
+        }
+    });
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let response = Client::new()
+        .get(format!("http://127.0.0.1:{port}/moved"))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    drop(taken);
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn test_forward_proxy() {
+    use debug_proxy::forward::{CertificateAuthority, ForwardProxy};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream_server = start_test_server(3019).await;
+    let target_server = start_test_server(3020).await;
+
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3019".to_string(),
+    )
+    .with_forward_proxy(ForwardProxy::default());
+    let proxy_server = start_proxy_server(proxy, 8099).await;
+
+    let ca_dir = tempfile::tempdir().unwrap();
+    let ca = Arc::new(CertificateAuthority::load_or_create(ca_dir.path()).unwrap());
+    let mitm_recorder = RequestRecorder::new(10);
+    let mitm_proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        mitm_recorder.clone(),
+        "127.0.0.1:3019".to_string(),
+    )
+    .with_forward_proxy(ForwardProxy {
+        mitm: Some(Arc::clone(&ca)),
+    });
+    let mitm_proxy_server = start_proxy_server(mitm_proxy, 8100).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    // Absolute-form requests go to the host they name
+    let client = Client::builder()
+        .proxy(reqwest::Proxy::http("http://127.0.0.1:8099").unwrap())
+        .build()
+        .unwrap();
+    let response = client
+        .get("http://127.0.0.1:3020/absolute?page=2")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions[0].request.path, "/absolute?page=2");
+    assert_eq!(
+        transactions[0].request.url.as_deref(),
+        Some("http://127.0.0.1:3020/absolute?page=2")
+    );
+    assert_eq!(
+        transactions[0].request.query,
+        vec![("page".to_string(), "2".to_string())]
+    );
+    assert_eq!(
+        transactions[0].request.target.as_deref(),
+        Some("http://127.0.0.1:3020")
+    );
+
+    // Without interception CONNECT tunnels are passed through
+    let mut tunnel = tokio::net::TcpStream::connect("127.0.0.1:8099")
+        .await
+        .unwrap();
+    tunnel
+        .write_all(b"CONNECT 127.0.0.1:3020 HTTP/1.1\r\nHost: 127.0.0.1:3020\r\n\r\n")
+        .await
+        .unwrap();
+    let mut reply = Vec::new();
+    while !reply.ends_with(b"\r\n\r\n") {
+        reply.push(tunnel.read_u8().await.unwrap());
+    }
+    assert!(reply.starts_with(b"HTTP/1.1 200"));
+    tunnel
+        .write_all(b"GET /tunneled HTTP/1.1\r\nHost: 127.0.0.1:3020\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut tunneled = String::new();
+    tunnel.read_to_string(&mut tunneled).await.unwrap();
+    assert!(tunneled.contains("Hello from test server"));
+    let transactions = recorder.get_transactions();
+    assert!(transactions
+        .iter()
+        .any(|t| t.request.method == "CONNECT" && t.request.path == "127.0.0.1:3020"));
+
+    // With interception the HTTPS request inside the tunnel is recorded.
+    // Nothing serves HTTPS on 3021, so the upstream request fails.
+    let client = Client::builder()
+        .proxy(reqwest::Proxy::all("http://127.0.0.1:8100").unwrap())
+        .add_root_certificate(reqwest::Certificate::from_pem(ca.cert_pem().as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    let response = client
+        .get("https://127.0.0.1:3021/secret")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 502);
+    let transactions = mitm_recorder.get_transactions();
+    assert_eq!(transactions[0].request.path, "/secret");
+    assert_eq!(
+        transactions[0].request.target.as_deref(),
+        Some("https://127.0.0.1:3021")
+    );
+
+    upstream_server.abort();
+    target_server.abort();
+    proxy_server.abort();
+    mitm_proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_debug_headers() {
+    let upstream_server = start_test_server(3028).await;
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        debug_headers: true,
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3028".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8104).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8104/test")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    let id = response.headers()["x-debug-proxy-id"].to_str().unwrap();
+    assert_eq!(id, recorder.get_transactions()[0].request.id);
+    let timing = response.headers()["server-timing"].to_str().unwrap();
+    for metric in ["queue;dur=", "upstream;dur=", "proxy;dur="] {
+        assert!(timing.contains(metric), "{timing}");
+    }
+
+    let response = client
+        .post("http://localhost:8104/_proxy/api/config?token=test-token")
+        .json(&serde_json::json!({ "debug_headers": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .get("http://localhost:8104/test")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(response.headers().get("x-debug-proxy-id").is_none());
+    assert!(response.headers().get("server-timing").is_none());
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_cors_mode() {
+    let upstream_server = start_test_server(3029).await;
+
+    let config = ProxyConfig {
+        cors: Some("http://localhost:5173".parse().unwrap()),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3029".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8105).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .request(reqwest::Method::OPTIONS, "http://localhost:8105/api/items")
+        .header("origin", "http://localhost:5173")
+        .header("access-control-request-method", "POST")
+        .header("access-control-request-headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to send preflight");
+    assert_eq!(response.status(), 204);
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        "http://localhost:5173"
+    );
+    assert_eq!(response.headers()["access-control-allow-methods"], "POST");
+
+    let response = client
+        .post("http://localhost:8105/api/items")
+        .header("origin", "http://localhost:5173")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        "http://localhost:5173"
+    );
+    assert_eq!(
+        response.headers()["access-control-allow-credentials"],
+        "true"
+    );
+
+    // Both are recorded, the preflight with the proxy's own answer
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0].request.method, "OPTIONS");
+    assert_eq!(transactions[0].response.as_ref().unwrap().status, 204);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_expect_continue() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream_server = start_test_server(3030).await;
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3030".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8106).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    // The client holds the body back until the proxy asks for it
+    let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8106")
+        .await
+        .unwrap();
+    stream
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+              Expect: 100-continue\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut interim = [0u8; 25];
+    tokio::time::timeout(Duration::from_secs(2), stream.read_exact(&mut interim))
+        .await
+        .expect("No interim response")
+        .unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"hello").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].interim_responses, vec![100]);
+    assert_eq!(transactions[0].request.body.size, 5);
+
+    // Expectations other than 100-continue are refused
+    let response = Client::new()
+        .post("http://localhost:8106/upload")
+        .header("expect", "something-else")
+        .body("hello")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 417);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_http2_trailers() {
+    use hyper::body::HttpBody;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    // An h2c upstream that echoes the request trailer in its own trailers
+    let upstream_server = tokio::spawn(async {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|mut req: Request<Body>| async move {
+                while req.body_mut().data().await.is_some() {}
+                let checksum = req
+                    .body_mut()
+                    .trailers()
+                    .await
+                    .unwrap()
+                    .and_then(|trailers| trailers.get("x-checksum").cloned());
+                let (mut sender, body) = Body::channel();
+                tokio::spawn(async move {
+                    sender.send_data("pong".into()).await.unwrap();
+                    let mut trailers = http::HeaderMap::new();
+                    trailers.insert("grpc-status", "0".parse().unwrap());
+                    if let Some(checksum) = checksum {
+                        trailers.insert("x-request-checksum", checksum);
+                    }
+                    sender.send_trailers(trailers).await.unwrap();
+                });
+                Ok::<_, Infallible>(Response::new(body))
+            }))
+        });
+        Server::bind(&([127, 0, 0, 1], 3031).into())
+            .http2_only(true)
+            .serve(make_svc)
+            .await
+            .unwrap();
+    });
+    let plain_server = start_test_server(3032).await;
+
+    let recorder = RequestRecorder::new(10);
+    let config = ProxyConfig {
+        upstream_http2: true,
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3031".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8107).await;
+    let plain_proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        RequestRecorder::new(10),
+        "127.0.0.1:3032".to_string(),
+    );
+    let plain_proxy_server = start_proxy_server(plain_proxy, 8108).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = hyper::Client::builder()
+        .http2_only(true)
+        .build_http::<Body>();
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        sender.send_data("ping".into()).await.unwrap();
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("x-checksum", "abc".parse().unwrap());
+        sender.send_trailers(trailers).await.unwrap();
+    });
+    let request = Request::post("http://127.0.0.1:8107/echo")
+        .body(body)
+        .unwrap();
+    let mut response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), 200);
+    let mut received = Vec::new();
+    while let Some(chunk) = response.body_mut().data().await {
+        received.extend_from_slice(&chunk.unwrap());
+    }
+    assert_eq!(received, b"pong");
+    let trailers = response.body_mut().trailers().await.unwrap().unwrap();
+    assert_eq!(trailers["grpc-status"], "0");
+    assert_eq!(trailers["x-request-checksum"], "abc");
+
+    let transactions = recorder.get_transactions();
+    assert_eq!(
+        transactions[0].request.trailers,
+        vec![("x-checksum".to_string(), "abc".to_string())]
+    );
+    let response_trailers = &transactions[0].response.as_ref().unwrap().trailers;
+    assert!(response_trailers.contains(&("grpc-status".to_string(), "0".to_string())));
+
+    // HTTP/2 clients also reach HTTP/1.1 upstreams
+    let response = client
+        .get("http://127.0.0.1:8108/".parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    upstream_server.abort();
+    plain_server.abort();
+    proxy_server.abort();
+    plain_proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_truncated_response_recorded() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Promises 100 bytes, sends 10 and hangs up
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3033")
+        .await
+        .unwrap();
+    let upstream_server = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+                      X-Upstream: partial\r\nContent-Length: 100\r\n\r\n0123456789",
+                )
+                .await;
+        }
+    });
+
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3033".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8109).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let response = Client::new()
+        .get("http://localhost:8109/download")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 502);
+
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions.len(), 1);
+    let transaction = &transactions[0];
+    assert!(transaction.error.is_some());
+    let recorded = transaction.response.as_ref().expect("Response not kept");
+    assert_eq!(recorded.status, 200);
+    assert!(recorded.incomplete);
+    assert_eq!(recorded.body.size, 10);
+    assert!(recorded
+        .headers
+        .contains(&("x-upstream".to_string(), "partial".to_string())));
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_safe_mode() {
+    use debug_proxy::safe_mode::SafeMode;
+
+    let upstream_server = start_test_server(3034).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        safe_mode: SafeMode {
+            block_writes: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3034".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8110).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8110/users")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    let response = client
+        .delete("http://localhost:8110/users/1")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 403);
+
+    let transactions = recorder.get_transactions();
+    assert!(transactions[0].blocked.is_none());
+    assert_eq!(
+        transactions[1].blocked.as_deref(),
+        Some("DELETE requests are blocked")
+    );
+    assert_eq!(transactions[1].response.as_ref().unwrap().status, 403);
+
+    // Blocking can be turned off at runtime
+    client
+        .post("http://localhost:8110/_proxy/api/config?token=test-token")
+        .json(&serde_json::json!({ "safe_mode": false }))
+        .send()
+        .await
+        .unwrap();
+    let response = client
+        .delete("http://localhost:8110/users/1")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_route_timeout_override() {
+    use debug_proxy::config::RouteOverride;
+
+    let upstream_server = start_slow_test_server(3035, Duration::from_millis(300)).await;
+    let config = ProxyConfig {
+        upstream_timeout: Duration::from_millis(100),
+        routes: vec![RouteOverride {
+            path: "/export".to_string(),
+            method: None,
+            upstream_timeout_ms: Some(2000),
+            client_timeout_ms: None,
+            truncate_body_at: None,
+            client_identity: None,
+        }],
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3035".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8111).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8111/users")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 503);
+    let response = client
+        .get("http://localhost:8111/export")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_body_search() {
+    let upstream_server = start_test_server(3036).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3036".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8112).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    for body in [r#"{"order": "ORD-1234"}"#, r#"{"order": null}"#] {
+        client
+            .post("http://localhost:8112/orders")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to send request");
+    }
+
+    let search = |q: &str| {
+        client
+            .get("http://localhost:8112/_proxy/api/search")
+            .query(&[("q", q), ("token", "test-token")])
+            .send()
+    };
+    let hits: serde_json::Value = search(r"ORD-\d+")
+        .await
+        .expect("Failed to search")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    let hits = hits.as_array().unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0]["path"], "/orders");
+    assert_eq!(hits[0]["matches"][0]["part"], "request");
+    assert_eq!(
+        hits[0]["matches"][0]["context"][0],
+        r#"{"order": "ORD-1234"}"#
+    );
+
+    // Response bodies are searched too, newest first
+    let hits: serde_json::Value = search("Hello from")
+        .await
+        .expect("Failed to search")
+        .json()
+        .await
+        .expect("Failed to parse JSON");
+    assert_eq!(hits.as_array().unwrap().len(), 2);
+    assert_eq!(hits[1]["id"], recorder.get_transactions()[0].request.id);
+
+    let response = search("(unclosed").await.expect("Failed to search");
+    assert_eq!(response.status(), 400);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_snapshot_save_and_load() {
+    use debug_proxy::snapshot::Snapshot;
+
+    let upstream_server = start_test_server(3037).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3037".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8113).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    for path in ["/first", "/second"] {
+        client
+            .get(format!("http://localhost:8113{path}"))
+            .send()
+            .await
+            .expect("Failed to send request");
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("repro.json");
+    let snapshot_url = "http://localhost:8113/_proxy/api/snapshot?token=test-token";
+    let response = client
+        .post(snapshot_url)
+        .json(&serde_json::json!({ "path": path }))
+        .send()
+        .await
+        .expect("Failed to save snapshot");
+    assert_eq!(response.status(), 200);
+    let saved: serde_json::Value = response.json().await.expect("Failed to parse JSON");
+    assert_eq!(saved["transactions"], 2);
+
+    // Existing files are left alone
+    let response = client
+        .post(snapshot_url)
+        .json(&serde_json::json!({ "path": path }))
+        .send()
+        .await
+        .expect("Failed to save snapshot");
+    assert_eq!(response.status(), 500);
+
+    // A new recorder picks up where the old one left off, newest kept
+    let restored = RequestRecorder::new(1);
+    restored.restore(Snapshot::load(&path).unwrap().transactions);
+    let transactions = restored.get_transactions();
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].request.path, "/second");
+    assert_eq!(
+        transactions[0].response.as_ref().unwrap().body.preview,
+        "Hello from test server"
+    );
+    let id = &transactions[0].request.id;
+    assert!(restored.get_transaction(id).is_some());
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_capture_sessions() {
+    let upstream_server = start_test_server(3038).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3038".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8114).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let sessions_url = "http://localhost:8114/_proxy/api/sessions?token=test-token";
+    let control = |body: serde_json::Value| client.post(sessions_url).json(&body).send();
+    let get = |path: &'static str| client.get(format!("http://localhost:8114{path}")).send();
+
+    let response = control(serde_json::json!({ "action": "start", "name": "login-bug" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    get("/login").await.unwrap();
+    control(serde_json::json!({ "action": "stop" }))
+        .await
+        .unwrap();
+    get("/between").await.unwrap();
+    control(serde_json::json!({ "action": "start" }))
+        .await
+        .unwrap();
+    get("/retry").await.unwrap();
+
+    // Session names are unique
+    let response = control(serde_json::json!({ "action": "start", "name": "login-bug" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 409);
+
+    let transactions = recorder.get_transactions();
+    let sessions: Vec<_> = transactions
+        .iter()
+        .map(|t| t.request.session.as_deref())
+        .collect();
+    assert_eq!(sessions, [Some("login-bug"), None, Some("session-2")]);
+
+    let listed: serde_json::Value = client
+        .get(sessions_url)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed[0]["name"], "login-bug");
+    assert_eq!(listed[0]["transactions"], 1);
+    assert!(listed[0]["stopped_at"].is_u64());
+    assert!(listed[1]["stopped_at"].is_null());
+
+    let exported = client
+        .get("http://localhost:8114/_proxy/api/export?format=jsonl&session=login-bug&token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(exported.lines().count(), 1);
+    assert!(exported.contains("/login"));
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_response_transforms() {
+    let upstream_server = start_test_server(3039).await;
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3039".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8115).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let transforms_url = "http://localhost:8115/_proxy/api/transforms?token=test-token";
+    let response = client
+        .put(transforms_url)
+        .json(&serde_json::json!({
+            "rules": [r#"response.header.x-masked = "yes" when path matches "/api/*""#]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .post(transforms_url)
+        .json(&serde_json::json!({
+            "rule": r#"response.status = 418 when path == "/api/me""#
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .post(transforms_url)
+        .json(&serde_json::json!({ "rule": "response.status = teapot" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let listed: serde_json::Value = client
+        .get(transforms_url)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed["rules"].as_array().unwrap().len(), 2);
+
+    let response = client
+        .get("http://localhost:8115/api/me")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 418);
+    assert_eq!(response.headers()["x-masked"], "yes");
+    let response = client
+        .get("http://localhost:8115/other")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(!response.headers().contains_key("x-masked"));
+
+    // The history keeps what the upstream sent and which rules changed it
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions[0].response.as_ref().unwrap().status, 200);
+    assert_eq!(transactions[0].transforms.len(), 2);
+    assert!(transactions[1].transforms.is_empty());
+
+    let response = client.delete(transforms_url).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .get("http://localhost:8115/api/me")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_route_script() {
+    use debug_proxy::script::RouteScript;
+
+    let default_server = start_test_server(3040).await;
+    let tenant_server = start_test_server(3041).await;
+
+    let script = RouteScript::compile(
+        r#"
+        let tenant = request.headers["x-tenant"];
+        if tenant == "acme" { "127.0.0.1:3041" } else if tenant == "broken" { throw "unknown tenant" }
+        "#,
+    )
+    .unwrap();
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::default(),
+        recorder.clone(),
+        "127.0.0.1:3040".to_string(),
+    )
+    .with_route_script(script);
+    let proxy_server = start_proxy_server(proxy, 8116).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    for tenant in ["acme", "other", "broken"] {
+        let response = client
+            .get("http://localhost:8116/data")
+            .header("x-tenant", tenant)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    // A failing script falls back to the default upstream and is recorded
+    let transactions = recorder.get_transactions();
+    let targets: Vec<_> = transactions
+        .iter()
+        .map(|t| t.request.target.as_deref())
+        .collect();
+    assert_eq!(targets, [Some("http://127.0.0.1:3041"), None, None]);
+    assert!(transactions[1].route_script_error.is_none());
+    assert!(transactions[2]
+        .route_script_error
+        .as_deref()
+        .unwrap()
+        .contains("unknown tenant"));
+
+    default_server.abort();
+    tenant_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_proxy_overhead() {
+    let upstream_server = start_test_server(3042).await;
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        safe_mode: debug_proxy::safe_mode::SafeMode {
+            block_paths: vec!["/blocked".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3042".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8117).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    client
+        .post("http://localhost:8117/upload")
+        .body("x".repeat(4096))
+        .send()
+        .await
+        .unwrap();
+    client
+        .get("http://localhost:8117/blocked")
+        .send()
+        .await
+        .unwrap();
+
+    let transactions = recorder.get_transactions();
+    let proxied = transactions[0].overhead.unwrap();
+    assert!(proxied.upstream_us.is_some());
+    assert!(proxied.response_body_us.is_some());
+    assert_eq!(
+        proxied.proxy_us,
+        proxied.before_upstream_us + proxied.after_upstream_us
+    );
+
+    // Without an upstream all of the time is the proxy's
+    let blocked = transactions[1].overhead.unwrap();
+    assert!(blocked.upstream_us.is_none());
+    assert_eq!(blocked.proxy_us, blocked.before_upstream_us);
+
+    let stats: serde_json::Value = client
+        .get("http://localhost:8117/_proxy/api/stats?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(stats["proxy_overhead_us"]["max"].is_u64());
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn test_upstream_tls() {
+    use debug_proxy::upstream::{ClientIdentity, UpstreamCa};
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+
+    // A private CA signing the upstream's certificate and the proxy's
+    // client certificate
+    let dir = tempfile::tempdir().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new());
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params).unwrap();
+    let ca_file = dir.path().join("ca.pem");
+    std::fs::write(&ca_file, ca.serialize_pem().unwrap()).unwrap();
+    let server =
+        Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()])).unwrap();
+    let server_chain = vec![rustls::Certificate(
+        server.serialize_der_with_signer(&ca).unwrap(),
+    )];
+    let server_key = rustls::PrivateKey(server.serialize_private_key_der());
+    let client =
+        Certificate::from_params(CertificateParams::new(vec!["client".to_string()])).unwrap();
+    let (client_cert, client_key) = (dir.path().join("client.pem"), dir.path().join("key.pem"));
+    std::fs::write(&client_cert, client.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+    std::fs::write(&client_key, client.serialize_private_key_pem()).unwrap();
+
+    let tls_server = start_tls_test_server(
+        3043,
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(server_chain.clone(), server_key.clone())
+            .unwrap(),
+    )
+    .await;
+    let mut client_roots = rustls::RootCertStore::empty();
+    client_roots
+        .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+        .unwrap();
+    let mtls_server = start_tls_test_server(
+        3044,
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(
+                rustls::server::AllowAnyAuthenticatedClient::new(client_roots).boxed(),
+            )
+            .with_single_cert(server_chain, server_key)
+            .unwrap(),
+    )
+    .await;
+
+    let ca_for = |host: &str| format!("{host}={}", ca_file.display()).parse::<UpstreamCa>();
+    let identity = ClientIdentity::load(&client_cert, &client_key).unwrap();
+    let cases = [
+        // Upstream, CA bundles, client identity, expected status
+        ("https://localhost:3043", vec![], None, 502),
+        (
+            "https://localhost:3043",
+            vec![ca_for("example.com").unwrap()],
+            None,
+            502,
+        ),
+        (
+            "https://localhost:3043",
+            vec![ca_for("localhost").unwrap()],
+            None,
+            200,
+        ),
+        (
+            "https://localhost:3044",
+            vec![ca_for("localhost").unwrap()],
+            None,
+            502,
+        ),
+        (
+            "https://localhost:3044",
+            vec![ca_for("localhost").unwrap()],
+            Some(identity),
+            200,
+        ),
+    ];
+    let client = Client::new();
+    for (i, (upstream, upstream_ca, identity, status)) in cases.into_iter().enumerate() {
+        let config = ProxyConfig {
+            access_token: "test-token".to_string(),
+            upstream_ca,
+            upstream_client_identity: identity,
+            ..Default::default()
+        };
+        let recorder = RequestRecorder::new(10);
+        let proxy = DebugProxy::new(
+            SharedConfig::new(config),
+            recorder.clone(),
+            upstream.to_string(),
+        );
+        let port = 8118 + i as u16;
+        let proxy_server = start_proxy_server(proxy, port).await;
+        sleep(Duration::from_millis(100)).await;
+
+        let response = client
+            .get(format!("http://localhost:{port}/secure"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "case {i}");
+        if i == 0 {
+            // An untrusted certificate is a TLS failure, not a refused connection
+            let events: Vec<serde_json::Value> = client
+                .get(format!(
+                    "http://localhost:{port}/_proxy/api/events?token=test-token"
+                ))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert!(
+                events
+                    .iter()
+                    .any(|event| event["kind"] == "upstream_tls_error"),
+                "{events:?}"
+            );
+        }
+        if status == 200 {
+            let tls = recorder.get_transactions()[0]
+                .connection
+                .clone()
+                .and_then(|connection| connection.tls)
+                .expect("No TLS details recorded");
+            assert!(tls.version.starts_with("TLSv1."), "{}", tls.version);
+            let certificate = tls.certificate.unwrap();
+            assert_eq!(certificate.names, ["localhost"]);
+            assert!(!certificate.expired);
+        }
+        proxy_server.abort();
+    }
+    assert!(ca_for("localhost").is_ok());
+    assert!(format!("{}", client_key.display())
+        .parse::<UpstreamCa>()
+        .is_err());
+
+    tls_server.abort();
+    mtls_server.abort();
+}
+
+#[tokio::test]
+async fn test_upstream_headers() {
+    use debug_proxy::upstream::UpstreamHeader;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    // Echoes the credentials the upstream received
+    let upstream_server = tokio::spawn(async {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let header = |name: &str| {
+                    req.headers()
+                        .get(name)
+                        .map_or("-", |v| v.to_str().unwrap())
+                        .to_string()
+                };
+                let body = format!("{} {}", header("authorization"), header("x-api-key"));
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            }))
+        });
+        let _ = Server::bind(&([127, 0, 0, 1], 3045).into())
+            .serve(make_svc)
+            .await;
+    });
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        upstream_headers: vec![
+            UpstreamHeader::basic_auth("staging:s3cret").unwrap(),
+            "X-Api-Key: key-123".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3045".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8123).await;
+    sleep(Duration::from_millis(100)).await;
+
+    // The configured credentials replace the client's
+    let client = Client::new();
+    let body = client
+        .get("http://localhost:8123/private")
+        .header("authorization", "Bearer from-browser")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "Basic c3RhZ2luZzpzM2NyZXQ= key-123");
+
+    // The recording names the injected headers without their values
+    let transactions = recorder.get_transactions();
+    assert_eq!(
+        transactions[0].injected_headers,
+        ["authorization", "x-api-key"]
+    );
+    let recorded = serde_json::to_string(&transactions[0].request).unwrap();
+    assert!(!recorded.contains("key-123"));
+    assert!(!recorded.contains("c3RhZ2luZzpzM2NyZXQ="));
+    let config = client
+        .get("http://localhost:8123/_proxy/api/config?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(config.contains("x-api-key: [redacted]"), "{config}");
+    assert!(!config.contains("key-123"));
+
+    assert!(UpstreamHeader::basic_auth("no-password").is_err());
+    assert!("X-Api-Key".parse::<UpstreamHeader>().is_err());
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_no_cache() {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    // Answers revalidations with 304 and everything else with a cacheable 200
+    let upstream_server = tokio::spawn(async {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let response = if req.headers().contains_key("if-none-match") {
+                    Response::builder().status(304).body(Body::empty())
+                } else {
+                    Response::builder()
+                        .header("etag", "\"v1\"")
+                        .header("cache-control", "max-age=3600")
+                        .body(Body::from("fresh"))
+                };
+                Ok::<_, Infallible>(response.unwrap())
+            }))
+        });
+        let _ = Server::bind(&([127, 0, 0, 1], 3046).into())
+            .serve(make_svc)
+            .await;
+    });
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        no_cache: true,
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3046".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8124).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8124/asset.js")
+        .header("if-none-match", "\"v1\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    assert!(response.headers().get("etag").is_none());
+    assert_eq!(response.text().await.unwrap(), "fresh");
+
+    // The recording keeps what the upstream sent
+    let transactions = recorder.get_transactions();
+    let headers = &transactions[0].response.as_ref().unwrap().headers;
+    assert!(headers.iter().any(|(name, _)| name == "etag"));
+
+    // Turning it off lets revalidations through again
+    client
+        .post("http://localhost:8124/_proxy/api/config?token=test-token")
+        .json(&serde_json::json!({ "no_cache": false }))
+        .send()
+        .await
+        .unwrap();
+    let response = client
+        .get("http://localhost:8124/asset.js")
+        .header("if-none-match", "\"v1\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_connection_events() {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use tokio::io::AsyncWriteExt;
+
+    // Slow enough for the client to give up first
+    let upstream_server = tokio::spawn(async {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|_req: Request<Body>| async {
+                sleep(Duration::from_millis(500)).await;
+                Ok::<_, Infallible>(Response::new(Body::from("late")))
+            }))
+        });
+        let _ = Server::bind(&([127, 0, 0, 1], 3047).into())
+            .serve(make_svc)
+            .await;
+    });
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config.clone()),
+        recorder.clone(),
+        "127.0.0.1:3047".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8125).await;
+    // Nothing listens on 3048
+    let refused = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3048".to_string(),
+    );
+    let refused_server = start_proxy_server(refused, 8126).await;
+    sleep(Duration::from_millis(100)).await;
+
+    // A client that dies halfway through its request body
+    let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8125")
+        .await
+        .unwrap();
+    stream
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\npartial",
+        )
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(100)).await;
+    drop(stream);
+
+    // A client that gives up waiting for the response
+    let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8125")
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(100)).await;
+    drop(stream);
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let events: Vec<serde_json::Value> = client
+        .get("http://localhost:8125/_proxy/api/events?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|event| event["kind"].as_str().unwrap())
+        .collect();
+    assert!(kinds.contains(&"client_reset"), "{kinds:?}");
+    let aborted = events
+        .iter()
+        .find(|event| event["kind"] == "client_aborted")
+        .unwrap();
+    assert_eq!(aborted["method"], "GET");
+    assert_eq!(aborted["path"], "/slow");
+
+    // The transaction is marked instead of waiting for the cancelled upstream
+    let transaction = recorder
+        .get_transaction(aborted["request_id"].as_str().unwrap())
+        .unwrap();
+    assert!(transaction.response.is_none());
+    assert_eq!(transaction.error.as_deref(), Some("Client aborted"));
+    let abort = transaction.client_aborted.unwrap();
+    assert!((50..500).contains(&abort.duration_ms), "{abort:?}");
+    // Nothing arrives once the upstream would have answered
+    sleep(Duration::from_millis(500)).await;
+    let transaction = recorder.get_transaction(&transaction.request.id).unwrap();
+    assert!(transaction.response.is_none());
+    let disconnected = events
+        .iter()
+        .find(|event| event["kind"] == "client_disconnected")
+        .unwrap();
+    assert_eq!(disconnected["requests"], 1);
+    assert!(
+        kinds
+            .iter()
+            .filter(|kind| **kind == "client_connected")
+            .count()
+            >= 3
+    );
+
+    // Only newer events with `since`
+    let last = events.last().unwrap()["seq"].as_u64().unwrap();
+    let newer: Vec<serde_json::Value> = client
+        .get(format!(
+            "http://localhost:8125/_proxy/api/events?token=test-token&since={last}"
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(newer
+        .iter()
+        .all(|event| event["seq"].as_u64().unwrap() > last));
+
+    // An upstream that refuses connections
+    let response = client
+        .get("http://localhost:8126/api/users")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 502);
+    let events: Vec<serde_json::Value> = client
+        .get("http://localhost:8126/_proxy/api/events?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let failed = events
+        .iter()
+        .find(|event| event["kind"] == "upstream_connect_failed")
+        .unwrap();
+    assert_eq!(failed["upstream"], "127.0.0.1:3048");
+    assert!(failed["request_id"].is_string());
+
+    let response = client
+        .delete("http://localhost:8126/_proxy/api/events?token=test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    upstream_server.abort();
+    proxy_server.abort();
+    refused_server.abort();
+}
+
+#[tokio::test]
+async fn test_mock_server() {
+    use debug_proxy::mock::{MockServer, MOCK_HEADER};
+    use debug_proxy::{RequestInfo, ResponseInfo};
+    use http::{HeaderMap, Method, StatusCode, Version};
+
+    // Record a session without an upstream
+    let recorder = RequestRecorder::new(20);
+    let record = |method: &Method, path: &str, body: &str, status: u16, response: &str| {
+        let headers = HeaderMap::new();
+        let request_id = recorder.record_request(RequestInfo {
+            method,
+            path,
+            version: Version::HTTP_11,
+            headers: &headers,
+            body: body.as_bytes(),
+            client_addr: "127.0.0.1:5000".to_string(),
+            listener: None,
+            target: None,
+            trailers: None,
+            truncate_at: 1024,
+        });
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert("content-type", "application/json".parse().unwrap());
+        response_headers.insert("etag", format!("\"{response}\"").parse().unwrap());
+        recorder.record_response(ResponseInfo {
+            request_id: &request_id,
+            status: StatusCode::from_u16(status).unwrap(),
+            version: Version::HTTP_11,
+            headers: &response_headers,
+            body: response.as_bytes(),
+            duration_ms: 5,
+            trailers: None,
+            truncate_at: 1024,
+        });
+    };
+    record(&Method::GET, "/api/job", "", 200, "pending");
+    record(&Method::GET, "/api/job", "", 200, "done");
+    record(&Method::POST, "/api/orders", "{\"item\":1}", 201, "order-1");
+    record(&Method::POST, "/api/orders", "{\"item\":2}", 201, "order-2");
+    record(&Method::GET, "/api/items?page=1", "", 200, "items");
+
+    let dir = tempfile::tempdir().unwrap();
+    let traffic = dir.path().join("traffic.jsonl");
+    std::fs::write(
+        &traffic,
+        debug_proxy::export::to_jsonl(&recorder.get_transactions()),
+    )
+    .unwrap();
+    let mock = MockServer::load(&traffic).unwrap();
+    assert_eq!(mock.len(), 5);
+    let mock_server = tokio::spawn(mock.serve(([127, 0, 0, 1], 8127).into()));
+
+    let upstream_server = start_test_server(3049).await;
+    let snapshot = dir.path().join("snapshot.json");
+    debug_proxy::snapshot::Snapshot::new(recorder.get_transactions())
+        .save(&snapshot)
+        .unwrap();
+    let passthrough = MockServer::load(&snapshot)
+        .unwrap()
+        .with_passthrough("127.0.0.1:3049".to_string());
+    let passthrough_server = tokio::spawn(passthrough.serve(([127, 0, 0, 1], 8128).into()));
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let get = |path: &str| client.get(format!("http://localhost:8127{path}")).send();
+
+    // Repeated requests take the recorded responses in turn
+    for expected in ["pending", "done", "pending"] {
+        let response = get("/api/job").await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert!(response.headers().contains_key(MOCK_HEADER));
+        assert_eq!(response.text().await.unwrap(), expected);
+    }
+
+    // The recording of the same body wins
+    let response = client
+        .post("http://localhost:8127/api/orders")
+        .body("{\"item\":2}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    assert_eq!(response.text().await.unwrap(), "order-2");
+
+    // Another query still finds the path
+    let response = get("/api/items?page=2").await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "items");
+
+    // Revalidation against the recorded ETag
+    let response = client
+        .get("http://localhost:8127/api/items?page=1")
+        .header("if-none-match", "\"items\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    let response = get("/api/missing").await.unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .delete("http://localhost:8127/api/job")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    // Unrecorded requests reach the passthrough upstream
+    let response = client
+        .get("http://localhost:8128/api/missing")
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key(MOCK_HEADER));
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+    let response = client
+        .get("http://localhost:8128/api/job")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "pending");
+
+    let empty = dir.path().join("empty.jsonl");
+    std::fs::write(&empty, "").unwrap();
+    assert!(MockServer::load(&empty).unwrap().is_empty());
+    std::fs::write(&empty, "not json\n").unwrap();
+    assert!(MockServer::load(&empty).is_err());
+
+    mock_server.abort();
+    passthrough_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_offline_mode() {
+    use debug_proxy::mock::MOCK_HEADER;
+
+    let upstream_server = start_test_server(3050).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(20);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3050".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8129).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8129/api/users?page=1")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.headers().get(MOCK_HEADER).is_none());
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+    sleep(Duration::from_millis(50)).await;
+    let recorded_id = recorder.get_transactions()[0].request.id.clone();
+
+    let set_offline = |mode: &'static str| {
+        client
+            .post("http://localhost:8129/_proxy/api/config?token=test-token")
+            .json(&serde_json::json!({ "offline": mode }))
+            .send()
+    };
+    set_offline("strict").await.unwrap();
+
+    // Recorded requests are answered from history
+    let response = client
+        .get("http://localhost:8129/api/users?page=1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()[MOCK_HEADER], recorded_id.as_str());
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+
+    // Misses are refused without contacting the upstream
+    let response = client
+        .get("http://localhost:8129/api/orders")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+
+    // Or go to the upstream in passthrough mode
+    set_offline("passthrough").await.unwrap();
+    let response = client
+        .get("http://localhost:8129/api/orders")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers().get(MOCK_HEADER).is_none());
+    sleep(Duration::from_millis(50)).await;
+
+    let transactions = recorder.get_transactions();
+    let served = transactions
+        .iter()
+        .filter(|t| t.served_from.is_some())
+        .collect::<Vec<_>>();
+    assert_eq!(served.len(), 1);
+    assert_eq!(served[0].served_from.as_deref(), Some(recorded_id.as_str()));
+    assert_eq!(served[0].response.as_ref().unwrap().status, 200);
+    let refused = transactions
+        .iter()
+        .find(|t| t.error.is_some())
+        .expect("the strict miss is recorded");
+    assert_eq!(refused.request.path, "/api/orders");
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_probes() {
+    let upstream_server = start_test_server(3051).await;
+    sleep(Duration::from_millis(50)).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(50);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3051".to_string(),
+    )
+    .with_probes(vec![
+        "/health@100ms".parse().unwrap(),
+        "POST /api/ping@1m".parse().unwrap(),
+    ]);
+    let proxy_server = start_proxy_server(proxy, 8130).await;
+    sleep(Duration::from_millis(450)).await;
+
+    // Probes are recorded like traffic, from the "probe" client
+    let transactions = recorder.get_transactions();
+    let health = transactions
+        .iter()
+        .filter(|t| t.request.path == "/health")
+        .collect::<Vec<_>>();
+    assert!(health.len() >= 3, "{} health probes", health.len());
+    assert!(health.iter().all(|t| t.request.client_addr == "probe"));
+    assert!(health
+        .iter()
+        .all(|t| t.response.as_ref().is_some_and(|r| r.status == 200)));
+
+    let client = Client::new();
+    let timelines: serde_json::Value = client
+        .get("http://localhost:8130/_proxy/api/probes?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(timelines[0]["method"], "GET");
+    assert_eq!(timelines[0]["path"], "/health");
+    assert_eq!(timelines[0]["interval_ms"], 100);
+    assert!(timelines[0]["results"].as_array().unwrap().len() >= 3);
+    assert_eq!(timelines[0]["results"][0]["status"], 200);
+    // The first tick fires right away
+    assert_eq!(timelines[1]["method"], "POST");
+    assert_eq!(timelines[1]["results"].as_array().unwrap().len(), 1);
+
+    // And in the waterfall, with when each started and ended
+    let timeline: serde_json::Value = client
+        .get("http://localhost:8130/_proxy/api/timeline?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let entries = timeline["entries"].as_array().unwrap();
+    assert!(entries.len() >= 4);
+    for entry in entries.iter().filter(|entry| entry["path"] == "/health") {
+        assert_eq!(entry["status"], 200);
+        assert!(entry["end"].as_u64().unwrap() >= entry["start"].as_u64().unwrap());
+    }
+    assert!(timeline["end"].as_u64().unwrap() >= timeline["start"].as_u64().unwrap());
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_inflight_requests() {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    // /slow takes a while to answer, /drip to finish its body
+    let upstream_server = tokio::spawn(async {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                if req.uri().path() == "/slow" {
+                    sleep(Duration::from_millis(400)).await;
+                    return Ok::<_, Infallible>(Response::new(Body::from("slow")));
+                }
+                let (mut sender, body) = Body::channel();
+                tokio::spawn(async move {
+                    let _ = sender.send_data("first".into()).await;
+                    sleep(Duration::from_millis(400)).await;
+                    let _ = sender.send_data("last".into()).await;
+                });
+                Ok(Response::new(body))
+            }))
+        });
+        let _ = Server::bind(&([127, 0, 0, 1], 3052).into())
+            .serve(make_svc)
+            .await;
+    });
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        upstream_timeout: Duration::from_secs(2),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3052".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8131).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let requests = ["/slow", "/drip?n=1"].map(|path| {
+        let request = client.get(format!("http://localhost:8131{path}")).send();
+        tokio::spawn(async move { request.await.unwrap().text().await.unwrap() })
+    });
+    sleep(Duration::from_millis(200)).await;
+
+    let inflight = || async {
+        client
+            .get("http://localhost:8131/_proxy/api/inflight?token=test-token")
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap()
+    };
+    let listed = inflight().await;
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), 2);
+    let state = |path: &str| {
+        let request = listed.iter().find(|r| r["path"] == path).unwrap();
+        assert!(request["elapsed_ms"].as_u64().unwrap() >= 100);
+        assert!(!request["id"].as_str().unwrap().is_empty());
+        request["state"].clone()
+    };
+    assert_eq!(state("/slow"), "awaiting-upstream");
+    assert_eq!(state("/drip?n=1"), "streaming-response");
+
+    let [slow, drip] = requests;
+    assert_eq!(slow.await.unwrap(), "slow");
+    assert_eq!(drip.await.unwrap(), "firstlast");
+    assert_eq!(inflight().await, serde_json::json!([]));
+
+    // Cancelling answers the client right away with the chosen status
+    let request = client.get("http://localhost:8131/slow").send();
+    let slow = tokio::spawn(async move {
+        let started = std::time::Instant::now();
+        let response = request.await.unwrap();
+        (response.status(), started.elapsed())
+    });
+    sleep(Duration::from_millis(100)).await;
+    let id = inflight().await[0]["id"].as_str().unwrap().to_string();
+    let cancel = |status: &str| {
+        client
+            .post(format!(
+                "http://localhost:8131/_proxy/api/inflight/{id}/cancel?token=test-token{status}"
+            ))
+            .send()
+    };
+    assert_eq!(cancel("&status=abc").await.unwrap().status(), 400);
+    assert_eq!(cancel("&status=504").await.unwrap().status(), 200);
+    let (status, elapsed) = slow.await.unwrap();
+    assert_eq!(status, 504);
+    assert!(elapsed < Duration::from_millis(300));
+    assert_eq!(cancel("").await.unwrap().status(), 404);
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_proxy_service() {
+    use hyper::server::conn::AddrStream;
+    use hyper::service::{make_service_fn, service_fn, Service};
+    use hyper::{Body, Request, Server};
+    use std::convert::Infallible;
+
+    let upstream_server = start_test_server(3053).await;
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3053".to_string(),
+    );
+    let service = proxy.into_service();
+
+    // Host the service in another server, behind a middleware that tags
+    // responses and passes the client address on
+    let host_server = tokio::spawn(async move {
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
+            let remote_addr = conn.remote_addr();
+            let service = service.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                    let mut service = service.clone();
+                    req.extensions_mut().insert(remote_addr);
+                    async move {
+                        let mut response = service.call(req).await?;
+                        response
+                            .headers_mut()
+                            .insert("x-host-app", "1".parse().unwrap());
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+        let _ = Server::bind(&([127, 0, 0, 1], 8132).into())
+            .serve(make_svc)
+            .await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://127.0.0.1:8132/hello")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-host-app"], "1");
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].request.path, "/hello");
+    assert!(transactions[0]
+        .request
+        .client_addr
+        .starts_with("127.0.0.1:"));
+
+    // The admin API is served too
+    let response = client
+        .get("http://127.0.0.1:8132/_proxy/api/logs?token=test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    host_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_test_proxy() {
+    use debug_proxy::test_support::{TestProxy, TransactionFilter};
+
+    let upstream_server = start_test_server(3054).await;
+    let proxy = TestProxy::start("127.0.0.1:3054").await.unwrap();
+    assert_ne!(proxy.addr().port(), 0);
+
+    let client = Client::new();
+    for path in ["/users?page=1", "/users/1", "/orders"] {
+        let response = client.get(proxy.url(path)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+    let response = client
+        .post(proxy.url("/users"))
+        .body("{}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let users = TransactionFilter::new().with_path_prefix("/users");
+    assert_eq!(proxy.transactions(&users).await.len(), 3);
+    let listed = proxy
+        .transactions(
+            &TransactionFilter::new()
+                .with_method(http::Method::GET)
+                .with_path("/users")
+                .with_status(200),
+        )
+        .await;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].request.path, "/users?page=1");
+    assert!(proxy
+        .transactions(&TransactionFilter::new().with_failed(true))
+        .await
+        .is_empty());
+    let orders = proxy
+        .wait_for(
+            &TransactionFilter::new().with_path("/orders"),
+            1,
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+    assert_eq!(orders.len(), 1);
+    assert!(proxy
+        .wait_for(
+            &TransactionFilter::new().with_path("/never"),
+            1,
+            Duration::from_millis(50),
+        )
+        .await
+        .is_err());
+
+    let response = client
+        .get(proxy.admin_url("/_proxy/api/logs"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let addr = proxy.addr();
+    proxy.shutdown().await;
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_health_endpoints() {
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3055".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8133).await;
+    sleep(Duration::from_millis(100)).await;
+
+    // Neither needs the token
+    let client = Client::new();
+    let response = client
+        .get("http://127.0.0.1:8133/_proxy/healthz")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let health: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["pid"], std::process::id());
+
+    // Nothing listens on the upstream yet
+    let response = client
+        .get("http://127.0.0.1:8133/_proxy/readyz")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+    let readiness: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(readiness["ready"], false);
+    assert_eq!(readiness["upstreams"][0]["upstream"], "127.0.0.1:3055");
+    assert_eq!(readiness["upstreams"][0]["reachable"], false);
+    assert!(readiness["upstreams"][0]["error"].is_string());
+    assert!(readiness["process"].is_null());
+
+    let upstream_server = start_test_server(3055).await;
+    sleep(Duration::from_millis(100)).await;
+    let response = client
+        .get("http://127.0.0.1:8133/_proxy/readyz")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let readiness: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(readiness["ready"], true);
+    assert_eq!(readiness["upstreams"][0]["reachable"], true);
+
+    // Other admin paths still need it
+    let response = client
+        .get("http://127.0.0.1:8133/_proxy/api/stats")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let version: serde_json::Value = client
+        .get("http://127.0.0.1:8133/_proxy/api/version?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(version["name"], "debug-proxy");
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        version["admin_api_version"],
+        debug_proxy::version::ADMIN_API_VERSION
+    );
+    assert_eq!(
+        version["features"],
+        serde_json::json!(["ui", "tls", "decoders"])
+    );
+    let capabilities = version["capabilities"].as_array().unwrap();
+    assert!(capabilities.contains(&"export-har".into()));
+    assert!(capabilities.contains(&"mitm".into()));
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    let upstream_server = start_test_server(3056).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        upstream_timeout: Duration::from_secs(2),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3056".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8134).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let admin = |path: &str| format!("http://127.0.0.1:8134{path}?token=test-token");
+    client
+        .get("http://127.0.0.1:8134/hello")
+        .send()
+        .await
+        .unwrap();
+    let response = client
+        .post(admin("/_proxy/api/config"))
+        .body(r#"{"upstream_timeout_ms": 1500, "max_history_size": 100}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .delete(admin("/_proxy/api/logs"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    // Reads and requests without the token are left out
+    client.get(admin("/_proxy/api/logs")).send().await.unwrap();
+    let response = client
+        .delete("http://127.0.0.1:8134/_proxy/api/logs")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let audit: serde_json::Value = client
+        .get(admin("/_proxy/api/audit"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let entries = audit.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["method"], "POST");
+    assert_eq!(entries[0]["path"], "/_proxy/api/config");
+    assert_eq!(entries[0]["status"], 200);
+    assert!(entries[0]["client_addr"]
+        .as_str()
+        .unwrap()
+        .starts_with("127.0.0.1:"));
+    assert!(entries[0]["timestamp"].as_u64().unwrap() > 0);
+    // Only the setting that changed is listed
+    assert_eq!(
+        entries[0]["changes"],
+        serde_json::json!([{"field": "upstream_timeout_ms", "before": 2000, "after": 1500}])
+    );
+    assert_eq!(entries[1]["method"], "DELETE");
+    assert_eq!(entries[1]["path"], "/_proxy/api/logs");
+    assert!(entries[1].get("changes").is_none());
+
+    let first = entries[0]["id"].as_u64().unwrap();
+    let newer: serde_json::Value = client
+        .get(format!("{}&after={first}", admin("/_proxy/api/audit")))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(newer.as_array().unwrap().len(), 1);
+    assert_eq!(newer[0]["path"], "/_proxy/api/logs");
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_admin_token_lockout() {
+    let upstream_server = start_test_server(3057).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3057".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8135).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let stats = |token: &str| format!("http://127.0.0.1:8135/_proxy/api/stats?token={token}");
+    for _ in 0..6 {
+        let response = client.get(stats("guess")).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+    }
+
+    // Locked out, even with the right token
+    let response = client.get(stats("test-token")).send().await.unwrap();
+    assert_eq!(response.status(), 429);
+    let retry_after: u64 = response.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 300);
+
+    // Health checks and proxied traffic are not affected
+    let response = client
+        .get("http://127.0.0.1:8135/_proxy/healthz")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .get("http://127.0.0.1:8135/hello")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_asset_access() {
+    let upstream_server = start_test_server(3058).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3058".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8136).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let asset = "http://127.0.0.1:8136/_proxy/assets/index.js";
+    let response = client.get(asset).send().await.unwrap();
+    assert_eq!(response.status(), 403);
+    let response = client
+        .get(asset)
+        .header("cookie", "debug_proxy_assets=forged")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    // The admin page hands out the cookie, and is not cached
+    let response = client
+        .get("http://127.0.0.1:8136/_proxy?token=test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    let set_cookie = response.headers()["set-cookie"].to_str().unwrap();
+    assert!(set_cookie.contains("Path=/_proxy/assets; HttpOnly"));
+    assert!(!set_cookie.contains("test-token"));
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+    // With the cookie or the token, a missing asset is a 404
+    let response = client
+        .get(asset)
+        .header("cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .get(format!("{asset}?token=test-token"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .get(format!("{asset}?token=wrong"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    // Other paths load the UI for its history routing, unless they name a file
+    let response = client
+        .get("http://127.0.0.1:8136/_proxy/requests/abc?token=test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/html");
+    let response = client
+        .get("http://127.0.0.1:8136/_proxy/missing.js?token=test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .get("http://127.0.0.1:8136/_proxy/api/missing?token=test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .get("http://127.0.0.1:8136/_proxy/requests/abc")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_local_admin_listener() {
+    let upstream_server = start_test_server(3059).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3059".to_string(),
+    );
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let local_admin = listener.local_addr().unwrap();
+    let admin_server = {
+        let proxy = proxy.clone();
+        tokio::spawn(async move { proxy.serve_local_admin(listener).await })
+    };
+    let proxy_server = start_proxy_server(proxy, 8137).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get(format!("http://{local_admin}/_proxy/api/stats"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .post(format!("http://{local_admin}/_proxy/api/config"))
+        .body(r#"{"upstream_timeout_ms": 1500}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    // Cross-site and rebound requests are turned away
+    let response = client
+        .post(format!("http://{local_admin}/_proxy/api/config"))
+        .header("origin", "http://evil.example")
+        .body(r#"{"upstream_timeout_ms": 1}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    let response = client
+        .get(format!("http://{local_admin}/_proxy/api/stats"))
+        .header("host", format!("evil.example:{}", local_admin.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    let response = client
+        .get(format!(
+            "http://localhost:{}/_proxy/api/stats",
+            local_admin.port()
+        ))
+        .header("origin", format!("http://{local_admin}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    // Only the admin API is served there
+    let response = client
+        .get(format!("http://{local_admin}/hello"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    // The public listener still wants the token
+    let response = client
+        .get("http://127.0.0.1:8137/_proxy/api/stats")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    let audit: serde_json::Value = client
+        .get("http://127.0.0.1:8137/_proxy/api/audit?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(audit[0]["path"], "/_proxy/api/config");
+    assert_eq!(audit[0]["changes"][0]["field"], "upstream_timeout_ms");
+
+    admin_server.abort();
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_memory_limit_pauses_recording() {
+    let upstream_server = start_test_server(3060).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    // Any process is over a 1 byte limit
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3060".to_string(),
+    )
+    .with_memory_limit(1);
+    let proxy_server = start_proxy_server(proxy, 8138).await;
+    sleep(Duration::from_millis(200)).await;
+
+    // Still proxied, just not recorded
+    let client = Client::new();
+    let response = client
+        .get("http://127.0.0.1:8138/hello")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+
+    let stats: serde_json::Value = client
+        .get("http://127.0.0.1:8138/_proxy/api/stats?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["requests"], 0);
+    assert_eq!(stats["recording_paused"], true);
+    assert_eq!(stats["memory"]["pressure"], "paused");
+    assert_eq!(stats["memory"]["unrecorded"], 1);
+    assert_eq!(stats["memory"]["limit_bytes"], 1);
+    assert!(stats["memory"]["rss_bytes"].as_u64().unwrap() > 1);
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_export_bundle() {
+    let upstream_server = start_test_server(3061).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3061".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8139).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    client
+        .post("http://127.0.0.1:8139/api/items")
+        .header("content-type", "application/json")
+        .body(r#"{"name":"widget"}"#)
+        .send()
+        .await
+        .unwrap();
+
+    let response = client
+        .get("http://127.0.0.1:8139/_proxy/api/export/bundle?token=test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/zip");
+    let bytes = response.bytes().await.unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "bodies/001-api-items-request.json",
+            "bodies/001-api-items-response.txt",
+            "bundle.json",
+            "config.json",
+            "stats.json",
+            "traffic.har",
+        ]
+    );
+    let read = |archive: &mut zip::ZipArchive<_>, name: &str| {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name(name).unwrap(), &mut content).unwrap();
+        content
+    };
+    assert_eq!(
+        read(&mut archive, "bodies/001-api-items-request.json"),
+        r#"{"name":"widget"}"#
+    );
+    let har: serde_json::Value = serde_json::from_str(&read(&mut archive, "traffic.har")).unwrap();
+    assert_eq!(har["log"]["entries"].as_array().unwrap().len(), 1);
+    let bundle: serde_json::Value =
+        serde_json::from_str(&read(&mut archive, "bundle.json")).unwrap();
+    assert_eq!(bundle["transactions"], 1);
+
+    // A range after the request leaves it out
+    let since = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+        + 60_000;
+    let bytes = client
+        .get(format!(
+            "http://127.0.0.1:8139/_proxy/api/export/bundle?token=test-token&since={since}"
+        ))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    assert!(archive
+        .file_names()
+        .all(|name| !name.starts_with("bodies/")));
+    let response = client
+        .get("http://127.0.0.1:8139/_proxy/api/export/bundle?token=test-token&until=yesterday")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_logs_after_seq() {
+    let upstream_server = start_test_server(3062).await;
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        RequestRecorder::new(10),
+        "127.0.0.1:3062".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8140).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    for path in ["/first", "/second", "/third"] {
+        client
+            .get(format!("http://127.0.0.1:8140{path}"))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let logs: Vec<serde_json::Value> = client
+        .get("http://127.0.0.1:8140/_proxy/api/logs?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let seqs: Vec<u64> = logs
+        .iter()
+        .map(|t| t["request"]["seq"].as_u64().unwrap())
+        .collect();
+    assert_eq!(seqs.len(), 3);
+    assert!(seqs.windows(2).all(|pair| pair[0] != pair[1]));
+
+    let first = *seqs.iter().min().unwrap();
+    let after: Vec<serde_json::Value> = client
+        .get(format!(
+            "http://127.0.0.1:8140/_proxy/api/logs?token=test-token&after={first}"
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(after.len(), 2);
+    assert!(after
+        .iter()
+        .all(|t| t["request"]["seq"].as_u64().unwrap() > first));
+    assert!(after.iter().all(|t| t["request"]["path"] != "/first"));
+
+    let response = client
+        .get("http://127.0.0.1:8140/_proxy/api/logs?token=test-token&after=soon")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_malformed_response_shows_raw_bytes() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3063")
+        .await
+        .unwrap();
+    let upstream_server = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nNot a header\r\nContent-Length: 2\r\n\r\nok")
+                .await;
+        }
+    });
+
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3063".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8141).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let response = Client::new()
+        .get("http://127.0.0.1:8141/broken?page=1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 502);
+
+    let transactions = recorder.get_transactions();
+    let error = transactions[0].error.as_deref().unwrap();
+    assert!(error.starts_with("Upstream error: "), "{error}");
+    assert!(
+        error.contains(r#"began with 54 bytes: "HTTP/1.1 200 OK\r\nNot a header\r\n"#),
+        "{error}"
+    );
+
+    // Identical requests at the same time each get their own bytes
+    let echo_listener = tokio::net::TcpListener::bind("127.0.0.1:3072")
+        .await
+        .unwrap();
+    let echo_server = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = echo_listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let n = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..n]).to_string();
+                let tag = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("x-tag: "))
+                    .unwrap_or_default()
+                    .to_string();
+                sleep(Duration::from_millis(50)).await;
+                let _ = stream
+                    .write_all(format!("HTTP/1.1 200 OK\r\nbroken {tag}\r\n\r\n").as_bytes())
+                    .await;
+            });
+        }
+    });
+    let echo_recorder = RequestRecorder::new(10);
+    let echo_proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        echo_recorder.clone(),
+        "127.0.0.1:3072".to_string(),
+    );
+    let echo_proxy_server = start_proxy_server(echo_proxy, 8153).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let requests: Vec<_> = (0..4)
+        .map(|tag| {
+            let request = client
+                .get("http://127.0.0.1:8153/same")
+                .header("x-tag", tag.to_string())
+                .send();
+            tokio::spawn(request)
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(request.await.unwrap().unwrap().status(), 502);
+    }
+    let transactions = echo_recorder.get_transactions();
+    assert_eq!(transactions.len(), 4);
+    for transaction in &transactions {
+        let (_, tag) = transaction
+            .request
+            .headers
+            .iter()
+            .find(|(name, _)| name == "x-tag")
+            .unwrap();
+        let error = transaction.error.as_deref().unwrap();
+        assert!(error.contains(&format!("broken {tag}\\r\\n")), "{error}");
+    }
+
+    upstream_server.abort();
+    proxy_server.abort();
+    echo_server.abort();
+    echo_proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_upgrade_tunnel() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Switches to a made-up protocol that echoes what it gets in upper case
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3064")
+        .await
+        .unwrap();
+    let upstream_server = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    if stream.read(&mut byte).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                    head.push(byte[0]);
+                }
+                stream
+                    .write_all(
+                        b"HTTP/1.1 101 Switching Protocols\r\n\
+                          Connection: upgrade\r\nUpgrade: shout/1\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                let mut buf = [0u8; 64];
+                while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                    let _ = stream.write_all(&buf[..n].to_ascii_uppercase()).await;
+                }
+            });
+        }
+    });
+
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3064".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8142).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = tokio::net::TcpStream::connect("127.0.0.1:8142")
+        .await
+        .unwrap();
+    client
+        .write_all(
+            b"GET /shout HTTP/1.1\r\nHost: localhost\r\n\
+              Connection: upgrade\r\nUpgrade: shout/1\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        client.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+
+    client.write_all(b"hello").await.unwrap();
+    let mut echoed = [0u8; 5];
+    client.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"HELLO");
+    drop(client);
+    sleep(Duration::from_millis(200)).await;
+
+    let transactions = recorder.get_transactions();
+    let transaction = &transactions[0];
+    assert_eq!(transaction.response.as_ref().unwrap().status, 101);
+    let tunnel = transaction.tunnel.as_ref().expect("Tunnel not recorded");
+    assert_eq!(tunnel.protocol, "shout/1");
+    assert_eq!(tunnel.bytes_to_upstream, 5);
+    assert_eq!(tunnel.bytes_to_client, 5);
+    assert!(tunnel.closed_at.is_some());
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_tcp_forwarding() {
+    use debug_proxy::tcp::TcpForwarder;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Answers each line with +OK, like a very small Redis
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3065")
+        .await
+        .unwrap();
+    let upstream_server = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 64];
+                while let Ok(1..) = stream.read(&mut buf).await {
+                    let _ = stream.write_all(b"+OK\r\n").await;
+                }
+            });
+        }
+    });
+
+    let forwarder = TcpForwarder::new("127.0.0.1:3065".to_string()).with_hex_dump(4);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:8143")
+        .await
+        .unwrap();
+    let forwarder_server = tokio::spawn(forwarder.clone().serve(listener));
+
+    let mut client = tokio::net::TcpStream::connect("127.0.0.1:8143")
+        .await
+        .unwrap();
+    client.write_all(b"PING\r\n").await.unwrap();
+    let mut reply = [0u8; 5];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+OK\r\n");
+    drop(client);
+    sleep(Duration::from_millis(200)).await;
+
+    let stats = forwarder.stats();
+    assert_eq!(stats.connections, 1);
+    assert_eq!(stats.active, 0);
+    assert_eq!(stats.failed, 0);
+    assert_eq!(stats.bytes_to_upstream, 6);
+    assert_eq!(stats.bytes_to_client, 5);
+
+    let events = serde_json::to_value(forwarder.events().get(None)).unwrap();
+    assert_eq!(events[0]["kind"], "client_connected");
+    assert_eq!(events[1]["kind"], "tunnel_closed");
+    assert_eq!(events[1]["protocol"], "tcp");
+    assert_eq!(events[1]["bytes_to_upstream"], 6);
+    assert_eq!(events[1]["bytes_to_client"], 5);
+
+    // Nothing listens on the upstream any more
+    upstream_server.abort();
+    sleep(Duration::from_millis(100)).await;
+    let forwarder = TcpForwarder::new("127.0.0.1:1".to_string());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:8144")
+        .await
+        .unwrap();
+    let refused_server = tokio::spawn(forwarder.clone().serve(listener));
+    let mut client = tokio::net::TcpStream::connect("127.0.0.1:8144")
+        .await
+        .unwrap();
+    let mut rest = Vec::new();
+    let _ = client.read_to_end(&mut rest).await;
+    assert!(rest.is_empty());
+    assert_eq!(forwarder.stats().failed, 1);
+    let events = serde_json::to_value(forwarder.events().get(None)).unwrap();
+    assert_eq!(events[1]["kind"], "upstream_connect_failed");
+
+    forwarder_server.abort();
+    refused_server.abort();
+}
+
+#[tokio::test]
+async fn test_udp_relay() {
+    use debug_proxy::udp::UdpRelay;
+
+    // Echoes each datagram back reversed
+    let upstream = tokio::net::UdpSocket::bind("127.0.0.1:3066").await.unwrap();
+    let upstream_server = tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, from) = upstream.recv_from(&mut buf).await.unwrap();
+            buf[..len].reverse();
+            upstream.send_to(&buf[..len], from).await.unwrap();
+        }
+    });
+
+    let relay = UdpRelay::new("127.0.0.1:3066".to_string())
+        .with_idle_timeout(Duration::from_millis(300))
+        .with_hex_dump(2);
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:8145").await.unwrap();
+    let relay_server = tokio::spawn(relay.clone().serve(socket));
+
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect("127.0.0.1:8145").await.unwrap();
+    let mut reply = [0u8; 512];
+    for datagram in [&b"ping"[..], b"hello"] {
+        client.send(datagram).await.unwrap();
+        let len = tokio::time::timeout(Duration::from_secs(2), client.recv(&mut reply))
+            .await
+            .expect("No reply through the relay")
+            .unwrap();
+        let mut expected = datagram.to_vec();
+        expected.reverse();
+        assert_eq!(&reply[..len], expected.as_slice());
+    }
+
+    let flows = relay.flows();
+    assert_eq!(flows.len(), 1);
+    let flow = &flows[0];
+    assert!(!flow.closed);
+    assert_eq!(flow.client_addr, client.local_addr().unwrap().to_string());
+    assert_eq!(flow.to_upstream.datagrams, 2);
+    assert_eq!(flow.to_upstream.bytes, 9);
+    assert_eq!(flow.to_upstream.min_size, Some(4));
+    assert_eq!(flow.to_upstream.max_size, 5);
+    assert_eq!(flow.to_upstream.samples, ["7069", "6865"]);
+    assert_eq!(flow.to_client.datagrams, 2);
+    assert_eq!(flow.to_client.samples, ["676e", "6f6c"]);
+
+    // The flow closes once both sides go quiet
+    sleep(Duration::from_millis(600)).await;
+    let stats = relay.stats();
+    assert_eq!(stats.flows, 1);
+    assert_eq!(stats.active, 0);
+    assert_eq!(stats.datagrams_to_client, 2);
+    assert!(relay.flows()[0].closed);
+
+    relay_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_public_tunnel_marks_requests() {
+    use debug_proxy::public_tunnel::{PublicTunnel, TunnelProvider};
+
+    let upstream_server = start_test_server(3067).await;
+    let tunnel = PublicTunnel::default();
+    let recorder = RequestRecorder::new(10);
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3067".to_string(),
+    )
+    .with_public_tunnel(tunnel.clone());
+    let proxy_server = start_proxy_server(proxy, 8146).await;
+
+    // Stands in for a tunnel client that prints its URL
+    let provider: TunnelProvider =
+        "echo connecting; echo 'ready at https://demo.tunnel.test/ for {port}'; sleep 5"
+            .parse()
+            .unwrap();
+    let process = ProcessManager::new(provider.command(8146));
+    process.start().await.unwrap();
+    let url = tokio::time::timeout(Duration::from_secs(5), tunnel.discover(&provider, &process))
+        .await
+        .expect("No public URL printed");
+    assert_eq!(url.as_deref(), Some("https://demo.tunnel.test"));
+    process.stop().await.unwrap();
+
+    let client = Client::new();
+    client
+        .get("http://127.0.0.1:8146/local")
+        .send()
+        .await
+        .unwrap();
+    client
+        .get("http://127.0.0.1:8146/webhook")
+        .header("host", "demo.tunnel.test")
+        .send()
+        .await
+        .unwrap();
+    client
+        .get("http://127.0.0.1:8146/rewritten")
+        .header("x-forwarded-host", "demo.tunnel.test")
+        .send()
+        .await
+        .unwrap();
+    recorder.flush().await;
+
+    let via: Vec<(String, bool)> = recorder
+        .get_transactions()
+        .iter()
+        .map(|t| (t.request.path.clone(), t.via_public_tunnel))
+        .collect();
+    assert!(via.contains(&("/local".to_string(), false)));
+    assert!(via.contains(&("/webhook".to_string(), true)));
+    assert!(via.contains(&("/rewritten".to_string(), true)));
+
+    let stats: serde_json::Value = client
+        .get("http://127.0.0.1:8146/_proxy/api/stats?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["via_public_tunnel"], 2);
+    assert_eq!(stats["public_url"], "https://demo.tunnel.test");
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_inbox_captures_and_replays() {
+    use debug_proxy::inbox::Inbox;
+
+    let recorder = RequestRecorder::new(10);
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    // No upstream, so every path is captured
+    let inbox: Inbox = "/hooks".parse().unwrap();
+    let proxy = DebugProxy::new(SharedConfig::new(config), recorder.clone(), String::new())
+        .with_inbox(inbox.with_catch_all());
+    let proxy_server = start_proxy_server(proxy, 8147).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .post("http://127.0.0.1:8147/hooks/github")
+        .header("x-github-event", "push")
+        .body(r#"{"ref":"main"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let captured: serde_json::Value = response.json().await.unwrap();
+    let id = captured["captured"].as_str().unwrap().to_string();
+    let response = client
+        .get("http://127.0.0.1:8147/elsewhere")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let inbox: Vec<serde_json::Value> = client
+        .get("http://127.0.0.1:8147/_proxy/api/inbox?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(inbox.len(), 2);
+    assert_eq!(inbox[0]["request"]["path"], "/hooks/github");
+    assert_eq!(inbox[0]["inbox"], true);
+
+    let replay_url =
+        format!("http://127.0.0.1:8147/_proxy/api/inbox/replay?token=test-token&id={id}");
+    let response = client.post(&replay_url).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+
+    let upstream_server = start_test_server(3068).await;
+    sleep(Duration::from_millis(100)).await;
+    let replayed: serde_json::Value = client
+        .post(format!("{replay_url}&upstream=127.0.0.1:3068"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(replayed["status"], 200);
+    recorder.flush().await;
+
+    let replay = recorder
+        .get_transaction(replayed["id"].as_str().unwrap())
+        .unwrap();
+    assert_eq!(replay.replayed_from.as_deref(), Some(id.as_str()));
+    assert!(!replay.inbox);
+    assert_eq!(replay.request.method, "POST");
+    assert_eq!(replay.request.body.preview.as_str(), r#"{"ref":"main"}"#);
+    assert!(replay
+        .request
+        .headers
+        .contains(&("x-github-event".to_string(), "push".to_string())));
+    assert_eq!(
+        replay.response.unwrap().body.preview.as_str(),
+        "Hello from test server"
+    );
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_hold_and_release() {
+    use debug_proxy::hold::HoldQueue;
+
+    let upstream_server = start_test_server(3069).await;
+    let recorder = RequestRecorder::new(20);
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let holds = HoldQueue::new(vec![
+        "POST /hooks/*".parse().unwrap(),
+        "/slow@200ms open".parse().unwrap(),
+    ]);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3069".to_string(),
+    )
+    .with_holds(holds);
+    let proxy_server = start_proxy_server(proxy, 8148).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let mut queued = Vec::new();
+    for (path, body) in [("/hooks/a", "first"), ("/hooks/b", "second")] {
+        let response = client
+            .post(format!("http://127.0.0.1:8148{path}"))
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 202);
+        let body: serde_json::Value = response.json().await.unwrap();
+        queued.push(body["queued"].as_str().unwrap().to_string());
+    }
+
+    let held: Vec<serde_json::Value> = client
+        .get("http://127.0.0.1:8148/_proxy/api/held?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(held.len(), 2);
+    assert_eq!(held[0]["path"], "/hooks/a");
+    assert_eq!(held[0]["release_at"], serde_json::Value::Null);
+
+    // Released in the opposite order to arrival
+    for id in queued.iter().rev() {
+        let response = client
+            .post(format!(
+                "http://127.0.0.1:8148/_proxy/api/held/release?token=test-token&id={id}"
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        sleep(Duration::from_millis(100)).await;
+    }
+    let response = client
+        .post("http://127.0.0.1:8148/_proxy/api/held/release?token=test-token&id=missing")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let started = std::time::Instant::now();
+    let response = client
+        .get("http://127.0.0.1:8148/slow")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "Hello from test server");
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    recorder.flush().await;
+
+    let transactions = recorder.get_transactions();
+    let delivered: Vec<String> = transactions
+        .iter()
+        .filter_map(|t| t.held_from.clone())
+        .collect();
+    assert_eq!(delivered, [queued[1].clone(), queued[0].clone()]);
+    assert!(transactions.iter().all(|t| t.replayed_from.is_none()));
+    for id in &queued {
+        let original = recorder.get_transaction(id).unwrap();
+        let held = original.held.unwrap();
+        assert_eq!(held.released_by, "admin");
+        let delivery = recorder
+            .get_transaction(held.delivered_as.as_deref().unwrap())
+            .unwrap();
+        assert_eq!(
+            delivery.request.body.preview.as_str(),
+            original.request.body.preview.as_str()
+        );
+        assert_eq!(delivery.response.unwrap().status, 200);
+    }
+    let slow = transactions
+        .iter()
+        .find(|t| t.request.path == "/slow")
+        .unwrap();
+    let held = slow.held.as_ref().unwrap();
+    assert_eq!(held.released_by, "delay");
+    assert!(held.held_ms >= 200);
+    assert_eq!(held.delivered_as, None);
+
+    proxy_server.abort();
+    upstream_server.abort();
+}
+
+#[tokio::test]
+async fn test_mock_rule_templates() {
+    use debug_proxy::mock::{MockRule, MockServer};
+
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yaml");
+    std::fs::write(
+        &rules,
+        r#"
+rules:
+  - method: GET
+    path: /users/{id}
+    body: '{"id": "{{ path.id }}", "page": {{ query.page }}, "trace": "{{ header.X-Trace }}", "ref": "{{ uuid }}"}'
+  - method: post
+    path: /orders
+    status: 201
+    headers:
+      location: /orders/1
+    body: "created {{ body.items.0.sku }} for {{ body.customer }}"
+"#,
+    )
+    .unwrap();
+    let mock = MockServer::new(Vec::new()).with_rules(MockRule::load(&rules).unwrap());
+    let mock_server = tokio::spawn(mock.serve(([127, 0, 0, 1], 8149).into()));
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let mut refs = Vec::new();
+    for _ in 0..2 {
+        let response = client
+            .get("http://127.0.0.1:8149/users/42?page=3")
+            .header("x-trace", "abc")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let user: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(user["id"], "42");
+        assert_eq!(user["page"], 3);
+        assert_eq!(user["trace"], "abc");
+        refs.push(user["ref"].as_str().unwrap().to_string());
+    }
+    // Helpers give a new value for every request
+    assert_ne!(refs[0], refs[1]);
+
+    let response = client
+        .post("http://127.0.0.1:8149/orders")
+        .body(r#"{"customer": "ada", "items": [{"sku": "A-1"}]}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    assert_eq!(response.headers()["location"], "/orders/1");
+    assert_eq!(response.headers()["content-type"], "text/plain");
+    assert_eq!(response.text().await.unwrap(), "created A-1 for ada");
+
+    // Nothing recorded and no rule for it
+    let response = client
+        .get("http://127.0.0.1:8149/orders")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    mock_server.abort();
+}
+
+#[tokio::test]
+async fn test_mock_rule_sequences() {
+    use debug_proxy::mock::{MockRule, MockServer};
+
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.yaml");
+    std::fs::write(
+        &rules,
+        r#"
+rules:
+  - path: /jobs/{id}
+    key: "{{ path.id }}"
+    sequence:
+      - status: 202
+        body: '{"id": "{{ path.id }}", "state": "pending"}'
+      - body: '{"id": "{{ path.id }}", "state": "done"}'
+  - path: /flaky
+    then: cycle
+    sequence:
+      - body: ok
+      - body: ok
+      - status: 500
+        body: failed
+"#,
+    )
+    .unwrap();
+    let mock = MockServer::new(Vec::new()).with_rules(MockRule::load(&rules).unwrap());
+    let mock_server = tokio::spawn(mock.serve(([127, 0, 0, 1], 8150).into()));
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let status = |path: &'static str| {
+        let client = client.clone();
+        async move {
+            client
+                .get(format!("http://127.0.0.1:8150{path}"))
+                .send()
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        }
+    };
+
+    // Each job polls through its own sequence, then stays done
+    assert_eq!(status("/jobs/1").await, 202);
+    assert_eq!(status("/jobs/2").await, 202);
+    assert_eq!(status("/jobs/1").await, 200);
+    assert_eq!(status("/jobs/1").await, 200);
+    let job: serde_json::Value = client
+        .get("http://127.0.0.1:8150/jobs/2")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(job["state"], "done");
+    assert_eq!(job["id"], "2");
+
+    let mut flaky = Vec::new();
+    for _ in 0..6 {
+        flaky.push(status("/flaky").await);
+    }
+    assert_eq!(flaky, [200, 200, 500, 200, 200, 500]);
+
+    mock_server.abort();
+}
+
+#[cfg(feature = "decoders")]
+#[tokio::test]
+async fn test_decompression() {
+    use debug_proxy::Decompression;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use std::io::Write;
+
+    // Gzips its answer whenever the client accepts it, which a missing
+    // Accept-Encoding does
+    let upstream_server = tokio::spawn(async {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let gzip = req
+                    .headers()
+                    .get("accept-encoding")
+                    .and_then(|value| value.to_str().ok())
+                    .is_none_or(|value| value.contains("gzip"));
+                let response = if gzip {
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(b"plain text").unwrap();
+                    Response::builder()
+                        .header("content-encoding", "gzip")
+                        .body(Body::from(encoder.finish().unwrap()))
+                } else {
+                    Response::builder().body(Body::from("plain text"))
+                };
+                Ok::<_, Infallible>(response.unwrap())
+            }))
+        });
+        let _ = Server::bind(&([127, 0, 0, 1], 3070).into())
+            .serve(make_svc)
+            .await;
+    });
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        decompression: Decompression::Decode,
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3070".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8151).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8151/page")
+        .header("accept-encoding", "gzip")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.headers().get("content-encoding").is_none());
+    assert_eq!(response.text().await.unwrap(), "plain text");
+    let transactions = recorder.get_transactions();
+    let recorded = transactions[0].response.as_ref().unwrap();
+    assert_eq!(recorded.body.preview, "plain text");
+
+    // Identity mode keeps the upstream from compressing at all
+    client
+        .post("http://localhost:8151/_proxy/api/config?token=test-token")
+        .json(&serde_json::json!({ "decompression": "identity" }))
+        .send()
+        .await
+        .unwrap();
+    let response = client
+        .get("http://localhost:8151/page")
+        .header("accept-encoding", "gzip")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.headers().get("content-encoding").is_none());
+    assert_eq!(response.text().await.unwrap(), "plain text");
+
+    // Off passes the compressed body through untouched
+    client
+        .post("http://localhost:8151/_proxy/api/config?token=test-token")
+        .json(&serde_json::json!({ "decompression": "off" }))
+        .send()
+        .await
+        .unwrap();
+    let response = client
+        .get("http://localhost:8151/page")
+        .header("accept-encoding", "gzip")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_body_size_warnings() {
+    use debug_proxy::body_size::{BodyPart, SizeWarning};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Answers /short with 10 of the 100 bytes it promises
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3071")
+        .await
+        .unwrap();
+    let upstream_server = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let n = stream.read(&mut request).await.unwrap_or(0);
+            let response: &[u8] = if request[..n].starts_with(b"GET /short") {
+                b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n0123456789"
+            } else {
+                b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nhello world!"
+            };
+            let _ = stream.write_all(response).await;
+        }
+    });
+
+    let config = ProxyConfig {
+        access_token: "test-token".to_string(),
+        max_body_size: 8,
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3071".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8152).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .post("http://localhost:8152/upload")
+        .body("0123456789")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "hello world!");
+    let response = client
+        .get("http://localhost:8152/short")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 502);
+
+    let transactions = recorder.get_transactions();
+    assert_eq!(
+        transactions[0].size_warnings,
+        vec![
+            SizeWarning::OverLimit {
+                part: BodyPart::Request,
+                size: 10,
+                limit: 8,
+            },
+            SizeWarning::OverLimit {
+                part: BodyPart::Response,
+                size: 12,
+                limit: 8,
+            },
+        ]
+    );
+    assert_eq!(
+        transactions[1].size_warnings,
+        vec![
+            SizeWarning::OverLimit {
+                part: BodyPart::Response,
+                size: 10,
+                limit: 8,
+            },
+            SizeWarning::LengthMismatch {
+                part: BodyPart::Response,
+                content_length: 100,
+                received: 10,
+            },
+        ]
+    );
+
+    // The event log carries them too
+    let events: Vec<serde_json::Value> = client
+        .get("http://localhost:8152/_proxy/api/events?token=test-token")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let mismatch = events
+        .iter()
+        .find(|event| event["kind"] == "body_size" && event["warning"] == "length_mismatch")
+        .unwrap();
+    assert_eq!(mismatch["path"], "/short");
+    assert_eq!(mismatch["content_length"], 100);
+    assert_eq!(mismatch["request_id"], transactions[1].request.id.as_str());
+
+    upstream_server.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_split_routing() {
+    let upstream_a = start_test_server(3022).await;
+    let upstream_b = start_test_server(3023).await;
+
+    let config = ProxyConfig {
+        upstream_b: Some("127.0.0.1:3023".to_string()),
+        split_percent: 25,
+        access_token: "test-token".to_string(),
+        ..Default::default()
+    };
+    let recorder = RequestRecorder::new(20);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(config),
+        recorder.clone(),
+        "127.0.0.1:3022".to_string(),
+    );
+    let proxy_server = start_proxy_server(proxy, 8101).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let send_requests = |count: usize| {
+        let client = client.clone();
+        async move {
+            for _ in 0..count {
+                let response = client
+                    .get("http://localhost:8101/split")
+                    .send()
+                    .await
+                    .expect("Failed to send request");
+                assert_eq!(response.status(), 200);
+            }
+        }
+    };
+    let to_b = |recorder: &RequestRecorder| {
+        recorder
+            .get_transactions()
+            .iter()
+            .filter(|t| t.request.target.as_deref() == Some("http://127.0.0.1:3023"))
+            .count()
+    };
+
+    send_requests(8).await;
+    assert_eq!(to_b(&recorder), 2);
+
+    // The split can be changed while running
+    let response = client
+        .post("http://localhost:8101/_proxy/api/config?token=test-token")
+        .json(&serde_json::json!({ "split_percent": 0 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    send_requests(4).await;
+    assert_eq!(to_b(&recorder), 2);
+    assert_eq!(recorder.get_transactions().len(), 12);
+
+    upstream_a.abort();
+    upstream_b.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_sticky_instances() {
+    use debug_proxy::balancer::{Balancer, Stickiness};
+
+    let instance_a = start_test_server(3024).await;
+    let instance_b = start_test_server(3025).await;
+
+    let recorder = RequestRecorder::new(10);
+    let proxy = DebugProxy::new(
+        SharedConfig::new(ProxyConfig::default()),
+        recorder.clone(),
+        "127.0.0.1:3024".to_string(),
+    )
+    .with_balancer(Arc::new(
+        Balancer::new(vec![
+            "127.0.0.1:3024".to_string(),
+            "127.0.0.1:3025".to_string(),
+        ])
+        .with_stickiness(Stickiness::Cookie),
+    ));
+    let proxy_server = start_proxy_server(proxy, 8102).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let response = client
+        .get("http://localhost:8102/login")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    let cookie = response
+        .headers()
+        .get("set-cookie")
+        .expect("No sticky cookie")
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+
+    for _ in 0..3 {
+        let response = client
+            .get("http://localhost:8102/profile")
+            .header("cookie", &cookie)
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().get("set-cookie").is_none());
+    }
+
+    let transactions = recorder.get_transactions();
+    assert_eq!(transactions.len(), 4);
+    let first = transactions[0].request.target.clone();
+    assert!(first.is_some());
+    assert!(transactions.iter().all(|t| t.request.target == first));
+
+    instance_a.abort();
+    instance_b.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_upstream_failover() {
+    use debug_proxy::balancer::{Balancer, HealthCheck};
+
+    // Nothing listens on 3027
+    let instance = start_test_server(3026).await;
+
+    let recorder = RequestRecorder::new(10);
+    let config = SharedConfig::new(ProxyConfig::default());
+    let balancer = Arc::new(
+        Balancer::new(vec![
+            "127.0.0.1:3026".to_string(),
+            "127.0.0.1:3027".to_string(),
+        ])
+        .with_health_check(HealthCheck {
+            path: None,
+            interval: Duration::from_millis(50),
+            threshold: 1,
+        }),
+    );
+    balancer.spawn_health_checks(&config.read());
+    let token = config.get_access_token();
+    let proxy = DebugProxy::new(config, recorder.clone(), "127.0.0.1:3026".to_string())
+        .with_balancer(balancer);
+    let proxy_server = start_proxy_server(proxy, 8103).await;
+
+    // Wait for servers to be ready and the first probes to finish
+    sleep(Duration::from_millis(300)).await;
+
+    let client = Client::new();
+    for _ in 0..4 {
+        let response = client
+            .get("http://localhost:8103/test")
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), 200);
+    }
+
+    let transactions = recorder.get_transactions();
+    let failovers: Vec<_> = transactions
+        .iter()
+        .filter_map(|t| t.failover_from.as_deref())
+        .collect();
+    assert_eq!(failovers, vec!["127.0.0.1:3027"; 2]);
+
+    let stats: serde_json::Value = client
+        .get(format!(
+            "http://localhost:8103/_proxy/api/stats?token={token}"
+        ))
+        .send()
+        .await
+        .expect("Failed to get stats")
+        .json()
+        .await
+        .unwrap();
+    let health = stats["upstream_health"].as_array().unwrap();
+    assert_eq!(health[0]["healthy"], true);
+    assert_eq!(health[1]["healthy"], false);
+    assert_eq!(health[1]["failovers"], 2);
+
+    instance.abort();
+    proxy_server.abort();
+}
+
+#[tokio::test]
+async fn test_outbound_proxies() {
+    use debug_proxy::outbound::OutboundProxy;
+
+    let upstream_server = start_test_server(3014).await;
+    let socks_server = start_socks5_server(3015).await;
+
+    // The upstream host only resolves on the SOCKS5 proxy's side
+    let socks_config = ProxyConfig {
+        outbound_proxy: Some(OutboundProxy::parse("socks5://127.0.0.1:3015").unwrap()),
+        ..Default::default()
+    };
+    let socks_proxy = DebugProxy::new(
+        SharedConfig::new(socks_config),
+        RequestRecorder::new(10),
+        "upstream.invalid:3014".to_string(),
+    );
+    let socks_proxy_server = start_proxy_server(socks_proxy, 8093).await;
+
+    // An HTTP proxy gets absolute-form requests; the test server answers
+    // them itself
+    let http_config = ProxyConfig {
+        outbound_proxy: Some(OutboundProxy::parse("http://127.0.0.1:3014").unwrap()),
+        ..Default::default()
+    };
+    let http_proxy = DebugProxy::new(
+        SharedConfig::new(http_config),
+        RequestRecorder::new(10),
+        "upstream.invalid:9".to_string(),
+    );
+    let http_proxy_server = start_proxy_server(http_proxy, 8094).await;
+
+    // Wait for servers to be ready
+    sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    for port in [8093, 8094] {
+        let response = client
+            .get(format!("http://localhost:{port}/through-proxy"))
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "Hello from test server");
+    }
+
+    upstream_server.abort();
+    socks_server.abort();
+    socks_proxy_server.abort();
+    http_proxy_server.abort();
+}
+
+/// A SOCKS5 proxy without authentication that resolves `upstream.invalid`
+/// to 127.0.0.1.
+async fn start_socks5_server(port: u16) -> tokio::task::JoinHandle<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut client, _)) = listener.accept().await else {
+                return;
+            };
+            tokio::spawn(async move {
+                let mut greeting = [0u8; 2];
+                client.read_exact(&mut greeting).await.unwrap();
+                let mut methods = vec![0u8; greeting[1] as usize];
+                client.read_exact(&mut methods).await.unwrap();
+                client.write_all(&[0x05, 0x00]).await.unwrap();
+
+                let mut request = [0u8; 4];
+                client.read_exact(&mut request).await.unwrap();
+                assert_eq!(request[3], 0x03, "expected a domain name");
+                let len = client.read_u8().await.unwrap() as usize;
+                let mut host = vec![0u8; len];
+                client.read_exact(&mut host).await.unwrap();
+                let port = client.read_u16().await.unwrap();
+                assert_eq!(host, b"upstream.invalid");
+
+                let mut upstream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .unwrap();
+                client
+                    .write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0])
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            });
+        }
+    })
+}
+
+async fn response_etag(client: &Client, url: &str) -> String {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .expect("Failed to send request");
+    response.headers()["etag"].to_str().unwrap().to_string()
+}
+
+async fn start_test_server(port: u16) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Request, Response, Server};
+        use std::convert::Infallible;
+
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|_req: Request<Body>| async {
+                Ok::<_, Infallible>(Response::new(Body::from("Hello from test server")))
+            }))
+        });
+
+        let addr = ([127, 0, 0, 1], port).into();
+        let server = Server::bind(&addr).serve(make_svc);
+
+        if let Err(e) = server.await {
+            eprintln!("Test server error: {e}");
+        }
+    })
+}
+
+/// Serves "Hello from test server" over TLS with `config`.
+#[cfg(feature = "tls")]
+async fn start_tls_test_server(
+    port: u16,
+    config: rustls::ServerConfig,
+) -> tokio::task::JoinHandle<()> {
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        use hyper::service::service_fn;
+        use hyper::{Body, Request, Response};
+        use std::convert::Infallible;
+
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                return;
+            };
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let service = service_fn(|_req: Request<Body>| async {
+                    Ok::<_, Infallible>(Response::new(Body::from("Hello from test server")))
+                });
+                let _ = hyper::server::conn::Http::new()
+                    .serve_connection(stream, service)
+                    .await;
+            });
         }
     })
 }
@@ -1,4 +1,4 @@
-import { useState, useEffect } from 'react'
+import { useState, useEffect, useRef } from 'react'
 import { 
   Settings, 
   Activity, 
@@ -55,17 +55,40 @@ interface HttpTransaction {
   error?: string;
 }
 
+// Deep links such as /_proxy/requests/<id> open that transaction
+const requestIdFromPath = (pathname: string): string | null => {
+  const match = pathname.match(/^\/_proxy\/requests\/([^/]+)\/?$/);
+  return match ? decodeURIComponent(match[1]) : null;
+};
+
 function App() {
   const [config, setConfig] = useState<Config | null>(null);
   const [transactions, setTransactions] = useState<HttpTransaction[]>([]);
   const [loading, setLoading] = useState(true);
   const [notification, setNotification] = useState<{ message: string; type: 'success' | 'error' } | null>(null);
   const [refreshing, setRefreshing] = useState(false);
+  const [selectedId, setSelectedId] = useState<string | null>(
+    requestIdFromPath(window.location.pathname)
+  );
 
   // Get token from URL params
   const urlParams = new URLSearchParams(window.location.search);
   const token = urlParams.get('token') || '';
 
+  // Follow the browser's back and forward buttons
+  useEffect(() => {
+    const onPopState = () => setSelectedId(requestIdFromPath(window.location.pathname));
+    window.addEventListener('popstate', onPopState);
+    return () => window.removeEventListener('popstate', onPopState);
+  }, []);
+
+  const selectTransaction = (id: string | null) => {
+    const path = id ? `/_proxy/requests/${encodeURIComponent(id)}` : '/_proxy/';
+    // Keep the query, which holds the token
+    window.history.pushState(null, '', `${path}${window.location.search}`);
+    setSelectedId(id);
+  };
+
   // Auto-refresh transactions every 5 seconds
   useEffect(() => {
     const interval = setInterval(() => {
@@ -285,7 +308,14 @@ function App() {
               </div>
             ) : (
               transactions.map((transaction) => (
-                <TransactionCard key={transaction.request.id} transaction={transaction} />
+                <TransactionCard
+                  key={transaction.request.id}
+                  transaction={transaction}
+                  expanded={transaction.request.id === selectedId}
+                  onToggle={() =>
+                    selectTransaction(transaction.request.id === selectedId ? null : transaction.request.id)
+                  }
+                />
               ))
             )}
           </div>
@@ -295,8 +325,23 @@ function App() {
   );
 }
 
-function TransactionCard({ transaction }: { transaction: HttpTransaction }) {
-  const [expanded, setExpanded] = useState(false);
+function TransactionCard({
+  transaction,
+  expanded,
+  onToggle,
+}: {
+  transaction: HttpTransaction;
+  expanded: boolean;
+  onToggle: () => void;
+}) {
+  const cardRef = useRef<HTMLDivElement>(null);
+
+  // Bring a deep-linked transaction into view
+  useEffect(() => {
+    if (expanded) {
+      cardRef.current?.scrollIntoView({ block: 'nearest' });
+    }
+  }, [expanded]);
   
   const formatTimestamp = (timestamp: string | number) => {
     try {
@@ -320,8 +365,8 @@ function TransactionCard({ transaction }: { transaction: HttpTransaction }) {
   };
 
   return (
-    <div className={`transaction-card ${expanded ? 'expanded' : ''}`}>
-      <div className="transaction-header" onClick={() => setExpanded(!expanded)}>
+    <div ref={cardRef} className={`transaction-card ${expanded ? 'expanded' : ''}`}>
+      <div className="transaction-header" onClick={onToggle}>
         <div className="transaction-basic">
           <span className={`method method-${transaction.request.method.toLowerCase()}`}>
             {transaction.request.method}
@@ -5,4 +5,8 @@ import react from '@vitejs/plugin-react'
 export default defineConfig({
   plugins: [react()],
   base: '/_proxy/',
+  build: {
+    // Served at /_proxy/manifest.json, listing the hashed file of each entry
+    manifest: 'manifest.json',
+  },
 })